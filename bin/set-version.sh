@@ -1,2 +1,2 @@
-cargo set-version $1
+cargo set-version --workspace $1
 node ./bin/update-version.mjs $1
\ No newline at end of file
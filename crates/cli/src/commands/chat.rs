@@ -0,0 +1,325 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ env, io::{ self, BufRead, Write }, path::PathBuf, process::ExitCode };
+
+use clap::{ Args, ValueEnum };
+use opla_core::providers::llm::{
+    LlmCompletionOptions,
+    LlmParameter,
+    LlmQueryCompletion,
+    LlmUsage,
+};
+
+use crate::{
+    messages::{ load_messages, save_messages, Message },
+    providers::{
+        llama_cpp::LlamaCppInferenceClient,
+        openai::{ OpenAIInferenceClient, OPENAI_API_URL },
+        to_llm_parameters,
+        LlmInferenceInterface,
+        LlmParameters,
+    },
+};
+
+const HELP: &str =
+    "/model <model>       switch the model, a GGUF file for the local server
+/system [prompt]     set the system prompt, or remove it
+/set <key> <value>   set a parameter of the completion
+/unset <key>         remove a parameter
+/params              show the parameters
+/clear               start a new conversation
+/save [file]         save the conversation to a messages.json file
+/exit                quit";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Provider {
+    // The llama.cpp server started by the app.
+    Local,
+    Openai,
+}
+
+#[derive(Args)]
+pub struct ChatArgs {
+    #[arg(long, value_enum, default_value_t = Provider::Local)]
+    provider: Provider,
+    #[arg(
+        short,
+        long,
+        help = "GGUF file loaded by the local server, for its chat template, or remote model name"
+    )]
+    model: Option<String>,
+    #[arg(short, long, help = "System prompt")]
+    system: Option<String>,
+    #[arg(short, long = "param", value_name = "KEY=VALUE", help = "Completion parameter")]
+    parameters: Vec<String>,
+    #[arg(long, default_value = "127.0.0.1", help = "Host of the local server")]
+    host: String,
+    #[arg(long, default_value_t = 8081, help = "Port of the local server")]
+    port: u16,
+    #[arg(long, default_value = OPENAI_API_URL, help = "URL of an OpenAI compatible API")]
+    api_url: String,
+    #[arg(long, help = "API key, OPENAI_API_KEY by default")]
+    api_key: Option<String>,
+    #[arg(long, help = "messages.json file to continue and save the conversation to")]
+    save: Option<PathBuf>,
+    #[arg(long, help = "Wait for the whole answer instead of streaming it")]
+    no_stream: bool,
+}
+
+struct ChatSession {
+    client: Box<dyn LlmInferenceInterface>,
+    system: Option<String>,
+    parameters: LlmParameters,
+    messages: Vec<Message>,
+    save: Option<PathBuf>,
+    stream: bool,
+}
+
+fn create_client(args: &ChatArgs) -> Result<Box<dyn LlmInferenceInterface>, String> {
+    match args.provider {
+        Provider::Local => {
+            let mut client = LlamaCppInferenceClient::new(&args.host, args.port);
+            if let Some(ref model) = args.model {
+                client.set_model(model)?;
+            }
+            Ok(Box::new(client))
+        }
+        Provider::Openai => {
+            let model = args.model.as_ref().ok_or("A model is required, use --model")?;
+            let secret_key = args.api_key.clone().or_else(|| env::var("OPENAI_API_KEY").ok());
+            Ok(Box::new(OpenAIInferenceClient::new(&args.api_url, secret_key, model)))
+        }
+    }
+}
+
+fn format_usage(usage: &LlmUsage) -> Option<String> {
+    let tokens = usage.completion_tokens?;
+    Some(match usage.completion_per_second {
+        Some(speed) => format!("[{} tokens, {:.1} tokens/s]", tokens, speed),
+        None => format!("[{} tokens]", tokens),
+    })
+}
+
+impl ChatSession {
+    fn set_parameter(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if !self.client.parameter_keys().contains(&key) {
+            return Err(
+                format!(
+                    "Unknown parameter {}, expected one of: {}",
+                    key,
+                    self.client.parameter_keys().join(", ")
+                )
+            );
+        }
+        self.parameters.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        match self.save {
+            Some(ref path) => save_messages(path, &self.messages),
+            None => Ok(()),
+        }
+    }
+
+    // Returns false to quit.
+    fn run_command(&mut self, line: &str) -> Result<bool, String> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "/exit" | "/quit" => {
+                return Ok(false);
+            }
+            "/help" => println!("{}", HELP),
+            "/model" if !argument.is_empty() => {
+                self.client.set_model(argument)?;
+                println!("Model: {}", self.client.name());
+            }
+            "/model" => println!("Model: {}", self.client.name()),
+            "/system" if argument.is_empty() => {
+                self.system = None;
+            }
+            "/system" => {
+                self.system = Some(argument.to_string());
+            }
+            "/set" =>
+                match argument.split_once(char::is_whitespace) {
+                    Some((key, value)) => self.set_parameter(key, value.trim())?,
+                    None => {
+                        return Err("Usage: /set <key> <value>".to_string());
+                    }
+                }
+            "/unset" => {
+                self.parameters.remove(argument);
+            }
+            "/params" => {
+                for (key, value) in self.parameters.iter() {
+                    println!("{} = {}", key, value);
+                }
+            }
+            "/clear" => {
+                self.messages.clear();
+                self.save()?;
+            }
+            "/save" => {
+                if !argument.is_empty() {
+                    self.save = Some(PathBuf::from(argument));
+                }
+                match self.save {
+                    Some(ref path) => {
+                        self.save()?;
+                        println!("Saved to {:?}", path);
+                    }
+                    None => {
+                        return Err("Usage: /save <file>".to_string());
+                    }
+                }
+            }
+            _ => {
+                return Err(format!("Unknown command {}, type /help", command));
+            }
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, prompt: &str) -> Result<(), String> {
+        let mut user_message = Message::user(prompt);
+        let mut messages: Vec<_> = self.messages
+            .iter()
+            .filter_map(|message| message.to_llm_message())
+            .collect();
+        messages.extend(user_message.to_llm_message());
+
+        let mut parameters = to_llm_parameters(&self.parameters);
+        parameters.push(LlmParameter {
+            key: "stream".to_string(),
+            value: self.stream.to_string(),
+        });
+        let query = LlmQueryCompletion {
+            conversation_id: None,
+            message_id: None,
+            messages,
+            prompt: None,
+            parameters: Some(parameters),
+        };
+        let options = LlmCompletionOptions {
+            context_window_policy: None,
+            keep_system: None,
+            system: self.system.clone(),
+        };
+
+        let mut stdout = io::stdout();
+        let response = self.client.call_completion(
+            &query,
+            Some(options),
+            &mut |token| {
+                let _ = write!(stdout, "{}", token);
+                let _ = stdout.flush();
+            }
+        )?;
+        println!();
+        if let Some(usage) = response.usage.as_ref().and_then(format_usage) {
+            eprintln!("{}", usage);
+        }
+
+        let mut assistant_message = Message::assistant(&self.client.name(), &response.content);
+        user_message.sibling = Some(assistant_message.id.clone());
+        assistant_message.sibling = Some(user_message.id.clone());
+        self.messages.push(user_message);
+        self.messages.push(assistant_message);
+        self.save()
+    }
+}
+
+pub fn run(args: &ChatArgs) -> ExitCode {
+    let mut session = match create_client(args) {
+        Ok(client) =>
+            ChatSession {
+                client,
+                system: args.system.clone(),
+                parameters: LlmParameters::new(),
+                messages: Vec::new(),
+                save: args.save.clone(),
+                stream: !args.no_stream,
+            },
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    };
+    for parameter in args.parameters.iter() {
+        let result = match parameter.split_once('=') {
+            Some((key, value)) => session.set_parameter(key, value),
+            None => Err(format!("Invalid parameter {}, expected KEY=VALUE", parameter)),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    }
+    // An existing conversation is continued.
+    if let Some(ref path) = args.save {
+        if path.exists() {
+            match load_messages(path) {
+                Ok(messages) => {
+                    session.messages = messages;
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return ExitCode::from(2);
+                }
+            }
+        }
+    }
+
+    println!("Chat with {}, type /help for commands.", session.client.name());
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+            // End of input.
+            None => {
+                println!();
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let result = if line.starts_with('/') {
+            session.run_command(line)
+        } else {
+            session.send(line).map(|_| true)
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                break;
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,125 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ fs, path::{ Path, PathBuf }, process::ExitCode };
+
+use clap::Args;
+use serde::Serialize;
+use tokenizer::Tokenizer;
+
+use super::tokenize::TokenizerArgs;
+
+#[derive(Args)]
+pub struct CountArgs {
+    #[arg(required = true, help = "Files, or directories read recursively")]
+    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    #[arg(long, help = "Print the counts as JSON")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct FileCount {
+    path: PathBuf,
+    tokens: usize,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct CountReport {
+    tokenizer: String,
+    files: Vec<FileCount>,
+    total: usize,
+    errors: Vec<String>,
+}
+
+// Hidden files and directories, like .git, are skipped.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs
+        ::read_dir(path)
+        .map_err(|err| format!("Can't read {:?}: {}", path, err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            !path
+                .file_name()
+                .map(|name| name.to_string_lossy().starts_with('.'))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        collect_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+fn count(tokenizer: &Tokenizer, args: &CountArgs) -> CountReport {
+    let mut report = CountReport {
+        tokenizer: tokenizer.name.clone(),
+        files: Vec::new(),
+        total: 0,
+        errors: Vec::new(),
+    };
+    let mut files = Vec::new();
+    for path in args.paths.iter() {
+        if let Err(err) = collect_files(path, &mut files) {
+            report.errors.push(err);
+        }
+    }
+    for path in files {
+        // Binary files are reported and not counted.
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let tokens = tokenizer.encode(&text, args.tokenizer.add_special).len();
+                report.total += tokens;
+                report.files.push(FileCount { path, tokens, bytes: text.len() });
+            }
+            Err(err) => report.errors.push(format!("Can't read {:?}: {}", path, err)),
+        }
+    }
+    report
+}
+
+pub fn run(args: &CountArgs) -> ExitCode {
+    let tokenizer = match args.tokenizer.create_tokenizer() {
+        Ok(tokenizer) => tokenizer,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = count(&tokenizer, args);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        let width = report.total.to_string().len();
+        for file in report.files.iter() {
+            println!("{:>width$}  {}", file.tokens, file.path.display(), width = width);
+        }
+        println!("{:>width$}  total ({})", report.total, report.tokenizer, width = width);
+        for err in report.errors.iter() {
+            eprintln!("{}", err);
+        }
+    }
+    if report.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::process::ExitCode;
+
+use clap::Args;
+use opla_core::gguf::GGUFDiff;
+
+use super::read_gguf;
+
+#[derive(Args)]
+pub struct DiffArgs {
+    left: String,
+    right: String,
+    #[arg(long, help = "Print the differences as JSON")]
+    json: bool,
+}
+
+fn print_diff(diff: &GGUFDiff) {
+    for metadata in diff.added_metadata.iter() {
+        println!("+ {} = {}", metadata.key, metadata.value);
+    }
+    for metadata in diff.removed_metadata.iter() {
+        println!("- {} = {}", metadata.key, metadata.value);
+    }
+    for metadata in diff.changed_metadata.iter() {
+        println!("~ {} = {} -> {}", metadata.key, metadata.left, metadata.right);
+    }
+    for metadata in diff.unknown_metadata.iter() {
+        println!("? {} = {} not compared", metadata.key, metadata.left);
+    }
+    for tensor in diff.added_tensors.iter() {
+        println!("+ {} {:?} {:?}", tensor.name, tensor.dimensions, tensor.tensor_type);
+    }
+    for tensor in diff.removed_tensors.iter() {
+        println!("- {} {:?} {:?}", tensor.name, tensor.dimensions, tensor.tensor_type);
+    }
+    for tensor in diff.changed_tensors.iter() {
+        println!(
+            "~ {} {:?} {:?} -> {:?} {:?} ({:+} bytes)",
+            tensor.name,
+            tensor.left_dimensions,
+            tensor.left_type,
+            tensor.right_dimensions,
+            tensor.right_type,
+            tensor.size_delta
+        );
+    }
+    println!(
+        "Tensors size: {} -> {} ({:+} bytes)",
+        diff.left_tensors_size,
+        diff.right_tensors_size,
+        diff.size_delta
+    );
+}
+
+// Same convention as diff(1): 0 if the files are the same, 1 if they differ, 2 on errors.
+pub fn run(args: &DiffArgs) -> ExitCode {
+    let diff = match (read_gguf(&args.left), read_gguf(&args.right)) {
+        (Ok(left), Ok(right)) => left.diff(&right),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print_diff(&diff);
+    }
+    if diff.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::process::ExitCode;
+
+use clap::Args;
+use opla_core::{
+    adapter::{ is_adapter, LoraAdapter },
+    architecture::{ validate_gguf, ArchitectureValidation },
+    descriptor::ModelDescriptor,
+    gguf::{ GGUFByteOrder, GGUFMetadataValue, GGUF },
+    memory::{ MemoryOptions, MemoryRequirements },
+};
+use serde::Serialize;
+
+use super::{ format_count, format_size, read_gguf };
+
+// Longer string values, like chat templates, are cut in the metadata listing.
+const MAX_VALUE_LEN: usize = 80;
+
+#[derive(Args)]
+pub struct InspectArgs {
+    path: String,
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+    #[arg(long, help = "Context size of the memory estimate, the model's one by default")]
+    context_size: Option<u64>,
+    #[arg(long, default_value_t = 0, help = "Layers offloaded to the GPU in the memory estimate")]
+    gpu_layers: u64,
+    #[arg(long, help = "Don't list the tensors")]
+    no_tensors: bool,
+}
+
+#[derive(Serialize)]
+struct InspectReport {
+    path: String,
+    valid: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gguf: Option<GGUF>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    descriptor: Option<ModelDescriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<ArchitectureValidation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adapter: Option<LoraAdapter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryRequirements>,
+}
+
+impl InspectReport {
+    fn new(args: &InspectArgs) -> InspectReport {
+        let mut report = InspectReport {
+            path: args.path.clone(),
+            valid: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            gguf: None,
+            descriptor: None,
+            validation: None,
+            adapter: None,
+            memory: None,
+        };
+        let gguf = match read_gguf(&args.path) {
+            Ok(gguf) => gguf,
+            Err(err) => {
+                report.errors.push(err);
+                return report;
+            }
+        };
+
+        // Adapters have no hyperparameters, only their tensors are checked.
+        if is_adapter(&gguf) {
+            match LoraAdapter::from_gguf(&gguf) {
+                Ok(adapter) => {
+                    report.adapter = Some(adapter);
+                }
+                Err(err) => report.errors.push(err),
+            }
+        } else {
+            let validation = validate_gguf(&gguf);
+            if let Some(err) = validation.to_error() {
+                report.errors.push(err);
+            }
+            report.warnings.extend(validation.to_warning());
+            report.validation = Some(validation);
+            match ModelDescriptor::from_gguf(&gguf) {
+                Ok(descriptor) => {
+                    let options = MemoryOptions {
+                        context_size: args.context_size,
+                        gpu_layers: args.gpu_layers,
+                        ..MemoryOptions::default()
+                    };
+                    let memory = MemoryRequirements::estimate(&gguf, &descriptor, &options);
+                    report.memory = Some(memory);
+                    report.descriptor = Some(descriptor);
+                }
+                // Already reported by the validation.
+                Err(err) if report.errors.is_empty() => report.errors.push(err),
+                Err(_) => {}
+            }
+        }
+        report.valid = report.errors.is_empty();
+        report.gguf = Some(gguf);
+        report
+    }
+}
+
+fn format_value(value: &GGUFMetadataValue) -> String {
+    let value = value.to_string();
+    let line = value.lines().next().unwrap_or_default();
+    if line.len() < value.len() || line.chars().count() > MAX_VALUE_LEN {
+        let line: String = line.chars().take(MAX_VALUE_LEN).collect();
+        format!("{}... ({} chars)", line, value.chars().count())
+    } else {
+        value
+    }
+}
+
+fn print_summary(report: &InspectReport, gguf: &GGUF) {
+    println!("{}", report.path);
+    let byte_order = match gguf.header.byte_order {
+        GGUFByteOrder::LittleEndian => "little-endian",
+        GGUFByteOrder::BigEndian => "big-endian",
+    };
+    println!("  Format        GGUF v{}, {}", gguf.header.version, byte_order);
+    if let Some(ref descriptor) = report.descriptor {
+        println!("  Architecture  {}", descriptor.architecture);
+        if let Some(ref name) = descriptor.name {
+            println!("  Name          {}", name);
+        }
+        println!("  Parameters    {}", format_count(descriptor.parameter_count));
+        if let Some(ref quantization) = descriptor.quantization {
+            match descriptor.bits_per_weight {
+                Some(bits) => {
+                    println!("  Quantization  {}, {:.2} bits per weight", quantization, bits);
+                }
+                None => println!("  Quantization  {}", quantization),
+            }
+        }
+        println!("  Context       {}", descriptor.context_length);
+    }
+    if let Some(ref adapter) = report.adapter {
+        match adapter.architecture {
+            Some(ref architecture) => println!("  Adapter       LoRA for {}", architecture),
+            None => println!("  Adapter       LoRA"),
+        }
+        if let Some(ref name) = adapter.name {
+            println!("  Name          {}", name);
+        }
+        if let Some(rank) = adapter.rank {
+            println!("  Rank          {}", rank);
+        }
+        if let Some(alpha) = adapter.alpha {
+            println!("  Alpha         {}", alpha);
+        }
+    }
+    println!(
+        "  Tensors       {}, {}",
+        gguf.tensors.len(),
+        format_size(gguf.tensors_size())
+    );
+    if let Some(ref memory) = report.memory {
+        println!(
+            "  Memory        {} at context {} (weights {}, KV cache {}, compute {})",
+            format_size(memory.total()),
+            memory.context_size,
+            format_size(memory.weights),
+            format_size(memory.kv_cache),
+            format_size(memory.compute)
+        );
+        if memory.vram > 0 {
+            println!(
+                "                RAM {}, VRAM {}",
+                format_size(memory.ram),
+                format_size(memory.vram)
+            );
+        }
+    }
+}
+
+fn print_report(report: &InspectReport, args: &InspectArgs) {
+    let gguf = match report.gguf {
+        Some(ref gguf) => gguf,
+        None => {
+            return;
+        }
+    };
+    print_summary(report, gguf);
+
+    println!();
+    println!("Metadata ({})", gguf.header.metadata_kv.len());
+    for metadata in gguf.header.metadata_kv.iter() {
+        println!("  {} = {}", metadata.key, format_value(&metadata.value));
+    }
+
+    if !args.no_tensors {
+        println!();
+        println!("Tensors ({})", gguf.tensors.len());
+        let width = gguf.tensors
+            .iter()
+            .map(|t| t.name.len())
+            .max()
+            .unwrap_or_default();
+        for tensor in gguf.tensors.iter() {
+            println!(
+                "  {:width$}  {:<6}  {:<24}  {}",
+                tensor.name,
+                tensor.tensor_type.to_string(),
+                format!("{:?}", tensor.dimensions),
+                format_size(tensor.size()),
+                width = width
+            );
+        }
+    }
+}
+
+// Exits with 1 if the file can't be read or is not a valid model, to audit model folders.
+pub fn run(args: &InspectArgs) -> ExitCode {
+    let report = InspectReport::new(args);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_report(&report, args);
+        for warning in report.warnings.iter() {
+            eprintln!("Warning: {}", warning);
+        }
+        for err in report.errors.iter() {
+            eprintln!("{}", err);
+        }
+    }
+    if report.valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
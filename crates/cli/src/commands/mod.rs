@@ -0,0 +1,66 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod chat;
+pub mod count;
+pub mod diff;
+pub mod inspect;
+pub mod models;
+pub mod serve;
+pub mod tokenize;
+
+use opla_core::gguf::GGUF;
+
+// Reads a model, with all its shards if it is split.
+pub fn read_gguf(path: &str) -> Result<GGUF, String> {
+    GGUF::read_split(path).map_err(|err| format!("Can't read {}: {}", path, err))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = (bytes as f64) / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+// Parameter counts as usually written in model names: 135M, 8.03B.
+pub fn format_count(count: u64) -> String {
+    match count {
+        0..1_000 => count.to_string(),
+        1_000..1_000_000 => format!("{:.1}K", (count as f64) / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.0}M", (count as f64) / 1e6),
+        _ => format!("{:.2}B", (count as f64) / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ format_count, format_size };
+
+    #[test]
+    fn format_numbers() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.50 KiB");
+        assert_eq!(format_size(4_920_000_000), "4.58 GiB");
+        assert_eq!(format_count(134_515_008), "135M");
+        assert_eq!(format_count(8_030_261_248), "8.03B");
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ fs, path::{ Path, PathBuf }, process::ExitCode };
+
+use clap::{ Args, Subcommand };
+use opla_core::{
+    descriptor::ModelDescriptor,
+    gguf::{ GgufError, GGUF },
+    hf::{ file_url, HF_SEARCH_LIMIT },
+    models::{ Entity, Model, Resource },
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    downloader::download_file,
+    hf::{ get_hf_model, search_hf_models },
+    store::Store,
+};
+use super::{ format_size, read_gguf };
+
+#[derive(Args)]
+pub struct ModelsArgs {
+    #[command(subcommand)]
+    command: ModelsCommand,
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    #[command(about = "List the installed models")]
+    List(ListArgs),
+    #[command(about = "Search the GGUF models of the Hugging Face hub")]
+    Search(SearchArgs),
+    #[command(about = "Download a model, or add a local model file")]
+    Install(InstallArgs),
+    #[command(about = "Remove an installed model")]
+    Uninstall(UninstallArgs),
+    #[command(about = "Set the model used by default by the app and the server")]
+    SetDefault(SetDefaultArgs),
+}
+
+#[derive(Args)]
+struct ListArgs {
+    #[arg(long, help = "Print the models as JSON")]
+    json: bool,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    query: String,
+    #[arg(long, default_value_t = HF_SEARCH_LIMIT, help = "Maximum number of models")]
+    limit: usize,
+    #[arg(long, help = "Print the models as JSON")]
+    json: bool,
+}
+
+#[derive(Args)]
+struct InstallArgs {
+    #[arg(help = "Hugging Face repository or file (owner/repo/file.gguf), URL, or local file")]
+    source: String,
+    #[arg(long, help = "SHA256 checksum of the file")]
+    sha256: Option<String>,
+    #[arg(long = "default", help = "Set as the default model")]
+    set_default: bool,
+}
+
+#[derive(Args)]
+struct UninstallArgs {
+    #[arg(help = "Id or name of the model")]
+    model: String,
+    #[arg(long, help = "Also delete the model file")]
+    delete: bool,
+}
+
+#[derive(Args)]
+struct SetDefaultArgs {
+    #[arg(help = "Id or name of the model")]
+    model: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum ModelSource {
+    Local(PathBuf),
+    Url(String),
+    HuggingFace {
+        repo_id: String,
+        file_name: Option<String>,
+    },
+}
+
+impl ModelSource {
+    fn parse(source: &str) -> Result<ModelSource, String> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            return Ok(ModelSource::Url(source.to_string()));
+        }
+        let path = PathBuf::from(source);
+        if path.exists() {
+            return Ok(ModelSource::Local(path));
+        }
+        let parts: Vec<&str> = source.splitn(3, '/').collect();
+        match parts[..] {
+            [owner, repo] if !owner.is_empty() && !repo.is_empty() =>
+                Ok(ModelSource::HuggingFace { repo_id: source.to_string(), file_name: None }),
+            [owner, repo, file_name] if !owner.is_empty() && !repo.is_empty() =>
+                Ok(ModelSource::HuggingFace {
+                    repo_id: format!("{}/{}", owner, repo),
+                    file_name: Some(file_name.to_string()),
+                }),
+            _ => Err(format!("File not found: {}", source)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ModelItem {
+    id: Option<String>,
+    name: String,
+    state: Option<String>,
+    file: Option<PathBuf>,
+    size: Option<u64>,
+    default: bool,
+}
+
+pub fn run(args: &ModelsArgs) -> ExitCode {
+    let result = match &args.command {
+        ModelsCommand::List(args) => list(args),
+        ModelsCommand::Search(args) => search(args),
+        ModelsCommand::Install(args) => install(args),
+        ModelsCommand::Uninstall(args) => uninstall(args),
+        ModelsCommand::SetDefault(args) => set_default(args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list(args: &ListArgs) -> Result<(), String> {
+    let store = Store::load()?;
+    let models_path = store.models.get_models_path()?;
+    let default_id = store.get_local_active_model_id();
+    let items: Vec<ModelItem> = store.models.items
+        .iter()
+        .map(|entity| {
+            let id = entity.reference.id.clone();
+            let file = entity.reference.id
+                .as_deref()
+                .and_then(|id| store.models.get_path(id.to_string()).ok())
+                .map(PathBuf::from);
+            let size = file
+                .as_ref()
+                .and_then(|file| fs::metadata(file).ok())
+                .map(|metadata| metadata.len())
+                .or(entity.reference.file_size);
+            ModelItem {
+                default: id.is_some() && id == default_id,
+                id,
+                name: entity.reference.name.clone(),
+                state: entity.state.clone(),
+                file,
+                size,
+            }
+        })
+        .collect();
+
+    if args.json {
+        let report = json!({ "path": models_path, "models": items });
+        println!("{}", serde_json::to_string_pretty(&report).map_err(|err| err.to_string())?);
+        return Ok(());
+    }
+    println!("Models folder: {}", models_path.display());
+    if items.is_empty() {
+        println!("No models installed");
+        return Ok(());
+    }
+    let width = items
+        .iter()
+        .map(|item| item.name.len())
+        .max()
+        .unwrap_or_default();
+    for item in items {
+        println!(
+            "{} {:width$}  {:36}  {:11}  {:>10}",
+            if item.default { "*" } else { " " },
+            item.name,
+            item.id.unwrap_or_default(),
+            item.state.unwrap_or_default(),
+            item.size.map(format_size).unwrap_or_default(),
+            width = width
+        );
+    }
+    Ok(())
+}
+
+fn search(args: &SearchArgs) -> Result<(), String> {
+    let models = search_hf_models(&args.query, args.limit)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&models).map_err(|err| err.to_string())?);
+        return Ok(());
+    }
+    if models.is_empty() {
+        println!("No models found");
+    }
+    for model in models {
+        let downloads = model.downloads.map(|d| format!("  {} downloads", d)).unwrap_or_default();
+        let likes = model.likes.map(|l| format!("  {} likes", l)).unwrap_or_default();
+        println!("{}{}{}", model.id, downloads, likes);
+        for file_name in model.gguf_files() {
+            println!("  {}/{}", model.id, file_name);
+        }
+    }
+    Ok(())
+}
+
+// A repository with a single GGUF file can be installed by its name.
+fn select_hf_file(repo_id: &str) -> Result<String, String> {
+    let model = get_hf_model(repo_id)?;
+    match model.gguf_files()[..] {
+        [] => Err(format!("No GGUF file in {}", repo_id)),
+        [file_name] => Ok(file_name.to_string()),
+        ref files => {
+            let files: Vec<String> = files
+                .iter()
+                .map(|file_name| format!("  {}/{}", repo_id, file_name))
+                .collect();
+            Err(format!("Choose a file of {}:\n{}", repo_id, files.join("\n")))
+        }
+    }
+}
+
+// Only the header is downloaded, to check the model before downloading it.
+fn check_remote_model(url: &str) -> Result<(), String> {
+    let mut gguf = GGUF::new(url);
+    match gguf.read_url(url) {
+        // Same as the app, a model that can't be described is still installed.
+        Ok(()) => {
+            if let Err(err) = ModelDescriptor::from_gguf(&gguf) {
+                eprintln!("Can't describe model {}: {}", url, err);
+            }
+            Ok(())
+        }
+        Err(GgufError::Io { source, .. }) => {
+            eprintln!("Can't read the header of {}: {}", url, source);
+            Ok(())
+        }
+        Err(err) => Err(format!("{}: {}", url, err)),
+    }
+}
+
+fn file_name_of(path: &str) -> Result<String, String> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('/').next() {
+        Some(file_name) if !file_name.is_empty() => Ok(file_name.to_string()),
+        _ => Err(format!("No file name in {}", path)),
+    }
+}
+
+fn install(args: &InstallArgs) -> Result<(), String> {
+    let mut store = Store::load()?;
+    let (mut model, url, path, file_name) = match ModelSource::parse(&args.source)? {
+        ModelSource::Local(file) => {
+            let file = fs::canonicalize(&file).map_err(|err| format!("{:?}: {}", file, err))?;
+            let gguf = read_gguf(&file.to_string_lossy())?;
+            if let Err(err) = ModelDescriptor::from_gguf(&gguf) {
+                eprintln!("Can't describe model {}: {}", file.display(), err);
+            }
+            let file_name = file_name_of(&file.to_string_lossy())?;
+            let path = file.parent().unwrap_or(Path::new("/")).to_string_lossy().to_string();
+            (Model::new(file_name.clone()), None, path, file_name)
+        }
+        ModelSource::Url(url) => {
+            let file_name = file_name_of(&url)?;
+            let mut model = Model::new(file_name.clone());
+            model.download = Some(Resource { url: url.clone(), name: None });
+            (model, Some(url), String::new(), file_name)
+        }
+        ModelSource::HuggingFace { repo_id, file_name } => {
+            let file_name = match file_name {
+                Some(file_name) => file_name,
+                None => select_hf_file(&repo_id)?,
+            };
+            let url = file_url(&repo_id, &file_name);
+            let owner = repo_id.split('/').next().unwrap_or_default().to_string();
+            // Files in a folder of the repository are stored flat, like the app does.
+            let file_name = file_name_of(&file_name)?;
+            let mut model = Model::new(file_name.clone());
+            model.id = Some(repo_id);
+            model.author = Some(Entity { name: owner.clone(), email: None, url: None });
+            model.download = Some(Resource { url: url.clone(), name: None });
+            (model, Some(url), owner, file_name)
+        }
+    };
+
+    let same_model = store.models.items
+        .iter_mut()
+        .find(|m| m.path.as_ref() == Some(&path) && m.file_name.as_ref() == Some(&file_name));
+    if let Some(same_model) = same_model {
+        if same_model.state.as_deref() != Some("removed") {
+            return Err(format!("Model already installed: {}", file_name));
+        }
+        same_model.state = Some("ok".to_string());
+        println!("Model restored: {}", file_name);
+        return store.save();
+    }
+
+    let model_path = store.models.get_full_path(path.clone(), Some(file_name.clone()))?;
+    if let Some(url) = url {
+        check_remote_model(&url)?;
+        if let Some(parent) = model_path.parent() {
+            fs::create_dir_all(parent).map_err(|err|
+                format!("Failed to create model directory {:?}: {}", parent, err)
+            )?;
+        }
+        model.sha = args.sha256.clone();
+        model.file_size = Some(download_file(&url, &model_path, args.sha256.as_deref())?);
+    }
+
+    let has_default = store
+        .get_local_active_model_id()
+        .is_some_and(|id| store.has_model(&id));
+    let (entity, model_id) = store.models.create_model(
+        model,
+        Some("ok".to_string()),
+        Some(path),
+        Some(file_name)
+    );
+    store.models.add_model(entity);
+    if let Err(err) = store.models.update_model_from_file(&model_id) {
+        eprintln!("Can't read model file: {}", err);
+    }
+    if args.set_default || !has_default {
+        store.set_local_active_model_id(&model_id);
+    }
+    store.save()?;
+    println!("Model installed: {} {}", model_id, model_path.display());
+    Ok(())
+}
+
+// Split models are deleted with all their shards.
+fn delete_model_file(file: &Path) -> Result<(), String> {
+    let files = match GGUF::read_split(&file.to_string_lossy()) {
+        Ok(gguf) if !gguf.splits().is_empty() => {
+            gguf
+                .splits()
+                .iter()
+                .map(|shard| PathBuf::from(&shard.file_name))
+                .collect()
+        }
+        _ => vec![file.to_path_buf()],
+    };
+    for file in files {
+        fs::remove_file(&file).map_err(|err| format!("Can't delete {:?}: {}", file, err))?;
+    }
+    Ok(())
+}
+
+fn uninstall(args: &UninstallArgs) -> Result<(), String> {
+    let mut store = Store::load()?;
+    let file = store.models.get_path(args.model.clone());
+    let model = store.models
+        .remove_model(&args.model, false)
+        .ok_or_else(|| format!("Model not found: {}", args.model))?;
+    store.clear_local_model(&model.reference);
+    store.save()?;
+    if args.delete {
+        delete_model_file(Path::new(&file?))?;
+    }
+    println!("Model uninstalled: {}", model.reference.name);
+    Ok(())
+}
+
+fn set_default(args: &SetDefaultArgs) -> Result<(), String> {
+    let mut store = Store::load()?;
+    let model = store.models
+        .get_model_entity(&args.model)
+        .ok_or_else(|| format!("Model not found: {}", args.model))?;
+    let state = model.state.unwrap_or_default();
+    if state != "ok" {
+        return Err(format!("Model {} is not ready: {}", args.model, state));
+    }
+    let model_id = model.reference.id.ok_or("Model without id")?;
+    store.set_local_active_model_id(&model_id);
+    store.save()?;
+    println!("Default model: {} {}", model.reference.name, model_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ file_name_of, ModelSource };
+
+    #[test]
+    fn model_sources() {
+        assert_eq!(
+            ModelSource::parse("TheBloke/phi-2-GGUF/phi-2.Q4_K_M.gguf").unwrap(),
+            ModelSource::HuggingFace {
+                repo_id: "TheBloke/phi-2-GGUF".to_string(),
+                file_name: Some("phi-2.Q4_K_M.gguf".to_string()),
+            }
+        );
+        assert!(
+            matches!(
+                ModelSource::parse("TheBloke/phi-2-GGUF").unwrap(),
+                ModelSource::HuggingFace { file_name: None, .. }
+            )
+        );
+        assert!(ModelSource::parse("phi-2.gguf").is_err());
+        assert_eq!(file_name_of("https://host/models/phi.gguf?download=true").unwrap(), "phi.gguf");
+    }
+}
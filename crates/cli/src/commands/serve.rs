@@ -0,0 +1,538 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    env,
+    fs::{ self, File },
+    path::{ Path, PathBuf },
+    process::{ Child, Command, ExitCode, Stdio },
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use chrono::{ DateTime, Utc };
+use clap::{ Args, Subcommand };
+use opla_core::{
+    adapter::LoraAdapter,
+    architecture::validate_gguf,
+    configuration::{
+        MetadataValue,
+        ServerConfiguration,
+        ServerParameterDefinition,
+        ServerParameterType,
+    },
+    directories::{ get_config_directory, get_data_directory },
+    gguf::GGUF,
+    llama_cpp::LLAMACPP_PARAMETERS_DEFINITIONS,
+};
+use serde::{ Deserialize, Serialize };
+use sysinfo::{ Pid, ProcessStatus, ProcessesToUpdate, Signal, System };
+use ureq::Agent;
+
+use crate::{ messages::date_format, store::Store };
+use super::read_gguf;
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[command(subcommand)]
+    command: ServeCommand,
+}
+
+#[derive(Subcommand)]
+enum ServeCommand {
+    #[command(about = "Start the server, in the foreground unless --detach is set")]
+    Start(StartArgs),
+    #[command(about = "Stop the running server")]
+    Stop(StopArgs),
+    #[command(about = "Print the status of the server")]
+    Status(StatusArgs),
+}
+
+// Parameters not set here are the ones of the app settings.
+#[derive(Args)]
+struct StartArgs {
+    #[arg(
+        short,
+        long,
+        help = "Id or name of an installed model, or a GGUF file, the default model if not set"
+    )]
+    model: Option<String>,
+    #[arg(long, help = "Path of the llama.cpp server executable")]
+    binary: Option<PathBuf>,
+    #[arg(long)]
+    host: Option<String>,
+    #[arg(long)]
+    port: Option<i32>,
+    #[arg(short = 'c', long)]
+    context_size: Option<i32>,
+    #[arg(short = 't', long)]
+    threads: Option<i32>,
+    #[arg(long = "gpu-layers")]
+    n_gpu_layers: Option<i32>,
+    #[arg(long, help = "LoRA adapter file")]
+    lora: Option<String>,
+    #[arg(
+        short = 'p',
+        long = "parameter",
+        value_name = "KEY=VALUE",
+        help = "Server parameter, like mlock=true or batch_size=1024"
+    )]
+    parameters: Vec<String>,
+    #[arg(short, long, help = "Run in the background, the output is written to a log file")]
+    detach: bool,
+    #[arg(long, help = "Print the command without starting the server")]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct StopArgs {
+    #[arg(long, default_value_t = 10, help = "Seconds to wait before killing the server")]
+    timeout: u64,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    #[arg(long, help = "Print the status as JSON")]
+    json: bool,
+}
+
+// Written while the server runs, so it can be found by the other commands.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ServerState {
+    pid: u32,
+    // Start time of the process, a pid alone could have been reused.
+    start_time: u64,
+    #[serde(with = "date_format")]
+    started_at: DateTime<Utc>,
+    binary: PathBuf,
+    arguments: Vec<String>,
+    model_id: String,
+    model_path: String,
+    url: String,
+    log: Option<PathBuf>,
+}
+
+impl ServerState {
+    fn path() -> Result<PathBuf, String> {
+        Ok(get_config_directory()?.join("server.json"))
+    }
+
+    fn load() -> Result<Option<ServerState>, String> {
+        let path = ServerState::path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs
+            ::read_to_string(&path)
+            .map_err(|err| format!("Can't read {:?}: {}", path, err))?;
+        serde_json::from_str(&data).map(Some).map_err(|err| format!("Invalid {:?}: {}", path, err))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = ServerState::path()?;
+        fs::create_dir_all(get_config_directory()?).map_err(|err| err.to_string())?;
+        let data = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&path, data).map_err(|err| format!("Can't write {:?}: {}", path, err))
+    }
+
+    fn remove(&self) {
+        // Another server could have been started since.
+        if let Ok(Some(state)) = ServerState::load() {
+            if state.pid == self.pid && state.start_time == self.start_time {
+                let _ = ServerState::path().map(fs::remove_file);
+            }
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        process_start_time(self.pid) == Some(self.start_time)
+    }
+}
+
+// None if the process does not exist anymore.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    system
+        .process(pid)
+        .filter(|process| process.status() != ProcessStatus::Zombie)
+        .map(|process| process.start_time())
+}
+
+// Asks the process to terminate, and kills it if it is still running after the timeout.
+fn terminate(pid: u32, timeout: Duration) {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    let Some(process) = system.process(pid) else {
+        return;
+    };
+    if process.kill_with(Signal::Term) != Some(true) {
+        process.kill();
+        return;
+    }
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        thread::sleep(Duration::from_millis(100));
+        if process_start_time(pid.as_u32()).is_none() {
+            return;
+        }
+    }
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    if let Some(process) = system.process(pid) {
+        process.kill();
+    }
+}
+
+// Same lookup as the sidecar of the app: next to the executable, then the binary of the
+// settings, then the llama-server of the PATH.
+fn resolve_binary(binary: Option<&Path>, configured: &str) -> PathBuf {
+    if let Some(binary) = binary {
+        return binary.to_path_buf();
+    }
+    let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()));
+    let configured = PathBuf::from(format!("{}{}", configured, env::consts::EXE_SUFFIX));
+    let sidecar = PathBuf::from(format!("llama.cpp.server{}", env::consts::EXE_SUFFIX));
+    let mut candidates = vec![];
+    if let Some(exe_dir) = exe_dir {
+        candidates.push(exe_dir.join(&sidecar));
+        candidates.push(exe_dir.join(&configured));
+    }
+    candidates.push(configured);
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(format!("llama-server{}", env::consts::EXE_SUFFIX)))
+}
+
+fn parameter_value(
+    definition: &ServerParameterDefinition,
+    value: &str
+) -> Result<MetadataValue, String> {
+    let invalid = |err: &dyn ToString| format!("Invalid {}: {}", definition.key, err.to_string());
+    match definition.r#type {
+        ServerParameterType::String => Ok(MetadataValue::String(value.to_string())),
+        ServerParameterType::Integer =>
+            value
+                .parse()
+                .map(MetadataValue::Integer)
+                .map_err(|err| invalid(&err)),
+        ServerParameterType::Number =>
+            value
+                .parse()
+                .map(MetadataValue::Number)
+                .map_err(|err| invalid(&err)),
+        ServerParameterType::Boolean =>
+            value
+                .parse()
+                .map(MetadataValue::Boolean)
+                .map_err(|err| invalid(&err)),
+    }
+}
+
+// KEY=VALUE, a boolean parameter can be set with its key only.
+fn parse_parameter(parameter: &str) -> Result<(String, MetadataValue), String> {
+    let (key, value) = parameter.split_once('=').unwrap_or((parameter, "true"));
+    let definition = match LLAMACPP_PARAMETERS_DEFINITIONS.get(key) {
+        Some(definition) if key != "model" => definition,
+        _ => {
+            let mut keys: Vec<&str> = LLAMACPP_PARAMETERS_DEFINITIONS.keys()
+                .copied()
+                .filter(|key| *key != "model")
+                .collect();
+            keys.sort();
+            return Err(format!("Unknown parameter {}, valid ones: {}", key, keys.join(", ")));
+        }
+    };
+    Ok((key.to_string(), parameter_value(definition, value)?))
+}
+
+fn apply_overrides(
+    args: &StartArgs,
+    configuration: &mut ServerConfiguration
+) -> Result<(), String> {
+    for parameter in args.parameters.iter() {
+        let (key, value) = parse_parameter(parameter)?;
+        configuration.parameters.insert(key, value);
+    }
+    if let Some(host) = &args.host {
+        configuration.set_parameter_string("host", host.clone());
+    }
+    let integers = [
+        ("port", args.port),
+        ("context_size", args.context_size),
+        ("threads", args.threads),
+        ("n_gpu_layers", args.n_gpu_layers),
+    ];
+    for (key, value) in integers {
+        if let Some(value) = value {
+            configuration.set_parameter_int(key, value);
+        }
+    }
+    if let Some(lora) = &args.lora {
+        configuration.set_parameter_string("lora", lora.clone());
+    }
+    Ok(())
+}
+
+// Sets model_id and model_path, llama.cpp loads the other shards of a split model from
+// the first one.
+fn set_model(
+    store: &Store,
+    model: Option<&str>,
+    configuration: &mut ServerConfiguration
+) -> Result<GGUF, String> {
+    let model_id = match model {
+        Some(model) => model.to_string(),
+        None =>
+            configuration
+                .get_optional_parameter_string("model_id")
+                .filter(|id| store.has_model(id))
+                .or_else(|| store.get_local_active_model_id())
+                .ok_or("No default model, set one with --model or models set-default")?,
+    };
+    let (model_id, model_path) = if Path::new(&model_id).is_file() {
+        (model_id.clone(), model_id)
+    } else {
+        let entity = store.models
+            .get_model_entity(&model_id)
+            .ok_or_else(|| format!("Model not found: {}", model_id))?;
+        let model_path = store.models.get_path(model_id.clone())?;
+        (entity.reference.id.unwrap_or(model_id), model_path)
+    };
+    let gguf = read_gguf(&model_path)?;
+    // llama.cpp fails with an opaque error on incomplete models.
+    let validation = validate_gguf(&gguf);
+    if let Some(error) = validation.to_error() {
+        return Err(format!("Invalid model {}: {}", model_path, error));
+    }
+    if let Some(warning) = validation.to_warning() {
+        eprintln!("Warning: {}", warning);
+    }
+    let model_path = gguf
+        .splits()
+        .first()
+        .map(|shard| shard.file_name.clone())
+        .unwrap_or(model_path);
+    configuration.set_parameter_string("model_id", model_id);
+    configuration.set_parameter_string("model_path", model_path);
+    Ok(gguf)
+}
+
+fn check_lora(configuration: &ServerConfiguration, gguf: &GGUF) -> Result<(), String> {
+    let lora = configuration
+        .get_optional_parameter_string("lora")
+        .filter(|lora| !lora.is_empty());
+    if let Some(lora) = lora {
+        let mut adapter = GGUF::new(&lora);
+        adapter.read(&lora).map_err(|err| format!("Can't read {}: {}", lora, err))?;
+        let compatibility = LoraAdapter::from_gguf(&adapter)?.check_compatibility(gguf);
+        if let Some(error) = compatibility.to_error() {
+            return Err(format!("Invalid LoRA adapter {}: {}", lora, error));
+        }
+    }
+    Ok(())
+}
+
+fn server_url(configuration: &ServerConfiguration) -> String {
+    format!(
+        "http://{}:{}",
+        configuration.get_parameter_string("host", "127.0.0.1".to_string()),
+        configuration.get_parameter_int("port", 8080)
+    )
+}
+
+fn start(args: &StartArgs) -> Result<ExitCode, String> {
+    let store = Store::load()?;
+    let mut configuration = store.server.configuration.clone();
+    apply_overrides(args, &mut configuration)?;
+    let gguf = set_model(&store, args.model.as_deref(), &mut configuration)?;
+    check_lora(&configuration, &gguf)?;
+    let model_path = configuration.get_parameter_string("model_path", String::new());
+    let arguments = configuration.to_args(&model_path, &LLAMACPP_PARAMETERS_DEFINITIONS);
+    let binary = resolve_binary(args.binary.as_deref(), &store.server.binary);
+    if args.dry_run {
+        println!("{} {}", binary.display(), arguments.join(" "));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(state) = ServerState::load()? {
+        if state.is_running() {
+            return Err(format!("Server already running, pid {} {}", state.pid, state.url));
+        }
+    }
+    let mut command = Command::new(&binary);
+    command.args(&arguments);
+    let log = if args.detach {
+        let log = get_data_directory()?.join("llama.cpp.server.log");
+        fs::create_dir_all(get_data_directory()?).map_err(|err| err.to_string())?;
+        let file = File::create(&log).map_err(|err| format!("Can't write {:?}: {}", log, err))?;
+        let file_err = file.try_clone().map_err(|err| err.to_string())?;
+        command.stdin(Stdio::null()).stdout(file).stderr(file_err);
+        // Not interrupted by the signals sent to the terminal.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        Some(log)
+    } else {
+        None
+    };
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("Can't start {}: {}", binary.display(), err))?;
+    let state = ServerState {
+        pid: child.id(),
+        start_time: process_start_time(child.id()).unwrap_or_default(),
+        started_at: Utc::now(),
+        binary,
+        arguments,
+        model_id: configuration.get_parameter_string("model_id", String::new()),
+        model_path,
+        url: server_url(&configuration),
+        log,
+    };
+    state.save()?;
+    eprintln!("Server started, pid {} {} {}", state.pid, state.url, state.model_path);
+
+    if args.detach {
+        // Errors of the arguments or of the model are reported right away.
+        thread::sleep(Duration::from_millis(500));
+        if let Ok(Some(status)) = child.try_wait() {
+            state.remove();
+            let log = state.log.unwrap_or_default();
+            return Err(format!("Server exited with {}, see {}", status, log.display()));
+        }
+        if let Some(log) = &state.log {
+            eprintln!("Logs: {}", log.display());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let result = wait(&mut child);
+    state.remove();
+    result
+}
+
+// Runs until the server exits, or a signal is received.
+fn wait(child: &mut Child) -> Result<ExitCode, String> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = Arc::clone(&interrupted);
+    ctrlc
+        ::set_handler(move || handler_interrupted.store(true, Ordering::SeqCst))
+        .map_err(|err| err.to_string())?;
+    loop {
+        if let Some(status) = child.try_wait().map_err(|err| err.to_string())? {
+            if interrupted.load(Ordering::SeqCst) || status.success() {
+                return Ok(ExitCode::SUCCESS);
+            }
+            eprintln!("Server exited with {}", status);
+            return Ok(ExitCode::FAILURE);
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            eprintln!("Stopping server");
+            terminate(child.id(), Duration::from_secs(10));
+            child.wait().map_err(|err| err.to_string())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn stop(args: &StopArgs) -> Result<ExitCode, String> {
+    let state = match ServerState::load()? {
+        Some(state) if state.is_running() => state,
+        Some(state) => {
+            state.remove();
+            return Err("Server is not running".to_string());
+        }
+        None => {
+            return Err("Server is not running".to_string());
+        }
+    };
+    terminate(state.pid, Duration::from_secs(args.timeout));
+    state.remove();
+    println!("Server stopped, pid {}", state.pid);
+    Ok(ExitCode::SUCCESS)
+}
+
+// The health endpoint of llama.cpp answers 503 while the model is loading.
+fn health(url: &str) -> String {
+    let agent: Agent = Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(Duration::from_secs(2)))
+        .build()
+        .into();
+    match agent.get(&format!("{}/health", url)).call() {
+        Ok(response) if response.status().is_success() => "ok".to_string(),
+        Ok(response) if response.status() == 503 => "loading".to_string(),
+        Ok(response) => format!("error {}", response.status()),
+        Err(err) => format!("unreachable: {}", err),
+    }
+}
+
+fn status(args: &StatusArgs) -> Result<ExitCode, String> {
+    let state = ServerState::load()?.filter(|state| state.is_running());
+    let health = state.as_ref().map(|state| health(&state.url));
+    if args.json {
+        let status = serde_json::json!({
+            "status": if state.is_some() { "started" } else { "stopped" },
+            "server": state,
+            "health": health,
+        });
+        println!("{}", serde_json::to_string_pretty(&status).map_err(|err| err.to_string())?);
+    } else if let Some(state) = &state {
+        println!("Server started, pid {} since {}", state.pid, state.started_at.to_rfc3339());
+        println!("URL: {}", state.url);
+        println!("Model: {} {}", state.model_id, state.model_path);
+        println!("Health: {}", health.unwrap_or_default());
+        if let Some(log) = &state.log {
+            println!("Logs: {}", log.display());
+        }
+    } else {
+        println!("Server stopped");
+    }
+    Ok(if state.is_some() { ExitCode::SUCCESS } else { ExitCode::from(3) })
+}
+
+pub fn run(args: &ServeArgs) -> ExitCode {
+    let result = match &args.command {
+        ServeCommand::Start(args) => start(args),
+        ServeCommand::Stop(args) => stop(args),
+        ServeCommand::Status(args) => status(args),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use opla_core::configuration::MetadataValue;
+
+    use super::parse_parameter;
+
+    #[test]
+    fn server_parameters() {
+        assert_eq!(
+            parse_parameter("batch_size=1024").unwrap(),
+            ("batch_size".to_string(), MetadataValue::Integer(1024))
+        );
+        assert_eq!(parse_parameter("mlock").unwrap().1, MetadataValue::Boolean(true));
+        assert_eq!(parse_parameter("rope_freq_scale=0.5").unwrap().1, MetadataValue::Number(0.5));
+        assert!(parse_parameter("port=eighty").is_err());
+        assert!(parse_parameter("model=phi.gguf").is_err());
+        assert!(parse_parameter("unknown=1").is_err());
+    }
+}
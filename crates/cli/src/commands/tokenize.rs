@@ -0,0 +1,238 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    env,
+    fs,
+    io::{ self, IsTerminal, Read },
+    process::ExitCode,
+};
+
+use clap::{ Args, ValueEnum };
+use serde::Serialize;
+use tokenizer::{ TokenId, Tokenizer };
+
+use super::read_gguf;
+
+// Background colors of the token boundaries.
+const TOKEN_COLORS: [u8; 5] = [153, 186, 217, 151, 223];
+
+#[derive(Args)]
+pub struct TokenizerArgs {
+    #[arg(
+        short,
+        long,
+        conflicts_with = "model",
+        help = "Name of the encoding, cl100k_base by default"
+    )]
+    encoding: Option<String>,
+    #[arg(short, long, help = "GGUF model to read the tokenizer from")]
+    model: Option<String>,
+    #[arg(long, help = "Add the BOS and EOS tokens expected by the model")]
+    pub add_special: bool,
+}
+
+impl TokenizerArgs {
+    pub fn create_tokenizer(&self) -> Result<Tokenizer, String> {
+        match self.model {
+            Some(ref model) => Tokenizer::from_gguf(&read_gguf(model)?),
+            None => Tokenizer::from_encoding(self.encoding.as_deref().unwrap_or("cl100k_base")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Args)]
+pub struct TokenizeArgs {
+    #[arg(help = "Text to tokenize, read from --file or stdin if not given")]
+    text: Option<String>,
+    #[arg(short, long, conflicts_with = "text")]
+    file: Option<String>,
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    #[arg(short, long, help = "Decode token ids, separated by spaces or commas, to text")]
+    decode: bool,
+    #[arg(long, help = "Print the token ids instead of the token boundaries")]
+    ids: bool,
+    #[arg(long, help = "Print the tokens as JSON")]
+    json: bool,
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+}
+
+#[derive(Serialize)]
+struct TokenJson {
+    id: TokenId,
+    text: String,
+}
+
+fn read_input(args: &TokenizeArgs) -> Result<String, String> {
+    if let Some(ref text) = args.text {
+        return Ok(text.clone());
+    }
+    match args.file {
+        Some(ref file) =>
+            fs::read_to_string(file).map_err(|err| format!("Can't read {}: {}", file, err)),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map_err(|err| err.to_string())?;
+            Ok(text)
+        }
+    }
+}
+
+fn parse_ids(input: &str) -> Result<Vec<TokenId>, String> {
+    input
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|_| format!("Invalid token id: {}", id)))
+        .collect()
+}
+
+// Tokens are grouped until their bytes are valid UTF-8, a character can be split
+// across several tokens.
+fn token_pieces(
+    tokenizer: &Tokenizer,
+    tokens: &[TokenId]
+) -> Result<Vec<(Vec<TokenId>, String)>, String> {
+    let mut pieces = Vec::new();
+    let mut ids = Vec::new();
+    let mut bytes = Vec::new();
+    for token in tokens {
+        ids.push(*token);
+        bytes.extend(tokenizer.decode_bytes(&[*token])?);
+        if let Ok(text) = std::str::from_utf8(&bytes) {
+            pieces.push((ids.clone(), text.to_string()));
+            ids.clear();
+            bytes.clear();
+        }
+    }
+    if !ids.is_empty() {
+        pieces.push((ids, String::from_utf8_lossy(&bytes).to_string()));
+    }
+    Ok(pieces)
+}
+
+fn print_boundaries(pieces: &[(Vec<TokenId>, String)], color: bool) {
+    let mut output = String::new();
+    for (index, (_, text)) in pieces.iter().enumerate() {
+        if !color {
+            if index > 0 {
+                output.push('|');
+            }
+            output.push_str(&text.replace('\n', "\\n"));
+            continue;
+        }
+        // New lines are made visible, the color is reset before them to not fill the line.
+        let background = TOKEN_COLORS[index % TOKEN_COLORS.len()];
+        for (line_index, line) in text.split('\n').enumerate() {
+            if line_index > 0 {
+                output.push_str("\\n\n");
+            }
+            if !line.is_empty() {
+                output.push_str(&format!("\x1b[48;5;{}m\x1b[30m{}\x1b[0m", background, line));
+            }
+        }
+    }
+    println!("{}", output);
+}
+
+fn use_color(mode: ColorMode) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+fn tokenize(args: &TokenizeArgs) -> Result<(), String> {
+    let tokenizer = args.tokenizer.create_tokenizer()?;
+    let input = read_input(args)?;
+
+    if args.decode {
+        let tokens = parse_ids(&input)?;
+        let text = tokenizer.decode(&tokens)?;
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&text).unwrap());
+        } else {
+            println!("{}", text);
+        }
+        return Ok(());
+    }
+
+    let tokens = tokenizer.encode(&input, args.tokenizer.add_special);
+    if args.json {
+        let tokens = tokens
+            .iter()
+            .map(|token| -> Result<TokenJson, String> {
+                Ok(TokenJson {
+                    id: *token,
+                    text: String::from_utf8_lossy(&tokenizer.decode_bytes(&[*token])?).to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        println!("{}", serde_json::to_string_pretty(&tokens).unwrap());
+    } else if args.ids {
+        let ids: Vec<String> = tokens
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        println!("{}", ids.join(" "));
+    } else {
+        print_boundaries(&token_pieces(&tokenizer, &tokens)?, use_color(args.color));
+    }
+    let plural = if tokens.len() == 1 { "" } else { "s" };
+    eprintln!("{} token{}, {}", tokens.len(), plural, tokenizer.name);
+    Ok(())
+}
+
+pub fn run(args: &TokenizeArgs) -> ExitCode {
+    match tokenize(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokenizer::Tokenizer;
+
+    use super::{ parse_ids, token_pieces };
+
+    #[test]
+    fn pieces_and_ids() {
+        assert_eq!(parse_ids("[9906, 1917]").unwrap(), vec![9906, 1917]);
+        assert!(parse_ids("9906 x").is_err());
+
+        let tokenizer = Tokenizer::from_encoding("cl100k_base").unwrap();
+        let text = "Hi 🦀";
+        let pieces = token_pieces(&tokenizer, &tokenizer.encode(text, false)).unwrap();
+        let joined: String = pieces
+            .iter()
+            .map(|(_, piece)| piece.as_str())
+            .collect();
+        assert_eq!(joined, text);
+        // The crab is split into several tokens.
+        assert!(pieces.last().unwrap().0.len() > 1);
+    }
+}
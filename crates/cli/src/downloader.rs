@@ -0,0 +1,102 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Downloads of model files, with a progress bar on the terminal.
+
+use std::{ fs::{ self, File }, io::{ BufWriter, Read, Write }, path::{ Path, PathBuf } };
+
+use indicatif::{ ProgressBar, ProgressStyle };
+use sha2::{ Digest, Sha256 };
+
+use crate::providers::create_agent;
+
+const PROGRESS_TEMPLATE: &str =
+    "{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}";
+
+// The file is written next to its destination, and only moved there once it is complete
+// and its checksum is verified: an interrupted download never leaves a truncated model.
+pub fn download_file(url: &str, path: &Path, sha: Option<&str>) -> Result<u64, String> {
+    let response = create_agent()
+        .get(url)
+        .call()
+        .map_err(|err| format!("Failed to download {}: {}", url, err))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Failed to download {}: HTTP error {}", url, status));
+    }
+    let file_size = response.body().content_length();
+
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let progress = match file_size {
+        Some(size) => {
+            let style = ProgressStyle::with_template(PROGRESS_TEMPLATE)
+                .map_err(|err| err.to_string())?
+                .progress_chars("=> ");
+            ProgressBar::new(size).with_style(style)
+        }
+        None => ProgressBar::new_spinner(),
+    };
+    progress.set_message(file_name.clone());
+
+    let mut part_path = PathBuf::from(path);
+    part_path.set_file_name(format!("{}.part", file_name));
+    let result = write_file(response.into_body().into_reader(), &part_path, &progress);
+    progress.finish_and_clear();
+    let (transfered, digest) = result.inspect_err(|_| {
+        let _ = fs::remove_file(&part_path);
+    })?;
+
+    let error = if file_size.is_some_and(|size| size != transfered) {
+        Some("Wrong downloaded file size".to_string())
+    } else if sha.is_some_and(|sha| !sha.eq_ignore_ascii_case(&digest)) {
+        Some(format!("Wrong checksum: {}", digest))
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        let _ = fs::remove_file(&part_path);
+        return Err(error);
+    }
+    fs::rename(&part_path, path).map_err(|err| format!("Can't write {:?}: {}", path, err))?;
+    Ok(transfered)
+}
+
+fn write_file<R: Read>(
+    mut reader: R,
+    path: &Path,
+    progress: &ProgressBar
+) -> Result<(u64, String), String> {
+    let file = File::create(path).map_err(|err| format!("Failed to create {:?}: {}", path, err))?;
+    let mut writer = BufWriter::new(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut transfered = 0;
+    loop {
+        let read = reader.read(&mut buffer).map_err(|err| format!("Download error: {}", err))?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..read])
+            .map_err(|err| format!("Failed to write {:?}: {}", path, err))?;
+        hasher.update(&buffer[..read]);
+        transfered += read as u64;
+        progress.inc(read as u64);
+    }
+    writer.flush().map_err(|err| format!("Failed to write {:?}: {}", path, err))?;
+    Ok((transfered, format!("{:x}", hasher.finalize())))
+}
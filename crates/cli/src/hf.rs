@@ -0,0 +1,49 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Search of the GGUF models of the Hugging Face hub, with the query and the types of the app.
+
+use std::io::Read;
+
+use opla_core::hf::{ model_url, search_parameters, search_url, HFModel };
+use serde::de::DeserializeOwned;
+
+use crate::providers::create_agent;
+
+fn get_json<T: DeserializeOwned>(url: &str, query: &[(&str, String)]) -> Result<T, String> {
+    let mut request = create_agent().get(url);
+    for (key, value) in query {
+        request = request.query(key, value);
+    }
+    let response = request.call().map_err(|err| format!("Failed to get {}: {}", url, err))?;
+    let status = response.status();
+    let mut body = String::new();
+    response
+        .into_body()
+        .into_reader()
+        .read_to_string(&mut body)
+        .map_err(|err| format!("Failed to read {}: {}", url, err))?;
+    if !status.is_success() {
+        return Err(format!("HTTP error {}: {}", status, body.trim()));
+    }
+    serde_json::from_str(&body).map_err(|err| format!("Invalid response from {}: {}", url, err))
+}
+
+pub fn search_hf_models(query: &str, limit: usize) -> Result<Vec<HFModel>, String> {
+    get_json(&search_url(), &search_parameters(query, limit))
+}
+
+pub fn get_hf_model(repo_id: &str) -> Result<HFModel, String> {
+    get_json(&model_url(repo_id), &[])
+}
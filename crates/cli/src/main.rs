@@ -12,16 +12,61 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::env;
+mod commands;
+mod downloader;
+mod hf;
+mod messages;
+mod providers;
+mod store;
 
-use opla_core::gguf::GGUF;
+use std::process::ExitCode;
 
-fn main() {
-    println!("Opla CLI WIP");
-    let args: Vec<_> = env::args().collect();
-    if args.len() > 1 {
-        println!("The first argument is {}", args[1]);
+use clap::{ Parser, Subcommand };
+
+use commands::{
+    chat::ChatArgs,
+    count::CountArgs,
+    diff::DiffArgs,
+    inspect::InspectArgs,
+    models::ModelsArgs,
+    serve::ServeArgs,
+    tokenize::TokenizeArgs,
+};
+
+#[derive(Parser)]
+#[command(name = "opla-cli", version, about = "Opla command line tools", propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Print the summary, metadata, tensors and memory footprint of a model")]
+    Inspect(InspectArgs),
+    #[command(about = "Compare the metadata and tensors of two GGUF files")]
+    Diff(DiffArgs),
+    #[command(about = "Chat with the local llama.cpp server or an OpenAI compatible API")]
+    Chat(ChatArgs),
+    #[command(about = "Encode text to tokens, or decode tokens to text")]
+    Tokenize(TokenizeArgs),
+    #[command(about = "Count the tokens of files")]
+    Count(CountArgs),
+    #[command(about = "List, search, install and remove the models of the desktop app")]
+    Models(ModelsArgs),
+    #[command(about = "Start, stop and get the status of the llama.cpp server")]
+    Serve(ServeArgs),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Inspect(args) => commands::inspect::run(&args),
+        Command::Diff(args) => commands::diff::run(&args),
+        Command::Chat(args) => commands::chat::run(&args),
+        Command::Tokenize(args) => commands::tokenize::run(&args),
+        Command::Count(args) => commands::count::run(&args),
+        Command::Models(args) => commands::models::run(&args),
+        Command::Serve(args) => commands::serve::run(&args),
     }
-    let mut gguf = GGUF::new(&args[1]);
-    gguf.read(&args[1]).unwrap();
 }
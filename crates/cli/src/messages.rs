@@ -0,0 +1,193 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Conversation messages in the format of the messages.json files of the desktop app,
+// so a conversation started in the terminal can be continued in the app.
+
+use std::{ collections::BTreeMap, fs, path::Path };
+
+use chrono::{ DateTime, Utc };
+use opla_core::providers::llm::LlmMessage;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Author {
+    pub role: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub metadata: Option<BTreeMap<String, Value>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Content {
+    pub r#type: String,
+    pub parts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub raw: Option<Vec<String>>,
+}
+
+// The app also accepts a plain string as content.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Full(Content),
+}
+
+impl MessageContent {
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Full(content) => content.parts.join("\n"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    pub id: String,
+    #[serde(with = "date_format", alias = "createdAt", default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "date_format", alias = "updatedAt", default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    pub author: Author,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub content: Option<MessageContent>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub sibling: Option<String>,
+    // Fields of the app that are not used here are kept as is.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Message {
+    pub fn new(author: Author, text: &str) -> Message {
+        let now = Utc::now();
+        Message {
+            id: Uuid::new_v4().to_string(),
+            created_at: now,
+            updated_at: now,
+            author,
+            content: Some(
+                MessageContent::Full(Content {
+                    r#type: "text".to_string(),
+                    parts: vec![text.to_string()],
+                    raw: Some(vec![text.to_string()]),
+                })
+            ),
+            status: Some("delivered".to_string()),
+            sibling: None,
+            other: BTreeMap::new(),
+        }
+    }
+
+    pub fn user(text: &str) -> Message {
+        Message::new(
+            Author { role: "user".to_string(), name: "you".to_string(), metadata: None },
+            text
+        )
+    }
+
+    pub fn assistant(model: &str, text: &str) -> Message {
+        let metadata = BTreeMap::from([("modelId".to_string(), Value::from(model))]);
+        Message::new(
+            Author {
+                role: "assistant".to_string(),
+                name: model.to_string(),
+                metadata: Some(metadata),
+            },
+            text
+        )
+    }
+
+    // Notes are only displayed by the app, they are not sent to the model.
+    pub fn to_llm_message(&self) -> Option<LlmMessage> {
+        match self.author.role.as_str() {
+            "user" | "assistant" | "system" =>
+                Some(
+                    LlmMessage::new(
+                        &self.author.role,
+                        &self.content.as_ref().map(|c| c.text()).unwrap_or_default()
+                    )
+                ),
+            _ => None,
+        }
+    }
+}
+
+pub fn load_messages(path: &Path) -> Result<Vec<Message>, String> {
+    let data = fs::read_to_string(path).map_err(|err| format!("Can't read {:?}: {}", path, err))?;
+    serde_json::from_str(&data).map_err(|err| format!("Invalid {:?}: {}", path, err))
+}
+
+pub fn save_messages(path: &Path, messages: &[Message]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(messages).map_err(|err| err.to_string())?;
+    fs::write(path, json).map_err(|err| format!("Can't write {:?}: {}", path, err))
+}
+
+// Same format as the app: ISO 8601 strings, milliseconds timestamps are also read.
+pub mod date_format {
+    use chrono::{ DateTime, Utc };
+    use serde::{ de, Deserialize, Deserializer, Serializer };
+    use serde_json::Value;
+
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.fZ";
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+        where D: Deserializer<'de>
+    {
+        match Value::deserialize(deserializer)? {
+            Value::String(s) => {
+                let date = DateTime::parse_from_rfc3339(&s).map_err(de::Error::custom)?;
+                Ok(date.with_timezone(&Utc))
+            }
+            value =>
+                DateTime::from_timestamp_millis(value.as_i64().unwrap_or_default()).ok_or_else(||
+                    de::Error::custom("Invalid timestamp millis")
+                ),
+        }
+    }
+
+    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Message, MessageContent };
+
+    #[test]
+    fn app_messages() {
+        let json =
+            r#"[{"id":"1","createdAt":1718000000000,"updatedAt":1718000000000,"author":{"role":"user","name":"you"},"content":"Hello"},{"id":"2","created_at":"2024-06-10T06:13:20.000Z","updated_at":"2024-06-10T06:13:20.000Z","author":{"role":"assistant","name":"Llama"},"content":{"type":"text","parts":["Hi"]},"assets":[]}]"#;
+        let messages: Vec<Message> = serde_json::from_str(json).unwrap();
+        assert_eq!(messages[0].created_at, messages[1].created_at);
+        assert!(matches!(messages[0].content, Some(MessageContent::Text(_))));
+        assert_eq!(messages[1].to_llm_message().unwrap().content, "Hi");
+
+        let value = serde_json::to_value(&messages).unwrap();
+        assert_eq!(value[0]["created_at"], "2024-06-10T06:13:20Z");
+        assert_eq!(value[1]["assets"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use opla_core::{
+    chat_template::ChatTemplate,
+    gguf::GGUF,
+    providers::{
+        llama_cpp::{
+            LlamaCppChatCompletionChunk,
+            LlamaCppCompletionResponse,
+            LLAMACPP_COMPLETION_PARAMETERS,
+        },
+        llm::{ LlmCompletionOptions, LlmCompletionResponse, LlmQueryCompletion },
+    },
+};
+use ureq::Agent;
+
+use super::{ create_agent, post_json, read_events, LlmInferenceInterface };
+
+pub struct LlamaCppInferenceClient {
+    agent: Agent,
+    pub host: String,
+    pub port: u16,
+    // GGUF file loaded by the server, its chat template is used to format the prompt.
+    pub model: Option<String>,
+    pub chat_template: Option<ChatTemplate>,
+}
+
+impl LlamaCppInferenceClient {
+    pub fn new(host: &str, port: u16) -> Self {
+        LlamaCppInferenceClient {
+            agent: create_agent(),
+            host: host.to_string(),
+            port,
+            model: None,
+            chat_template: None,
+        }
+    }
+
+    fn get_api(&self, endpoint: &str) -> String {
+        format!("http://{}:{}/{}", self.host, self.port, endpoint)
+    }
+
+    fn to_prompt(&self, query: &LlmQueryCompletion, system: Option<&str>) -> String {
+        match self.chat_template {
+            Some(ref template) =>
+                match query.to_chat_prompt(template, system) {
+                    Ok(prompt) => prompt,
+                    Err(err) => {
+                        eprintln!("Chat template error, fallback to raw prompt: {}", err);
+                        query.to_raw_prompt(system)
+                    }
+                }
+            None => query.to_raw_prompt(system),
+        }
+    }
+}
+
+impl LlmInferenceInterface for LlamaCppInferenceClient {
+    fn name(&self) -> String {
+        match self.model {
+            Some(ref model) =>
+                Path::new(model)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or(model.clone()),
+            None => "llama.cpp".to_string(),
+        }
+    }
+
+    fn parameter_keys(&self) -> &'static [&'static str] {
+        LLAMACPP_COMPLETION_PARAMETERS
+    }
+
+    fn set_model(&mut self, model: &str) -> Result<(), String> {
+        let gguf = GGUF::read_split(model).map_err(|err| format!("Can't read {}: {}", model, err))?;
+        self.chat_template = match ChatTemplate::from_gguf(&gguf) {
+            Ok(template) => Some(template),
+            Err(err) => {
+                eprintln!("{}, the raw prompt is used", err);
+                None
+            }
+        };
+        self.model = Some(model.to_string());
+        Ok(())
+    }
+
+    fn call_completion(
+        &mut self,
+        query: &LlmQueryCompletion,
+        completion_options: Option<LlmCompletionOptions>,
+        callback: &mut dyn FnMut(&str)
+    ) -> Result<LlmCompletionResponse, String> {
+        let system = completion_options.and_then(|options| options.system);
+        let parameters = query.to_llama_cpp_parameters(self.to_prompt(query, system.as_deref()));
+        let stream = parameters.stream.unwrap_or(false);
+        let reader = post_json(&self.agent, &self.get_api("completion"), None, &parameters)?;
+
+        if !stream {
+            let response: LlamaCppCompletionResponse = serde_json
+                ::from_reader(reader)
+                .map_err(|err| format!("Failed to parse response: {}", err))?;
+            callback(&response.content);
+            return Ok(response.to_llm_response());
+        }
+
+        let mut response = LlmCompletionResponse::new(0, "finished", "");
+        read_events(reader, |data| {
+            let chunk = LlamaCppChatCompletionChunk::from_event(data)?;
+            callback(&chunk.content);
+            response.content.push_str(&chunk.content);
+            if let Some(ref timings) = chunk.timings {
+                response.usage = Some(timings.to_llm_usage());
+            }
+            Ok(!chunk.is_stop())
+        })?;
+        Ok(response)
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Blocking clients of the inference providers of the desktop app: the local llama.cpp
+// server and the OpenAI compatible APIs. Requests and responses are the app's ones, from
+// opla_core::providers, only the HTTP client differs.
+
+pub mod llama_cpp;
+pub mod openai;
+
+use std::{ collections::BTreeMap, io::{ BufRead, BufReader, Read } };
+
+use opla_core::providers::llm::{
+    LlmCompletionOptions,
+    LlmCompletionResponse,
+    LlmParameter,
+    LlmQueryCompletion,
+};
+use serde::Serialize;
+use serde_json::Value;
+use ureq::{ Agent, BodyReader };
+
+// Parameters are kept as strings like in the app's presets, the request bodies of
+// opla_core::providers convert them.
+pub type LlmParameters = BTreeMap<String, String>;
+
+pub fn to_llm_parameters(parameters: &LlmParameters) -> Vec<LlmParameter> {
+    parameters
+        .iter()
+        .map(|(key, value)| LlmParameter { key: key.clone(), value: value.clone() })
+        .collect()
+}
+
+pub trait LlmInferenceInterface {
+    fn name(&self) -> String;
+
+    // Keys accepted by set_parameter.
+    fn parameter_keys(&self) -> &'static [&'static str];
+
+    // Switches the model, a model name for remote APIs, a GGUF file for llama.cpp.
+    fn set_model(&mut self, model: &str) -> Result<(), String>;
+
+    // Tokens are given to the callback as they are received when the stream parameter is set.
+    fn call_completion(
+        &mut self,
+        query: &LlmQueryCompletion,
+        completion_options: Option<LlmCompletionOptions>,
+        callback: &mut dyn FnMut(&str)
+    ) -> Result<LlmCompletionResponse, String>;
+}
+
+// Error statuses are returned as responses, to read the error message of the body.
+pub fn create_agent() -> Agent {
+    Agent::config_builder().http_status_as_error(false).build().into()
+}
+
+pub fn post_json(
+    agent: &Agent,
+    url: &str,
+    secret_key: Option<&str>,
+    body: &impl Serialize
+) -> Result<BodyReader<'static>, String> {
+    let mut request = agent.post(url).header("Content-Type", "application/json");
+    if let Some(secret_key) = secret_key {
+        request = request.header("Authorization", &format!("Bearer {}", secret_key));
+    }
+    let body = serde_json::to_string(body).map_err(|err| err.to_string())?;
+    let response = request
+        .send(body)
+        .map_err(|err| format!("Failed to send to {}: {}", url, err))?;
+    let status = response.status();
+    let reader = response.into_body().into_reader();
+    if !status.is_success() {
+        let mut body = String::new();
+        let _ = BufReader::new(reader).take(64 * 1024).read_to_string(&mut body);
+        return Err(format!("HTTP error {}: {}", status, error_message(&body)));
+    }
+    Ok(reader)
+}
+
+// OpenAI errors are {"error": {"message": ...}}, llama.cpp ones may also be plain text.
+fn error_message(body: &str) -> String {
+    serde_json
+        ::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| {
+            let error = value.get("error").unwrap_or(&value);
+            error
+                .get("message")
+                .and_then(|message| message.as_str())
+                .map(|message| message.to_string())
+        })
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+// Calls the callback with the data of each server-sent event, until it returns false.
+pub fn read_events<R: Read>(
+    reader: R,
+    mut callback: impl FnMut(&str) -> Result<bool, String>
+) -> Result<(), String> {
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|err| format!("Error in event stream: {}", err))?;
+        if let Some(data) = line.strip_prefix("data:") {
+            if !callback(data.trim_start())? {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_events;
+
+    #[test]
+    fn events() {
+        let stream = "data: {\"content\":\"Hel\"}\n\n: ping\n\ndata: {\"content\":\"lo\"}\n\n";
+        let mut events = Vec::new();
+        read_events(stream.as_bytes(), |data| {
+            events.push(data.to_string());
+            Ok(true)
+        }).unwrap();
+        assert_eq!(events, vec!["{\"content\":\"Hel\"}", "{\"content\":\"lo\"}"]);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use opla_core::providers::{
+    llm::{ LlmCompletionOptions, LlmCompletionResponse, LlmQueryCompletion },
+    openai::{
+        OpenAIBodyCompletion,
+        OpenAIChatCompletion,
+        OpenAIChatCompletionChunk,
+        OPENAI_COMPLETION_PARAMETERS,
+    },
+};
+use ureq::Agent;
+
+use super::{ create_agent, post_json, read_events, LlmInferenceInterface };
+
+pub const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+
+pub struct OpenAIInferenceClient {
+    agent: Agent,
+    pub api: String,
+    pub secret_key: Option<String>,
+    pub model: String,
+}
+
+impl OpenAIInferenceClient {
+    pub fn new(api: &str, secret_key: Option<String>, model: &str) -> Self {
+        OpenAIInferenceClient {
+            agent: create_agent(),
+            api: api.trim_end_matches('/').to_string(),
+            secret_key,
+            model: model.to_string(),
+        }
+    }
+}
+
+impl LlmInferenceInterface for OpenAIInferenceClient {
+    fn name(&self) -> String {
+        self.model.clone()
+    }
+
+    fn parameter_keys(&self) -> &'static [&'static str] {
+        OPENAI_COMPLETION_PARAMETERS
+    }
+
+    fn set_model(&mut self, model: &str) -> Result<(), String> {
+        self.model = model.to_string();
+        Ok(())
+    }
+
+    fn call_completion(
+        &mut self,
+        query: &LlmQueryCompletion,
+        completion_options: Option<LlmCompletionOptions>,
+        callback: &mut dyn FnMut(&str)
+    ) -> Result<LlmCompletionResponse, String> {
+        let parameters = OpenAIBodyCompletion::new(self.model.clone(), query, completion_options);
+        let stream = parameters.stream.unwrap_or(false);
+        let url = format!("{}/chat/completions", self.api);
+        let reader = post_json(&self.agent, &url, self.secret_key.as_deref(), &parameters)?;
+
+        if !stream {
+            let completion: OpenAIChatCompletion = serde_json
+                ::from_reader(reader)
+                .map_err(|err| format!("Failed to deserialize response: {}", err))?;
+            let response = completion.to_llm_response();
+            callback(&response.content);
+            return Ok(response);
+        }
+
+        let mut chunks = Vec::new();
+        read_events(reader, |data| {
+            match OpenAIChatCompletionChunk::from_event(data)? {
+                Some(chunk) => {
+                    callback(chunk.content());
+                    chunks.push(chunk);
+                    Ok(true)
+                }
+                // End of the stream.
+                None => Ok(false),
+            }
+        })?;
+        Ok(OpenAIChatCompletion::from_chunks(&chunks).to_llm_response())
+    }
+}
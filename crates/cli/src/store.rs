@@ -0,0 +1,159 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Configuration of the desktop app: config.json is read and written in place, so models
+// installed from the command line show up in the app, and the other way around.
+// The models and the server use the types of the app, the other fields are kept as is.
+
+use std::{ collections::BTreeMap, fs, path::PathBuf };
+
+use opla_core::{
+    directories::get_config_directory,
+    models::{ Model, ModelStorage },
+    server_storage::ServerStorage,
+};
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+// Name of the provider of the local models in the app.
+pub const LOCAL_PROVIDER: &str = "Opla";
+
+// Used when the app has never been launched.
+const DEFAULT_CONFIG: &str = include_str!("../../../webapp/native/assets/opla_default_config.json");
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Service {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none", alias = "modelId", default)]
+    pub model_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", alias = "providerIdOrName", default)]
+    pub provider_id_or_name: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ServiceStorage {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub active_service: Option<Service>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Store {
+    pub server: ServerStorage,
+    #[serde(default)]
+    pub models: ModelStorage,
+    #[serde(default)]
+    pub services: ServiceStorage,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Store {
+    pub fn config_path() -> Result<PathBuf, String> {
+        Ok(get_config_directory()?.join("config.json"))
+    }
+
+    pub fn load() -> Result<Store, String> {
+        let config_path = Store::config_path()?;
+        let config_data = if config_path.exists() {
+            fs
+                ::read_to_string(&config_path)
+                .map_err(|err| format!("Can't read {:?}: {}", config_path, err))?
+        } else {
+            DEFAULT_CONFIG.to_string()
+        };
+        serde_json
+            ::from_str(&config_data)
+            .map_err(|err| format!("Invalid {:?}: {}", config_path, err))
+    }
+
+    // Written to a temporary file first, the app must never read a partial configuration.
+    pub fn save(&self) -> Result<(), String> {
+        let config_path = Store::config_path()?;
+        let config_dir = get_config_directory()?;
+        fs::create_dir_all(&config_dir).map_err(|err| err.to_string())?;
+        let config_data = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        let temp_path = config_dir.join("config.json.tmp");
+        fs::write(&temp_path, config_data).map_err(|err|
+            format!("Can't write {:?}: {}", temp_path, err)
+        )?;
+        fs::rename(&temp_path, &config_path).map_err(|err|
+            format!("Can't write {:?}: {}", config_path, err)
+        )
+    }
+
+    pub fn has_model(&self, id_or_name: &str) -> bool {
+        self.models.get_model_entity(id_or_name).is_some()
+    }
+
+    pub fn get_local_active_model_id(&self) -> Option<String> {
+        let service = self.services.active_service.as_ref()?;
+        let provider = service.provider_id_or_name.as_deref();
+        if service.r#type != "model" || provider != Some(LOCAL_PROVIDER) {
+            return None;
+        }
+        service.model_id.clone()
+    }
+
+    // Same as the app, the server is also configured to load the model at its next start.
+    pub fn set_local_active_model_id(&mut self, model_id: &str) {
+        self.services.active_service = Some(Service {
+            r#type: "model".to_string(),
+            model_id: Some(model_id.to_string()),
+            provider_id_or_name: Some(LOCAL_PROVIDER.to_string()),
+            other: BTreeMap::new(),
+        });
+        self.server.configuration.set_parameter_string("model_id", model_id.to_string());
+        self.server.configuration.remove_parameter("model_path");
+    }
+
+    pub fn clear_local_model(&mut self, model: &Model) {
+        let is_model = |id: Option<&str>| id.is_some_and(|id| model.is_same_id_or_name(id));
+        let active_model_id = self.get_local_active_model_id();
+        if is_model(active_model_id.as_deref()) {
+            self.services.active_service = None;
+        }
+        let server_model_id = self.server.configuration.get_optional_parameter_string("model_id");
+        if is_model(server_model_id.as_deref()) {
+            self.server.configuration.remove_model();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Store;
+
+    #[test]
+    fn app_config() {
+        let json =
+            r#"{"settings":{"start_app":true},"server":{"name":"llama.cpp","binary":"llama.cpp.server","parameters":{"port":8081}},"models":{"items":[{"id":"1","name":"phi","base_model":"TheBloke/phi","created_at":"2024-06-10T06:13:20Z","state":"ok","path":"TheBloke","file_name":"phi.gguf"}]},"downloads":[]}"#;
+        let mut store: Store = serde_json::from_str(json).unwrap();
+        assert_eq!(store.models.items[0].file_name.as_deref(), Some("phi.gguf"));
+        store.set_local_active_model_id("1");
+        assert_eq!(store.get_local_active_model_id().as_deref(), Some("1"));
+
+        let value = serde_json::to_value(&store).unwrap();
+        assert_eq!(value["settings"]["start_app"], true);
+        assert_eq!(value["server"]["binary"], "llama.cpp.server");
+        assert_eq!(value["server"]["parameters"]["model_id"], "1");
+        assert_eq!(value["models"]["items"][0]["created_at"], "2024-06-10T06:13:20+00:00");
+        assert_eq!(value["services"]["active_service"]["provider_id_or_name"], "Opla");
+
+        let model = store.models.items[0].reference.clone();
+        store.clear_local_model(&model);
+        assert!(store.get_local_active_model_id().is_none());
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Models of the Hugging Face hub as returned by its API, and the search of the GGUF models.
+
+use std::str::FromStr;
+
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+
+use crate::{ formats::option_date_format, models::{ Entity, Model, Resource } };
+
+pub const HF_URL: &str = "https://huggingface.co";
+
+pub const HF_SEARCH_LIMIT: usize = 10;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HFConfig {
+    pub model_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HFSibling {
+    #[serde(rename = "rfilename")]
+    pub r_filename: Option<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HFModel {
+    pub id: String,
+    pub author: Option<String>,
+    #[serde(
+        rename = "lastModified",
+        with = "option_date_format",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub last_modified: Option<DateTime<Utc>>,
+    #[serde(
+        rename = "createdAt",
+        with = "option_date_format",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+    pub sha: Option<String>,
+    pub downloads: Option<u64>,
+    pub likes: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub config: Option<HFConfig>,
+    pub siblings: Option<Vec<HFSibling>>,
+}
+
+impl HFModel {
+    pub fn to_model(&self) -> Model {
+        let name = self.id.split('/').next_back().unwrap_or(&self.id).to_string();
+        let mut model = Model::new(name);
+        model.id = Some(self.id.clone());
+        model.created_at = self.created_at;
+        model.updated_at = self.last_modified;
+        model.author = self.author.as_ref().and_then(|author| Entity::from_str(author).ok());
+        model.include = self.siblings.as_ref().map(|siblings| {
+            siblings
+                .iter()
+                .filter_map(|sibling| sibling.r_filename.as_ref())
+                .map(|r_filename| {
+                    let mut submodel = Model::new(r_filename.clone());
+                    submodel.download = Resource::from_str(&file_url(&self.id, r_filename)).ok();
+                    submodel
+                })
+                .collect()
+        });
+        model
+    }
+
+    pub fn gguf_files(&self) -> Vec<&str> {
+        self.siblings
+            .iter()
+            .flatten()
+            .filter_map(|sibling| sibling.r_filename.as_deref())
+            .filter(|file_name| file_name.ends_with(".gguf"))
+            .collect()
+    }
+}
+
+pub fn file_url(repo_id: &str, file_name: &str) -> String {
+    format!("{}/{}/resolve/main/{}", HF_URL, repo_id, file_name)
+}
+
+pub fn model_url(repo_id: &str) -> String {
+    format!("{}/api/models/{}", HF_URL, repo_id)
+}
+
+pub fn search_url() -> String {
+    format!("{}/api/models", HF_URL)
+}
+
+// Query of search_url, only the models with GGUF files are returned, with their files.
+pub fn search_parameters(query: &str, limit: usize) -> Vec<(&'static str, String)> {
+    vec![
+        ("search", query.to_string()),
+        ("filter", "gguf".to_string()),
+        ("limit", limit.to_string()),
+        ("full", "true".to_string()),
+        ("config", "true".to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HFModel;
+
+    #[test]
+    fn hf_model() {
+        let json =
+            r#"{"id":"TheBloke/phi-2-GGUF","author":"TheBloke","lastModified":"2023-12-18T20:25:43.000Z","tags":["gguf"],"siblings":[{"rfilename":"README.md"},{"rfilename":"phi-2.Q4_K_M.gguf"}]}"#;
+        let hf_model: HFModel = serde_json::from_str(json).unwrap();
+        assert_eq!(hf_model.gguf_files(), vec!["phi-2.Q4_K_M.gguf"]);
+
+        let model = hf_model.to_model();
+        assert_eq!(model.name, "phi-2-GGUF");
+        assert_eq!(model.author.unwrap().name, "TheBloke");
+        let include = model.include.unwrap();
+        assert_eq!(include.len(), 2);
+        assert_eq!(
+            include[1].download.as_ref().unwrap().url,
+            "https://huggingface.co/TheBloke/phi-2-GGUF/resolve/main/phi-2.Q4_K_M.gguf"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+
+use super::{ GgufError, GGUF };
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GGUFTensorChecksum {
+    pub name: String,
+    // Lowercase hex SHA256 of the tensor data, padding excluded.
+    pub sha256: String,
+}
+
+// Checksums of every tensor of a model, stored in a JSON file next to it.
+// Tensors are identified by name, so checksums stay valid when a model is split or merged.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GGUFChecksums {
+    pub tensors: Vec<GGUFTensorChecksum>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GGUFVerifyReport {
+    pub verified: Vec<String>,
+    // Data doesn't match its checksum or can't be read from the shards.
+    pub corrupted: Vec<String>,
+    // Tensors with a checksum that are not in the model.
+    pub missing: Vec<String>,
+    // Tensors of the model without a checksum.
+    pub unchecked: Vec<String>,
+}
+
+impl GGUFVerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty()
+    }
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+impl GGUFChecksums {
+    // Path of the checksums file of a model.
+    pub fn sidecar_path(path: &str) -> String {
+        format!("{}.checksums.json", path)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tensors
+            .iter()
+            .find(|t| t.name == name)
+            .map(|t| t.sha256.as_str())
+    }
+
+    pub fn read(path: &str) -> Result<GGUFChecksums, String> {
+        let content = fs
+            ::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {}", path, err))?;
+        serde_json::from_str(&content).map_err(|err| format!("Invalid checksums {}: {}", path, err))
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(path, content).map_err(|err| format!("Can't write {}: {}", path, err))
+    }
+}
+
+impl GGUF {
+    // Hashes the data of every tensor, shards of a split model included.
+    pub fn compute_checksums(&self) -> Result<GGUFChecksums, GgufError> {
+        let mut tensors = Vec::new();
+        for tensor in self.tensors.iter() {
+            tensors.push(GGUFTensorChecksum {
+                name: tensor.name.clone(),
+                sha256: sha256(self.get_tensor_data(tensor)?),
+            });
+        }
+        Ok(GGUFChecksums { tensors })
+    }
+
+    // Computes the checksums and writes them next to the model, returns the path of the file.
+    pub fn write_checksums(&self, path: &str) -> Result<String, String> {
+        let checksums = self.compute_checksums().map_err(|err| err.to_string())?;
+        let sidecar_path = GGUFChecksums::sidecar_path(path);
+        checksums.write(&sidecar_path)?;
+        Ok(sidecar_path)
+    }
+
+    // Every tensor is checked, so all the corrupted ones are reported.
+    pub fn verify_checksums(&self, checksums: &GGUFChecksums) -> GGUFVerifyReport {
+        let mut report = GGUFVerifyReport::default();
+        for tensor in self.tensors.iter() {
+            let expected = match checksums.get(&tensor.name) {
+                Some(expected) => expected,
+                None => {
+                    report.unchecked.push(tensor.name.clone());
+                    continue;
+                }
+            };
+            match self.get_tensor_data(tensor) {
+                Ok(data) if sha256(data).eq_ignore_ascii_case(expected) => {
+                    report.verified.push(tensor.name.clone());
+                }
+                _ => report.corrupted.push(tensor.name.clone()),
+            }
+        }
+        report.missing = checksums.tensors
+            .iter()
+            .filter(|t| self.get_tensor_info(&t.name).is_none())
+            .map(|t| t.name.clone())
+            .collect();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        gguf::{ GGMLType, GGUFChecksums, GGUF },
+        testing::{ create_model, temp_path, tensor, write_model },
+    };
+
+    #[test]
+    fn verify_tensors() {
+        let path = temp_path("checksums");
+        let tensors: Vec<_> = ["token_embd.weight", "blk.0.attn_q.weight", "output.weight"]
+            .iter()
+            .map(|name| tensor(name, &[32, 4], GGMLType::F32))
+            .collect();
+        write_model(&create_model("llama", &[], &tensors), &path);
+        let path = path.to_str().unwrap();
+
+        let mut gguf = GGUF::new(path);
+        gguf.read(path).unwrap();
+        let sidecar_path = gguf.write_checksums(path).unwrap();
+        let checksums = GGUFChecksums::read(&sidecar_path).unwrap();
+        assert_eq!(checksums.tensors.len(), 3);
+        assert_eq!(checksums.get("output.weight").unwrap().len(), 64);
+        assert!(gguf.verify_checksums(&checksums).is_valid());
+
+        // One byte changed in the second tensor.
+        let mut data = fs::read(path).unwrap();
+        let offset = (gguf.tensor_data_offset + gguf.tensors[1].offset) as usize;
+        data[offset + 7] ^= 0xff;
+        fs::write(path, &data).unwrap();
+        let mut corrupted = GGUF::new(path);
+        corrupted.read(path).unwrap();
+        let report = corrupted.verify_checksums(&checksums);
+        assert!(!report.is_valid());
+        assert_eq!(report.corrupted, vec!["blk.0.attn_q.weight"]);
+        assert_eq!(report.verified.len(), 2);
+
+        // Partial copy to a preallocated file, the end of the last tensor is not written.
+        let len = data.len();
+        data[len - 16..].fill(0);
+        fs::write(path, &data).unwrap();
+        let mut truncated = GGUF::new(path);
+        truncated.read(path).unwrap();
+        let mut checksums = checksums.clone();
+        checksums.tensors.remove(0);
+        checksums.tensors[0].name = "blk.1.attn_q.weight".to_string();
+        let report = truncated.verify_checksums(&checksums);
+        assert_eq!(report.corrupted, vec!["output.weight"]);
+        assert_eq!(report.missing, vec!["blk.1.attn_q.weight"]);
+        assert_eq!(report.unchecked, vec!["token_embd.weight", "blk.0.attn_q.weight"]);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(sidecar_path).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use half::{ bf16, f16 };
+
+use super::{ GGMLType, GGUFByteOrder, GGUFTensorInfo, GgufError, GGUF };
+
+// Number of elements in a K-quant super-block.
+const QK_K: usize = 256;
+
+// Decodes tensor data to f32, following the reference implementation of ggml-quants.c.
+// Block scales and plain values use the byte order of the file.
+pub fn dequantize(
+    tensor_type: GGMLType,
+    data: &[u8],
+    byte_order: GGUFByteOrder
+) -> Result<Vec<f32>, String> {
+    let type_size = tensor_type.type_size() as usize;
+    if !data.len().is_multiple_of(type_size) {
+        return Err(
+            format!(
+                "{} data size {} is not a multiple of block size {}",
+                tensor_type,
+                data.len(),
+                type_size
+            )
+        );
+    }
+    let reader = BlockReader { byte_order };
+    let dequantize_block: fn(&BlockReader, &[u8], &mut [f32]) = match tensor_type {
+        GGMLType::F32 => |r, b, y| {
+            y[0] = f32::from_bits(r.u32(b));
+        },
+        GGMLType::F16 => |r, b, y| {
+            y[0] = r.f16(b);
+        },
+        GGMLType::BF16 => |r, b, y| {
+            y[0] = bf16::from_bits(r.u16(b)).to_f32();
+        },
+        GGMLType::F64 => |r, b, y| {
+            y[0] = f64::from_bits(r.u64(b)) as f32;
+        },
+        GGMLType::I8 => |_, b, y| {
+            y[0] = b[0] as i8 as f32;
+        },
+        GGMLType::I16 => |r, b, y| {
+            y[0] = r.u16(b) as i16 as f32;
+        },
+        GGMLType::I32 => |r, b, y| {
+            y[0] = r.u32(b) as i32 as f32;
+        },
+        GGMLType::I64 => |r, b, y| {
+            y[0] = r.u64(b) as i64 as f32;
+        },
+        GGMLType::Q4_0 => dequantize_q4_0,
+        GGMLType::Q4_1 => dequantize_q4_1,
+        GGMLType::Q5_0 => dequantize_q5_0,
+        GGMLType::Q5_1 => dequantize_q5_1,
+        GGMLType::Q8_0 => dequantize_q8_0,
+        GGMLType::Q2_K => dequantize_q2_k,
+        GGMLType::Q3_K => dequantize_q3_k,
+        GGMLType::Q4_K => dequantize_q4_k,
+        GGMLType::Q5_K => dequantize_q5_k,
+        GGMLType::Q6_K => dequantize_q6_k,
+        GGMLType::Q8_K => dequantize_q8_k,
+        _ => {
+            return Err(format!("dequantization of {} is not supported", tensor_type));
+        }
+    };
+
+    let block_size = tensor_type.block_size() as usize;
+    let mut values = vec![0.0; (data.len() / type_size) * block_size];
+    for (block, y) in data.chunks_exact(type_size).zip(values.chunks_exact_mut(block_size)) {
+        dequantize_block(&reader, block, y);
+    }
+    Ok(values)
+}
+
+impl GGUF {
+    // Tensor values decoded to f32, the tensor data is read from the mapped file.
+    pub fn get_tensor_f32(&self, tensor: &GGUFTensorInfo) -> Result<Vec<f32>, GgufError> {
+        let data = self.get_tensor_data(tensor)?;
+        dequantize(tensor.tensor_type, data, self.header.byte_order).map_err(|message|
+            GgufError::invalid_value(self.tensor_data_offset + tensor.offset, message)
+        )
+    }
+}
+
+struct BlockReader {
+    byte_order: GGUFByteOrder,
+}
+
+impl BlockReader {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.byte_order {
+            GGUFByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            GGUFByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.byte_order {
+            GGUFByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            GGUFByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let mut value = [0; 8];
+        value.copy_from_slice(&bytes[..8]);
+        match self.byte_order {
+            GGUFByteOrder::LittleEndian => u64::from_le_bytes(value),
+            GGUFByteOrder::BigEndian => u64::from_be_bytes(value),
+        }
+    }
+
+    fn f16(&self, bytes: &[u8]) -> f32 {
+        f16::from_bits(self.u16(bytes)).to_f32()
+    }
+}
+
+// Block layouts, see the block_* structs of ggml-common.h.
+
+fn dequantize_q4_0(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let qs = &block[2..18];
+    for j in 0..16 {
+        y[j] = (((qs[j] & 0x0f) as i32) - 8) as f32 * d;
+        y[j + 16] = (((qs[j] >> 4) as i32) - 8) as f32 * d;
+    }
+}
+
+fn dequantize_q4_1(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let m = r.f16(&block[2..]);
+    let qs = &block[4..20];
+    for j in 0..16 {
+        y[j] = ((qs[j] & 0x0f) as f32) * d + m;
+        y[j + 16] = ((qs[j] >> 4) as f32) * d + m;
+    }
+}
+
+fn dequantize_q5_0(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let qh = u32::from_le_bytes([block[2], block[3], block[4], block[5]]);
+    let qs = &block[6..22];
+    for j in 0..16 {
+        let xh_0 = (((qh >> j) << 4) & 0x10) as u8;
+        let xh_1 = ((qh >> (j + 12)) & 0x10) as u8;
+        y[j] = ((((qs[j] & 0x0f) | xh_0) as i32) - 16) as f32 * d;
+        y[j + 16] = ((((qs[j] >> 4) | xh_1) as i32) - 16) as f32 * d;
+    }
+}
+
+fn dequantize_q5_1(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let m = r.f16(&block[2..]);
+    let qh = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let qs = &block[8..24];
+    for j in 0..16 {
+        let xh_0 = (((qh >> j) << 4) & 0x10) as u8;
+        let xh_1 = ((qh >> (j + 12)) & 0x10) as u8;
+        y[j] = (((qs[j] & 0x0f) | xh_0) as f32) * d + m;
+        y[j + 16] = (((qs[j] >> 4) | xh_1) as f32) * d + m;
+    }
+}
+
+fn dequantize_q8_0(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    for (value, q) in y.iter_mut().zip(block[2..34].iter()) {
+        *value = (*q as i8 as f32) * d;
+    }
+}
+
+fn dequantize_q2_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let scales = &block[0..16];
+    let qs = &block[16..80];
+    let d = r.f16(&block[80..]);
+    let min = r.f16(&block[82..]);
+    let mut is = 0;
+    for n in 0..QK_K / 128 {
+        let q = &qs[n * 32..];
+        let y = &mut y[n * 128..];
+        for j in 0..4 {
+            let shift = j * 2;
+            for half in 0..2 {
+                let sc = scales[is];
+                is += 1;
+                let dl = d * ((sc & 0x0f) as f32);
+                let ml = min * ((sc >> 4) as f32);
+                for l in 0..16 {
+                    let q = (q[l + half * 16] >> shift) & 3;
+                    y[j * 32 + half * 16 + l] = dl * (q as f32) - ml;
+                }
+            }
+        }
+    }
+}
+
+fn dequantize_q3_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    const KMASK1: u32 = 0x03030303;
+    const KMASK2: u32 = 0x0f0f0f0f;
+    let hmask = &block[0..32];
+    let qs = &block[32..96];
+    let d_all = r.f16(&block[108..]);
+
+    // 16 scales of 6 bits packed in 12 bytes.
+    let mut aux = [0u32; 4];
+    for (i, value) in aux.iter_mut().take(3).enumerate() {
+        *value = u32::from_le_bytes([
+            block[96 + i * 4],
+            block[97 + i * 4],
+            block[98 + i * 4],
+            block[99 + i * 4],
+        ]);
+    }
+    let tmp = aux[2];
+    aux[2] = ((aux[0] >> 4) & KMASK2) | (((tmp >> 4) & KMASK1) << 4);
+    aux[3] = ((aux[1] >> 4) & KMASK2) | (((tmp >> 6) & KMASK1) << 4);
+    aux[0] = (aux[0] & KMASK2) | ((tmp & KMASK1) << 4);
+    aux[1] = (aux[1] & KMASK2) | (((tmp >> 2) & KMASK1) << 4);
+    let scales: Vec<i8> = aux
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .map(|value| value as i8)
+        .collect();
+
+    let mut is = 0;
+    let mut m = 1u8;
+    for n in 0..QK_K / 128 {
+        let q = &qs[n * 32..];
+        let y = &mut y[n * 128..];
+        for j in 0..4 {
+            let shift = j * 2;
+            for half in 0..2 {
+                let dl = d_all * (((scales[is] as i32) - 32) as f32);
+                is += 1;
+                for l in 0..16 {
+                    let index = l + half * 16;
+                    let high = if (hmask[index] & m) != 0 { 0 } else { 4 };
+                    let q = (((q[index] >> shift) & 3) as i32) - high;
+                    y[j * 32 + index] = dl * (q as f32);
+                }
+            }
+            m <<= 1;
+        }
+    }
+}
+
+// Scale and min of a sub-block, packed on 6 bits in the 12 bytes of Q4_K and Q5_K.
+pub(super) fn get_scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        ((q[j + 4] & 0x0f) | ((q[j - 4] >> 6) << 4), (q[j + 4] >> 4) | ((q[j] >> 6) << 4))
+    }
+}
+
+fn dequantize_q4_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let min = r.f16(&block[2..]);
+    let scales = &block[4..16];
+    let qs = &block[16..144];
+    for (n, q) in qs.chunks_exact(32).enumerate() {
+        let (sc, m) = get_scale_min_k4(n * 2, scales);
+        let (d1, m1) = (d * (sc as f32), min * (m as f32));
+        let (sc, m) = get_scale_min_k4(n * 2 + 1, scales);
+        let (d2, m2) = (d * (sc as f32), min * (m as f32));
+        let y = &mut y[n * 64..];
+        for l in 0..32 {
+            y[l] = d1 * ((q[l] & 0x0f) as f32) - m1;
+            y[l + 32] = d2 * ((q[l] >> 4) as f32) - m2;
+        }
+    }
+}
+
+fn dequantize_q5_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = r.f16(block);
+    let min = r.f16(&block[2..]);
+    let scales = &block[4..16];
+    let qh = &block[16..48];
+    let qs = &block[48..176];
+    for (n, ql) in qs.chunks_exact(32).enumerate() {
+        let (sc, m) = get_scale_min_k4(n * 2, scales);
+        let (d1, m1) = (d * (sc as f32), min * (m as f32));
+        let (sc, m) = get_scale_min_k4(n * 2 + 1, scales);
+        let (d2, m2) = (d * (sc as f32), min * (m as f32));
+        let u1 = 1u8 << (n * 2);
+        let u2 = 2u8 << (n * 2);
+        let y = &mut y[n * 64..];
+        for l in 0..32 {
+            let high1 = if (qh[l] & u1) != 0 { 16 } else { 0 };
+            let high2 = if (qh[l] & u2) != 0 { 16 } else { 0 };
+            y[l] = d1 * (((ql[l] & 0x0f) + high1) as f32) - m1;
+            y[l + 32] = d2 * (((ql[l] >> 4) + high2) as f32) - m2;
+        }
+    }
+}
+
+fn dequantize_q6_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let ql = &block[0..128];
+    let qh = &block[128..192];
+    let scales = &block[192..208];
+    let d = r.f16(&block[208..]);
+    for n in 0..QK_K / 128 {
+        let ql = &ql[n * 64..];
+        let qh = &qh[n * 32..];
+        let sc = &scales[n * 8..];
+        let y = &mut y[n * 128..];
+        for l in 0..32 {
+            let is = l / 16;
+            let q1 = (((ql[l] & 0x0f) | ((qh[l] & 3) << 4)) as i32) - 32;
+            let q2 = (((ql[l + 32] & 0x0f) | (((qh[l] >> 2) & 3) << 4)) as i32) - 32;
+            let q3 = (((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32) - 32;
+            let q4 = (((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32) - 32;
+            y[l] = d * ((sc[is] as i8) as f32) * (q1 as f32);
+            y[l + 32] = d * ((sc[is + 2] as i8) as f32) * (q2 as f32);
+            y[l + 64] = d * ((sc[is + 4] as i8) as f32) * (q3 as f32);
+            y[l + 96] = d * ((sc[is + 6] as i8) as f32) * (q4 as f32);
+        }
+    }
+}
+
+fn dequantize_q8_k(r: &BlockReader, block: &[u8], y: &mut [f32]) {
+    let d = f32::from_bits(r.u32(block));
+    for (value, q) in y.iter_mut().zip(block[4..4 + QK_K].iter()) {
+        *value = (*q as i8 as f32) * d;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use half::f16;
+
+    use crate::gguf::{ GGMLType, GGUFByteOrder };
+    use super::dequantize;
+
+    // Type, scales, weighted sum and sampled values of a block.
+    type Fixture<'a> = (GGMLType, Vec<(usize, &'a [u8])>, f64, Vec<(usize, f32)>);
+
+    // Pseudo random block with its scales set, the expected values were computed
+    // with a transcription of the ggml-quants.c reference implementation.
+    fn create_block(tensor_type: GGMLType, scales: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut block: Vec<u8> = (0..tensor_type.type_size())
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        for (offset, scale) in scales {
+            block[*offset..*offset + scale.len()].copy_from_slice(scale);
+        }
+        block
+    }
+
+    #[test]
+    fn dequantize_fixtures() {
+        let d = f16::from_f32(0.5).to_le_bytes();
+        let m = f16::from_f32(0.25).to_le_bytes();
+        let d_q8_k = 0.125f32.to_le_bytes();
+        #[rustfmt::skip]
+        let fixtures: [Fixture; 11] = [
+            (GGMLType::Q4_0, vec![(0, &d)], 113.0,
+                vec![(0, -1.0), (1, -2.0), (5, 1.0), (15, -1.5), (16, -2.5), (17, -0.5), (30, 1.5), (31, -0.5)]),
+            (GGMLType::Q4_1, vec![(0, &d), (2, &m)], 604.5,
+                vec![(0, 6.25), (1, 4.75), (5, 7.25), (15, 4.75), (16, 7.25), (17, 3.75), (30, 2.25), (31, 0.75)]),
+            (GGMLType::Q5_0, vec![(0, &d)], 127.0,
+                vec![(0, -0.5), (1, 5.0), (5, 1.5), (15, -0.5), (16, -2.0), (17, -1.0), (30, 3.0), (31, -1.0)]),
+            (GGMLType::Q5_1, vec![(0, &d), (2, &m)], 1275.0,
+                vec![(0, 5.75), (1, 7.25), (5, 15.25), (15, 7.25), (16, 12.25), (17, 12.25), (30, 8.75), (31, 14.75)]),
+            (GGMLType::Q8_0, vec![(0, &d)], 879.5,
+                vec![(0, 27.0), (1, 58.0), (5, -11.0), (15, 58.5), (16, 39.5), (17, 12.5), (30, 45.0), (31, -10.0)]),
+            (GGMLType::Q2_K, vec![(80, &d), (82, &m)], 6174.25,
+                vec![(0, -2.5), (1, 4.0), (17, -1.75), (100, 4.5), (127, 1.5), (128, 3.5), (150, 5.0), (201, 15.75), (254, 3.75), (255, -3.75)]),
+            (GGMLType::Q3_K, vec![(108, &d)], 627.0,
+                vec![(0, -5.0), (1, 10.0), (17, 0.0), (100, 31.5), (127, 18.0), (128, 6.5), (150, 1.5), (201, 18.0), (254, -26.0), (255, 13.0)]),
+            (GGMLType::Q4_K, vec![(0, &d), (2, &m)], 180051.75,
+                vec![(0, 173.25), (1, 107.25), (17, 261.25), (100, 41.25), (127, 62.25), (128, 293.25), (150, 140.75), (201, 377.75), (254, 153.75), (255, 279.75)]),
+            (GGMLType::Q5_K, vec![(0, &d), (2, &m)], 372024.75,
+                vec![(0, 195.25), (1, 613.25), (17, 107.25), (100, 650.25), (127, 209.25), (128, 567.75), (150, 689.75), (201, 724.25), (254, 90.75), (255, 437.25)]),
+            (GGMLType::Q6_K, vec![(208, &d)], 49902.5,
+                vec![(0, 1812.5), (1, 1375.0), (17, 142.5), (100, 518.5), (127, -56.0), (128, 1187.5), (150, -1309.5), (201, -1937.5), (254, -68.0), (255, -119.0)]),
+            (GGMLType::Q8_K, vec![(0, &d_q8_k)], 235.0,
+                vec![(0, -2.5), (1, 15.125), (17, -2.125), (100, 11.125), (127, -11.25), (128, 6.875), (150, -0.625), (201, -9.125), (254, -15.25), (255, 5.875)]),
+        ];
+        for (tensor_type, scales, weighted_sum, samples) in fixtures {
+            let block = create_block(tensor_type, &scales);
+            let values = dequantize(tensor_type, &block, GGUFByteOrder::LittleEndian).unwrap();
+            assert_eq!(values.len() as u64, tensor_type.block_size());
+            for (index, expected) in samples {
+                assert_eq!(values[index], expected, "{} value {}", tensor_type, index);
+            }
+            // Weighted to catch values in the wrong position.
+            let sum: f64 = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (*v as f64) * (((i % 7) + 1) as f64))
+                .sum();
+            assert_eq!(sum, weighted_sum, "{} weighted sum", tensor_type);
+        }
+    }
+
+    #[test]
+    fn dequantize_plain_types() {
+        let values = [1.5f32, -2.0, 65504.0];
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|v| f16::from_f32(*v).to_be_bytes())
+            .collect();
+        assert_eq!(dequantize(GGMLType::F16, &data, GGUFByteOrder::BigEndian).unwrap(), values);
+
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|v| half::bf16::from_f32(*v).to_le_bytes())
+            .collect();
+        let result = dequantize(GGMLType::BF16, &data, GGUFByteOrder::LittleEndian).unwrap();
+        assert_eq!(&result[..2], &values[..2]);
+
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(dequantize(GGMLType::F32, &data, GGUFByteOrder::LittleEndian).unwrap(), values);
+        assert!(dequantize(GGMLType::F32, &data[1..], GGUFByteOrder::LittleEndian).is_err());
+        assert!(dequantize(GGMLType::IQ2_XXS, &[0; 66], GGUFByteOrder::LittleEndian).is_err());
+    }
+}
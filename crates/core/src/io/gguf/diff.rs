@@ -0,0 +1,236 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Serialize };
+
+use super::{ GGMLType, GGUFMetadata, GGUFMetadataValue, GGUFTensorInfo, GGUF };
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GGUFMetadataDiff {
+    pub key: String,
+    pub left: GGUFMetadataValue,
+    pub right: GGUFMetadataValue,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GGUFTensorDiff {
+    pub name: String,
+    pub left_dimensions: Vec<u64>,
+    pub right_dimensions: Vec<u64>,
+    pub left_type: GGMLType,
+    pub right_type: GGMLType,
+    pub size_delta: i64,
+}
+
+// Differences between two files, left is the reference.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GGUFDiff {
+    pub added_metadata: Vec<GGUFMetadata>,
+    pub removed_metadata: Vec<GGUFMetadata>,
+    pub changed_metadata: Vec<GGUFMetadataDiff>,
+    // Values that couldn't be read on both sides, ie arrays of a remote header.
+    pub unknown_metadata: Vec<GGUFMetadataDiff>,
+    pub added_tensors: Vec<GGUFTensorInfo>,
+    pub removed_tensors: Vec<GGUFTensorInfo>,
+    pub changed_tensors: Vec<GGUFTensorDiff>,
+    pub left_tensors_size: u64,
+    pub right_tensors_size: u64,
+    pub size_delta: i64,
+}
+
+impl GGUFDiff {
+    pub fn new(left: &GGUF, right: &GGUF) -> GGUFDiff {
+        let mut diff = GGUFDiff {
+            left_tensors_size: left.tensors_size(),
+            right_tensors_size: right.tensors_size(),
+            size_delta: size_delta(left.tensors_size(), right.tensors_size()),
+            ..GGUFDiff::default()
+        };
+
+        for metadata in left.header.metadata_kv.iter() {
+            let other = match right.header.metadata_kv.iter().find(|m| m.key == metadata.key) {
+                Some(other) => other,
+                None => {
+                    diff.removed_metadata.push(metadata.clone());
+                    continue;
+                }
+            };
+            let metadata_diff = GGUFMetadataDiff {
+                key: metadata.key.clone(),
+                left: metadata.value.clone(),
+                right: other.value.clone(),
+            };
+            match metadata_equals(left, &metadata.value, right, &other.value) {
+                Some(true) => {}
+                Some(false) => diff.changed_metadata.push(metadata_diff),
+                None => diff.unknown_metadata.push(metadata_diff),
+            }
+        }
+        diff.added_metadata = right.header.metadata_kv
+            .iter()
+            .filter(|m| left.get_metadata_value(&m.key).is_none())
+            .cloned()
+            .collect();
+
+        for tensor in left.tensors.iter() {
+            match right.get_tensor_info(&tensor.name) {
+                Some(other) if
+                    other.dimensions != tensor.dimensions ||
+                    other.tensor_type != tensor.tensor_type
+                => {
+                    diff.changed_tensors.push(GGUFTensorDiff {
+                        name: tensor.name.clone(),
+                        left_dimensions: tensor.dimensions.clone(),
+                        right_dimensions: other.dimensions.clone(),
+                        left_type: tensor.tensor_type,
+                        right_type: other.tensor_type,
+                        size_delta: size_delta(tensor.size(), other.size()),
+                    });
+                }
+                Some(_) => {}
+                None => diff.removed_tensors.push(tensor.clone()),
+            }
+        }
+        diff.added_tensors = right.tensors
+            .iter()
+            .filter(|t| left.get_tensor_info(&t.name).is_none())
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    // Values not compared are not known to be the same.
+    pub fn is_empty(&self) -> bool {
+        self.added_metadata.is_empty() &&
+            self.removed_metadata.is_empty() &&
+            self.changed_metadata.is_empty() &&
+            self.unknown_metadata.is_empty() &&
+            self.added_tensors.is_empty() &&
+            self.removed_tensors.is_empty() &&
+            self.changed_tensors.is_empty()
+    }
+}
+
+impl GGUF {
+    pub fn diff(&self, other: &GGUF) -> GGUFDiff {
+        GGUFDiff::new(self, other)
+    }
+}
+
+fn size_delta(left: u64, right: u64) -> i64 {
+    (right as i64).wrapping_sub(left as i64)
+}
+
+// Arrays not loaded are compared from the mapped files. None when their values can't be read,
+// e.g. for a remote header, and their type and length are the same.
+fn metadata_equals(
+    left: &GGUF,
+    left_value: &GGUFMetadataValue,
+    right: &GGUF,
+    right_value: &GGUFMetadataValue
+) -> Option<bool> {
+    match (left_value, right_value) {
+        (GGUFMetadataValue::Array(a), GGUFMetadataValue::Array(b)) => {
+            if a.value_type != b.value_type || a.len != b.len {
+                return Some(false);
+            }
+            match (left.get_array_values(a), right.get_array_values(b)) {
+                (Ok(a), Ok(b)) => Some(a == b),
+                _ => None,
+            }
+        }
+        _ => Some(left_value == right_value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gguf::{
+            GGMLType,
+            GGUFMetadataArrayValue,
+            GGUFMetadataValue,
+            GGUFMetadataValueType,
+            GGUF,
+            GGUF_LAZY_ARRAY_LEN,
+        },
+        testing::{ create_model, tensor },
+    };
+
+    fn create_llama(quantization: GGMLType) -> GGUF {
+        create_model(
+            "llama",
+            &[("llama.block_count", GGUFMetadataValue::Uint32(1))],
+            &[
+                tensor("token_embd.weight", &[4096, 256], quantization),
+                tensor("blk.0.attn_q.weight", &[4096, 256], quantization),
+            ]
+        )
+    }
+
+    #[test]
+    fn diff_metadata_and_tensors() {
+        let left = create_llama(GGMLType::F16);
+        assert!(left.diff(&left).is_empty());
+
+        let mut right = create_llama(GGMLType::Q8_0);
+        right.set_metadata("llama.block_count", GGUFMetadataValue::Uint64(1));
+        right.set_metadata("general.name", GGUFMetadataValue::String("test".to_string()));
+        right.header.metadata_kv.retain(|m| m.key != "general.architecture");
+        right.tensors.pop();
+        right.tensors.push(tensor("output.weight", &[4096], GGMLType::F32));
+
+        let diff = left.diff(&right);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added_metadata[0].key, "general.name");
+        assert_eq!(diff.removed_metadata[0].key, "general.architecture");
+        // A value stored with another type is a change.
+        assert_eq!(diff.changed_metadata[0].key, "llama.block_count");
+        assert_eq!(diff.added_tensors[0].name, "output.weight");
+        assert_eq!(diff.removed_tensors[0].name, "blk.0.attn_q.weight");
+        assert_eq!(diff.changed_tensors[0].name, "token_embd.weight");
+        assert_eq!(diff.changed_tensors[0].right_type, GGMLType::Q8_0);
+        assert_eq!(diff.changed_tensors[0].size_delta, (4096 * 256 * 34) / 32 - 4096 * 256 * 2);
+        assert_eq!(
+            diff.size_delta,
+            (diff.right_tensors_size as i64) - (diff.left_tensors_size as i64)
+        );
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["changed_metadata"][0]["right"]["type"], "UInt64");
+        assert_eq!(json["changed_metadata"][0]["right"]["value"], 1);
+    }
+
+    #[test]
+    fn arrays_not_loaded_are_not_compared() {
+        // As read from a remote header, the values are neither loaded nor mapped.
+        let tokens = GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+            value_type: GGUFMetadataValueType::String,
+            len: GGUF_LAZY_ARRAY_LEN + 1,
+            value: Vec::new(),
+            offset: Some(1024),
+        });
+        let mut left = create_llama(GGMLType::F16);
+        left.set_metadata("tokenizer.ggml.tokens", tokens.clone());
+        let mut right = create_llama(GGMLType::F16);
+        right.set_metadata("tokenizer.ggml.tokens", tokens);
+
+        let diff = left.diff(&right);
+        assert!(diff.changed_metadata.is_empty());
+        assert_eq!(diff.unknown_metadata.len(), 1);
+        assert_eq!(diff.unknown_metadata[0].key, "tokenizer.ggml.tokens");
+        assert!(!diff.is_empty());
+    }
+}
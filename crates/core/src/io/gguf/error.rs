@@ -0,0 +1,151 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use thiserror::Error;
+
+// Errors returned while reading or writing a GGUF file.
+// The offset is the position in the file where the error occurred.
+#[derive(Error, Debug)]
+pub enum GgufError {
+    #[error("not a valid GGUF file, bad magic {magic:02x?} at offset {offset}")]
+    BadMagic {
+        offset: u64,
+        magic: [u8; 4],
+    },
+
+    #[error("unsupported GGUF version {version} at offset {offset}")]
+    UnsupportedVersion {
+        offset: u64,
+        version: u32,
+    },
+
+    #[error("truncated GGUF file at offset {offset}")]
+    Truncated {
+        offset: u64,
+    },
+
+    #[error("invalid {kind} type {value} at offset {offset}")]
+    InvalidType {
+        offset: u64,
+        kind: &'static str,
+        value: u32,
+    },
+
+    #[error("{kind} length {length} exceeds limit {limit} at offset {offset}")]
+    OversizedLength {
+        offset: u64,
+        kind: &'static str,
+        length: u64,
+        limit: u64,
+    },
+
+    #[error("invalid UTF-8 string at offset {offset}")]
+    InvalidUtf8 {
+        offset: u64,
+    },
+
+    #[error("invalid value at offset {offset}: {message}")]
+    InvalidValue {
+        offset: u64,
+        message: String,
+    },
+
+    #[error("metadata {key} not found")]
+    MissingKey {
+        key: String,
+    },
+
+    #[error("invalid split model: {message}")]
+    InvalidSplit {
+        message: String,
+    },
+
+    #[error("GGUF data not available: {message}")]
+    NotLoaded {
+        message: String,
+    },
+
+    #[error("can't write GGUF file: {message}")]
+    Write {
+        message: String,
+    },
+
+    #[error("i/o error at offset {offset}: {source}")]
+    Io {
+        offset: u64,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl GgufError {
+    pub fn io(offset: u64, source: io::Error) -> GgufError {
+        if source.kind() == io::ErrorKind::UnexpectedEof {
+            return GgufError::Truncated { offset };
+        }
+        GgufError::Io { offset, source }
+    }
+
+    pub fn write(message: impl Into<String>) -> GgufError {
+        GgufError::Write { message: message.into() }
+    }
+
+    pub fn invalid_value(offset: u64, message: impl Into<String>) -> GgufError {
+        GgufError::InvalidValue { offset, message: message.into() }
+    }
+
+    // Byte offset where the error occurred, if it happened while reading.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            | GgufError::BadMagic { offset, .. }
+            | GgufError::UnsupportedVersion { offset, .. }
+            | GgufError::Truncated { offset }
+            | GgufError::InvalidType { offset, .. }
+            | GgufError::OversizedLength { offset, .. }
+            | GgufError::InvalidUtf8 { offset }
+            | GgufError::InvalidValue { offset, .. }
+            | GgufError::Io { offset, .. } => Some(*offset),
+            | GgufError::MissingKey { .. }
+            | GgufError::InvalidSplit { .. }
+            | GgufError::NotLoaded { .. }
+            | GgufError::Write { .. } => None,
+        }
+    }
+}
+
+// Limits enforced while reading, to protect against hostile or corrupted files.
+#[derive(Clone, Debug)]
+pub struct GGUFReadLimits {
+    pub max_string_length: u64,
+    pub max_array_length: u64,
+    pub max_array_depth: u32,
+    pub max_metadata_kv_count: u64,
+    pub max_tensor_count: u64,
+    pub max_dimensions: u32,
+}
+
+impl Default for GGUFReadLimits {
+    fn default() -> Self {
+        GGUFReadLimits {
+            max_string_length: 16 * 1024 * 1024,
+            max_array_length: 16 * 1024 * 1024,
+            max_array_depth: 8,
+            max_metadata_kv_count: 64 * 1024,
+            max_tensor_count: 1024 * 1024,
+            // GGML_MAX_DIMS
+            max_dimensions: 4,
+        }
+    }
+}
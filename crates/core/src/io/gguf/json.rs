@@ -0,0 +1,307 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+use serde_json::Value;
+
+use super::{ GGUFMetadataArrayValue, GGUFMetadataValue, GGUFMetadataValueType };
+
+// An array as serialized, its elements are typed with value_type once read.
+#[derive(Deserialize)]
+pub(super) struct GGUFMetadataArrayJson {
+    value_type: GGUFMetadataValueType,
+    len: u64,
+    value: Vec<Value>,
+    #[serde(default)]
+    offset: Option<u64>,
+}
+
+impl TryFrom<GGUFMetadataArrayJson> for GGUFMetadataArrayValue {
+    type Error = String;
+
+    fn try_from(array: GGUFMetadataArrayJson) -> Result<Self, Self::Error> {
+        let value = array.value
+            .into_iter()
+            .map(|value| value_from_json(array.value_type, value))
+            .collect::<Result<Vec<GGUFMetadataValue>, String>>()?;
+        if array.offset.is_none() && (value.len() as u64) != array.len {
+            return Err(format!("array len {} doesn't match its {} values", array.len, value.len()));
+        }
+        Ok(GGUFMetadataArrayValue {
+            value_type: array.value_type,
+            len: array.len,
+            value,
+            offset: array.offset,
+        })
+    }
+}
+
+// JSON has no number for NaN and infinite floats, they are written as strings. null is read
+// as NaN, as serde_json writes non-finite floats.
+fn float_from_json(value: &Value) -> Option<f64> {
+    match value {
+        Value::Null => Some(f64::NAN),
+        Value::String(value) =>
+            match value.as_str() {
+                "inf" => Some(f64::INFINITY),
+                "-inf" => Some(f64::NEG_INFINITY),
+                "nan" => Some(f64::NAN),
+                _ => None,
+            }
+        _ => value.as_f64(),
+    }
+}
+
+fn serialize_float<F, S>(value: F, serializer: S) -> Result<S::Ok, S::Error>
+    where F: Serialize + Into<f64> + Copy, S: Serializer
+{
+    let float: f64 = value.into();
+    if float.is_nan() {
+        serializer.serialize_str("nan")
+    } else if float.is_infinite() {
+        serializer.serialize_str(if float > 0.0 { "inf" } else { "-inf" })
+    } else {
+        value.serialize(serializer)
+    }
+}
+
+fn deserialize_float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    float_from_json(&value).ok_or_else(||
+        serde::de::Error::custom(format!("invalid float: {}", value))
+    )
+}
+
+pub(super) mod float32 {
+    use serde::{ Deserializer, Serializer };
+
+    pub fn serialize<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_float(*value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+        super::deserialize_float(deserializer).map(|value| value as f32)
+    }
+}
+
+pub(super) mod float64 {
+    use serde::{ Deserializer, Serializer };
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_float(*value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        super::deserialize_float(deserializer)
+    }
+}
+
+fn value_from_json(
+    value_type: GGUFMetadataValueType,
+    value: Value
+) -> Result<GGUFMetadataValue, String> {
+    let int = |value: &Value| value.as_i64();
+    let uint = |value: &Value| value.as_u64();
+    let result = match value_type {
+        GGUFMetadataValueType::UInt8 =>
+            uint(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Uint8),
+        GGUFMetadataValueType::Int8 =>
+            int(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Int8),
+        GGUFMetadataValueType::UInt16 =>
+            uint(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Uint16),
+        GGUFMetadataValueType::Int16 =>
+            int(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Int16),
+        GGUFMetadataValueType::UInt32 =>
+            uint(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Uint32),
+        GGUFMetadataValueType::Int32 =>
+            int(&value).and_then(|v| v.try_into().ok()).map(GGUFMetadataValue::Int32),
+        GGUFMetadataValueType::UInt64 => uint(&value).map(GGUFMetadataValue::Uint64),
+        GGUFMetadataValueType::Int64 => int(&value).map(GGUFMetadataValue::Int64),
+        GGUFMetadataValueType::Float32 =>
+            float_from_json(&value).map(|v| GGUFMetadataValue::Float32(v as f32)),
+        GGUFMetadataValueType::Float64 => float_from_json(&value).map(GGUFMetadataValue::Float64),
+        GGUFMetadataValueType::Bool => value.as_bool().map(GGUFMetadataValue::Bool),
+        GGUFMetadataValueType::String =>
+            value.as_str().map(|v| GGUFMetadataValue::String(v.to_string())),
+        GGUFMetadataValueType::Array => {
+            return serde_json
+                ::from_value(value)
+                .map(GGUFMetadataValue::Array)
+                .map_err(|err| err.to_string());
+        }
+    };
+    result.ok_or_else(|| format!("invalid {} value: {}", value_type, value))
+}
+
+pub(super) fn serialize_array_values<S: Serializer>(
+    values: &[GGUFMetadataValue],
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(UntaggedValue))
+}
+
+struct UntaggedValue<'a>(&'a GGUFMetadataValue);
+
+impl Serialize for UntaggedValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            GGUFMetadataValue::Uint8(v) => v.serialize(serializer),
+            GGUFMetadataValue::Int8(v) => v.serialize(serializer),
+            GGUFMetadataValue::Uint16(v) => v.serialize(serializer),
+            GGUFMetadataValue::Int16(v) => v.serialize(serializer),
+            GGUFMetadataValue::Uint32(v) => v.serialize(serializer),
+            GGUFMetadataValue::Int32(v) => v.serialize(serializer),
+            GGUFMetadataValue::Float32(v) => serialize_float(*v, serializer),
+            GGUFMetadataValue::Uint64(v) => v.serialize(serializer),
+            GGUFMetadataValue::Int64(v) => v.serialize(serializer),
+            GGUFMetadataValue::Float64(v) => serialize_float(*v, serializer),
+            GGUFMetadataValue::Bool(v) => v.serialize(serializer),
+            GGUFMetadataValue::String(v) => v.serialize(serializer),
+            GGUFMetadataValue::Array(v) => v.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        gguf::{
+            GGMLType,
+            GGUFMetadataArrayValue,
+            GGUFMetadataValue,
+            GGUFMetadataValueType,
+            GGUF,
+        },
+        testing::tensor,
+    };
+
+    fn array(
+        value_type: GGUFMetadataValueType,
+        value: Vec<GGUFMetadataValue>
+    ) -> GGUFMetadataValue {
+        GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+            value_type,
+            len: value.len() as u64,
+            value,
+            offset: None,
+        })
+    }
+
+    #[test]
+    fn tagged_json_round_trip() {
+        let mut gguf = GGUF::new("test.gguf");
+        gguf.header.version = 3;
+        gguf.set_metadata("general.architecture", GGUFMetadataValue::String("llama".to_string()));
+        gguf.set_metadata("llama.block_count", GGUFMetadataValue::Uint32(32));
+        gguf.set_metadata("llama.context_length", GGUFMetadataValue::Uint64(8192));
+        gguf.set_metadata("llama.rope.freq_base", GGUFMetadataValue::Float32(500000.0));
+        gguf.set_metadata(
+            "llama.attention.layer_norm_rms_epsilon",
+            GGUFMetadataValue::Float32(1e-5)
+        );
+        gguf.set_metadata("tokenizer.ggml.add_bos_token", GGUFMetadataValue::Bool(true));
+        gguf.set_metadata("test.int8", GGUFMetadataValue::Int8(-3));
+        gguf.set_metadata(
+            "tokenizer.ggml.token_type",
+            array(GGUFMetadataValueType::Int32, vec![
+                GGUFMetadataValue::Int32(1),
+                GGUFMetadataValue::Int32(3),
+            ])
+        );
+        gguf.set_metadata(
+            "test.nested",
+            array(GGUFMetadataValueType::Array, vec![
+                array(GGUFMetadataValueType::UInt8, vec![GGUFMetadataValue::Uint8(7)]),
+            ])
+        );
+        gguf.set_metadata(
+            "tokenizer.ggml.tokens",
+            GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+                value_type: GGUFMetadataValueType::String,
+                len: 128256,
+                value: Vec::new(),
+                offset: Some(1024),
+            })
+        );
+        gguf.set_metadata("test.infinity", GGUFMetadataValue::Float32(f32::INFINITY));
+        gguf.set_metadata("test.nan", GGUFMetadataValue::Float64(f64::NAN));
+        gguf.set_metadata(
+            "test.floats",
+            array(GGUFMetadataValueType::Float32, vec![
+                GGUFMetadataValue::Float32(f32::NEG_INFINITY),
+                GGUFMetadataValue::Float32(f32::NAN),
+                GGUFMetadataValue::Float32(0.5),
+            ])
+        );
+        gguf.tensors.push(tensor("token_embd.weight", &[4096, 128256], GGMLType::Q4_K));
+
+        let json = serde_json::to_value(&gguf).unwrap();
+        let metadata = &json["header"]["metadata_kv"];
+        assert_eq!(metadata[1]["value"], json!({ "type": "UInt32", "value": 32 }));
+        assert_eq!(metadata[2]["value"]["type"], "UInt64");
+        assert_eq!(metadata[7]["value"]["value"]["value"], json!([1, 3]));
+        assert_eq!(metadata[9]["value"]["value"]["offset"], 1024);
+        assert_eq!(metadata[10]["value"], json!({ "type": "Float32", "value": "inf" }));
+        assert_eq!(metadata[11]["value"]["value"], "nan");
+        assert_eq!(metadata[12]["value"]["value"]["value"], json!(["-inf", "nan", 0.5]));
+        assert_eq!(json["tensors"][0]["tensor_type"], "Q4_K");
+
+        let reloaded: GGUF = serde_json::from_value(json).unwrap();
+        assert_eq!(reloaded.header.metadata_kv.len(), gguf.header.metadata_kv.len());
+        for (a, b) in gguf.header.metadata_kv.iter().zip(reloaded.header.metadata_kv.iter()) {
+            // NaN is not equal to itself, values are compared as printed.
+            assert_eq!(format!("{:?}", a.value), format!("{:?}", b.value), "{}", a.key);
+        }
+        assert_eq!(reloaded.tensors[0].dimensions, gguf.tensors[0].dimensions);
+
+        let invalid = json!({
+            "type": "Array",
+            "value": { "value_type": "UInt8", "len": 1, "value": [300] },
+        });
+        assert!(serde_json::from_value::<GGUFMetadataValue>(invalid).is_err());
+    }
+
+    #[test]
+    fn display_values() {
+        assert_eq!(GGUFMetadataValue::Uint8(7).to_string(), "7");
+        assert_eq!(GGUFMetadataValue::Int64(-42).to_string(), "-42");
+        assert_eq!(GGUFMetadataValue::Float32(1e-5).to_string(), "0.00001");
+        assert_eq!(GGUFMetadataValue::Float64(0.5).to_string(), "0.5");
+        assert_eq!(GGUFMetadataValue::Bool(true).to_string(), "true");
+        assert_eq!(GGUFMetadataValue::String("llama".to_string()).to_string(), "llama");
+
+        let tokens: Vec<GGUFMetadataValue> = (0..10)
+            .map(|i| GGUFMetadataValue::String(format!("t{}", i)))
+            .collect();
+        let tokens = array(GGUFMetadataValueType::String, tokens);
+        assert_eq!(
+            tokens.to_string(),
+            r#"["t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", ... 10 items]"#
+        );
+        assert!(format!("{:#}", tokens).ends_with(r#""t9"]"#));
+        let nested = array(GGUFMetadataValueType::Array, vec![
+            array(GGUFMetadataValueType::Int32, vec![GGUFMetadataValue::Int32(1)]),
+        ]);
+        assert_eq!(nested.to_string(), "[[1]]");
+        let lazy = GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+            value_type: GGUFMetadataValueType::String,
+            len: 128256,
+            value: Vec::new(),
+            offset: Some(1024),
+        });
+        assert_eq!(lazy.to_string(), "[String; 128256]");
+    }
+}
@@ -12,10 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{ fmt::{ self, Display, Formatter }, fs::File, io::{ BufReader, Read } };
+use std::{
+    collections::HashSet,
+    fmt::{ self, Display, Formatter },
+    fs::File,
+    io::{ Cursor, Read, Seek },
+    sync::Arc,
+};
+use memmap2::Mmap;
 use serde::{ Deserialize, Serialize };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+mod checksum;
+mod dequantize;
+mod diff;
+mod error;
+mod json;
+mod parser;
+mod quantize;
+mod split;
+mod tensor;
+mod writer;
+pub use checksum::{ sha256, GGUFChecksums, GGUFTensorChecksum, GGUFVerifyReport };
+pub use dequantize::dequantize;
+pub use diff::{ GGUFDiff, GGUFMetadataDiff, GGUFTensorDiff };
+pub use error::{ GGUFReadLimits, GgufError };
+use parser::GGUFParser;
+pub use quantize::{ quantize, GGUFQuantizeOptions, GGML_QUANTIZATION_VERSION };
+pub use split::{
+    split_path,
+    split_prefix,
+    GGUFSplitLimit,
+    GGUF_SPLIT_COUNT,
+    GGUF_SPLIT_NO,
+    GGUF_SPLIT_TENSORS_COUNT,
+};
+pub use tensor::{ align_offset, GGMLType, GGUFTensorInfo, GGUF_DEFAULT_ALIGNMENT };
+pub use writer::{ GGUF_MAGIC, GGUF_VERSION };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GGUFMetadataValueType {
     // The value is a 8-bit unsigned integer.
     UInt8 = 0,
@@ -38,7 +72,7 @@ pub enum GGUFMetadataValueType {
     // The value is a UTF-8 non-null-terminated string, with length prepended.
     String = 8,
     // The value is an array of other values, with the length and type prepended.
-    ///
+    //
     // Arrays can be nested, and the length of the array is the number of elements in the array, not the number of bytes.
     Array = 9,
     // The value is a 64-bit unsigned little-endian integer.
@@ -74,51 +108,166 @@ impl TryFrom<u32> for GGUFMetadataValueType {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(untagged)]
+impl GGUFMetadataValueType {
+    // Size in bytes of a value, None for variable length types.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            GGUFMetadataValueType::UInt8 | GGUFMetadataValueType::Int8 => Some(1),
+            GGUFMetadataValueType::UInt16 | GGUFMetadataValueType::Int16 => Some(2),
+            | GGUFMetadataValueType::UInt32
+            | GGUFMetadataValueType::Int32
+            | GGUFMetadataValueType::Float32 => Some(4),
+            GGUFMetadataValueType::Bool => Some(1),
+            | GGUFMetadataValueType::UInt64
+            | GGUFMetadataValueType::Int64
+            | GGUFMetadataValueType::Float64 => Some(8),
+            GGUFMetadataValueType::String | GGUFMetadataValueType::Array => None,
+        }
+    }
+}
+
+// Arrays longer than this are not decoded when the header is read,
+// only their offset is recorded. See GGUF::get_array_values.
+pub const GGUF_LAZY_ARRAY_LEN: u64 = 1024;
+
+// Number of elements shown when an array is displayed, the alternate format shows all of them.
+pub const GGUF_ARRAY_PREVIEW_LEN: usize = 8;
+
+// Serialized with the name of its GGUFMetadataValueType, ie {"type": "UInt32", "value": 4096},
+// so a value is reloaded with the same type. Infinite and NaN floats are the strings "inf",
+// "-inf" and "nan".
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
 pub enum GGUFMetadataValue {
+    #[serde(rename = "UInt8")]
     Uint8(u8),
     Int8(i8),
+    #[serde(rename = "UInt16")]
     Uint16(u16),
     Int16(i16),
+    #[serde(rename = "UInt32")]
     Uint32(u32),
     Int32(i32),
+    #[serde(with = "json::float32")]
     Float32(f32),
+    #[serde(rename = "UInt64")]
     Uint64(u64),
     Int64(i64),
+    #[serde(with = "json::float64")]
     Float64(f64),
     Bool(bool),
     String(String),
     Array(GGUFMetadataArrayValue),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl GGUFMetadataValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            GGUFMetadataValue::Uint8(v) => Some(*v as u64),
+            GGUFMetadataValue::Uint16(v) => Some(*v as u64),
+            GGUFMetadataValue::Uint32(v) => Some(*v as u64),
+            GGUFMetadataValue::Uint64(v) => Some(*v),
+            GGUFMetadataValue::Int8(v) => u64::try_from(*v).ok(),
+            GGUFMetadataValue::Int16(v) => u64::try_from(*v).ok(),
+            GGUFMetadataValue::Int32(v) => u64::try_from(*v).ok(),
+            GGUFMetadataValue::Int64(v) => u64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            GGUFMetadataValue::Float32(v) => Some(*v as f64),
+            GGUFMetadataValue::Float64(v) => Some(*v),
+            GGUFMetadataValue::Int8(v) => Some(*v as f64),
+            GGUFMetadataValue::Int16(v) => Some(*v as f64),
+            GGUFMetadataValue::Int32(v) => Some(*v as f64),
+            GGUFMetadataValue::Int64(v) => Some(*v as f64),
+            _ => self.as_u64().map(|v| v as f64),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GGUFMetadataValue::String(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            GGUFMetadataValue::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+// Elements are serialized without their type, it is given by value_type.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "json::GGUFMetadataArrayJson")]
 pub struct GGUFMetadataArrayValue {
     pub value_type: GGUFMetadataValueType,
     pub len: u64,
+    #[serde(serialize_with = "json::serialize_array_values")]
     pub value: Vec<GGUFMetadataValue>,
+    // Position in the file of the first element, when the values are not loaded.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub offset: Option<u64>,
+}
+
+impl GGUFMetadataArrayValue {
+    pub fn is_loaded(&self) -> bool {
+        self.offset.is_none()
+    }
 }
 
 impl Display for GGUFMetadataValue {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        let str: String = match self {
-            /* GGUFMetadataValue::Uint8(v) => v.to_string(),
-            GGUFMetadataValue::Int8(v) => v.to_string(),
-            GGUFMetadataValue::Uint16(v) => v.to_string(),
-            GGUFMetadataValue::Int16(v) => v.to_string(),
-            GGUFMetadataValue::Uint32(v) => v.to_string(),
-            GGUFMetadataValue::Int32(v) => v.to_string(),
-            GGUFMetadataValue::Float32(v) => v.to_string(),
-            GGUFMetadataValue::Uint64(v) => v.to_string(),
-            GGUFMetadataValue::Int64(v) => v.to_string(),
-            GGUFMetadataValue::Float64(v) => v.to_string(),
-            GGUFMetadataValue::Bool(v) => v.to_string(), */
-            GGUFMetadataValue::String(v) => v.to_string(),
-            GGUFMetadataValue::Array(v) => format!("[:{:?}]", v.len),
-            _ => format!("{:?}", self),
-        };
-        fmt.write_str(&str)?;
-        Ok(())
+        match self {
+            GGUFMetadataValue::Uint8(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Int8(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Uint16(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Int16(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Uint32(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Int32(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Float32(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Uint64(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Int64(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Float64(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::Bool(v) => write!(fmt, "{}", v),
+            GGUFMetadataValue::String(v) => fmt.write_str(v),
+            GGUFMetadataValue::Array(v) => Display::fmt(v, fmt),
+        }
+    }
+}
+
+// Strings are quoted in arrays, long arrays are truncated: ["a", "b", ... 32000 items].
+impl Display for GGUFMetadataArrayValue {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if !self.is_loaded() {
+            return write!(fmt, "[{}; {}]", self.value_type, self.len);
+        }
+        let count = if fmt.alternate() { self.value.len() } else { GGUF_ARRAY_PREVIEW_LEN };
+        fmt.write_str("[")?;
+        for (index, value) in self.value.iter().take(count).enumerate() {
+            if index > 0 {
+                fmt.write_str(", ")?;
+            }
+            match value {
+                GGUFMetadataValue::String(v) => write!(fmt, "{:?}", v)?,
+                _ => Display::fmt(value, fmt)?,
+            }
+        }
+        if self.value.len() > count {
+            write!(fmt, ", ... {} items", self.len)?;
+        }
+        fmt.write_str("]")
+    }
+}
+
+impl Display for GGUFMetadataValueType {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self)
     }
 }
 
@@ -135,12 +284,32 @@ pub struct GGUFHeader {
     pub tensor_count: u64,
     pub metadata_kv_count: u64,
     pub metadata_kv: Vec<GGUFMetadata>,
+    #[serde(default)]
+    pub byte_order: GGUFByteOrder,
+}
+
+// Byte order of the file, detected from the version field.
+// Big-endian files are produced for s390x, tensor data uses the same byte order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GGUFByteOrder {
+    #[default]
+    LittleEndian,
+    BigEndian,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GGUF {
     pub file_name: String,
     pub header: GGUFHeader,
+    pub tensors: Vec<GGUFTensorInfo>,
+    pub alignment: u64,
+    // Absolute position in the file of the tensor data section.
+    pub tensor_data_offset: u64,
+    #[serde(skip)]
+    mmap: Option<Arc<Mmap>>,
+    // Shards of a model read with read_split, tensor data is read from them.
+    #[serde(skip)]
+    splits: Vec<GGUF>,
 }
 
 impl GGUF {
@@ -152,169 +321,347 @@ impl GGUF {
                 tensor_count: 0,
                 metadata_kv_count: 0,
                 metadata_kv: Vec::new(),
+                byte_order: GGUFByteOrder::LittleEndian,
             },
+            tensors: Vec::new(),
+            alignment: GGUF_DEFAULT_ALIGNMENT,
+            tensor_data_offset: 0,
+            mmap: None,
+            splits: Vec::new(),
         }
     }
 
-    fn parse_metadata_value(
-        &mut self,
-        reader: &mut BufReader<File>,
-        value_type: &GGUFMetadataValueType
-    ) -> Result<GGUFMetadataValue, anyhow::Error> {
-        match value_type {
-            GGUFMetadataValueType::UInt8 => {
-                let mut value = [0; 1];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Uint8(value[0]))
-            }
-            GGUFMetadataValueType::Int8 => {
-                let mut value = [0; 1];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Int8(value[0] as i8))
-            }
-            GGUFMetadataValueType::UInt16 => {
-                let mut value = [0; 2];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Uint16(u16::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Int16 => {
-                let mut value = [0; 2];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Int16(i16::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::UInt32 => {
-                let mut value = [0; 4];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Uint32(u32::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Int32 => {
-                let mut value = [0; 4];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Int32(i32::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Float32 => {
-                let mut value = [0; 4];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Float32(f32::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Bool => {
-                let mut value = [0; 1];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Bool(value[0] != 0))
-            }
-            GGUFMetadataValueType::String => {
-                let mut value_length = [0; 8];
-                reader.read_exact(&mut value_length)?;
-                let length = u64::from_le_bytes(value_length) as usize;
-
-                let mut value = vec![0; length];
-                reader.read_exact(&mut value)?;
-                let value = String::from_utf8(value)?;
-                // println!("String length: {} value: {}", length, value);
-                Ok(GGUFMetadataValue::String(value))
-            }
-            GGUFMetadataValueType::Array => {
-                let mut value_type = [0; 4];
-                reader.read_exact(&mut value_type)?;
-                let value_type = u32::from_le_bytes(value_type);
-                let value_type = GGUFMetadataValueType::try_from(value_type).map_err(|err|
-                    anyhow::Error::msg(err.to_string())
-                )?;
+    pub fn get_metadata_value(&self, key: &str) -> Option<&GGUFMetadataValue> {
+        self.header.metadata_kv
+            .iter()
+            .find(|m| m.key == key)
+            .map(|m| &m.value)
+    }
 
-                let mut value_length = [0; 8];
-                reader.read_exact(&mut value_length)?;
-                let length = u64::from_le_bytes(value_length) as usize;
-                // println!("Array length: {} type: {:?}", length, value_type);
-                let mut value = Vec::new();
-                for _ in 0..length {
-                    value.push(self.parse_metadata_value(reader, &value_type)?);
-                }
-
-                Ok(
-                    GGUFMetadataValue::Array(GGUFMetadataArrayValue {
-                        value_type,
-                        len: length as u64,
-                        value,
-                    })
-                )
-            }
-            GGUFMetadataValueType::UInt64 => {
-                let mut value = [0; 8];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Uint64(u64::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Int64 => {
-                let mut value = [0; 8];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Int64(i64::from_le_bytes(value)))
-            }
-            GGUFMetadataValueType::Float64 => {
-                let mut value = [0; 8];
-                reader.read_exact(&mut value)?;
-                Ok(GGUFMetadataValue::Float64(f64::from_le_bytes(value)))
+    pub fn get_metadata_u64(&self, key: &str) -> Option<u64> {
+        self.get_metadata_value(key).and_then(|v| v.as_u64())
+    }
+
+    pub fn get_metadata_f64(&self, key: &str) -> Option<f64> {
+        self.get_metadata_value(key).and_then(|v| v.as_f64())
+    }
+
+    pub fn get_metadata_string(&self, key: &str) -> Option<&str> {
+        self.get_metadata_value(key).and_then(|v| v.as_str())
+    }
+
+    pub fn get_architecture(&self) -> Option<&str> {
+        self.get_metadata_string("general.architecture")
+    }
+
+    pub fn get_tensor_info(&self, name: &str) -> Option<&GGUFTensorInfo> {
+        self.tensors.iter().find(|t| t.name == name)
+    }
+
+    // Values of an array, decoded from the mapped file if they were not loaded.
+    pub fn get_array_values(
+        &self,
+        array: &GGUFMetadataArrayValue
+    ) -> Result<Vec<GGUFMetadataValue>, GgufError> {
+        if array.is_loaded() {
+            return Ok(array.value.clone());
+        }
+        self.read_array_values_from(Cursor::new(self.get_mapped_data()?), array)
+    }
+
+    // Values of an array not loaded, decoded from the source the header was read from.
+    pub fn read_array_values_from<R: Read + Seek>(
+        &self,
+        reader: R,
+        array: &GGUFMetadataArrayValue
+    ) -> Result<Vec<GGUFMetadataValue>, GgufError> {
+        let offset = match array.offset {
+            Some(offset) => offset,
+            None => {
+                return Ok(array.value.clone());
             }
+        };
+        let limits = GGUFReadLimits::default();
+        let mut parser = GGUFParser::new(reader, &limits)?;
+        parser.set_layout(self.header.version, self.header.byte_order);
+        parser.seek(offset)?;
+        parser.read_array_values(&array.value_type, array.len, false, 1)
+    }
+
+    pub fn get_metadata_array(&self, key: &str) -> Result<Vec<GGUFMetadataValue>, GgufError> {
+        match self.get_metadata_value(key) {
+            Some(GGUFMetadataValue::Array(array)) => self.get_array_values(array),
+            Some(value) =>
+                Err(GgufError::invalid_value(0, format!("{} is not an array: {}", key, value))),
+            None => Err(GgufError::MissingKey { key: key.to_string() }),
         }
     }
 
-    fn parse_metadata_kv(&mut self, reader: &mut BufReader<File>) -> Result<(), anyhow::Error> {
-        let mut key_length = [0; 8];
-        let mut value_type = [0; 4];
-        for _ in 0..self.header.metadata_kv_count {
-            reader.read_exact(&mut key_length)?;
-            let length = u64::from_le_bytes(key_length) as usize;
+    fn get_mapped_data(&self) -> Result<&[u8], GgufError> {
+        match self.mmap {
+            Some(ref mmap) => Ok(&mmap[..]),
+            None =>
+                Err(GgufError::NotLoaded {
+                    message: format!("file not mapped {}", self.file_name),
+                }),
+        }
+    }
 
-            let mut key = vec![0; length];
-            reader.read_exact(&mut key)?;
-            let key = String::from_utf8(key)?;
+    // Borrowed tensor data from the mapped file, no copy is made.
+    pub fn get_tensor_data(&self, tensor: &GGUFTensorInfo) -> Result<&[u8], GgufError> {
+        if !self.splits.is_empty() {
+            return self.get_split_tensor_data(&tensor.name);
+        }
+        let data = self.get_mapped_data()?;
+        let start = self.tensor_data_offset.saturating_add(tensor.offset);
+        let end = start.saturating_add(tensor.size());
+        if end > (data.len() as u64) {
+            return Err(GgufError::Truncated { offset: data.len() as u64 });
+        }
+        Ok(&data[start as usize..end as usize])
+    }
 
-            reader.read_exact(&mut value_type)?;
-            let value_type = u32::from_le_bytes(value_type);
-            let value_type = GGUFMetadataValueType::try_from(value_type).map_err(|err|
-                anyhow::Error::msg(err.to_string())
-            )?;
+    // Total size in bytes of the tensors' data.
+    pub fn tensors_size(&self) -> u64 {
+        self.tensors
+            .iter()
+            .map(|t| t.size())
+            .sum()
+    }
 
-            let value = self.parse_metadata_value(reader, &value_type)?;
+    fn read_alignment(&self) -> Result<u64, anyhow::Error> {
+        match self.get_metadata_value("general.alignment") {
+            Some(value) => parse_alignment(value).map_err(anyhow::Error::msg),
+            None => Ok(GGUF_DEFAULT_ALIGNMENT),
+        }
+    }
 
-            println!("{}={}", key, value);
+    // Reads the header and tensor infos from any source, tensor data is not read.
+    // Arrays not loaded can be decoded later with read_array_values_from.
+    pub fn read_from<R: Read + Seek>(
+        &mut self,
+        reader: R,
+        limits: &GGUFReadLimits
+    ) -> Result<(), GgufError> {
+        let mut parser = GGUFParser::new(reader, limits)?;
+        self.header = parser.read_header()?;
+        self.alignment = GGUF_DEFAULT_ALIGNMENT;
+
+        let mut keys = HashSet::new();
+        for _ in 0..self.header.metadata_kv_count {
+            let offset = parser.position();
+            let metadata = parser.read_metadata_kv()?;
+            if !keys.insert(metadata.key.clone()) {
+                return Err(
+                    GgufError::invalid_value(offset, format!("duplicate key {}", metadata.key))
+                );
+            }
+            if metadata.key == "general.alignment" {
+                self.alignment = parse_alignment(&metadata.value).map_err(|message|
+                    GgufError::invalid_value(offset, message)
+                )?;
+            }
+            self.header.metadata_kv.push(metadata);
+        }
 
-            self.header.metadata_kv.push(GGUFMetadata {
-                key,
-                value,
-                value_type,
-            });
+        let mut names = HashSet::new();
+        let mut positions = Vec::new();
+        self.tensors = Vec::new();
+        for _ in 0..self.header.tensor_count {
+            let offset = parser.position();
+            let tensor = parser.read_tensor_info()?;
+            if !names.insert(tensor.name.clone()) {
+                return Err(
+                    GgufError::invalid_value(offset, format!("duplicate tensor {}", tensor.name))
+                );
+            }
+            positions.push(offset);
+            self.tensors.push(tensor);
+        }
+
+        self.tensor_data_offset = align_offset(parser.position(), self.alignment);
+        for (tensor, offset) in self.tensors.iter().zip(positions) {
+            if tensor.offset % self.alignment != 0 {
+                return Err(
+                    GgufError::invalid_value(
+                        offset,
+                        format!("tensor {} offset {} is not aligned", tensor.name, tensor.offset)
+                    )
+                );
+            }
+            let end = self.tensor_data_offset
+                .checked_add(tensor.offset)
+                .and_then(|start| start.checked_add(tensor.size()));
+            if end.is_none_or(|end| end > parser.len()) {
+                return Err(GgufError::Truncated { offset: parser.len() });
+            }
         }
         Ok(())
     }
 
-    pub fn read(&mut self, path: &str) -> Result<(), String> {
-        println!("Reading GGUF file: {}", path);
-
-        let input = File::open(path).map_err(|err| err.to_string())?;
-        let mut reader = BufReader::new(input);
+    pub fn read(&mut self, path: &str) -> Result<(), GgufError> {
+        self.read_with_limits(path, &GGUFReadLimits::default())
+    }
 
-        let mut header = [0; 8];
-        reader.read_exact(&mut header).map_err(|err| err.to_string())?;
+    pub fn read_with_limits(&mut self, path: &str, limits: &GGUFReadLimits) -> Result<(), GgufError> {
+        let input = File::open(path).map_err(|err| GgufError::io(0, err))?;
+        // Safety: the file is only read, and is expected not to be modified while mapped.
+        let mmap = Arc::new(unsafe { Mmap::map(&input) }.map_err(|err| GgufError::io(0, err))?);
+        self.read_from(Cursor::new(&mmap[..]), limits)?;
+        self.mmap = Some(mmap);
+        Ok(())
+    }
+}
 
-        if header[0] != 0x47 && header[1] != 0x47 && header[2] != 0x55 && header[3] != 0x46 {
-            return Err("Not valid GGUF".to_string());
-        }
+#[cfg(feature = "http")]
+impl GGUF {
+    // Reads the header of a remote file using HTTP range requests,
+    // only the header and tensor infos are downloaded.
+    pub fn read_url(&mut self, url: &str) -> Result<(), GgufError> {
+        let reader = crate::http::HttpRangeReader::new(url).map_err(|err| GgufError::io(0, err))?;
+        self.read_from(reader, &GGUFReadLimits::default())
+    }
+}
 
-        // Models are little-endian by default.
-        self.header.version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
-        println!("Version: {}", self.header.version);
+fn parse_alignment(value: &GGUFMetadataValue) -> Result<u64, String> {
+    match value.as_u64() {
+        Some(alignment) if alignment.is_power_of_two() => Ok(alignment),
+        _ => Err(format!("invalid general.alignment {}", value)),
+    }
+}
 
-        reader.read_exact(&mut header).map_err(|err| err.to_string())?;
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        gguf::{
+            GGMLType,
+            GGUFMetadata,
+            GGUFMetadataArrayValue,
+            GGUFMetadataValue,
+            GGUFMetadataValueType,
+            GGUFTensorInfo,
+            GgufError,
+            GGUF,
+            GGUF_LAZY_ARRAY_LEN,
+        },
+        testing::{ string_bytes, temp_path },
+    };
+
+    // A v3 file with a long string array, a long u32 array, a short array
+    // and a single F32 [8] tensor whose data is 0..32.
+    fn create_file(tokens: u64) -> Vec<u8> {
+        let mut data = b"GGUF".to_vec();
+        data.extend(3u32.to_le_bytes());
+        data.extend(1u64.to_le_bytes());
+        data.extend(3u64.to_le_bytes());
+        data.extend(string_bytes("tokenizer.ggml.tokens"));
+        data.extend(9u32.to_le_bytes());
+        data.extend(8u32.to_le_bytes());
+        data.extend(tokens.to_le_bytes());
+        for i in 0..tokens {
+            data.extend(string_bytes(&format!("token{}", i)));
+        }
+        data.extend(string_bytes("tokenizer.ggml.token_type"));
+        data.extend(9u32.to_le_bytes());
+        data.extend(4u32.to_le_bytes());
+        data.extend(tokens.to_le_bytes());
+        for i in 0..tokens {
+            data.extend((i as u32).to_le_bytes());
+        }
+        data.extend(string_bytes("tokenizer.ggml.merges"));
+        data.extend(9u32.to_le_bytes());
+        data.extend(8u32.to_le_bytes());
+        data.extend(1u64.to_le_bytes());
+        data.extend(string_bytes("t o"));
+        data.extend(string_bytes("output.weight"));
+        data.extend(1u32.to_le_bytes());
+        data.extend(8u64.to_le_bytes());
+        data.extend((GGMLType::F32 as u32).to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.resize(data.len().next_multiple_of(32), 0);
+        data.extend(0..32u8);
+        data
+    }
 
-        self.header.tensor_count = u64::from_le_bytes(header);
-        println!("Tensor_count: {}", self.header.tensor_count);
+    fn read(name: &str, data: &[u8]) -> GGUF {
+        let path = temp_path(name);
+        fs::write(&path, data).unwrap();
+        let mut gguf = GGUF::new(path.to_str().unwrap());
+        gguf.read(path.to_str().unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+        gguf
+    }
 
-        reader.read_exact(&mut header).map_err(|err| err.to_string())?;
+    #[test]
+    fn lazy_arrays() {
+        let tokens = GGUF_LAZY_ARRAY_LEN + 6;
+        let gguf = read("lazy-arrays", &create_file(tokens));
+        match gguf.get_metadata_value("tokenizer.ggml.tokens") {
+            Some(GGUFMetadataValue::Array(array)) => {
+                assert!(!array.is_loaded());
+                assert!(array.value.is_empty());
+                assert_eq!(array.len, tokens);
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
 
-        self.header.metadata_kv_count = u64::from_le_bytes(header);
-        println!("Metadata_kv_count: {}", self.header.metadata_kv_count);
+        let values = gguf.get_metadata_array("tokenizer.ggml.tokens").unwrap();
+        assert_eq!(values.len() as u64, tokens);
+        assert_eq!(values[0].to_string(), "token0");
+        assert_eq!(values[1029].to_string(), "token1029");
+        let values = gguf.get_metadata_array("tokenizer.ggml.token_type").unwrap();
+        assert!(matches!(values[1029], GGUFMetadataValue::Uint32(1029)));
+
+        // Short arrays are decoded when the header is read.
+        match gguf.get_metadata_value("tokenizer.ggml.merges") {
+            Some(GGUFMetadataValue::Array(array)) => assert!(array.is_loaded()),
+            value => panic!("unexpected value {:?}", value),
+        }
+        assert_eq!(gguf.get_metadata_array("tokenizer.ggml.merges").unwrap().len(), 1);
+        assert_eq!(gguf.tensors.len(), 1);
+    }
 
-        self.parse_metadata_kv(&mut reader).map_err(|err| err.to_string())?;
+    #[test]
+    fn tensor_data() {
+        let gguf = read("tensor-data", &create_file(4));
+        let tensor = gguf.get_tensor_info("output.weight").unwrap();
+        assert_eq!(gguf.get_tensor_data(tensor).unwrap(), (0..32u8).collect::<Vec<u8>>());
+
+        let mut tensor = tensor.clone();
+        tensor.offset = 4;
+        assert!(matches!(gguf.get_tensor_data(&tensor), Err(GgufError::Truncated { .. })));
+        tensor.offset = u64::MAX;
+        assert!(matches!(gguf.get_tensor_data(&tensor), Err(GgufError::Truncated { .. })));
+    }
 
-        Ok(())
+    #[test]
+    fn not_mapped() {
+        let mut gguf = GGUF::new("test.gguf");
+        gguf.header.metadata_kv.push(GGUFMetadata {
+            key: "tokenizer.ggml.tokens".to_string(),
+            value_type: GGUFMetadataValueType::Array,
+            value: GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+                value_type: GGUFMetadataValueType::String,
+                len: GGUF_LAZY_ARRAY_LEN + 1,
+                value: Vec::new(),
+                offset: Some(24),
+            }),
+        });
+        gguf.tensors.push(GGUFTensorInfo {
+            name: "output.weight".to_string(),
+            n_dimensions: 1,
+            dimensions: vec![8],
+            tensor_type: GGMLType::F32,
+            offset: 0,
+        });
+        assert!(
+            matches!(
+                gguf.get_metadata_array("tokenizer.ggml.tokens"),
+                Err(GgufError::NotLoaded { .. })
+            )
+        );
+        assert!(matches!(gguf.get_tensor_data(&gguf.tensors[0]), Err(GgufError::NotLoaded { .. })));
     }
 }
@@ -11,3 +11,714 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+use std::io::{ Read, Seek, SeekFrom };
+
+use super::{
+    error::{ GGUFReadLimits, GgufError },
+    GGMLType,
+    GGUFByteOrder,
+    GGUFHeader,
+    GGUFMetadata,
+    GGUFMetadataArrayValue,
+    GGUFMetadataValue,
+    GGUFMetadataValueType,
+    GGUFTensorInfo,
+    GGUF_LAZY_ARRAY_LEN,
+    GGUF_MAGIC,
+    GGUF_VERSION,
+};
+
+// Low level reader of the GGUF binary layout, every read is bounds and limits checked.
+// The layout depends on the version and byte order found in the header:
+// v1 uses 32-bit lengths, counts and dimensions, v2 and v3 use 64-bit ones.
+pub(crate) struct GGUFParser<'a, R: Read + Seek> {
+    reader: R,
+    len: u64,
+    position: u64,
+    limits: &'a GGUFReadLimits,
+    version: u32,
+    byte_order: GGUFByteOrder,
+}
+
+macro_rules! read_number {
+    ($name:ident, $type:ty) => {
+        pub fn $name(&mut self) -> Result<$type, GgufError> {
+            let bytes = self.read_array()?;
+            Ok(match self.byte_order {
+                GGUFByteOrder::LittleEndian => <$type>::from_le_bytes(bytes),
+                GGUFByteOrder::BigEndian => <$type>::from_be_bytes(bytes),
+            })
+        }
+    };
+}
+
+impl<'a, R: Read + Seek> GGUFParser<'a, R> {
+    pub fn new(mut reader: R, limits: &'a GGUFReadLimits) -> Result<Self, GgufError> {
+        let len = reader.seek(SeekFrom::End(0)).map_err(|err| GgufError::io(0, err))?;
+        reader.seek(SeekFrom::Start(0)).map_err(|err| GgufError::io(0, err))?;
+        Ok(GGUFParser {
+            reader,
+            len,
+            position: 0,
+            limits,
+            version: GGUF_VERSION,
+            byte_order: GGUFByteOrder::LittleEndian,
+        })
+    }
+
+    // Layout used to decode values, set by read_header.
+    pub fn set_layout(&mut self, version: u32, byte_order: GGUFByteOrder) {
+        self.version = version;
+        self.byte_order = byte_order;
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len.saturating_sub(self.position)
+    }
+
+    pub fn seek(&mut self, offset: u64) -> Result<(), GgufError> {
+        if offset > self.len {
+            return Err(GgufError::Truncated { offset: self.len });
+        }
+        self.reader.seek(SeekFrom::Start(offset)).map_err(|err| GgufError::io(offset, err))?;
+        self.position = offset;
+        Ok(())
+    }
+
+    fn skip(&mut self, length: u64) -> Result<(), GgufError> {
+        if length > self.remaining() {
+            return Err(GgufError::Truncated { offset: self.len });
+        }
+        self.seek(self.position + length)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], GgufError> {
+        let offset = self.position;
+        let mut value = [0; N];
+        self.reader.read_exact(&mut value).map_err(|err| GgufError::io(offset, err))?;
+        self.position += N as u64;
+        Ok(value)
+    }
+
+    fn read_bytes(&mut self, length: u64) -> Result<Vec<u8>, GgufError> {
+        let offset = self.position;
+        // Checked before allocating, a hostile length can't exceed the file size.
+        if length > self.remaining() {
+            return Err(GgufError::Truncated { offset });
+        }
+        let mut value = vec![0; length as usize];
+        self.reader.read_exact(&mut value).map_err(|err| GgufError::io(offset, err))?;
+        self.position += length;
+        Ok(value)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, GgufError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    read_number!(read_u16, u16);
+    read_number!(read_i16, i16);
+    read_number!(read_u32, u32);
+    read_number!(read_i32, i32);
+    read_number!(read_f32, f32);
+    read_number!(read_u64, u64);
+    read_number!(read_i64, i64);
+    read_number!(read_f64, f64);
+
+    // Lengths, counts and dimensions, 32-bit in v1.
+    fn read_size(&mut self) -> Result<u64, GgufError> {
+        if self.version == 1 { Ok(self.read_u32()? as u64) } else { self.read_u64() }
+    }
+
+    // Smallest encoded size of a value, used to reject arrays that can't fit in the file.
+    fn minimum_size(&self, value_type: &GGUFMetadataValueType) -> u64 {
+        let size = if self.version == 1 { 4 } else { 8 };
+        match value_type {
+            GGUFMetadataValueType::String => size,
+            // Type and length of the nested array.
+            GGUFMetadataValueType::Array => 4 + size,
+            _ => value_type.size().unwrap_or_default(),
+        }
+    }
+
+    // Reads a length prefix and checks it against a limit.
+    fn read_length(&mut self, kind: &'static str, limit: u64) -> Result<u64, GgufError> {
+        let offset = self.position();
+        let length = self.read_size()?;
+        if length > limit {
+            return Err(GgufError::OversizedLength { offset, kind, length, limit });
+        }
+        Ok(length)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, GgufError> {
+        let length = self.read_length("string", self.limits.max_string_length)?;
+        let offset = self.position();
+        let value = self.read_bytes(length)?;
+        String::from_utf8(value).map_err(|_| GgufError::InvalidUtf8 { offset })
+    }
+
+    fn read_value_type(&mut self) -> Result<GGUFMetadataValueType, GgufError> {
+        let offset = self.position();
+        let value = self.read_u32()?;
+        GGUFMetadataValueType::try_from(value).map_err(|_| GgufError::InvalidType {
+            offset,
+            kind: "metadata value",
+            value,
+        })
+    }
+
+    pub fn read_header(&mut self) -> Result<GGUFHeader, GgufError> {
+        let magic: [u8; 4] = self.read_array()?;
+        if magic != GGUF_MAGIC {
+            return Err(GgufError::BadMagic { offset: 0, magic });
+        }
+
+        // The version is small, a file written on a big-endian host has the
+        // low bytes of the little-endian value set to zero.
+        let offset = self.position();
+        let bytes: [u8; 4] = self.read_array()?;
+        let (version, byte_order) = match u32::from_le_bytes(bytes) {
+            version if (version & 0xffff) != 0 => (version, GGUFByteOrder::LittleEndian),
+            _ => (u32::from_be_bytes(bytes), GGUFByteOrder::BigEndian),
+        };
+        if !(1..=GGUF_VERSION).contains(&version) {
+            return Err(GgufError::UnsupportedVersion { offset, version });
+        }
+        self.set_layout(version, byte_order);
+
+        let offset = self.position();
+        let tensor_count = self.read_size()?;
+        if tensor_count > self.limits.max_tensor_count {
+            return Err(GgufError::OversizedLength {
+                offset,
+                kind: "tensor count",
+                length: tensor_count,
+                limit: self.limits.max_tensor_count,
+            });
+        }
+
+        let offset = self.position();
+        let metadata_kv_count = self.read_size()?;
+        if metadata_kv_count > self.limits.max_metadata_kv_count {
+            return Err(GgufError::OversizedLength {
+                offset,
+                kind: "metadata count",
+                length: metadata_kv_count,
+                limit: self.limits.max_metadata_kv_count,
+            });
+        }
+
+        Ok(GGUFHeader {
+            version,
+            tensor_count,
+            metadata_kv_count,
+            metadata_kv: Vec::new(),
+            byte_order,
+        })
+    }
+
+    pub fn read_metadata_kv(&mut self) -> Result<GGUFMetadata, GgufError> {
+        let key = self.read_string()?;
+        let value_type = self.read_value_type()?;
+        let value = self.read_metadata_value(&value_type, true, 0)?;
+        Ok(GGUFMetadata {
+            key,
+            value_type,
+            value,
+        })
+    }
+
+    pub fn read_metadata_value(
+        &mut self,
+        value_type: &GGUFMetadataValueType,
+        lazy: bool,
+        depth: u32
+    ) -> Result<GGUFMetadataValue, GgufError> {
+        let value = match value_type {
+            GGUFMetadataValueType::UInt8 => GGUFMetadataValue::Uint8(self.read_u8()?),
+            GGUFMetadataValueType::Int8 => GGUFMetadataValue::Int8(self.read_u8()? as i8),
+            GGUFMetadataValueType::UInt16 => GGUFMetadataValue::Uint16(self.read_u16()?),
+            GGUFMetadataValueType::Int16 => GGUFMetadataValue::Int16(self.read_i16()?),
+            GGUFMetadataValueType::UInt32 => GGUFMetadataValue::Uint32(self.read_u32()?),
+            GGUFMetadataValueType::Int32 => GGUFMetadataValue::Int32(self.read_i32()?),
+            GGUFMetadataValueType::Float32 => GGUFMetadataValue::Float32(self.read_f32()?),
+            GGUFMetadataValueType::Bool => {
+                let offset = self.position();
+                match self.read_u8()? {
+                    0 => GGUFMetadataValue::Bool(false),
+                    1 => GGUFMetadataValue::Bool(true),
+                    value => {
+                        return Err(
+                            GgufError::invalid_value(offset, format!("invalid bool {}", value))
+                        );
+                    }
+                }
+            }
+            GGUFMetadataValueType::String => GGUFMetadataValue::String(self.read_string()?),
+            GGUFMetadataValueType::Array => {
+                return self.read_metadata_array(lazy, depth + 1);
+            }
+            GGUFMetadataValueType::UInt64 => GGUFMetadataValue::Uint64(self.read_u64()?),
+            GGUFMetadataValueType::Int64 => GGUFMetadataValue::Int64(self.read_i64()?),
+            GGUFMetadataValueType::Float64 => GGUFMetadataValue::Float64(self.read_f64()?),
+        };
+        Ok(value)
+    }
+
+    fn read_array_header(
+        &mut self,
+        depth: u32
+    ) -> Result<(GGUFMetadataValueType, u64), GgufError> {
+        if depth > self.limits.max_array_depth {
+            return Err(GgufError::OversizedLength {
+                offset: self.position(),
+                kind: "array depth",
+                length: depth as u64,
+                limit: self.limits.max_array_depth as u64,
+            });
+        }
+        let value_type = self.read_value_type()?;
+        let offset = self.position();
+        let length = self.read_length("array", self.limits.max_array_length)?;
+        // Each element uses at least one byte, a longer array can't fit in the file.
+        if length > self.remaining() {
+            return Err(GgufError::Truncated { offset });
+        }
+        Ok((value_type, length))
+    }
+
+    fn read_metadata_array(
+        &mut self,
+        lazy: bool,
+        depth: u32
+    ) -> Result<GGUFMetadataValue, GgufError> {
+        let (value_type, length) = self.read_array_header(depth)?;
+        if lazy && length > GGUF_LAZY_ARRAY_LEN {
+            let offset = self.position();
+            self.skip_metadata_array(&value_type, length, depth)?;
+            return Ok(
+                GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+                    value_type,
+                    len: length,
+                    value: Vec::new(),
+                    offset: Some(offset),
+                })
+            );
+        }
+        let value = self.read_array_values(&value_type, length, lazy, depth)?;
+        Ok(
+            GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+                value_type,
+                len: length,
+                value,
+                offset: None,
+            })
+        )
+    }
+
+    pub fn read_array_values(
+        &mut self,
+        value_type: &GGUFMetadataValueType,
+        length: u64,
+        lazy: bool,
+        depth: u32
+    ) -> Result<Vec<GGUFMetadataValue>, GgufError> {
+        let offset = self.position();
+        if length > self.limits.max_array_length {
+            return Err(GgufError::OversizedLength {
+                offset,
+                kind: "array",
+                length,
+                limit: self.limits.max_array_length,
+            });
+        }
+        if length.saturating_mul(self.minimum_size(value_type)) > self.remaining() {
+            return Err(GgufError::Truncated { offset });
+        }
+        let mut values = Vec::with_capacity(length as usize);
+        for _ in 0..length {
+            values.push(self.read_metadata_value(value_type, lazy, depth)?);
+        }
+        Ok(values)
+    }
+
+    fn skip_metadata_array(
+        &mut self,
+        value_type: &GGUFMetadataValueType,
+        length: u64,
+        depth: u32
+    ) -> Result<(), GgufError> {
+        match value_type {
+            GGUFMetadataValueType::String => {
+                for _ in 0..length {
+                    let length = self.read_length("string", self.limits.max_string_length)?;
+                    self.skip(length)?;
+                }
+            }
+            GGUFMetadataValueType::Array => {
+                for _ in 0..length {
+                    let (value_type, length) = self.read_array_header(depth + 1)?;
+                    self.skip_metadata_array(&value_type, length, depth + 1)?;
+                }
+            }
+            _ => {
+                let size = value_type.size().unwrap_or_default();
+                let offset = self.position();
+                let length = length
+                    .checked_mul(size)
+                    .ok_or(GgufError::Truncated { offset })?;
+                self.skip(length)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_tensor_info(&mut self) -> Result<GGUFTensorInfo, GgufError> {
+        let name = self.read_string()?;
+
+        let offset = self.position();
+        let n_dimensions = self.read_u32()?;
+        if n_dimensions > self.limits.max_dimensions {
+            return Err(GgufError::OversizedLength {
+                offset,
+                kind: "tensor dimensions",
+                length: n_dimensions as u64,
+                limit: self.limits.max_dimensions as u64,
+            });
+        }
+        let mut dimensions = Vec::with_capacity(n_dimensions as usize);
+        for _ in 0..n_dimensions {
+            dimensions.push(self.read_size()?);
+        }
+
+        let offset = self.position();
+        let value = self.read_u32()?;
+        let tensor_type = GGMLType::try_from(value).map_err(|_| GgufError::InvalidType {
+            offset,
+            kind: "tensor",
+            value,
+        })?;
+
+        let element_count = dimensions
+            .iter()
+            .try_fold(1u64, |count, dimension| count.checked_mul(*dimension))
+            .filter(|count| *count <= (i64::MAX as u64));
+        if element_count.is_none() {
+            return Err(GgufError::invalid_value(offset, format!("tensor {} is too large", name)));
+        }
+        let block_size = tensor_type.block_size();
+        if dimensions.first().is_some_and(|dimension| dimension % block_size != 0) {
+            return Err(
+                GgufError::invalid_value(
+                    offset,
+                    format!(
+                        "tensor {} first dimension is not a multiple of block size {}",
+                        name,
+                        block_size
+                    )
+                )
+            );
+        }
+
+        let offset = self.read_u64()?;
+        Ok(GGUFTensorInfo {
+            name,
+            n_dimensions,
+            dimensions,
+            tensor_type,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ borrow::Cow, io::Cursor };
+
+    use crate::{
+        gguf::{ GGMLType, GGUFByteOrder, GGUFMetadataValue, GGUFReadLimits, GgufError, GGUF },
+        testing::{ create_model, tensor },
+    };
+
+    // A small valid file, mutated by the tests below.
+    fn create_file() -> Vec<u8> {
+        create_file_with(3, GGUFByteOrder::LittleEndian)
+    }
+
+    fn create_file_with(version: u32, byte_order: GGUFByteOrder) -> Vec<u8> {
+        let mut gguf = create_model(
+            "llama",
+            &[
+                ("llama.context_length", GGUFMetadataValue::Uint32(2048)),
+                ("tokenizer.ggml.add_bos_token", GGUFMetadataValue::Bool(true)),
+            ],
+            &[tensor("output.weight", &[32, 2], GGMLType::Q8_0)]
+        );
+        gguf.header.version = version;
+        gguf.header.byte_order = byte_order;
+        let mut data = Vec::new();
+        gguf.write_to(&mut data, |tensor| Ok(Cow::Owned(vec![1; tensor.size() as usize]))).unwrap();
+        data
+    }
+
+    // v1 files can't be written anymore, lengths, counts and dimensions are 32-bit.
+    fn create_v1_file(byte_order: GGUFByteOrder) -> Vec<u8> {
+        let u32_bytes = |value: u32| match byte_order {
+            GGUFByteOrder::LittleEndian => value.to_le_bytes(),
+            GGUFByteOrder::BigEndian => value.to_be_bytes(),
+        };
+        let string_bytes = |value: &str| [&u32_bytes(value.len() as u32)[..], value.as_bytes()].concat();
+        let mut data = b"GGUF".to_vec();
+        data.extend(u32_bytes(1));
+        data.extend(u32_bytes(1));
+        data.extend(u32_bytes(3));
+        data.extend(string_bytes("general.architecture"));
+        data.extend(u32_bytes(8));
+        data.extend(string_bytes("llama"));
+        data.extend(string_bytes("llama.context_length"));
+        data.extend(u32_bytes(4));
+        data.extend(u32_bytes(2048));
+        // Strings are at least 4 bytes long in v1, the array ends close to the end of the file.
+        data.extend(string_bytes("tokenizer.ggml.merges"));
+        data.extend(u32_bytes(9));
+        data.extend(u32_bytes(8));
+        data.extend(u32_bytes(100));
+        for _ in 0..100 {
+            data.extend(string_bytes(""));
+        }
+        data.extend(string_bytes("output.weight"));
+        data.extend(u32_bytes(2));
+        data.extend(u32_bytes(32));
+        data.extend(u32_bytes(2));
+        data.extend(u32_bytes(GGMLType::Q8_0 as u32));
+        data.extend([0; 8]);
+        data.resize(data.len().next_multiple_of(32), 0);
+        data.extend([1; 68]);
+        data
+    }
+
+    fn parse(data: &[u8]) -> Result<GGUF, GgufError> {
+        let mut gguf = GGUF::new("test.gguf");
+        gguf.read_from(Cursor::new(data), &GGUFReadLimits::default())?;
+        Ok(gguf)
+    }
+
+    fn find(data: &[u8], pattern: &[u8]) -> usize {
+        data.windows(pattern.len())
+            .position(|window| window == pattern)
+            .unwrap()
+    }
+
+    #[test]
+    fn valid_file() {
+        let gguf = parse(&create_file()).unwrap();
+        assert_eq!(gguf.header.metadata_kv.len(), 3);
+        assert_eq!(gguf.tensors.len(), 1);
+    }
+
+    #[test]
+    fn version_1_files() {
+        for byte_order in [GGUFByteOrder::LittleEndian, GGUFByteOrder::BigEndian] {
+            let data = create_v1_file(byte_order);
+            let gguf = parse(&data).unwrap();
+            assert_eq!(gguf.header.version, 1);
+            assert_eq!(gguf.header.byte_order, byte_order);
+            assert_eq!(gguf.get_architecture(), Some("llama"));
+            assert_eq!(gguf.get_metadata_u64("llama.context_length"), Some(2048));
+            assert_eq!(gguf.tensors[0].dimensions, vec![32, 2]);
+            assert_eq!(gguf.tensor_data_offset + gguf.tensors_size(), data.len() as u64);
+
+            // Written back using the current layout, in the same byte order.
+            let mut upgraded = Vec::new();
+            gguf.write_to(&mut upgraded, |tensor| Ok(Cow::Owned(vec![1; tensor.size() as usize]))).unwrap();
+            let upgraded = parse(&upgraded).unwrap();
+            assert_eq!(upgraded.header.version, 3);
+            assert_eq!(upgraded.header.byte_order, byte_order);
+            assert_eq!(upgraded.get_metadata_u64("llama.context_length"), Some(2048));
+        }
+    }
+
+    #[test]
+    fn version_2_and_3_files() {
+        let little_endian = parse(&create_file()).unwrap();
+        for (version, byte_order) in [
+            (2, GGUFByteOrder::LittleEndian),
+            (2, GGUFByteOrder::BigEndian),
+            (3, GGUFByteOrder::BigEndian),
+        ] {
+            let data = create_file_with(version, byte_order);
+            let gguf = parse(&data).unwrap();
+            assert_eq!(gguf.header.version, version);
+            assert_eq!(gguf.header.byte_order, byte_order);
+            assert_eq!(gguf.tensors[0].dimensions, little_endian.tensors[0].dimensions);
+            for (metadata, expected) in gguf.header.metadata_kv
+                .iter()
+                .zip(little_endian.header.metadata_kv.iter()) {
+                assert_eq!(metadata.key, expected.key);
+                assert_eq!(metadata.value.to_string(), expected.value.to_string());
+            }
+
+            let mut written = Vec::new();
+            gguf.write_to(&mut written, |tensor| Ok(Cow::Owned(vec![1; tensor.size() as usize]))).unwrap();
+            assert_eq!(written, data);
+        }
+        assert_eq!(&create_file_with(3, GGUFByteOrder::BigEndian)[4..8], &[0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut data = create_file();
+        data[3] = b'X';
+        assert!(matches!(parse(&data), Err(GgufError::BadMagic { offset: 0, .. })));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut data = create_file();
+        data[4] = 42;
+        assert!(matches!(parse(&data), Err(GgufError::UnsupportedVersion { offset: 4, version: 42 })));
+    }
+
+    #[test]
+    fn oversized_string_length() {
+        let mut data = create_file();
+        // Length prefix of the first key
+        data[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::OversizedLength { offset: 24, .. })));
+
+        data[24..32].copy_from_slice(&(1024 * 1024u64).to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::Truncated { offset: 32 })));
+    }
+
+    #[test]
+    fn oversized_counts() {
+        let mut data = create_file();
+        data[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::OversizedLength { offset: 8, .. })));
+
+        let mut data = create_file();
+        data[16..24].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::OversizedLength { offset: 16, .. })));
+    }
+
+    #[test]
+    fn invalid_value_type() {
+        let mut data = create_file();
+        let offset = find(&data, b"general.architecture") + 20;
+        data[offset..offset + 4].copy_from_slice(&99u32.to_le_bytes());
+        match parse(&data) {
+            Err(GgufError::InvalidType { offset: error_offset, value: 99, .. }) => {
+                assert_eq!(error_offset, offset as u64);
+            }
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut data = create_file();
+        let offset = find(&data, b"llama.context_length");
+        data[offset] = 0xff;
+        match parse(&data) {
+            Err(GgufError::InvalidUtf8 { offset: error_offset }) => {
+                assert_eq!(error_offset, offset as u64);
+            }
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid_bool() {
+        let mut data = create_file();
+        let offset = find(&data, b"tokenizer.ggml.add_bos_token") + 28 + 4;
+        data[offset] = 2;
+        assert!(matches!(parse(&data), Err(GgufError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn invalid_tensor() {
+        let offset = find(&create_file(), b"output.weight") + 13;
+
+        let mut data = create_file();
+        data[offset..offset + 4].copy_from_slice(&5u32.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::OversizedLength { .. })));
+
+        let mut data = create_file();
+        data[offset + 20..offset + 24].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::InvalidType { kind: "tensor", .. })));
+
+        let mut data = create_file();
+        data[offset + 4..offset + 12].copy_from_slice(&31u64.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::InvalidValue { .. })));
+
+        let mut data = create_file();
+        data[offset + 24..offset + 32].copy_from_slice(&1u64.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::InvalidValue { .. })));
+
+        let mut data = create_file();
+        data[offset + 24..offset + 32].copy_from_slice(&4096u64.to_le_bytes());
+        assert!(matches!(parse(&data), Err(GgufError::Truncated { .. })));
+    }
+
+    #[test]
+    fn limits() {
+        let data = create_file();
+        let mut gguf = GGUF::new("test.gguf");
+        let limits = GGUFReadLimits {
+            max_string_length: 8,
+            ..GGUFReadLimits::default()
+        };
+        let result = gguf.read_from(Cursor::new(&data), &limits);
+        assert!(matches!(result, Err(GgufError::OversizedLength { .. })));
+    }
+
+    #[test]
+    fn truncated_files() {
+        let data = create_file();
+        // The padding after the last tensor is optional.
+        let gguf = parse(&data).unwrap();
+        let end = gguf.tensor_data_offset + gguf.tensors_size();
+        for length in 0..end as usize {
+            assert!(parse(&data[..length]).is_err(), "truncated at {} should fail", length);
+        }
+
+        let data = create_v1_file(GGUFByteOrder::LittleEndian);
+        let gguf = parse(&data).unwrap();
+        assert_eq!(gguf.get_metadata_array("tokenizer.ggml.merges").unwrap().len(), 100);
+        for length in 0..data.len() {
+            assert!(parse(&data[..length]).is_err(), "v1 truncated at {} should fail", length);
+        }
+    }
+
+    #[test]
+    fn mutated_files_do_not_panic() {
+        let data = create_file();
+        // xorshift, deterministic corpus of random byte mutations
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..5000 {
+            let mut mutated = data.clone();
+            let count = (next() % 4) + 1;
+            for _ in 0..count {
+                let index = (next() as usize) % mutated.len();
+                mutated[index] = next() as u8;
+            }
+            let _ = parse(&mutated);
+        }
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use half::{ bf16, f16 };
+
+use super::{
+    dequantize::get_scale_min_k4,
+    GGMLType,
+    GGUFByteOrder,
+    GGUFMetadataValue,
+    GGUFTensorInfo,
+    GGUF,
+};
+
+// Number of elements in a K-quant super-block.
+const QK_K: usize = 256;
+
+// Version of the quantization formats, GGML_QNT_VERSION.
+pub const GGML_QUANTIZATION_VERSION: u32 = 2;
+
+#[derive(Clone, Debug)]
+pub struct GGUFQuantizeOptions {
+    // Q8_0, Q4_0 or Q4_K.
+    pub tensor_type: GGMLType,
+    // Keeps token_embd.weight and output.weight with their source type, they are the
+    // tensors most sensitive to quantization.
+    pub keep_embeddings: bool,
+    // Other tensors copied with their source type.
+    pub keep_tensors: Vec<String>,
+}
+
+impl GGUFQuantizeOptions {
+    pub fn new(tensor_type: GGMLType) -> GGUFQuantizeOptions {
+        GGUFQuantizeOptions {
+            tensor_type,
+            keep_embeddings: false,
+            keep_tensors: Vec::new(),
+        }
+    }
+}
+
+// Encodes f32 values to tensor data, following the reference implementation of ggml-quants.c.
+// Block scales and plain values use the given byte order.
+pub fn quantize(
+    tensor_type: GGMLType,
+    values: &[f32],
+    byte_order: GGUFByteOrder
+) -> Result<Vec<u8>, String> {
+    let block_size = tensor_type.block_size() as usize;
+    if !values.len().is_multiple_of(block_size) {
+        return Err(
+            format!(
+                "{} values count {} is not a multiple of block size {}",
+                tensor_type,
+                values.len(),
+                block_size
+            )
+        );
+    }
+    let writer = BlockWriter { byte_order };
+    let quantize_block: fn(&BlockWriter, &[f32], &mut Vec<u8>) = match tensor_type {
+        GGMLType::F32 => |w, x, y| w.u32(y, x[0].to_bits()),
+        GGMLType::F16 => |w, x, y| w.f16(y, x[0]),
+        GGMLType::BF16 => |w, x, y| w.u16(y, bf16::from_f32(x[0]).to_bits()),
+        GGMLType::Q4_0 => quantize_q4_0,
+        GGMLType::Q8_0 => quantize_q8_0,
+        GGMLType::Q4_K => quantize_q4_k,
+        _ => {
+            return Err(format!("quantization to {} is not supported", tensor_type));
+        }
+    };
+
+    let mut data = Vec::with_capacity(
+        (values.len() / block_size) * (tensor_type.type_size() as usize)
+    );
+    for block in values.chunks_exact(block_size) {
+        quantize_block(&writer, block, &mut data);
+    }
+    Ok(data)
+}
+
+// llama_ftype of the files written by GGUF::quantize.
+fn quantize_file_type(tensor_type: GGMLType) -> Option<u32> {
+    match tensor_type {
+        GGMLType::Q4_0 => Some(2),
+        GGMLType::Q8_0 => Some(7),
+        // LLAMA_FTYPE_MOSTLY_Q4_K_S, every tensor uses the same type.
+        GGMLType::Q4_K => Some(14),
+        _ => None,
+    }
+}
+
+// Source types that are decoded and quantized again, other quantized types are only copied.
+fn is_requantizable(tensor_type: GGMLType) -> bool {
+    matches!(tensor_type, GGMLType::F32 | GGMLType::F16 | GGMLType::BF16 | GGMLType::Q8_0)
+}
+
+// Same rules as llama.cpp: norms, biases and router weights keep their type. Rows that don't
+// fill K-quant super-blocks fall back to Q8_0.
+fn quantized_tensor_type(tensor: &GGUFTensorInfo, options: &GGUFQuantizeOptions) -> GGMLType {
+    let is_embedding = tensor.name == "token_embd.weight" || tensor.name == "output.weight";
+    if
+        tensor.dimensions.len() < 2 ||
+        !tensor.name.ends_with(".weight") ||
+        tensor.name.contains("ffn_gate_inp") ||
+        (options.keep_embeddings && is_embedding) ||
+        options.keep_tensors.contains(&tensor.name)
+    {
+        return tensor.tensor_type;
+    }
+    [options.tensor_type, GGMLType::Q8_0]
+        .into_iter()
+        .find(|tensor_type| tensor.dimensions[0].is_multiple_of(tensor_type.block_size()))
+        .unwrap_or(tensor.tensor_type)
+}
+
+impl GGUF {
+    // Writes a copy of the model with its weights quantized, tensors are decoded one at a time.
+    pub fn quantize(&self, path: &str, options: &GGUFQuantizeOptions) -> Result<(), String> {
+        let file_type = quantize_file_type(options.tensor_type).ok_or_else(||
+            format!("Quantization to {} is not supported", options.tensor_type)
+        )?;
+        let mut output = self.clone();
+        for tensor in output.tensors.iter_mut() {
+            let tensor_type = quantized_tensor_type(tensor, options);
+            if tensor_type != tensor.tensor_type && !is_requantizable(tensor.tensor_type) {
+                return Err(
+                    format!(
+                        "Can't quantize tensor {} from {} to {}",
+                        tensor.name,
+                        tensor.tensor_type,
+                        tensor_type
+                    )
+                );
+            }
+            tensor.tensor_type = tensor_type;
+        }
+        output.set_metadata("general.file_type", GGUFMetadataValue::Uint32(file_type));
+        output.set_metadata(
+            "general.quantization_version",
+            GGUFMetadataValue::Uint32(GGML_QUANTIZATION_VERSION)
+        );
+
+        output.write_with_tensor_data(path, |tensor| {
+            let source = self
+                .get_tensor_info(&tensor.name)
+                .ok_or_else(|| anyhow::Error::msg(format!("Tensor not found {}", tensor.name)))?;
+            if source.tensor_type == tensor.tensor_type {
+                return Ok(Cow::Borrowed(self.get_tensor_data(source)?));
+            }
+            let values = self.get_tensor_f32(source)?;
+            let data = quantize(tensor.tensor_type, &values, self.header.byte_order).map_err(
+                anyhow::Error::msg
+            )?;
+            Ok(Cow::Owned(data))
+        }).map_err(|err| err.to_string())
+    }
+}
+
+struct BlockWriter {
+    byte_order: GGUFByteOrder,
+}
+
+impl BlockWriter {
+    fn u16(&self, data: &mut Vec<u8>, value: u16) {
+        match self.byte_order {
+            GGUFByteOrder::LittleEndian => data.extend(value.to_le_bytes()),
+            GGUFByteOrder::BigEndian => data.extend(value.to_be_bytes()),
+        }
+    }
+
+    fn u32(&self, data: &mut Vec<u8>, value: u32) {
+        match self.byte_order {
+            GGUFByteOrder::LittleEndian => data.extend(value.to_le_bytes()),
+            GGUFByteOrder::BigEndian => data.extend(value.to_be_bytes()),
+        }
+    }
+
+    fn f16(&self, data: &mut Vec<u8>, value: f32) {
+        self.u16(data, f16::from_f32(value).to_bits());
+    }
+}
+
+// Rounds half to even, as the float trick of ggml.
+fn nearest_int(value: f32) -> i32 {
+    value.round_ties_even() as i32
+}
+
+// Block layouts, see the block_* structs of ggml-common.h.
+
+fn quantize_q4_0(w: &BlockWriter, x: &[f32], y: &mut Vec<u8>) {
+    // The value with the largest magnitude maps to -8.
+    let mut amax = 0.0;
+    let mut max = 0.0;
+    for &value in x {
+        if amax < value.abs() {
+            amax = value.abs();
+            max = value;
+        }
+    }
+    let d = max / -8.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    w.f16(y, d);
+    for j in 0..16 {
+        let x0 = ((x[j] * id + 8.5) as i8).min(15) as u8;
+        let x1 = ((x[j + 16] * id + 8.5) as i8).min(15) as u8;
+        y.push(x0 | (x1 << 4));
+    }
+}
+
+fn quantize_q8_0(w: &BlockWriter, x: &[f32], y: &mut Vec<u8>) {
+    let amax = x.iter().fold(0.0f32, |amax, value| amax.max(value.abs()));
+    let d = amax / 127.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    w.f16(y, d);
+    y.extend(x.iter().map(|value| (value * id).round() as i8 as u8));
+}
+
+// Scale and min of a sub-block minimizing the weighted squared error, searching around
+// the min-max scale (make_qkx2_quants).
+fn make_qkx2_quants(
+    nmax: i32,
+    x: &[f32],
+    weights: &[f32],
+    l: &mut [u8],
+    rmin: f32,
+    rdelta: f32,
+    nstep: usize
+) -> (f32, f32) {
+    let mut min = x[0];
+    let mut max = x[0];
+    let mut sum_w = 0.0;
+    let mut sum_x = 0.0;
+    for (&value, &weight) in x.iter().zip(weights) {
+        min = min.min(value);
+        max = max.max(value);
+        sum_w += weight;
+        sum_x += weight * value;
+    }
+    if min > 0.0 {
+        min = 0.0;
+    }
+    if max == min {
+        l.fill(0);
+        return (0.0, -min);
+    }
+
+    let mut iscale = (nmax as f32) / (max - min);
+    let mut scale = 1.0 / iscale;
+    let mut best_mad = 0.0;
+    for ((q, &value), &weight) in l.iter_mut().zip(x).zip(weights) {
+        *q = nearest_int(iscale * (value - min)).clamp(0, nmax) as u8;
+        let diff = scale * (*q as f32) + min - value;
+        best_mad += weight * diff * diff;
+    }
+
+    let mut laux = [0u8; 32];
+    let laux = &mut laux[..x.len()];
+    for is in 0..=nstep {
+        iscale = (rmin + rdelta * (is as f32) + (nmax as f32)) / (max - min);
+        let (mut sum_l, mut sum_l2, mut sum_xl) = (0.0, 0.0, 0.0);
+        for ((q, &value), &weight) in laux.iter_mut().zip(x).zip(weights) {
+            *q = nearest_int(iscale * (value - min)).clamp(0, nmax) as u8;
+            let q = *q as f32;
+            sum_l += weight * q;
+            sum_l2 += weight * q * q;
+            sum_xl += weight * q * value;
+        }
+        let d = sum_w * sum_l2 - sum_l * sum_l;
+        if d > 0.0 {
+            let mut this_scale = (sum_w * sum_xl - sum_x * sum_l) / d;
+            let mut this_min = (sum_l2 * sum_x - sum_l * sum_xl) / d;
+            if this_min > 0.0 {
+                this_min = 0.0;
+                this_scale = sum_xl / sum_l2;
+            }
+            let mut mad = 0.0;
+            for ((&q, &value), &weight) in laux.iter().zip(x).zip(weights) {
+                let diff = this_scale * (q as f32) + this_min - value;
+                mad += weight * diff * diff;
+            }
+            if mad < best_mad {
+                l.copy_from_slice(laux);
+                best_mad = mad;
+                scale = this_scale;
+                min = this_min;
+            }
+        }
+    }
+    (scale, -min)
+}
+
+fn quantize_q4_k(w: &BlockWriter, x: &[f32], y: &mut Vec<u8>) {
+    let mut l = [0u8; QK_K];
+    let mut scales = [0.0f32; QK_K / 32];
+    let mut mins = [0.0f32; QK_K / 32];
+    let mut weights = [0.0f32; 32];
+    for (j, sub) in x.chunks_exact(32).enumerate() {
+        let sum_x2: f32 = sub
+            .iter()
+            .map(|value| value * value)
+            .sum();
+        let av_x = (sum_x2 / 32.0).sqrt();
+        for (weight, value) in weights.iter_mut().zip(sub) {
+            *weight = av_x + value.abs();
+        }
+        (scales[j], mins[j]) = make_qkx2_quants(
+            15,
+            sub,
+            &weights,
+            &mut l[j * 32..(j + 1) * 32],
+            -1.0,
+            0.1,
+            20
+        );
+    }
+
+    // Scales and mins are quantized again on 6 bits.
+    let max_scale = scales.iter().fold(0.0f32, |max, &scale| max.max(scale));
+    let max_min = mins.iter().fold(0.0f32, |max, &min| max.max(min));
+    let inv_scale = if max_scale > 0.0 { 63.0 / max_scale } else { 0.0 };
+    let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
+    let mut packed = [0u8; 12];
+    for j in 0..QK_K / 32 {
+        let ls = (nearest_int(inv_scale * scales[j]) as u8).min(63);
+        let lm = (nearest_int(inv_min * mins[j]) as u8).min(63);
+        if j < 4 {
+            packed[j] = ls;
+            packed[j + 4] = lm;
+        } else {
+            packed[j + 4] = (ls & 0x0f) | ((lm & 0x0f) << 4);
+            packed[j - 4] |= (ls >> 4) << 6;
+            packed[j] |= (lm >> 4) << 6;
+        }
+    }
+    let d = f16::from_f32(max_scale / 63.0);
+    let dmin = f16::from_f32(max_min / 63.0);
+
+    // Values are quantized again with the rounded scales.
+    for j in 0..QK_K / 32 {
+        let (sc, m) = get_scale_min_k4(j, &packed);
+        let dj = d.to_f32() * (sc as f32);
+        if dj == 0.0 {
+            continue;
+        }
+        let dm = dmin.to_f32() * (m as f32);
+        for (q, &value) in l[j * 32..(j + 1) * 32].iter_mut().zip(&x[j * 32..(j + 1) * 32]) {
+            *q = nearest_int((value + dm) / dj).clamp(0, 15) as u8;
+        }
+    }
+
+    w.u16(y, d.to_bits());
+    w.u16(y, dmin.to_bits());
+    y.extend(packed);
+    for chunk in l.chunks_exact(64) {
+        y.extend((0..32).map(|i| chunk[i] | (chunk[i + 32] << 4)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ borrow::Cow, fs };
+
+    use crate::{
+        gguf::{
+            dequantize,
+            quantize,
+            GGMLType,
+            GGUFByteOrder,
+            GGUFMetadataValue,
+            GGUFQuantizeOptions,
+            GGUF,
+        },
+        testing::{ create_model, temp_path, tensor },
+    };
+
+    fn values(count: usize) -> Vec<f32> {
+        (0..count).map(|i| ((i as f32) * 0.37).sin() * (1.0 + ((i % 7) as f32) * 0.1)).collect()
+    }
+
+    fn rmse(a: &[f32], b: &[f32]) -> f32 {
+        let sum: f32 = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        (sum / (a.len() as f32)).sqrt()
+    }
+
+    #[test]
+    fn quantize_round_trip() {
+        let x = values(512);
+        for (tensor_type, max_rmse) in [
+            (GGMLType::F16, 0.001),
+            (GGMLType::Q8_0, 0.01),
+            (GGMLType::Q4_0, 0.1),
+            (GGMLType::Q4_K, 0.06),
+        ] {
+            for byte_order in [GGUFByteOrder::LittleEndian, GGUFByteOrder::BigEndian] {
+                let data = quantize(tensor_type, &x, byte_order).unwrap();
+                let blocks = 512 / tensor_type.block_size();
+                assert_eq!(data.len() as u64, blocks * tensor_type.type_size());
+                let y = dequantize(tensor_type, &data, byte_order).unwrap();
+                assert!(rmse(&x, &y) < max_rmse, "{} rmse {}", tensor_type, rmse(&x, &y));
+            }
+        }
+        // Constant blocks are exact.
+        let data = quantize(GGMLType::Q4_K, &[0.0; 256], GGUFByteOrder::LittleEndian).unwrap();
+        assert!(data.iter().all(|b| *b == 0));
+        assert!(quantize(GGMLType::Q8_0, &x[..48], GGUFByteOrder::LittleEndian).is_err());
+        assert!(quantize(GGMLType::Q6_K, &x, GGUFByteOrder::LittleEndian).is_err());
+    }
+
+    #[test]
+    fn quantize_model() {
+        let source = temp_path("quantize-f16");
+        let output = temp_path("quantize-q4_k");
+        let gguf = create_model(
+            "llama",
+            &[("general.file_type", GGUFMetadataValue::Uint32(1))],
+            &[
+                tensor("token_embd.weight", &[256, 4], GGMLType::F16),
+                tensor("blk.0.attn_norm.weight", &[256], GGMLType::F16),
+                tensor("blk.0.attn_q.weight", &[256, 4], GGMLType::F16),
+                tensor("blk.0.ffn_down.weight", &[96, 4], GGMLType::F16),
+                tensor("output.weight", &[256, 4], GGMLType::F16),
+            ]
+        );
+        gguf.write_with_tensor_data(source.to_str().unwrap(), |tensor| {
+            let x = values(tensor.element_count() as usize);
+            Ok(Cow::Owned(quantize(GGMLType::F16, &x, GGUFByteOrder::LittleEndian).unwrap()))
+        }).unwrap();
+
+        let mut gguf = GGUF::new(source.to_str().unwrap());
+        gguf.read(source.to_str().unwrap()).unwrap();
+        let mut options = GGUFQuantizeOptions::new(GGMLType::Q4_K);
+        options.keep_embeddings = true;
+        // The source file is mapped.
+        assert!(gguf.quantize(source.to_str().unwrap(), &options).is_err());
+        let unsupported = GGUFQuantizeOptions::new(GGMLType::Q6_K);
+        assert!(gguf.quantize(output.to_str().unwrap(), &unsupported).is_err());
+        gguf.quantize(output.to_str().unwrap(), &options).unwrap();
+
+        let mut quantized = GGUF::new(output.to_str().unwrap());
+        quantized.read(output.to_str().unwrap()).unwrap();
+        let types: Vec<GGMLType> = quantized.tensors
+            .iter()
+            .map(|t| t.tensor_type)
+            .collect();
+        assert_eq!(types, vec![
+            GGMLType::F16,
+            GGMLType::F16,
+            GGMLType::Q4_K,
+            // 96 values don't fill a super-block.
+            GGMLType::Q8_0,
+            GGMLType::F16,
+        ]);
+        assert_eq!(quantized.get_metadata_u64("general.file_type"), Some(14));
+        assert_eq!(quantized.get_metadata_u64("general.quantization_version"), Some(2));
+        let tensor = quantized.get_tensor_info("blk.0.attn_q.weight").unwrap();
+        let y = quantized.get_tensor_f32(tensor).unwrap();
+        assert!(rmse(&values(1024), &y) < 0.06);
+
+        // Already quantized to Q4_K, tensors can't be quantized again.
+        options.keep_embeddings = false;
+        let again = temp_path("quantize-again");
+        assert!(quantized.quantize(again.to_str().unwrap(), &options).is_ok());
+        let q8_0 = GGUFQuantizeOptions::new(GGMLType::Q8_0);
+        assert!(quantized.quantize(again.to_str().unwrap(), &q8_0).is_err());
+        fs::remove_file(source).unwrap();
+        fs::remove_file(output).unwrap();
+        let _ = fs::remove_file(again);
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ borrow::Cow, collections::HashSet };
+
+use super::{ GGUFMetadataValue, GgufError, GGUF, GGUF_DEFAULT_ALIGNMENT };
+
+// Keys written by llama.cpp gguf-split.
+pub const GGUF_SPLIT_NO: &str = "split.no";
+pub const GGUF_SPLIT_COUNT: &str = "split.count";
+pub const GGUF_SPLIT_TENSORS_COUNT: &str = "split.tensors.count";
+
+// How tensors are distributed between shards when splitting a model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GGUFSplitLimit {
+    // Maximum number of tensors in a shard.
+    Tensors(u64),
+    // Maximum size in bytes of the tensor data of a shard.
+    Size(u64),
+}
+
+// Path of a shard: model-00001-of-00005.gguf, split_no starts at 0.
+pub fn split_path(prefix: &str, split_no: u64, split_count: u64) -> String {
+    format!("{}-{:05}-of-{:05}.gguf", prefix, split_no + 1, split_count)
+}
+
+// Prefix of the shards, the inverse of split_path.
+pub fn split_prefix(path: &str, split_no: u64, split_count: u64) -> Option<&str> {
+    path.strip_suffix(&format!("-{:05}-of-{:05}.gguf", split_no + 1, split_count))
+}
+
+impl GGUF {
+    pub fn split_no(&self) -> u64 {
+        self.get_metadata_u64(GGUF_SPLIT_NO).unwrap_or(0)
+    }
+
+    pub fn split_count(&self) -> u64 {
+        self.get_metadata_u64(GGUF_SPLIT_COUNT).unwrap_or(1).max(1)
+    }
+
+    // True if the metadata and tensors of the model are spread over several files.
+    pub fn is_split(&self) -> bool {
+        self.split_count() > 1
+    }
+
+    // Shards merged into this model, empty if it was read from a single file.
+    pub fn splits(&self) -> &[GGUF] {
+        &self.splits
+    }
+
+    // Reads a model from any of its shards, the sibling shards are discovered from the
+    // split metadata and presented as a single model. A file not split is read as is.
+    pub fn read_split(path: &str) -> Result<GGUF, GgufError> {
+        let mut gguf = GGUF::new(path);
+        gguf.read(path)?;
+        if !gguf.is_split() {
+            return Ok(gguf);
+        }
+        let (split_no, split_count) = (gguf.split_no(), gguf.split_count());
+        let prefix = split_prefix(path, split_no, split_count).ok_or_else(||
+            GgufError::InvalidSplit {
+                message: format!(
+                    "{} is not named as shard {} of {}",
+                    path,
+                    split_no + 1,
+                    split_count
+                ),
+            }
+        )?;
+        let mut shards = Vec::new();
+        for no in 0..split_count {
+            if no == split_no {
+                shards.push(gguf.clone());
+                continue;
+            }
+            let shard_path = split_path(prefix, no, split_count);
+            let mut shard = GGUF::new(&shard_path);
+            shard.read(&shard_path)?;
+            shards.push(shard);
+        }
+        GGUF::merge_splits(shards)
+    }
+
+    // A single model from its shards in order. Metadata comes from the first shard,
+    // tensor offsets are the ones of the merged file, data is read from the shards.
+    pub fn merge_splits(shards: Vec<GGUF>) -> Result<GGUF, GgufError> {
+        let first = shards.first().ok_or_else(|| GgufError::InvalidSplit {
+            message: "no shard".to_string(),
+        })?;
+        let split_count = shards.len() as u64;
+        let mut names = HashSet::new();
+        for (no, shard) in shards.iter().enumerate() {
+            if shard.split_no() != (no as u64) || shard.split_count() != split_count {
+                return Err(GgufError::InvalidSplit {
+                    message: format!(
+                        "{} is shard {} of {}, expected {} of {}",
+                        shard.file_name,
+                        shard.split_no() + 1,
+                        shard.split_count(),
+                        no + 1,
+                        split_count
+                    ),
+                });
+            }
+            if let Some(tensor) = shard.tensors.iter().find(|t| !names.insert(t.name.as_str())) {
+                return Err(GgufError::InvalidSplit {
+                    message: format!("duplicate tensor {} in {}", tensor.name, shard.file_name),
+                });
+            }
+        }
+        if let Some(count) = first.get_metadata_u64(GGUF_SPLIT_TENSORS_COUNT) {
+            if count != (names.len() as u64) {
+                return Err(GgufError::InvalidSplit {
+                    message: format!("expected {} tensors, found {}", count, names.len()),
+                });
+            }
+        }
+
+        // The mapped first shard is kept to decode arrays not loaded.
+        let mut gguf = first.clone();
+        for key in [GGUF_SPLIT_NO, GGUF_SPLIT_COUNT, GGUF_SPLIT_TENSORS_COUNT] {
+            gguf.remove_metadata(key);
+        }
+        gguf.tensors = shards
+            .iter()
+            .flat_map(|shard| shard.tensors.iter().cloned())
+            .collect();
+        let offsets = gguf.compute_tensor_offsets(gguf.alignment);
+        for (tensor, offset) in gguf.tensors.iter_mut().zip(offsets) {
+            tensor.offset = offset;
+        }
+        gguf.header.tensor_count = gguf.tensors.len() as u64;
+        gguf.tensor_data_offset = 0;
+        gguf.splits = shards;
+        Ok(gguf)
+    }
+
+    // Data of a tensor of a merged model, read from the shard that contains it.
+    pub(super) fn get_split_tensor_data(&self, name: &str) -> Result<&[u8], GgufError> {
+        for shard in self.splits.iter() {
+            if let Some(tensor) = shard.get_tensor_info(name) {
+                return shard.get_tensor_data(tensor);
+            }
+        }
+        Err(GgufError::NotLoaded { message: format!("tensor {} not found in shards", name) })
+    }
+
+    // Writes the model as shards named after prefix, as llama.cpp gguf-split does:
+    // the first shard has all the metadata, the others only the split keys.
+    // Returns the paths of the shards written.
+    pub fn write_splits(
+        &self,
+        prefix: &str,
+        limit: GGUFSplitLimit
+    ) -> Result<Vec<String>, GgufError> {
+        let groups = split_tensors(self, limit);
+        let split_count = groups.len() as u64;
+        let mut paths = Vec::new();
+        for (no, group) in groups.iter().enumerate() {
+            let path = split_path(prefix, no as u64, split_count);
+            // Cloned to keep the mapped file, arrays not loaded are decoded from it.
+            let mut shard = self.clone();
+            if no > 0 {
+                shard.header.metadata_kv.clear();
+                if self.alignment != GGUF_DEFAULT_ALIGNMENT {
+                    shard.set_metadata(
+                        "general.alignment",
+                        GGUFMetadataValue::Uint32(self.alignment as u32)
+                    );
+                }
+            }
+            shard.set_metadata(GGUF_SPLIT_NO, GGUFMetadataValue::Uint16(no as u16));
+            shard.set_metadata(GGUF_SPLIT_COUNT, GGUFMetadataValue::Uint16(split_count as u16));
+            shard.set_metadata(
+                GGUF_SPLIT_TENSORS_COUNT,
+                GGUFMetadataValue::Int32(self.tensors.len() as i32)
+            );
+            shard.tensors = group
+                .iter()
+                .map(|index| self.tensors[*index].clone())
+                .collect();
+            shard.write_with_tensor_data(&path, |tensor| {
+                self.get_tensor_data(tensor).map(Cow::Borrowed).map_err(anyhow::Error::from)
+            })?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+// Indexes of the tensors of each shard, in order. There is always at least one shard.
+fn split_tensors(gguf: &GGUF, limit: GGUFSplitLimit) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new()];
+    let mut size = 0;
+    for (index, tensor) in gguf.tensors.iter().enumerate() {
+        let group = groups.last().map(|group| group.len() as u64).unwrap_or_default();
+        let is_full = match limit {
+            GGUFSplitLimit::Tensors(max) => group >= max.max(1),
+            GGUFSplitLimit::Size(max) => group > 0 && size + tensor.size() > max,
+        };
+        if is_full {
+            groups.push(Vec::new());
+            size = 0;
+        }
+        if let Some(group) = groups.last_mut() {
+            group.push(index);
+        }
+        size += tensor.size();
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ fs, path::Path };
+
+    use crate::{
+        gguf::{ GGMLType, GGUF },
+        testing::{ create_model, temp_path, tensor, write_model },
+    };
+    use super::{ split_path, split_tensors, GGUFSplitLimit };
+
+    #[test]
+    fn split_and_merge() {
+        let prefix = temp_path("split").with_extension("");
+        let prefix = prefix.to_str().unwrap();
+        let source = format!("{}.gguf", prefix);
+        let merged = format!("{}-merged.gguf", prefix);
+        let tensors: Vec<_> = ["token_embd.weight", "blk.0.attn_q.weight", "output.weight"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| tensor(name, &[32, index as u64 + 1], GGMLType::F32))
+            .collect();
+        write_model(&create_model("llama", &[], &tensors), Path::new(&source));
+
+        let gguf = GGUF::read_split(&source).unwrap();
+        assert!(!gguf.is_split());
+        let paths = gguf.write_splits(prefix, GGUFSplitLimit::Tensors(2)).unwrap();
+        assert_eq!(paths, vec![split_path(prefix, 0, 2), split_path(prefix, 1, 2)]);
+
+        // Any shard gives the whole model.
+        let model = GGUF::read_split(&paths[1]).unwrap();
+        assert_eq!(model.splits().len(), 2);
+        assert_eq!(model.tensors.len(), 3);
+        assert_eq!(model.get_architecture(), Some("llama"));
+        assert!(model.get_metadata_value("split.count").is_none());
+        for tensor in model.tensors.iter() {
+            let original = gguf.get_tensor_info(&tensor.name).unwrap();
+            assert_eq!(tensor.offset, original.offset);
+            assert_eq!(
+                model.get_tensor_data(tensor).unwrap(),
+                gguf.get_tensor_data(original).unwrap()
+            );
+        }
+        model.write(&merged).unwrap();
+        assert_eq!(fs::read(&source).unwrap(), fs::read(&merged).unwrap());
+
+        fs::remove_file(&paths[1]).unwrap();
+        assert!(GGUF::read_split(&paths[0]).is_err());
+
+        let paths = [paths, vec![source, merged]].concat();
+        for path in paths.iter().filter(|path| fs::metadata(path).is_ok()) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn split_by_size() {
+        let mut gguf = GGUF::new("test.gguf");
+        for size in [64, 64, 128, 32] {
+            let name = format!("tensor{}", gguf.tensors.len());
+            gguf.tensors.push(tensor(&name, &[size / 4], GGMLType::F32));
+        }
+        assert_eq!(split_tensors(&gguf, GGUFSplitLimit::Size(128)), vec![vec![0, 1], vec![2], vec![3]]);
+        // A tensor larger than the limit gets its own shard.
+        assert_eq!(split_tensors(&gguf, GGUFSplitLimit::Size(100)), vec![vec![0], vec![1], vec![2], vec![3]]);
+        assert_eq!(split_tensors(&gguf, GGUFSplitLimit::Tensors(3)), vec![vec![0, 1, 2], vec![3]]);
+        assert_eq!(split_tensors(&GGUF::new("empty.gguf"), GGUFSplitLimit::Tensors(3)).len(), 1);
+    }
+}
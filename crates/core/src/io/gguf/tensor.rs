@@ -0,0 +1,324 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{ self, Display, Formatter };
+use serde::{ Deserialize, Serialize };
+
+// Default alignment of the tensor data section, see `general.alignment`.
+pub const GGUF_DEFAULT_ALIGNMENT: u64 = 32;
+
+// Tensor types as defined by ggml.h (enum ggml_type).
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GGMLType {
+    F32 = 0,
+    F16 = 1,
+    Q4_0 = 2,
+    Q4_1 = 3,
+    // Q4_2 = 4 and Q4_3 = 5 support has been removed
+    Q5_0 = 6,
+    Q5_1 = 7,
+    Q8_0 = 8,
+    Q8_1 = 9,
+    Q2_K = 10,
+    Q3_K = 11,
+    Q4_K = 12,
+    Q5_K = 13,
+    Q6_K = 14,
+    Q8_K = 15,
+    IQ2_XXS = 16,
+    IQ2_XS = 17,
+    IQ3_XXS = 18,
+    IQ1_S = 19,
+    IQ4_NL = 20,
+    IQ3_S = 21,
+    IQ2_S = 22,
+    IQ4_XS = 23,
+    I8 = 24,
+    I16 = 25,
+    I32 = 26,
+    I64 = 27,
+    F64 = 28,
+    IQ1_M = 29,
+    BF16 = 30,
+    // Q4_0_4_4 = 31, Q4_0_4_8 = 32 and Q4_0_8_8 = 33 support has been removed
+    TQ1_0 = 34,
+    TQ2_0 = 35,
+    // IQ4_NL_4_4 = 36, IQ4_NL_4_8 = 37 and IQ4_NL_8_8 = 38 support has been removed
+    MXFP4 = 39,
+}
+
+impl TryFrom<u32> for GGMLType {
+    type Error = String;
+
+    fn try_from(item: u32) -> Result<Self, Self::Error> {
+        Ok(match item {
+            0 => GGMLType::F32,
+            1 => GGMLType::F16,
+            2 => GGMLType::Q4_0,
+            3 => GGMLType::Q4_1,
+            6 => GGMLType::Q5_0,
+            7 => GGMLType::Q5_1,
+            8 => GGMLType::Q8_0,
+            9 => GGMLType::Q8_1,
+            10 => GGMLType::Q2_K,
+            11 => GGMLType::Q3_K,
+            12 => GGMLType::Q4_K,
+            13 => GGMLType::Q5_K,
+            14 => GGMLType::Q6_K,
+            15 => GGMLType::Q8_K,
+            16 => GGMLType::IQ2_XXS,
+            17 => GGMLType::IQ2_XS,
+            18 => GGMLType::IQ3_XXS,
+            19 => GGMLType::IQ1_S,
+            20 => GGMLType::IQ4_NL,
+            21 => GGMLType::IQ3_S,
+            22 => GGMLType::IQ2_S,
+            23 => GGMLType::IQ4_XS,
+            24 => GGMLType::I8,
+            25 => GGMLType::I16,
+            26 => GGMLType::I32,
+            27 => GGMLType::I64,
+            28 => GGMLType::F64,
+            29 => GGMLType::IQ1_M,
+            30 => GGMLType::BF16,
+            34 => GGMLType::TQ1_0,
+            35 => GGMLType::TQ2_0,
+            39 => GGMLType::MXFP4,
+            _ => {
+                return Err(format!("invalid ggml type 0x{:x}", item));
+            }
+        })
+    }
+}
+
+impl GGMLType {
+    // Number of elements stored in one block.
+    pub fn block_size(&self) -> u64 {
+        match self {
+            | GGMLType::F32
+            | GGMLType::F16
+            | GGMLType::BF16
+            | GGMLType::F64
+            | GGMLType::I8
+            | GGMLType::I16
+            | GGMLType::I32
+            | GGMLType::I64 => 1,
+            | GGMLType::Q4_0
+            | GGMLType::Q4_1
+            | GGMLType::Q5_0
+            | GGMLType::Q5_1
+            | GGMLType::Q8_0
+            | GGMLType::Q8_1
+            | GGMLType::IQ4_NL
+            | GGMLType::MXFP4 => 32,
+            _ => 256,
+        }
+    }
+
+    // Size in bytes of one block.
+    pub fn type_size(&self) -> u64 {
+        match self {
+            GGMLType::F32 => 4,
+            GGMLType::F16 => 2,
+            GGMLType::Q4_0 => 18,
+            GGMLType::Q4_1 => 20,
+            GGMLType::Q5_0 => 22,
+            GGMLType::Q5_1 => 24,
+            GGMLType::Q8_0 => 34,
+            GGMLType::Q8_1 => 36,
+            GGMLType::Q2_K => 84,
+            GGMLType::Q3_K => 110,
+            GGMLType::Q4_K => 144,
+            GGMLType::Q5_K => 176,
+            GGMLType::Q6_K => 210,
+            GGMLType::Q8_K => 292,
+            GGMLType::IQ2_XXS => 66,
+            GGMLType::IQ2_XS => 74,
+            GGMLType::IQ3_XXS => 98,
+            GGMLType::IQ1_S => 50,
+            GGMLType::IQ4_NL => 18,
+            GGMLType::IQ3_S => 110,
+            GGMLType::IQ2_S => 82,
+            GGMLType::IQ4_XS => 136,
+            GGMLType::I8 => 1,
+            GGMLType::I16 => 2,
+            GGMLType::I32 => 4,
+            GGMLType::I64 => 8,
+            GGMLType::F64 => 8,
+            GGMLType::IQ1_M => 56,
+            GGMLType::BF16 => 2,
+            GGMLType::TQ1_0 => 54,
+            GGMLType::TQ2_0 => 66,
+            GGMLType::MXFP4 => 17,
+        }
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.block_size() > 1
+    }
+
+    // Average number of bits used to store one element.
+    pub fn bits_per_weight(&self) -> f32 {
+        ((self.type_size() * 8) as f32) / (self.block_size() as f32)
+    }
+}
+
+impl Display for GGMLType {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GGUFTensorInfo {
+    pub name: String,
+    pub n_dimensions: u32,
+    pub dimensions: Vec<u64>,
+    pub tensor_type: GGMLType,
+    // Offset relative to the start of the tensor data section.
+    pub offset: u64,
+}
+
+impl GGUFTensorInfo {
+    pub fn element_count(&self) -> u64 {
+        self.dimensions.iter().product()
+    }
+
+    // Size in bytes of the tensor data, padding excluded.
+    pub fn size(&self) -> u64 {
+        let block_size = self.tensor_type.block_size();
+        (self.element_count() / block_size) * self.tensor_type.type_size()
+    }
+}
+
+pub fn align_offset(offset: u64, alignment: u64) -> u64 {
+    offset + ((alignment - (offset % alignment)) % alignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        gguf::{ align_offset, GGMLType, GGUF },
+        testing::{ string_bytes, temp_path, tensor },
+    };
+
+    // A v3 file with an F32 [4, 3] and a Q8_0 [64] tensor,
+    // returned with the expected position of the tensor data section.
+    fn create_file(alignment: Option<u32>) -> (Vec<u8>, u64) {
+        let mut data = b"GGUF".to_vec();
+        data.extend(3u32.to_le_bytes());
+        data.extend(2u64.to_le_bytes());
+        data.extend((1 + (alignment.is_some() as u64)).to_le_bytes());
+        data.extend(string_bytes("general.architecture"));
+        data.extend(8u32.to_le_bytes());
+        data.extend(string_bytes("llama"));
+        if let Some(alignment) = alignment {
+            data.extend(string_bytes("general.alignment"));
+            data.extend(4u32.to_le_bytes());
+            data.extend(alignment.to_le_bytes());
+        }
+        let alignment = alignment.unwrap_or(32) as u64;
+        let tensors = [
+            ("token_embd.weight", vec![4u64, 3], GGMLType::F32, 0),
+            ("output.weight", vec![64], GGMLType::Q8_0, align_offset(48, alignment)),
+        ];
+        for (name, dimensions, tensor_type, offset) in tensors.iter() {
+            data.extend(string_bytes(name));
+            data.extend((dimensions.len() as u32).to_le_bytes());
+            for dimension in dimensions.iter() {
+                data.extend(dimension.to_le_bytes());
+            }
+            data.extend((*tensor_type as u32).to_le_bytes());
+            data.extend(offset.to_le_bytes());
+        }
+        let tensor_data_offset = align_offset(data.len() as u64, alignment);
+        data.resize((tensor_data_offset + tensors[1].3) as usize, 0);
+        data.extend([1; 68]);
+        (data, tensor_data_offset)
+    }
+
+    fn read(name: &str, data: &[u8]) -> Result<GGUF, String> {
+        let path = temp_path(name);
+        fs::write(&path, data).unwrap();
+        let mut gguf = GGUF::new(path.to_str().unwrap());
+        let result = gguf.read(path.to_str().unwrap()).map_err(|err| err.to_string());
+        fs::remove_file(path).unwrap();
+        result.map(|_| gguf)
+    }
+
+    #[test]
+    fn offsets_alignment() {
+        assert_eq!(align_offset(0, 32), 0);
+        assert_eq!(align_offset(1, 32), 32);
+        assert_eq!(align_offset(32, 32), 32);
+        assert_eq!(align_offset(33, 64), 64);
+    }
+
+    #[test]
+    fn tensor_sizes() {
+        let mut tensor = tensor("blk.0.ffn_down.weight", &[256, 3], GGMLType::F16);
+        assert_eq!(tensor.element_count(), 768);
+        assert_eq!(tensor.size(), 1536);
+        tensor.tensor_type = GGMLType::Q8_0;
+        assert_eq!(tensor.size(), 24 * 34);
+        tensor.tensor_type = GGMLType::Q4_K;
+        assert_eq!(tensor.size(), 3 * 144);
+        assert_eq!(GGMLType::Q4_0.bits_per_weight(), 4.5);
+    }
+
+    #[test]
+    fn tensor_infos() {
+        let (data, _) = create_file(None);
+        let gguf = read("tensor-infos", &data).unwrap();
+        assert_eq!(gguf.tensors.len(), 2);
+        let embeddings = gguf.get_tensor_info("token_embd.weight").unwrap();
+        assert_eq!(embeddings.n_dimensions, 2);
+        assert_eq!(embeddings.dimensions, vec![4, 3]);
+        assert_eq!(embeddings.tensor_type, GGMLType::F32);
+        assert_eq!(embeddings.offset, 0);
+        let output = gguf.get_tensor_info("output.weight").unwrap();
+        assert_eq!(output.dimensions, vec![64]);
+        assert_eq!(output.tensor_type, GGMLType::Q8_0);
+        assert_eq!(output.offset, 64);
+        assert_eq!(gguf.tensors_size(), 48 + 68);
+        assert!(gguf.get_tensor_info("missing.weight").is_none());
+    }
+
+    #[test]
+    fn tensor_data_offset() {
+        let (data, expected) = create_file(None);
+        let gguf = read("default-alignment", &data).unwrap();
+        assert_eq!(gguf.alignment, 32);
+        assert_eq!(gguf.tensor_data_offset, expected);
+        assert_eq!(gguf.tensor_data_offset % 32, 0);
+
+        let (data, expected) = create_file(Some(64));
+        let gguf = read("custom-alignment", &data).unwrap();
+        assert_eq!(gguf.alignment, 64);
+        assert_eq!(gguf.tensor_data_offset, expected);
+        assert_eq!(gguf.tensor_data_offset % 64, 0);
+        assert_eq!(gguf.tensor_data_offset + gguf.tensors[1].offset + 68, data.len() as u64);
+    }
+
+    #[test]
+    fn invalid_alignment() {
+        for alignment in [3, 48] {
+            let (data, _) = create_file(Some(alignment));
+            assert!(read("invalid-alignment", &data).is_err());
+        }
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ borrow::Cow, fs::{ self, File }, io::{ BufWriter, Write }, path::Path };
+
+use super::{
+    align_offset,
+    GGUFByteOrder,
+    GGUFMetadataArrayValue,
+    GGUFMetadataValue,
+    GGUFMetadataValueType,
+    GGUFTensorInfo,
+    GgufError,
+    GGUF,
+};
+
+pub const GGUF_MAGIC: [u8; 4] = [0x47, 0x47, 0x55, 0x46];
+pub const GGUF_VERSION: u32 = 3;
+
+impl GGUF {
+    // Writes the header, metadata, tensor infos and tensor data to a new file.
+    // Tensor data is copied from the mapped source file.
+    pub fn write(&self, path: &str) -> Result<(), GgufError> {
+        self.write_with_tensor_data(path, |tensor| {
+            self.get_tensor_data(tensor).map(Cow::Borrowed).map_err(anyhow::Error::from)
+        })
+    }
+
+    // Same as write, but the data of each tensor is provided by the caller.
+    pub fn write_with_tensor_data<'a, F>(&self, path: &str, tensor_data: F) -> Result<(), GgufError>
+        where F: FnMut(&GGUFTensorInfo) -> Result<Cow<'a, [u8]>, anyhow::Error>
+    {
+        if self.is_same_file(path) {
+            return Err(GgufError::write(format!("can't overwrite mapped file {}", path)));
+        }
+        let output = File::create(path).map_err(|err| GgufError::Io { offset: 0, source: err })?;
+        let mut writer = BufWriter::new(output);
+        self.write_to(&mut writer, tensor_data)?;
+        writer.flush().map_err(|err| GgufError::Io { offset: 0, source: err })?;
+        Ok(())
+    }
+
+    pub fn write_to<'a, W: Write, F>(
+        &self,
+        writer: &mut W,
+        mut tensor_data: F
+    ) -> Result<(), GgufError>
+        where F: FnMut(&GGUFTensorInfo) -> Result<Cow<'a, [u8]>, anyhow::Error>
+    {
+        let alignment = self.read_alignment().map_err(|err| GgufError::write(err.to_string()))?;
+        // The byte order is kept, tensor data is copied as is.
+        let mut writer = GGUFWriter::new(writer, self.header.byte_order);
+
+        self.write_header(&mut writer)?;
+        for metadata in self.header.metadata_kv.iter() {
+            writer.write_string(&metadata.key)?;
+            writer.write_number(metadata.value.value_type() as u32)?;
+            self.write_metadata_value(&mut writer, &metadata.value)?;
+        }
+
+        let offsets = self.compute_tensor_offsets(alignment);
+        for (tensor, offset) in self.tensors.iter().zip(offsets.iter()) {
+            writer.write_string(&tensor.name)?;
+            writer.write_number(tensor.dimensions.len() as u32)?;
+            for dimension in tensor.dimensions.iter() {
+                writer.write_number(*dimension)?;
+            }
+            writer.write_number(tensor.tensor_type as u32)?;
+            writer.write_number(*offset)?;
+        }
+        writer.write_padding(alignment)?;
+
+        // The data section starts aligned, so do the tensors.
+        for tensor in self.tensors.iter() {
+            // Errors of the source are kept as is when they come from a GGUF file.
+            let data = tensor_data(tensor).map_err(|err| {
+                err.downcast::<GgufError>().unwrap_or_else(|err| GgufError::write(err.to_string()))
+            })?;
+            if (data.len() as u64) != tensor.size() {
+                return Err(
+                    GgufError::write(
+                        format!(
+                            "tensor {} data size mismatch: {} != {}",
+                            tensor.name,
+                            data.len(),
+                            tensor.size()
+                        )
+                    )
+                );
+            }
+            writer.write_bytes(&data)?;
+            writer.write_padding(alignment)?;
+        }
+        Ok(())
+    }
+
+    // Offsets of the tensors in the data section once written with the given alignment.
+    pub fn compute_tensor_offsets(&self, alignment: u64) -> Vec<u64> {
+        let mut offset = 0;
+        self.tensors
+            .iter()
+            .map(|tensor| {
+                let tensor_offset = offset;
+                offset = align_offset(offset + tensor.size(), alignment);
+                tensor_offset
+            })
+            .collect()
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: GGUFMetadataValue) {
+        let value_type = value.value_type();
+        match self.header.metadata_kv.iter_mut().find(|m| m.key == key) {
+            Some(metadata) => {
+                metadata.value = value;
+                metadata.value_type = value_type;
+            }
+            None => {
+                self.header.metadata_kv.push(super::GGUFMetadata {
+                    key: key.to_string(),
+                    value_type,
+                    value,
+                });
+            }
+        }
+        self.header.metadata_kv_count = self.header.metadata_kv.len() as u64;
+    }
+
+    pub fn remove_metadata(&mut self, key: &str) -> Option<super::GGUFMetadata> {
+        let index = self.header.metadata_kv.iter().position(|m| m.key == key)?;
+        let metadata = self.header.metadata_kv.remove(index);
+        self.header.metadata_kv_count = self.header.metadata_kv.len() as u64;
+        Some(metadata)
+    }
+
+    fn is_same_file(&self, path: &str) -> bool {
+        if self.splits.iter().any(|shard| shard.is_same_file(path)) {
+            return true;
+        }
+        if self.mmap.is_none() {
+            return false;
+        }
+        match (fs::canonicalize(Path::new(path)), fs::canonicalize(&self.file_name)) {
+            (Ok(path), Ok(file_name)) => path == file_name,
+            _ => false,
+        }
+    }
+
+    fn write_header<W: Write>(&self, writer: &mut GGUFWriter<W>) -> Result<(), GgufError> {
+        // v1 used 32-bit lengths, files are upgraded to the current layout.
+        let version = match self.header.version {
+            2 | 3 => self.header.version,
+            _ => GGUF_VERSION,
+        };
+        writer.write_bytes(&GGUF_MAGIC)?;
+        writer.write_number(version)?;
+        writer.write_number(self.tensors.len() as u64)?;
+        writer.write_number(self.header.metadata_kv.len() as u64)
+    }
+
+    fn write_metadata_value<W: Write>(
+        &self,
+        writer: &mut GGUFWriter<W>,
+        value: &GGUFMetadataValue
+    ) -> Result<(), GgufError> {
+        match value {
+            GGUFMetadataValue::Uint8(v) => writer.write_number(*v),
+            GGUFMetadataValue::Int8(v) => writer.write_number(*v),
+            GGUFMetadataValue::Uint16(v) => writer.write_number(*v),
+            GGUFMetadataValue::Int16(v) => writer.write_number(*v),
+            GGUFMetadataValue::Uint32(v) => writer.write_number(*v),
+            GGUFMetadataValue::Int32(v) => writer.write_number(*v),
+            GGUFMetadataValue::Float32(v) => writer.write_number(*v),
+            GGUFMetadataValue::Uint64(v) => writer.write_number(*v),
+            GGUFMetadataValue::Int64(v) => writer.write_number(*v),
+            GGUFMetadataValue::Float64(v) => writer.write_number(*v),
+            GGUFMetadataValue::Bool(v) => writer.write_bytes(&[*v as u8]),
+            GGUFMetadataValue::String(v) => writer.write_string(v),
+            GGUFMetadataValue::Array(v) => self.write_metadata_array(writer, v),
+        }
+    }
+
+    fn write_metadata_array<W: Write>(
+        &self,
+        writer: &mut GGUFWriter<W>,
+        array: &GGUFMetadataArrayValue
+    ) -> Result<(), GgufError> {
+        let values = self.get_array_values(array)?;
+        writer.write_number(array.value_type as u32)?;
+        writer.write_number(values.len() as u64)?;
+        for value in values.iter() {
+            if value.value_type() != array.value_type {
+                return Err(
+                    GgufError::write(
+                        format!(
+                            "array value type mismatch: {:?} != {:?}",
+                            value.value_type(),
+                            array.value_type
+                        )
+                    )
+                );
+            }
+            self.write_metadata_value(writer, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl GGUFMetadataValue {
+    pub fn value_type(&self) -> GGUFMetadataValueType {
+        match self {
+            GGUFMetadataValue::Uint8(_) => GGUFMetadataValueType::UInt8,
+            GGUFMetadataValue::Int8(_) => GGUFMetadataValueType::Int8,
+            GGUFMetadataValue::Uint16(_) => GGUFMetadataValueType::UInt16,
+            GGUFMetadataValue::Int16(_) => GGUFMetadataValueType::Int16,
+            GGUFMetadataValue::Uint32(_) => GGUFMetadataValueType::UInt32,
+            GGUFMetadataValue::Int32(_) => GGUFMetadataValueType::Int32,
+            GGUFMetadataValue::Float32(_) => GGUFMetadataValueType::Float32,
+            GGUFMetadataValue::Uint64(_) => GGUFMetadataValueType::UInt64,
+            GGUFMetadataValue::Int64(_) => GGUFMetadataValueType::Int64,
+            GGUFMetadataValue::Float64(_) => GGUFMetadataValueType::Float64,
+            GGUFMetadataValue::Bool(_) => GGUFMetadataValueType::Bool,
+            GGUFMetadataValue::String(_) => GGUFMetadataValueType::String,
+            GGUFMetadataValue::Array(_) => GGUFMetadataValueType::Array,
+        }
+    }
+}
+
+// Numbers written in the byte order of the file.
+trait ToBytes {
+    fn to_bytes(&self, byte_order: GGUFByteOrder) -> Vec<u8>;
+}
+
+macro_rules! impl_to_bytes {
+    ($($type:ty),*) => {
+        $(
+            impl ToBytes for $type {
+                fn to_bytes(&self, byte_order: GGUFByteOrder) -> Vec<u8> {
+                    match byte_order {
+                        GGUFByteOrder::LittleEndian => self.to_le_bytes().to_vec(),
+                        GGUFByteOrder::BigEndian => self.to_be_bytes().to_vec(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_to_bytes!(u8, i8, u16, i16, u32, i32, f32, u64, i64, f64);
+
+// Keeps track of the position in the file, to pad the sections
+// and to report where a write failed.
+struct GGUFWriter<'w, W: Write> {
+    writer: &'w mut W,
+    byte_order: GGUFByteOrder,
+    position: u64,
+}
+
+impl<'w, W: Write> GGUFWriter<'w, W> {
+    fn new(writer: &'w mut W, byte_order: GGUFByteOrder) -> GGUFWriter<'w, W> {
+        GGUFWriter { writer, byte_order, position: 0 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), GgufError> {
+        self.writer
+            .write_all(bytes)
+            .map_err(|err| GgufError::Io { offset: self.position, source: err })?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_number<T: ToBytes>(&mut self, value: T) -> Result<(), GgufError> {
+        self.write_bytes(&value.to_bytes(self.byte_order))
+    }
+
+    fn write_string(&mut self, value: &str) -> Result<(), GgufError> {
+        self.write_number(value.len() as u64)?;
+        self.write_bytes(value.as_bytes())
+    }
+
+    fn write_padding(&mut self, alignment: u64) -> Result<(), GgufError> {
+        let padding = align_offset(self.position, alignment) - self.position;
+        self.write_bytes(&vec![0; padding as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        gguf::{
+            GGMLType,
+            GGUFMetadataArrayValue,
+            GGUFMetadataValue,
+            GGUFMetadataValueType,
+            GgufError,
+            GGUF,
+            GGUF_LAZY_ARRAY_LEN,
+        },
+        testing::{ create_model, temp_path, tensor, write_model },
+    };
+
+    // Written by candle-core 0.9.1 (quantized::gguf_file::write), not by this writer:
+    // a v2 file with every value type, nested arrays and F32, F16, Q8_0 and Q4_0 tensors.
+    const CANDLE_FILE: &[u8] = include_bytes!("../../../tests/fixtures/candle-v2.gguf");
+
+    fn string_array(len: u64) -> GGUFMetadataValue {
+        GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+            value_type: GGUFMetadataValueType::String,
+            len,
+            value: (0..len).map(|i| GGUFMetadataValue::String(format!("token{}", i))).collect(),
+            offset: None,
+        })
+    }
+
+    fn create_llama() -> GGUF {
+        create_model(
+            "llama",
+            &[
+                ("general.name", GGUFMetadataValue::String("test".to_string())),
+                ("llama.context_length", GGUFMetadataValue::Uint32(4096)),
+                ("llama.rope.freq_base", GGUFMetadataValue::Float32(10000.0)),
+                ("tokenizer.ggml.add_bos_token", GGUFMetadataValue::Bool(true)),
+                ("tokenizer.ggml.tokens", string_array(GGUF_LAZY_ARRAY_LEN + 10)),
+            ],
+            &[
+                tensor("token_embd.weight", &[3, 5], GGMLType::F32),
+                tensor("blk.0.attn_q.weight", &[64, 2], GGMLType::Q8_0),
+            ]
+        )
+    }
+
+    #[test]
+    fn round_trip_is_identical() {
+        let first = temp_path("round-trip-first");
+        let second = temp_path("round-trip-second");
+        write_model(&create_llama(), &first);
+
+        let mut gguf = GGUF::new(first.to_str().unwrap());
+        gguf.read(first.to_str().unwrap()).unwrap();
+        assert_eq!(gguf.header.metadata_kv.len(), 6);
+        assert_eq!(gguf.tensors.len(), 2);
+        assert_eq!(gguf.tensors[1].offset, 64);
+        gguf.write(second.to_str().unwrap()).unwrap();
+
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn patch_metadata() {
+        let source = temp_path("patch-source");
+        let patched = temp_path("patch-patched");
+        write_model(&create_llama(), &source);
+
+        let mut gguf = GGUF::new(source.to_str().unwrap());
+        gguf.read(source.to_str().unwrap()).unwrap();
+        gguf.set_metadata("general.name", GGUFMetadataValue::String("patched".to_string()));
+        gguf.set_metadata(
+            "tokenizer.chat_template",
+            GGUFMetadataValue::String("{{ messages }}".to_string())
+        );
+        gguf.remove_metadata("llama.rope.freq_base");
+        gguf.write(patched.to_str().unwrap()).unwrap();
+
+        let mut result = GGUF::new(patched.to_str().unwrap());
+        result.read(patched.to_str().unwrap()).unwrap();
+        assert_eq!(result.get_metadata_value("general.name").unwrap().to_string(), "patched");
+        assert!(result.get_metadata_value("tokenizer.chat_template").is_some());
+        assert!(result.get_metadata_value("llama.rope.freq_base").is_none());
+        assert_eq!(result.get_metadata_array("tokenizer.ggml.tokens").unwrap().len(), 1034);
+        for tensor in result.tensors.iter() {
+            let original = gguf.get_tensor_info(&tensor.name).unwrap();
+            assert_eq!(
+                result.get_tensor_data(tensor).unwrap(),
+                gguf.get_tensor_data(original).unwrap()
+            );
+        }
+        fs::remove_file(source).unwrap();
+        fs::remove_file(patched).unwrap();
+    }
+
+    #[test]
+    fn refuse_to_overwrite_mapped_file() {
+        let path = temp_path("overwrite");
+        write_model(&create_llama(), &path);
+
+        let mut gguf = GGUF::new(path.to_str().unwrap());
+        gguf.read(path.to_str().unwrap()).unwrap();
+        assert!(matches!(gguf.write(path.to_str().unwrap()), Err(GgufError::Write { .. })));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rewrite_external_file() {
+        let source = temp_path("external-source");
+        let rewritten = temp_path("external-rewritten");
+        fs::write(&source, CANDLE_FILE).unwrap();
+
+        let mut gguf = GGUF::new(source.to_str().unwrap());
+        gguf.read(source.to_str().unwrap()).unwrap();
+        assert_eq!(gguf.header.version, 2);
+        assert_eq!(gguf.header.metadata_kv.len(), 18);
+        assert_eq!(gguf.tensors.len(), 4);
+        gguf.write(rewritten.to_str().unwrap()).unwrap();
+
+        assert_eq!(fs::read(&rewritten).unwrap(), CANDLE_FILE);
+        fs::remove_file(source).unwrap();
+        fs::remove_file(rewritten).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{ self, Read, Seek, SeekFrom };
+use ureq::Agent;
+
+pub const HTTP_RANGE_CHUNK_SIZE: u64 = 1024 * 1024;
+
+// A Read + Seek source over a remote file, fetched by chunks using HTTP range requests.
+// Only the chunks that are read are downloaded.
+pub struct HttpRangeReader {
+    agent: Agent,
+    url: String,
+    len: u64,
+    position: u64,
+    chunk_size: u64,
+    chunk_start: u64,
+    chunk: Vec<u8>,
+}
+
+impl HttpRangeReader {
+    pub fn new(url: &str) -> Result<Self, io::Error> {
+        Self::with_chunk_size(url, HTTP_RANGE_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(url: &str, chunk_size: u64) -> Result<Self, io::Error> {
+        let mut reader = HttpRangeReader {
+            agent: Agent::new_with_defaults(),
+            url: url.to_string(),
+            len: 0,
+            position: 0,
+            chunk_size: chunk_size.max(1),
+            chunk_start: 0,
+            chunk: Vec::new(),
+        };
+        // The first request also gives the total length of the file.
+        let (chunk, len) = reader.fetch(0)?;
+        reader.chunk = chunk;
+        reader.len = len;
+        Ok(reader)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn fetch(&self, start: u64) -> Result<(Vec<u8>, u64), io::Error> {
+        let end = start + self.chunk_size - 1;
+        let mut response = self.agent
+            .get(&self.url)
+            .header("Range", &format!("bytes={}-{}", start, end))
+            .call()
+            .map_err(io::Error::other)?;
+        if response.status() != 206 {
+            return Err(
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("range requests not supported: {} {}", response.status(), self.url)
+                )
+            );
+        }
+        let len = response
+            .headers()
+            .get("content-range")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_content_range_length)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid content-range"))?;
+        let chunk = response
+            .body_mut()
+            .with_config()
+            .limit(self.chunk_size + 1)
+            .read_to_vec()
+            .map_err(io::Error::other)?;
+        Ok((chunk, len))
+    }
+}
+
+// Total length from a "bytes 0-1023/146515" header value.
+fn parse_content_range_length(value: &str) -> Option<u64> {
+    let (unit, range) = value.trim().split_once(' ')?;
+    if unit != "bytes" {
+        return None;
+    }
+    range.rsplit_once('/')?.1.parse().ok()
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_end = self.chunk_start + (self.chunk.len() as u64);
+        if self.position < self.chunk_start || self.position >= chunk_end {
+            let start = self.position - (self.position % self.chunk_size);
+            let (chunk, _) = self.fetch(start)?;
+            if chunk.is_empty() {
+                return Ok(0);
+            }
+            self.chunk_start = start;
+            self.chunk = chunk;
+        }
+        let offset = (self.position - self.chunk_start) as usize;
+        let available = &self.chunk[offset..];
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None =>
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position")),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{ io::{ BufRead, BufReader, Read, Seek, SeekFrom, Write }, net::TcpListener, thread };
+
+    use super::HttpRangeReader;
+    use crate::gguf::{
+        GGUFMetadataArrayValue,
+        GGUFMetadataValue,
+        GGUFMetadataValueType,
+        GGUF,
+        GGUF_LAZY_ARRAY_LEN,
+    };
+
+    // Minimal HTTP server answering range requests on a static content, returns its url.
+    pub fn serve(content: Vec<u8>, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.gguf", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                    }
+                }
+                let (start, end) = range.unwrap();
+                let end = end.min(content.len() - 1);
+                let body = &content[start..=end];
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                    body.len(),
+                    start,
+                    end,
+                    content.len()
+                ).unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let content: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+        let url = serve(content.clone(), 3);
+        let mut reader = HttpRangeReader::with_chunk_size(&url, 256).unwrap();
+        assert_eq!(reader.len(), 1000);
+
+        let mut buffer = [0; 300];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &content[..300]);
+
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &content[990..]);
+    }
+
+    #[test]
+    fn read_remote_gguf_header() {
+        let mut gguf = GGUF::new("model.gguf");
+        gguf.set_metadata("general.architecture", GGUFMetadataValue::String("llama".to_string()));
+        gguf.set_metadata("llama.context_length", GGUFMetadataValue::Uint32(8192));
+        let len = GGUF_LAZY_ARRAY_LEN + 1;
+        gguf.set_metadata(
+            "tokenizer.ggml.scores",
+            GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+                value_type: GGUFMetadataValueType::Float32,
+                len,
+                value: (0..len).map(|i| GGUFMetadataValue::Float32(i as f32)).collect(),
+                offset: None,
+            })
+        );
+        let mut content = Vec::new();
+        gguf.write_to(&mut content, |_| unreachable!()).unwrap();
+        let url = serve(content, 2);
+
+        let mut remote = GGUF::new(&url);
+        remote.read_url(&url).unwrap();
+        assert_eq!(remote.get_architecture(), Some("llama"));
+        assert_eq!(remote.get_metadata_u64("llama.context_length"), Some(8192));
+
+        let scores = match remote.get_metadata_value("tokenizer.ggml.scores") {
+            Some(GGUFMetadataValue::Array(array)) => array.clone(),
+            _ => panic!("scores not found"),
+        };
+        assert!(!scores.is_loaded());
+        let reader = HttpRangeReader::new(&url).unwrap();
+        let values = remote.read_array_values_from(reader, &scores).unwrap();
+        assert_eq!(values.len() as u64, len);
+    }
+}
@@ -13,3 +13,6 @@
 // limitations under the License.
 
 pub mod gguf;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod safetensors;
@@ -0,0 +1,256 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeMap,
+    fs::{ self, File },
+    io::{ Cursor, Read, Seek, SeekFrom },
+    path::Path,
+    sync::Arc,
+};
+use memmap2::Mmap;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use crate::gguf::{ dequantize, GGMLType, GGUFByteOrder, GGUFTensorInfo };
+
+// Same limit as the safetensors crate.
+pub const SAFETENSORS_MAX_HEADER_SIZE: u64 = 100_000_000;
+
+#[derive(Clone, Debug, Deserialize)]
+struct SafeTensorsEntry {
+    dtype: String,
+    shape: Vec<u64>,
+    data_offsets: (u64, u64),
+}
+
+// Header of a .safetensors file: a JSON table of tensors followed by their data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SafeTensors {
+    pub file_name: String,
+    pub metadata: BTreeMap<String, String>,
+    // Dimensions are in ggml order, the fastest changing first, offsets are
+    // relative to the start of the data section.
+    pub tensors: Vec<GGUFTensorInfo>,
+    // Absolute position in the file of the tensor data section.
+    pub tensor_data_offset: u64,
+    // Content of the config.json of the model, if any.
+    pub config: Option<Value>,
+    #[serde(skip)]
+    mmap: Option<Arc<Mmap>>,
+}
+
+// Tensor types of safetensors that ggml can represent.
+pub fn dtype_to_ggml_type(dtype: &str) -> Option<GGMLType> {
+    let tensor_type = match dtype {
+        "F64" => GGMLType::F64,
+        "F32" => GGMLType::F32,
+        "F16" => GGMLType::F16,
+        "BF16" => GGMLType::BF16,
+        "I64" => GGMLType::I64,
+        "I32" => GGMLType::I32,
+        "I16" => GGMLType::I16,
+        "I8" => GGMLType::I8,
+        _ => {
+            return None;
+        }
+    };
+    Some(tensor_type)
+}
+
+impl SafeTensors {
+    pub fn new(file_name: &str) -> SafeTensors {
+        SafeTensors {
+            file_name: file_name.to_string(),
+            metadata: BTreeMap::new(),
+            tensors: Vec::new(),
+            tensor_data_offset: 0,
+            config: None,
+            mmap: None,
+        }
+    }
+
+    // Reads the header, and the config.json in the same directory if there is one.
+    pub fn read(&mut self, path: &str) -> Result<(), String> {
+        let input = File::open(path).map_err(|err| err.to_string())?;
+        // Safety: the file is only read, and is expected not to be modified while mapped.
+        let mmap = Arc::new(unsafe { Mmap::map(&input) }.map_err(|err| err.to_string())?);
+        self.read_from(Cursor::new(&mmap[..]))?;
+        self.mmap = Some(mmap);
+        if let Some(config) = Path::new(path).parent().map(|parent| parent.join("config.json")) {
+            if config.exists() {
+                self.read_config(config.to_str().unwrap_or_default())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_config(&mut self, path: &str) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        self.config = Some(
+            serde_json::from_str(&content).map_err(|err| format!("Invalid {}: {}", path, err))?
+        );
+        Ok(())
+    }
+
+    // Reads the header from any source, tensor data is not read.
+    pub fn read_from<R: Read + Seek>(&mut self, mut reader: R) -> Result<(), String> {
+        let len = reader.seek(SeekFrom::End(0)).map_err(|err| err.to_string())?;
+        reader.seek(SeekFrom::Start(0)).map_err(|err| err.to_string())?;
+        let mut size = [0u8; 8];
+        reader.read_exact(&mut size).map_err(|err| format!("Invalid safetensors file: {}", err))?;
+        let header_size = u64::from_le_bytes(size);
+        if header_size > SAFETENSORS_MAX_HEADER_SIZE || header_size + 8 > len {
+            return Err(format!("Invalid safetensors header size: {}", header_size));
+        }
+        let mut header = vec![0u8; header_size as usize];
+        reader.read_exact(&mut header).map_err(|err| err.to_string())?;
+        let header: BTreeMap<String, Value> = serde_json
+            ::from_slice(&header)
+            .map_err(|err| format!("Invalid safetensors header: {}", err))?;
+
+        self.tensor_data_offset = 8 + header_size;
+        self.metadata = BTreeMap::new();
+        self.tensors = Vec::new();
+        for (name, value) in header {
+            if name == "__metadata__" {
+                self.metadata = serde_json
+                    ::from_value(value)
+                    .map_err(|err| format!("Invalid safetensors metadata: {}", err))?;
+                continue;
+            }
+            let entry: SafeTensorsEntry = serde_json
+                ::from_value(value)
+                .map_err(|err| format!("Invalid tensor {}: {}", name, err))?;
+            let tensor_type = dtype_to_ggml_type(&entry.dtype).ok_or_else(||
+                format!("Unsupported dtype {} for tensor {}", entry.dtype, name)
+            )?;
+            let (start, end) = entry.data_offsets;
+            let tensor = GGUFTensorInfo {
+                name,
+                n_dimensions: entry.shape.len() as u32,
+                dimensions: entry.shape.iter().rev().copied().collect(),
+                tensor_type,
+                offset: start,
+            };
+            if end < start || end - start != tensor.size() || self.tensor_data_offset + end > len {
+                return Err(format!("Invalid data offsets for tensor {}", tensor.name));
+            }
+            self.tensors.push(tensor);
+        }
+        // Same order as the data.
+        self.tensors.sort_by_key(|tensor| tensor.offset);
+        Ok(())
+    }
+
+    pub fn get_tensor_info(&self, name: &str) -> Option<&GGUFTensorInfo> {
+        self.tensors.iter().find(|t| t.name == name)
+    }
+
+    // Borrowed tensor data from the mapped file, no copy is made.
+    pub fn get_tensor_data(&self, tensor: &GGUFTensorInfo) -> Result<&[u8], String> {
+        let data = match self.mmap {
+            Some(ref mmap) => &mmap[..],
+            None => {
+                return Err(format!("file not mapped {}", self.file_name));
+            }
+        };
+        let start = self.tensor_data_offset.saturating_add(tensor.offset);
+        let end = start.saturating_add(tensor.size());
+        if end > (data.len() as u64) {
+            return Err(format!("Truncated tensor {} in {}", tensor.name, self.file_name));
+        }
+        Ok(&data[start as usize..end as usize])
+    }
+
+    // Safetensors data is always little-endian.
+    pub fn get_tensor_f32(&self, tensor: &GGUFTensorInfo) -> Result<Vec<f32>, String> {
+        dequantize(tensor.tensor_type, self.get_tensor_data(tensor)?, GGUFByteOrder::LittleEndian)
+    }
+
+    // Total size in bytes of the tensors' data.
+    pub fn tensors_size(&self) -> u64 {
+        self.tensors
+            .iter()
+            .map(|t| t.size())
+            .sum()
+    }
+
+    // A value of config.json, multimodal models keep the language model one in text_config.
+    pub fn get_config_value(&self, key: &str) -> Option<&Value> {
+        let config = self.config.as_ref()?;
+        config
+            .get("text_config")
+            .and_then(|text_config| text_config.get(key))
+            .or_else(|| config.get(key))
+    }
+
+    pub fn get_config_u64(&self, key: &str) -> Option<u64> {
+        match self.get_config_value(key)? {
+            // eos_token_id can be a list.
+            Value::Array(values) => values.first().and_then(|v| v.as_u64()),
+            value => value.as_u64(),
+        }
+    }
+
+    pub fn get_config_f64(&self, key: &str) -> Option<f64> {
+        self.get_config_value(key).and_then(|v| v.as_f64())
+    }
+
+    pub fn get_config_string(&self, key: &str) -> Option<&str> {
+        self.get_config_value(key).and_then(|v| v.as_str())
+    }
+}
+
+#[cfg(feature = "http")]
+impl SafeTensors {
+    // Reads the header of a remote file using HTTP range requests.
+    pub fn read_url(&mut self, url: &str) -> Result<(), String> {
+        let reader = crate::http::HttpRangeReader::new(url).map_err(|err| err.to_string())?;
+        self.read_from(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::gguf::GGMLType;
+    use super::SafeTensors;
+
+    fn create_file(header: &str, data_size: usize) -> Vec<u8> {
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header.as_bytes());
+        file.extend(vec![0; data_size]);
+        file
+    }
+
+    #[test]
+    fn read_header() {
+        let header =
+            r#"{"__metadata__":{"format":"pt"},"model.norm.weight":{"dtype":"BF16","shape":[8],"data_offsets":[48,64]},"lm_head.weight":{"dtype":"F32","shape":[4,3],"data_offsets":[0,48]}}"#;
+        let mut safetensors = SafeTensors::new("model.safetensors");
+        safetensors.read_from(Cursor::new(create_file(header, 64))).unwrap();
+        assert_eq!(safetensors.metadata["format"], "pt");
+        assert_eq!(safetensors.tensor_data_offset, 8 + (header.len() as u64));
+        assert_eq!(safetensors.tensors[0].name, "lm_head.weight");
+        assert_eq!(safetensors.tensors[0].dimensions, vec![3, 4]);
+        assert_eq!(safetensors.tensors[1].tensor_type, GGMLType::BF16);
+        assert_eq!(safetensors.tensors_size(), 64);
+
+        assert!(safetensors.read_from(Cursor::new(create_file(header, 32))).is_err());
+        let header = r#"{"weight":{"dtype":"F8_E4M3","shape":[8],"data_offsets":[0,8]}}"#;
+        assert!(safetensors.read_from(Cursor::new(create_file(header, 8))).is_err());
+    }
+}
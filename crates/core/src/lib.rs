@@ -12,5 +12,28 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod api;
 mod io;
+mod model;
+pub mod providers;
+mod server;
+mod store;
+#[cfg(test)]
+mod testing;
+pub use api::hf;
 pub use io::gguf;
+#[cfg(feature = "http")]
+pub use io::http;
+pub use io::safetensors;
+pub use model::adapter;
+pub use model::architecture;
+pub use model::chat_template;
+pub use model::convert;
+pub use model::descriptor;
+pub use model::memory;
+pub use server::configuration;
+pub use server::llama_cpp;
+pub use store::directories;
+pub use store::formats;
+pub use store::models;
+pub use store::server_storage;
@@ -0,0 +1,313 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use serde::{ Deserialize, Serialize };
+
+use crate::gguf::GGUF;
+
+const LORA_A_SUFFIX: &str = ".lora_a";
+const LORA_B_SUFFIX: &str = ".lora_b";
+
+// Pair of low-rank matrices applied to a tensor of the base model, dimensions are in
+// ggml order: lora_a is [n_in, rank] and lora_b is [rank, n_out].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoraTensor {
+    pub name: String,
+    pub a_dimensions: Vec<u64>,
+    pub b_dimensions: Vec<u64>,
+}
+
+// LoRA adapter, as written by convert_lora_to_gguf.py of llama.cpp.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoraAdapter {
+    pub name: Option<String>,
+    pub architecture: Option<String>,
+    pub alpha: Option<f64>,
+    pub rank: Option<u64>,
+    pub tensors: Vec<LoraTensor>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LoraCompatibility {
+    pub compatible: bool,
+    pub architecture: Option<String>,
+    pub base_architecture: Option<String>,
+    pub embedding_length: Option<u64>,
+    pub base_embedding_length: Option<u64>,
+    pub rank: Option<u64>,
+    pub alpha: Option<f64>,
+    // Number of base model tensors the adapter applies to.
+    pub tensor_count: usize,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl LoraCompatibility {
+    // A message for the user, None if the adapter can be used with the model.
+    pub fn to_error(&self) -> Option<String> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        let mut message = format!("Incompatible LoRA adapter: {}", self.errors
+            .iter()
+            .take(5)
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(", "));
+        if self.errors.len() > 5 {
+            message.push_str(&format!(" and {} more", self.errors.len() - 5));
+        }
+        Some(message)
+    }
+}
+
+// Adapters have general.type set to adapter, older ones are only recognized by their tensors.
+pub fn is_adapter(gguf: &GGUF) -> bool {
+    gguf.get_metadata_string("general.type") == Some("adapter") ||
+        gguf.get_metadata_value("adapter.type").is_some() ||
+        gguf.tensors.iter().any(|t| t.name.ends_with(LORA_A_SUFFIX))
+}
+
+impl LoraAdapter {
+    pub fn from_gguf(gguf: &GGUF) -> Result<LoraAdapter, String> {
+        if !is_adapter(gguf) {
+            return Err(format!("{} is not an adapter", gguf.file_name));
+        }
+        let adapter_type = gguf.get_metadata_string("adapter.type").unwrap_or("lora");
+        if adapter_type != "lora" {
+            return Err(format!("Unsupported adapter type: {}", adapter_type));
+        }
+
+        let mut pairs = BTreeMap::new();
+        for tensor in gguf.tensors.iter() {
+            let (name, is_a) = if let Some(name) = tensor.name.strip_suffix(LORA_A_SUFFIX) {
+                (name, true)
+            } else if let Some(name) = tensor.name.strip_suffix(LORA_B_SUFFIX) {
+                (name, false)
+            } else {
+                return Err(format!("Unexpected adapter tensor: {}", tensor.name));
+            };
+            let pair = pairs.entry(name.to_string()).or_insert((None, None));
+            if is_a {
+                pair.0 = Some(tensor.dimensions.clone());
+            } else {
+                pair.1 = Some(tensor.dimensions.clone());
+            }
+        }
+        let mut tensors = Vec::new();
+        for (name, pair) in pairs {
+            match pair {
+                (Some(a_dimensions), Some(b_dimensions)) => {
+                    tensors.push(LoraTensor { name, a_dimensions, b_dimensions });
+                }
+                (Some(_), None) => {
+                    return Err(format!("Missing tensor {}{}", name, LORA_B_SUFFIX));
+                }
+                _ => {
+                    return Err(format!("Missing tensor {}{}", name, LORA_A_SUFFIX));
+                }
+            }
+        }
+
+        Ok(LoraAdapter {
+            name: gguf.get_metadata_string("general.name").map(|v| v.to_string()),
+            architecture: gguf.get_architecture().map(|v| v.to_string()),
+            alpha: gguf.get_metadata_f64("adapter.lora.alpha"),
+            rank: tensors
+                .iter()
+                .filter_map(|t| t.a_dimensions.get(1).copied())
+                .max(),
+            tensors,
+        })
+    }
+
+    // Embedding size the adapter was trained with, the input size of the attention and
+    // feed forward projections.
+    pub fn embedding_length(&self) -> Option<u64> {
+        ["attn_q.weight", "attn_k.weight", "attn_v.weight", "ffn_up.weight", "ffn_gate.weight"]
+            .iter()
+            .find_map(|suffix| self.tensors.iter().find(|t| t.name.ends_with(suffix)))
+            .and_then(|t| t.a_dimensions.first().copied())
+    }
+
+    // Same checks as llama.cpp when it loads an adapter, all problems are reported.
+    pub fn check_compatibility(&self, base: &GGUF) -> LoraCompatibility {
+        let base_architecture = base.get_architecture().map(|v| v.to_string());
+        let mut compatibility = LoraCompatibility {
+            architecture: self.architecture.clone(),
+            base_embedding_length: base_architecture
+                .as_ref()
+                .and_then(|architecture| {
+                    base.get_metadata_u64(&format!("{}.embedding_length", architecture))
+                }),
+            base_architecture,
+            embedding_length: self.embedding_length(),
+            rank: self.rank,
+            alpha: self.alpha,
+            ..LoraCompatibility::default()
+        };
+
+        if is_adapter(base) {
+            compatibility.errors.push(format!("{} is an adapter, not a model", base.file_name));
+        }
+        match (&compatibility.architecture, &compatibility.base_architecture) {
+            (Some(architecture), Some(base_architecture)) if architecture != base_architecture => {
+                compatibility.errors.push(
+                    format!(
+                        "adapter architecture {} doesn't match model architecture {}",
+                        architecture,
+                        base_architecture
+                    )
+                );
+            }
+            (None, _) => compatibility.warnings.push("Adapter architecture is unknown".to_string()),
+            _ => {}
+        }
+        if
+            let (Some(length), Some(base_length)) = (
+                compatibility.embedding_length,
+                compatibility.base_embedding_length,
+            )
+        {
+            if length != base_length {
+                compatibility.errors.push(
+                    format!(
+                        "adapter embedding size {} doesn't match model embedding size {}",
+                        length,
+                        base_length
+                    )
+                );
+            }
+        }
+        if self.alpha.is_none() {
+            compatibility.warnings.push(
+                "adapter.lora.alpha is missing, the adapter is not scaled by its rank".to_string()
+            );
+        }
+
+        for tensor in self.tensors.iter() {
+            let base_tensor = match base.get_tensor_info(&tensor.name) {
+                Some(base_tensor) => base_tensor,
+                None => {
+                    compatibility.errors.push(format!("tensor {} not found in model", tensor.name));
+                    continue;
+                }
+            };
+            if tensor.a_dimensions.get(1) != tensor.b_dimensions.first() {
+                compatibility.errors.push(
+                    format!("tensor {} lora_a and lora_b ranks don't match", tensor.name)
+                );
+                continue;
+            }
+            if
+                base_tensor.dimensions.first() != tensor.a_dimensions.first() ||
+                base_tensor.dimensions.get(1) != tensor.b_dimensions.get(1)
+            {
+                compatibility.errors.push(
+                    format!(
+                        "tensor {} shape {:?} doesn't match model shape {:?}",
+                        tensor.name,
+                        [tensor.a_dimensions.first(), tensor.b_dimensions.get(1)]
+                            .iter()
+                            .flatten()
+                            .collect::<Vec<_>>(),
+                        base_tensor.dimensions
+                    )
+                );
+                continue;
+            }
+            compatibility.tensor_count += 1;
+        }
+        if self.tensors.is_empty() {
+            compatibility.errors.push("adapter has no tensors".to_string());
+        }
+        compatibility.compatible = compatibility.errors.is_empty();
+        compatibility
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gguf::{ GGMLType, GGUFMetadataValue, GGUF },
+        testing::{ create_model, tensor },
+    };
+    use super::{ is_adapter, LoraAdapter };
+
+    fn create_llama(embedding_length: u64) -> GGUF {
+        create_model(
+            "llama",
+            &[("llama.embedding_length", GGUFMetadataValue::Uint32(embedding_length as u32))],
+            &[
+                tensor("blk.0.attn_q.weight", &[embedding_length, embedding_length], GGMLType::F16),
+                tensor("blk.0.attn_k.weight", &[embedding_length, 1024], GGMLType::F16),
+            ]
+        )
+    }
+
+    fn create_adapter(embedding_length: u64, rank: u64) -> GGUF {
+        let lora = |name: &str, dimensions: &[u64]| tensor(name, dimensions, GGMLType::F16);
+        create_model(
+            "llama",
+            &[
+                ("general.type", GGUFMetadataValue::String("adapter".to_string())),
+                ("adapter.type", GGUFMetadataValue::String("lora".to_string())),
+                ("adapter.lora.alpha", GGUFMetadataValue::Float32(32.0)),
+            ],
+            &[
+                lora("blk.0.attn_q.weight.lora_a", &[embedding_length, rank]),
+                lora("blk.0.attn_q.weight.lora_b", &[rank, embedding_length]),
+                lora("blk.0.attn_k.weight.lora_a", &[embedding_length, rank]),
+                lora("blk.0.attn_k.weight.lora_b", &[rank, 1024]),
+            ]
+        )
+    }
+
+    #[test]
+    fn compatible_adapter() {
+        let model = create_llama(4096);
+        assert!(!is_adapter(&model));
+        assert!(LoraAdapter::from_gguf(&model).is_err());
+
+        let adapter = LoraAdapter::from_gguf(&create_adapter(4096, 16)).unwrap();
+        assert_eq!(adapter.rank, Some(16));
+        assert_eq!(adapter.embedding_length(), Some(4096));
+        let compatibility = adapter.check_compatibility(&model);
+        assert!(compatibility.compatible, "{:?}", compatibility.errors);
+        assert_eq!(compatibility.tensor_count, 2);
+        assert!(compatibility.warnings.is_empty());
+        assert!(compatibility.to_error().is_none());
+    }
+
+    #[test]
+    fn incompatible_adapter() {
+        let mut gguf = create_adapter(2048, 8);
+        gguf.set_metadata("general.architecture", GGUFMetadataValue::String("qwen2".to_string()));
+        gguf.tensors.push(tensor("blk.1.attn_q.weight.lora_a", &[2048, 8], GGMLType::F16));
+        gguf.tensors.push(tensor("blk.1.attn_q.weight.lora_b", &[8, 2048], GGMLType::F16));
+        let adapter = LoraAdapter::from_gguf(&gguf).unwrap();
+        let compatibility = adapter.check_compatibility(&create_llama(4096));
+        assert!(!compatibility.compatible);
+        assert_eq!(compatibility.errors.len(), 5);
+        assert!(compatibility.errors[0].contains("architecture qwen2"));
+        assert!(compatibility.errors[1].contains("embedding size 2048"));
+        assert!(compatibility.errors[2].contains("blk.0.attn_k.weight shape [2048, 1024]"));
+        assert!(compatibility.errors.iter().any(|e| e.contains("blk.1.attn_q.weight not found")));
+        assert!(compatibility.to_error().unwrap().starts_with("Incompatible LoRA adapter"));
+
+        gguf.tensors.pop();
+        assert!(LoraAdapter::from_gguf(&gguf).unwrap_err().contains("blk.1.attn_q.weight.lora_b"));
+    }
+}
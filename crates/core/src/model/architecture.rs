@@ -0,0 +1,606 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use serde::{ Deserialize, Serialize };
+
+use crate::gguf::GGUF;
+
+// Known model architecture, as named in general.architecture. Keys are given without the
+// architecture prefix, "{bid}" in a tensor name stands for every block index.
+#[derive(Clone, Debug, Serialize)]
+pub struct ArchitectureDefinition {
+    pub name: &'static str,
+    // Hugging Face model_type values converted to this architecture.
+    pub hf_model_types: &'static [&'static str],
+    // Keys llama.cpp can't load the model without.
+    pub required_keys: &'static [&'static str],
+    // Other hyperparameters read when present.
+    pub hyperparameters: &'static [&'static str],
+    pub required_tensors: &'static [&'static str],
+    // Uses rotary position embeddings.
+    pub rope: bool,
+}
+
+const TRANSFORMER_KEYS: &[&str] = &[
+    "context_length",
+    "embedding_length",
+    "block_count",
+    "attention.head_count",
+];
+
+const SSM_KEYS: &[&str] = &[
+    "context_length",
+    "embedding_length",
+    "block_count",
+    "ssm.conv_kernel",
+    "ssm.inner_size",
+    "ssm.state_size",
+];
+
+const RWKV_KEYS: &[&str] = &["context_length", "embedding_length", "block_count", "wkv.head_size"];
+
+const TRANSFORMER_HYPERPARAMETERS: &[&str] = &[
+    "feed_forward_length",
+    "attention.head_count_kv",
+    "attention.key_length",
+    "attention.value_length",
+    "attention.layer_norm_epsilon",
+    "attention.layer_norm_rms_epsilon",
+    "attention.sliding_window",
+    "rope.dimension_count",
+    "rope.freq_base",
+    "rope.scaling.type",
+    "rope.scaling.factor",
+    "rope.scaling.original_context_length",
+    "expert_count",
+    "expert_used_count",
+    "vocab_size",
+];
+
+const SSM_HYPERPARAMETERS: &[&str] = &["ssm.time_step_rank", "ssm.group_count", "vocab_size"];
+
+const RWKV_HYPERPARAMETERS: &[&str] = &[
+    "feed_forward_length",
+    "attention.layer_norm_epsilon",
+    "time_mix_extra_dim",
+    "time_decay_extra_dim",
+    "rescale_every_n_layers",
+    "vocab_size",
+];
+
+const LLAMA_TENSORS: &[&str] = &[
+    "token_embd.weight",
+    "output_norm.weight",
+    "blk.{bid}.attn_norm.weight",
+    "blk.{bid}.attn_q.weight",
+    "blk.{bid}.attn_k.weight",
+    "blk.{bid}.attn_v.weight",
+    "blk.{bid}.attn_output.weight",
+    "blk.{bid}.ffn_norm.weight",
+];
+
+const fn transformer(
+    name: &'static str,
+    hf_model_types: &'static [&'static str],
+    required_tensors: &'static [&'static str],
+    rope: bool
+) -> ArchitectureDefinition {
+    ArchitectureDefinition {
+        name,
+        hf_model_types,
+        required_keys: TRANSFORMER_KEYS,
+        hyperparameters: TRANSFORMER_HYPERPARAMETERS,
+        required_tensors,
+        rope,
+    }
+}
+
+// Architectures of the bundled llama.cpp whose keys and tensors are known,
+// llama.cpp loads more of them.
+pub const ARCHITECTURES: &[ArchitectureDefinition] = &[
+    transformer("llama", &["llama", "mistral", "mixtral"], LLAMA_TENSORS, true),
+    transformer(
+        "qwen2",
+        &["qwen2"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_q.bias",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_k.bias",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_v.bias",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+        ],
+        true
+    ),
+    transformer(
+        "qwen3",
+        &["qwen3"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_q_norm.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_k_norm.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+        ],
+        true
+    ),
+    transformer(
+        "gemma",
+        &["gemma"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_gate.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "gemma2",
+        &["gemma2"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.post_attention_norm.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_gate.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+            "blk.{bid}.post_ffw_norm.weight",
+        ],
+        true
+    ),
+    transformer(
+        "gemma3",
+        &["gemma3", "gemma3_text"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_q_norm.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_k_norm.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.post_attention_norm.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_gate.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+            "blk.{bid}.post_ffw_norm.weight",
+        ],
+        true
+    ),
+    transformer(
+        "phi2",
+        &["phi"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "output.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "phi3",
+        &["phi3"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "starcoder2",
+        &["starcoder2"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "stablelm",
+        &["stablelm"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+        ],
+        true
+    ),
+    transformer(
+        "command-r",
+        &["cohere"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_gate.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "falcon",
+        &["falcon"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_qkv.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "gptneox",
+        &["gpt_neox"],
+        &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "output.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_qkv.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        true
+    ),
+    transformer(
+        "gpt2",
+        &["gpt2"],
+        &[
+            "token_embd.weight",
+            "position_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_qkv.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        false
+    ),
+    transformer(
+        "starcoder",
+        &["gpt_bigcode"],
+        &[
+            "token_embd.weight",
+            "position_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_qkv.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.ffn_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+        ],
+        false
+    ),
+    transformer(
+        "bert",
+        &["bert"],
+        &[
+            "token_embd.weight",
+            "token_embd_norm.weight",
+            "blk.{bid}.attn_q.weight",
+            "blk.{bid}.attn_k.weight",
+            "blk.{bid}.attn_v.weight",
+            "blk.{bid}.attn_output.weight",
+            "blk.{bid}.attn_output_norm.weight",
+            "blk.{bid}.ffn_up.weight",
+            "blk.{bid}.ffn_down.weight",
+            "blk.{bid}.layer_output_norm.weight",
+        ],
+        false
+    ),
+    transformer("jina-bert-v2", &[], &["token_embd.weight"], false),
+    transformer("bloom", &["bloom"], &["token_embd.weight", "output_norm.weight"], false),
+    transformer("mpt", &["mpt"], &["token_embd.weight", "output_norm.weight"], false),
+    transformer("refact", &[], &["token_embd.weight", "output_norm.weight"], false),
+    transformer("t5", &["t5"], &["token_embd.weight"], false),
+    transformer("t5encoder", &[], &["token_embd.weight"], false),
+    ArchitectureDefinition {
+        name: "mamba",
+        hf_model_types: &["mamba"],
+        required_keys: SSM_KEYS,
+        hyperparameters: SSM_HYPERPARAMETERS,
+        required_tensors: &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.ssm_in.weight",
+            "blk.{bid}.ssm_conv1d.weight",
+            "blk.{bid}.ssm_x.weight",
+            "blk.{bid}.ssm_dt.weight",
+            "blk.{bid}.ssm_a",
+            "blk.{bid}.ssm_d",
+            "blk.{bid}.ssm_out.weight",
+        ],
+        rope: false,
+    },
+    ArchitectureDefinition {
+        name: "mamba2",
+        hf_model_types: &["mamba2"],
+        required_keys: SSM_KEYS,
+        hyperparameters: SSM_HYPERPARAMETERS,
+        required_tensors: &["token_embd.weight", "output_norm.weight"],
+        rope: false,
+    },
+    ArchitectureDefinition {
+        name: "rwkv6",
+        hf_model_types: &["rwkv6"],
+        required_keys: RWKV_KEYS,
+        hyperparameters: RWKV_HYPERPARAMETERS,
+        required_tensors: &[
+            "token_embd.weight",
+            "output_norm.weight",
+            "blk.{bid}.attn_norm.weight",
+            "blk.{bid}.attn_norm_2.weight",
+        ],
+        rope: false,
+    },
+    ArchitectureDefinition {
+        name: "rwkv6qwen2",
+        hf_model_types: &[],
+        required_keys: RWKV_KEYS,
+        hyperparameters: RWKV_HYPERPARAMETERS,
+        required_tensors: &["token_embd.weight", "output_norm.weight"],
+        rope: false,
+    },
+    ArchitectureDefinition {
+        name: "rwkv7",
+        hf_model_types: &["rwkv7"],
+        required_keys: RWKV_KEYS,
+        hyperparameters: RWKV_HYPERPARAMETERS,
+        required_tensors: &["token_embd.weight", "output_norm.weight"],
+        rope: false,
+    },
+];
+
+// Used to describe architectures missing from the registry.
+pub const GENERIC_ARCHITECTURE: ArchitectureDefinition = transformer("", &[], &[], true);
+
+pub fn get_architecture_definition(name: &str) -> Option<&'static ArchitectureDefinition> {
+    ARCHITECTURES.iter().find(|definition| definition.name == name)
+}
+
+// Name used by llama.cpp for a Hugging Face model_type, when they differ.
+pub fn hf_architecture(model_type: &str) -> &str {
+    ARCHITECTURES.iter()
+        .find(|definition| definition.hf_model_types.contains(&model_type))
+        .map(|definition| definition.name)
+        .unwrap_or(model_type)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ArchitectureValidation {
+    pub architecture: Option<String>,
+    pub supported: bool,
+    pub missing_keys: Vec<String>,
+    pub missing_tensors: Vec<String>,
+}
+
+impl ArchitectureValidation {
+    pub fn is_valid(&self) -> bool {
+        self.supported && self.missing_keys.is_empty() && self.missing_tensors.is_empty()
+    }
+
+    // A message for the user, None if the model is valid or its architecture is unknown.
+    pub fn to_error(&self) -> Option<String> {
+        let architecture = match self.architecture {
+            Some(ref architecture) => architecture,
+            None => {
+                return Some("Missing metadata key: general.architecture".to_string());
+            }
+        };
+        if !self.supported {
+            return None;
+        }
+        let mut errors = Vec::new();
+        if !self.missing_keys.is_empty() {
+            errors.push(format!("Missing metadata keys: {}", self.missing_keys.join(", ")));
+        }
+        if !self.missing_tensors.is_empty() {
+            let mut message = format!("Missing tensors: {}", self.missing_tensors
+                .iter()
+                .take(5)
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(", "));
+            if self.missing_tensors.len() > 5 {
+                message.push_str(&format!(" and {} more", self.missing_tensors.len() - 5));
+            }
+            errors.push(message);
+        }
+        if errors.is_empty() {
+            None
+        } else {
+            Some(format!("Invalid {} model. {}", architecture, errors.join(". ")))
+        }
+    }
+
+    // An unknown architecture is not checked, llama.cpp decides if it can load it.
+    pub fn to_warning(&self) -> Option<String> {
+        match self.architecture {
+            Some(ref architecture) if !self.supported => {
+                Some(format!("Architecture {} is unknown, the model is not checked", architecture))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Checks that a model has the keys and tensors its architecture needs.
+pub fn validate_gguf(gguf: &GGUF) -> ArchitectureValidation {
+    let mut validation = ArchitectureValidation {
+        architecture: gguf.get_architecture().map(|architecture| architecture.to_string()),
+        ..ArchitectureValidation::default()
+    };
+    let definition = match gguf.get_architecture().and_then(get_architecture_definition) {
+        Some(definition) => definition,
+        None => {
+            return validation;
+        }
+    };
+    validation.supported = true;
+    let key = |name: &str| format!("{}.{}", definition.name, name);
+    validation.missing_keys = definition.required_keys
+        .iter()
+        .map(|name| key(name))
+        .filter(|key| gguf.get_metadata_value(key).is_none())
+        .collect();
+
+    let names: HashSet<&str> = gguf.tensors
+        .iter()
+        .map(|t| t.name.as_str())
+        .collect();
+    let block_count = gguf.get_metadata_u64(&key("block_count")).unwrap_or_default();
+    for pattern in definition.required_tensors.iter() {
+        let expected: Vec<String> = if pattern.contains("{bid}") {
+            (0..block_count).map(|bid| pattern.replace("{bid}", &bid.to_string())).collect()
+        } else {
+            vec![pattern.to_string()]
+        };
+        validation.missing_tensors.extend(
+            expected.into_iter().filter(|name| !names.contains(name.as_str()))
+        );
+    }
+    validation
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ gguf::{ GGMLType, GGUFMetadataValue, GGUF }, testing::tensor };
+    use super::{ hf_architecture, validate_gguf, LLAMA_TENSORS };
+
+    #[test]
+    fn validate_llama() {
+        let mut gguf = GGUF::new("test.gguf");
+        assert_eq!(
+            validate_gguf(&gguf).to_error().as_deref(),
+            Some("Missing metadata key: general.architecture")
+        );
+        gguf.set_metadata("general.architecture", GGUFMetadataValue::String("llama".to_string()));
+        gguf.set_metadata("llama.context_length", GGUFMetadataValue::Uint32(4096));
+        gguf.set_metadata("llama.embedding_length", GGUFMetadataValue::Uint32(4096));
+        gguf.set_metadata("llama.block_count", GGUFMetadataValue::Uint32(2));
+        for pattern in LLAMA_TENSORS {
+            for bid in 0..2 {
+                let name = pattern.replace("{bid}", &bid.to_string());
+                if gguf.get_tensor_info(&name).is_none() {
+                    gguf.tensors.push(tensor(&name, &[4096], GGMLType::F32));
+                }
+            }
+        }
+        gguf.tensors.retain(|t| t.name != "blk.1.attn_v.weight");
+
+        let validation = validate_gguf(&gguf);
+        assert!(validation.supported);
+        assert!(!validation.is_valid());
+        assert_eq!(validation.missing_keys, vec!["llama.attention.head_count"]);
+        assert_eq!(validation.missing_tensors, vec!["blk.1.attn_v.weight"]);
+        assert_eq!(
+            validation.to_error().as_deref(),
+            Some(
+                concat!(
+                    "Invalid llama model. Missing metadata keys: llama.attention.head_count. ",
+                    "Missing tensors: blk.1.attn_v.weight"
+                )
+            )
+        );
+
+        gguf.set_metadata("llama.attention.head_count", GGUFMetadataValue::Uint32(32));
+        gguf.tensors.push(tensor("blk.1.attn_v.weight", &[4096], GGMLType::F32));
+        assert!(validate_gguf(&gguf).is_valid());
+        assert!(validate_gguf(&gguf).to_error().is_none());
+        assert!(validate_gguf(&gguf).to_warning().is_none());
+
+        // Left to llama.cpp, which supports more architectures.
+        let architecture = GGUFMetadataValue::String("deepseek2".to_string());
+        gguf.set_metadata("general.architecture", architecture);
+        let validation = validate_gguf(&gguf);
+        assert!(!validation.supported);
+        assert!(validation.to_error().is_none());
+        assert_eq!(
+            validation.to_warning().as_deref(),
+            Some("Architecture deepseek2 is unknown, the model is not checked")
+        );
+    }
+
+    #[test]
+    fn hf_model_types() {
+        assert_eq!(hf_architecture("mistral"), "llama");
+        assert_eq!(hf_architecture("gpt_bigcode"), "starcoder");
+        assert_eq!(hf_architecture("qwen2"), "qwen2");
+        assert_eq!(hf_architecture("unknown"), "unknown");
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use minijinja::{ context, value::{ from_args, ValueKind }, Environment, Error, ErrorKind, State, Value };
+use serde::{ Deserialize, Serialize };
+
+use crate::gguf::GGUF;
+
+// Templates of Llama 2 and Llama 3 chat models, used for models without one.
+pub const LLAMA2_CHAT_TEMPLATE: &str =
+    "{% if messages[0]['role'] == 'system' %}{% set loop_messages = messages[1:] %}{% set system_message = messages[0]['content'] %}{% else %}{% set loop_messages = messages %}{% set system_message = false %}{% endif %}{% for message in loop_messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}{% endif %}{% if loop.index0 == 0 and system_message != false %}{% set content = '<<SYS>>\\n' + system_message + '\\n<</SYS>>\\n\\n' + message['content'] %}{% else %}{% set content = message['content'] %}{% endif %}{% if message['role'] == 'user' %}{{ bos_token + '[INST] ' + content.strip() + ' [/INST]' }}{% elif message['role'] == 'assistant' %}{{ ' '  + content.strip() + ' ' + eos_token }}{% endif %}{% endfor %}";
+
+pub const LLAMA3_CHAT_TEMPLATE: &str =
+    "{% set loop_messages = messages %}{% for message in loop_messages %}{% set content = '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n'+ message['content'] | trim + '<|eot_id|>' %}{% if loop.index0 == 0 %}{% set content = bos_token + content %}{% endif %}{{ content }}{% endfor %}{% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\n\n' }}{% endif %}";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+// A Jinja chat template, as found in tokenizer.chat_template or tokenizer_config.json.
+// Rendered the same way as transformers apply_chat_template.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatTemplate {
+    pub source: String,
+    pub bos_token: String,
+    pub eos_token: String,
+}
+
+impl ChatTemplate {
+    pub fn new(source: &str, bos_token: &str, eos_token: &str) -> Self {
+        ChatTemplate {
+            source: source.to_string(),
+            bos_token: bos_token.to_string(),
+            eos_token: eos_token.to_string(),
+        }
+    }
+
+    // The template embedded in the file, bos and eos tokens are taken from its vocabulary.
+    pub fn from_gguf(gguf: &GGUF) -> Result<ChatTemplate, String> {
+        let source = gguf
+            .get_metadata_string("tokenizer.chat_template")
+            .ok_or("Missing metadata key: tokenizer.chat_template")?;
+        Ok(ChatTemplate::from_gguf_with_source(gguf, source))
+    }
+
+    // A template set by the user, used with the bos and eos tokens of the file.
+    pub fn from_gguf_with_source(gguf: &GGUF, source: &str) -> ChatTemplate {
+        let tokens = gguf.get_metadata_array("tokenizer.ggml.tokens").unwrap_or_default();
+        let token = |key: &str| {
+            gguf.get_metadata_u64(key)
+                .and_then(|id| tokens.get(id as usize))
+                .and_then(|token| token.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        ChatTemplate {
+            source: source.to_string(),
+            bos_token: token("tokenizer.ggml.bos_token_id"),
+            eos_token: token("tokenizer.ggml.eos_token_id"),
+        }
+    }
+
+    pub fn render(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool
+    ) -> Result<String, String> {
+        let mut environment = Environment::new();
+        // Same options as transformers.
+        environment.set_trim_blocks(true);
+        environment.set_lstrip_blocks(true);
+        environment.set_unknown_method_callback(python_method);
+        environment.add_function("raise_exception", raise_exception);
+        environment
+            .add_template("chat_template", &self.source)
+            .map_err(|err| format!("Invalid chat template: {}", err))?;
+        let template = environment
+            .get_template("chat_template")
+            .map_err(|err| err.to_string())?;
+        template
+            .render(
+                context! {
+                    messages,
+                    add_generation_prompt,
+                    bos_token => self.bos_token,
+                    eos_token => self.eos_token,
+                }
+            )
+            .map_err(|err| {
+                match err.kind() {
+                    ErrorKind::InvalidOperation => err.detail().unwrap_or_default().to_string(),
+                    _ => format!("Chat template error: {}", err),
+                }
+            })
+    }
+}
+
+fn raise_exception(message: String) -> Result<String, Error> {
+    Err(Error::new(ErrorKind::InvalidOperation, message))
+}
+
+// Python methods called by templates written for transformers.
+fn python_method(state: &State, value: &Value, method: &str, args: &[Value]) -> Result<Value, Error> {
+    match (value.kind(), value.as_str(), method) {
+        (ValueKind::String, Some(text), "strip" | "lstrip" | "rstrip") => {
+            let (chars,): (Option<&str>,) = from_args(args)?;
+            let trim = |c: char| chars.map_or(c.is_whitespace(), |chars| chars.contains(c));
+            Ok(
+                Value::from(match method {
+                    "lstrip" => text.trim_start_matches(trim),
+                    "rstrip" => text.trim_end_matches(trim),
+                    _ => text.trim_matches(trim),
+                })
+            )
+        }
+        (ValueKind::String, Some(text), "startswith") => {
+            let (prefix,): (&str,) = from_args(args)?;
+            Ok(Value::from(text.starts_with(prefix)))
+        }
+        (ValueKind::String, Some(text), "endswith") => {
+            let (suffix,): (&str,) = from_args(args)?;
+            Ok(Value::from(text.ends_with(suffix)))
+        }
+        (ValueKind::String, Some(text), "lower") => Ok(Value::from(text.to_lowercase())),
+        (ValueKind::String, Some(text), "upper") => Ok(Value::from(text.to_uppercase())),
+        (ValueKind::String, Some(text), "split") => {
+            let (separator,): (Option<&str>,) = from_args(args)?;
+            Ok(
+                Value::from(match separator {
+                    Some(separator) => text.split(separator).map(Value::from).collect::<Vec<_>>(),
+                    None => text.split_whitespace().map(Value::from).collect::<Vec<_>>(),
+                })
+            )
+        }
+        (ValueKind::Map, _, "items") => {
+            let _: () = from_args(args)?;
+            state.apply_filter("items", std::slice::from_ref(value))
+        }
+        (ValueKind::Map, _, "get") => {
+            let (key, default): (Value, Option<Value>) = from_args(args)?;
+            let item = value.get_item(&key)?;
+            Ok(if item.is_undefined() { default.unwrap_or(Value::from(())) } else { item })
+        }
+        _ => Err(Error::from(ErrorKind::UnknownMethod)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChatMessage,
+        ChatTemplate,
+        LLAMA2_CHAT_TEMPLATE as LLAMA2,
+        LLAMA3_CHAT_TEMPLATE as LLAMA3,
+    };
+
+    const CHATML: &str =
+        "{% for message in messages %}{% if loop.first and messages[0]['role'] != 'system' %}{{ '<|im_start|>system\\nYou are a helpful assistant.<|im_end|>\\n' }}{% endif %}{{'<|im_start|>' + message['role'] + '\\n' + message['content'] + '<|im_end|>' + '\\n'}}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\\n' }}{% endif %}";
+
+    const MISTRAL: &str =
+        "{{ bos_token }}{% for message in messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}{% endif %}{% if message['role'] == 'user' %}{{ '[INST] ' + message['content'] + ' [/INST]' }}{% elif message['role'] == 'assistant' %}{{ message['content'] + eos_token}}{% else %}{{ raise_exception('Only user and assistant roles are supported!') }}{% endif %}{% endfor %}";
+
+    const GEMMA: &str =
+        "{{ bos_token }}{% if messages[0]['role'] == 'system' %}{{ raise_exception('System role not supported') }}{% endif %}{% for message in messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}{% endif %}{% if (message['role'] == 'assistant') %}{% set role = 'model' %}{% else %}{% set role = message['role'] %}{% endif %}{{ '<start_of_turn>' + role + '\n' + message['content'] | trim + '<end_of_turn>\n' }}{% endfor %}{% if add_generation_prompt %}{{'<start_of_turn>model\n'}}{% endif %}";
+
+    const PHI3: &str =
+        "{% for message in messages %}{% if message['role'] == 'system' %}{{'<|system|>\n' + message['content'] + '<|end|>\n'}}{% elif message['role'] == 'user' %}{{'<|user|>\n' + message['content'] + '<|end|>\n'}}{% elif message['role'] == 'assistant' %}{{'<|assistant|>\n' + message['content'] + '<|end|>\n'}}{% endif %}{% endfor %}{% if add_generation_prompt %}{{ '<|assistant|>\n' }}{% else %}{{ eos_token }}{% endif %}";
+
+    const ZEPHYR: &str =
+        "{% for message in messages %}\n{% if message['role'] == 'user' %}\n{{ '<|user|>\n' + message['content'] + eos_token }}\n{% elif message['role'] == 'system' %}\n{{ '<|system|>\n' + message['content'] + eos_token }}\n{% elif message['role'] == 'assistant' %}\n{{ '<|assistant|>\n'  + message['content'] + eos_token }}\n{% endif %}\n{% if loop.last and add_generation_prompt %}\n{{ '<|assistant|>' }}\n{% endif %}\n{% endfor %}";
+
+    fn conversation(system: bool) -> Vec<ChatMessage> {
+        let mut messages = Vec::new();
+        if system {
+            messages.push(ChatMessage::new("system", "You are a pirate."));
+        }
+        messages.push(ChatMessage::new("user", "Hello"));
+        messages.push(ChatMessage::new("assistant", " Ahoy! "));
+        messages.push(ChatMessage::new("user", "Where is the treasure?"));
+        messages
+    }
+
+    #[test]
+    fn render_model_families() {
+        let cases = [
+            (
+                ChatTemplate::new(CHATML, "", "<|im_end|>"),
+                true,
+                "<|im_start|>system\nYou are a pirate.<|im_end|>\n<|im_start|>user\nHello<|im_end|>\n<|im_start|>assistant\n Ahoy! <|im_end|>\n<|im_start|>user\nWhere is the treasure?<|im_end|>\n<|im_start|>assistant\n",
+            ),
+            (
+                ChatTemplate::new(CHATML, "", "<|im_end|>"),
+                false,
+                "<|im_start|>system\nYou are a helpful assistant.<|im_end|>\n<|im_start|>user\nHello<|im_end|>\n<|im_start|>assistant\n Ahoy! <|im_end|>\n<|im_start|>user\nWhere is the treasure?<|im_end|>\n<|im_start|>assistant\n",
+            ),
+            (
+                ChatTemplate::new(LLAMA2, "<s>", "</s>"),
+                true,
+                "<s>[INST] <<SYS>>\nYou are a pirate.\n<</SYS>>\n\nHello [/INST] Ahoy! </s><s>[INST] Where is the treasure? [/INST]",
+            ),
+            (
+                ChatTemplate::new(LLAMA3, "<|begin_of_text|>", "<|eot_id|>"),
+                true,
+                "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nYou are a pirate.<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nHello<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\nAhoy!<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nWhere is the treasure?<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n",
+            ),
+            (
+                ChatTemplate::new(MISTRAL, "<s>", "</s>"),
+                false,
+                "<s>[INST] Hello [/INST] Ahoy! </s>[INST] Where is the treasure? [/INST]",
+            ),
+            (
+                ChatTemplate::new(GEMMA, "<bos>", "<eos>"),
+                false,
+                "<bos><start_of_turn>user\nHello<end_of_turn>\n<start_of_turn>model\nAhoy!<end_of_turn>\n<start_of_turn>user\nWhere is the treasure?<end_of_turn>\n<start_of_turn>model\n",
+            ),
+            (
+                ChatTemplate::new(PHI3, "<s>", "<|endoftext|>"),
+                true,
+                "<|system|>\nYou are a pirate.<|end|>\n<|user|>\nHello<|end|>\n<|assistant|>\n Ahoy! <|end|>\n<|user|>\nWhere is the treasure?<|end|>\n<|assistant|>\n",
+            ),
+            (
+                ChatTemplate::new(ZEPHYR, "<s>", "</s>"),
+                true,
+                "<|system|>\nYou are a pirate.</s>\n<|user|>\nHello</s>\n<|assistant|>\n Ahoy! </s>\n<|user|>\nWhere is the treasure?</s>\n<|assistant|>\n",
+            ),
+        ];
+        for (template, system, expected) in cases {
+            let prompt = template.render(&conversation(system), true).unwrap();
+            assert_eq!(prompt, expected);
+        }
+    }
+
+    #[test]
+    fn raise_exception() {
+        let template = ChatTemplate::new(GEMMA, "<bos>", "<eos>");
+        let error = template.render(&conversation(true), true).unwrap_err();
+        assert_eq!(error, "System role not supported");
+
+        let template = ChatTemplate::new(MISTRAL, "<s>", "</s>");
+        let messages = vec![ChatMessage::new("assistant", "Hi")];
+        let error = template.render(&messages, true).unwrap_err();
+        assert!(error.starts_with("Conversation roles must alternate"));
+    }
+
+    #[test]
+    fn generation_prompt() {
+        let template = ChatTemplate::new(PHI3, "<s>", "<|endoftext|>");
+        let messages = vec![ChatMessage::new("user", "Hi")];
+        assert_eq!(template.render(&messages, false).unwrap(), "<|user|>\nHi<|end|>\n<|endoftext|>");
+        assert!(ChatTemplate::new("{% if %}", "", "").render(&messages, true).is_err());
+    }
+}
@@ -0,0 +1,702 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ borrow::Cow, collections::HashMap, f64::consts::PI, fs, path::Path };
+use half::f16;
+use serde_json::Value;
+
+use crate::{
+    chat_template::{ LLAMA2_CHAT_TEMPLATE, LLAMA3_CHAT_TEMPLATE },
+    gguf::{
+        GGMLType,
+        GGUFMetadataArrayValue,
+        GGUFMetadataValue,
+        GGUFMetadataValueType,
+        GGUFTensorInfo,
+        GGUF,
+        GGUF_VERSION,
+    },
+    safetensors::SafeTensors,
+};
+
+// Token types, as defined by llama.cpp (enum llama_token_type).
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_UNKNOWN: i32 = 2;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_UNUSED: i32 = 5;
+const TOKEN_TYPE_BYTE: i32 = 6;
+
+const SUPPORTED_ARCHITECTURES: [&str; 2] = ["LlamaForCausalLM", "MistralForCausalLM"];
+
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    // F16 or F32, 1-dimensional tensors are always written as F32.
+    pub output_type: GGMLType,
+    // Defaults to the name of the model in config.json.
+    pub name: Option<String>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            output_type: GGMLType::F16,
+            name: None,
+        }
+    }
+}
+
+// Where the values of a converted tensor come from.
+enum TensorSource {
+    // Tensor of a safetensors file, with the number of heads if its rows must be permuted.
+    File {
+        file: usize,
+        tensor: GGUFTensorInfo,
+        head_count: Option<usize>,
+    },
+    Values(Vec<f32>),
+}
+
+// Converts a Hugging Face checkpoint of the Llama family to GGUF, as convert_hf_to_gguf.py does.
+// The directory has config.json, tokenizer.json, tokenizer_config.json and the .safetensors files.
+pub fn convert_hf_to_gguf(
+    directory: &str,
+    output: &str,
+    options: &ConvertOptions
+) -> Result<(), String> {
+    if options.output_type != GGMLType::F16 && options.output_type != GGMLType::F32 {
+        return Err(format!("Unsupported output type: {}", options.output_type));
+    }
+    let directory = Path::new(directory);
+    let config = read_json(&directory.join("config.json"))?;
+    let architecture = config["architectures"]
+        .as_array()
+        .and_then(|architectures| architectures.first())
+        .and_then(|architecture| architecture.as_str())
+        .unwrap_or_default();
+    if !SUPPORTED_ARCHITECTURES.contains(&architecture) {
+        return Err(format!("Unsupported architecture: {}", architecture));
+    }
+
+    let mut paths: Vec<_> = fs
+        ::read_dir(directory)
+        .map_err(|err| err.to_string())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "safetensors"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(format!("No safetensors file in {}", directory.display()));
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let mut file = SafeTensors::new(&path.to_string_lossy());
+        file.read(&path.to_string_lossy())?;
+        files.push(file);
+    }
+
+    let mut gguf = GGUF::new(output);
+    gguf.header.version = GGUF_VERSION;
+    gguf.set_metadata("general.architecture", GGUFMetadataValue::String("llama".to_string()));
+    let name = options.name
+        .clone()
+        .or_else(|| {
+            config["_name_or_path"]
+                .as_str()
+                .and_then(|name| name.rsplit('/').next())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+        })
+        .or_else(|| directory.file_name().map(|name| name.to_string_lossy().to_string()));
+    if let Some(name) = name {
+        gguf.set_metadata("general.name", GGUFMetadataValue::String(name));
+    }
+    // LLAMA_FTYPE_ALL_F32 or LLAMA_FTYPE_MOSTLY_F16
+    let file_type = if options.output_type == GGMLType::F32 { 0 } else { 1 };
+    gguf.set_metadata("general.file_type", GGUFMetadataValue::Uint32(file_type));
+    let rope_freqs = set_hyperparameters(&mut gguf, &config)?;
+    set_tokenizer(&mut gguf, directory, &config)?;
+
+    let head_count = config_u64(&config, "num_attention_heads").unwrap_or(1) as usize;
+    let head_count_kv = config_u64(&config, "num_key_value_heads")
+        .map(|count| count as usize)
+        .unwrap_or(head_count);
+    let mut sources = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        for tensor in file.tensors.iter() {
+            let name = match gguf_tensor_name(&tensor.name)? {
+                Some(name) => name,
+                None => {
+                    continue;
+                }
+            };
+            if sources.contains_key(&name) {
+                return Err(format!("Duplicate tensor {}", tensor.name));
+            }
+            let permute = if name.ends_with("attn_q.weight") {
+                Some(head_count)
+            } else if name.ends_with("attn_k.weight") {
+                Some(head_count_kv)
+            } else {
+                None
+            };
+            gguf.tensors.push(GGUFTensorInfo {
+                name: name.clone(),
+                n_dimensions: tensor.n_dimensions,
+                dimensions: tensor.dimensions.clone(),
+                tensor_type: if tensor.dimensions.len() == 1 {
+                    GGMLType::F32
+                } else {
+                    options.output_type
+                },
+                offset: 0,
+            });
+            sources.insert(name, TensorSource::File {
+                file: index,
+                tensor: tensor.clone(),
+                head_count: permute,
+            });
+        }
+    }
+    if let Some(rope_freqs) = rope_freqs {
+        gguf.tensors.push(GGUFTensorInfo {
+            name: "rope_freqs.weight".to_string(),
+            n_dimensions: 1,
+            dimensions: vec![rope_freqs.len() as u64],
+            tensor_type: GGMLType::F32,
+            offset: 0,
+        });
+        sources.insert("rope_freqs.weight".to_string(), TensorSource::Values(rope_freqs));
+    }
+    gguf.header.tensor_count = gguf.tensors.len() as u64;
+
+    gguf.write_with_tensor_data(output, |tensor| {
+        let values = match sources.get(&tensor.name) {
+            Some(TensorSource::File { file, tensor: source, head_count }) => {
+                let values = files[*file].get_tensor_f32(source).map_err(anyhow::Error::msg)?;
+                match head_count {
+                    Some(head_count) => permute_rows(&values, source, *head_count)?,
+                    None => values,
+                }
+            }
+            Some(TensorSource::Values(values)) => values.clone(),
+            None => {
+                return Err(anyhow::Error::msg(format!("No data for tensor {}", tensor.name)));
+            }
+        };
+        let data: Vec<u8> = match tensor.tensor_type {
+            GGMLType::F16 =>
+                values
+                    .iter()
+                    .flat_map(|value| f16::from_f32(*value).to_le_bytes())
+                    .collect(),
+            _ =>
+                values
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect(),
+        };
+        Ok(Cow::Owned(data))
+    }).map_err(|err| err.to_string())
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let content = fs
+        ::read_to_string(path)
+        .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+    serde_json::from_str(&content).map_err(|err| format!("Invalid {}: {}", path.display(), err))
+}
+
+// Token ids can be a list in config.json, the first one is used.
+fn config_u64(config: &Value, key: &str) -> Option<u64> {
+    match &config[key] {
+        Value::Array(values) => values.first().and_then(|v| v.as_u64()),
+        value => value.as_u64(),
+    }
+}
+
+fn array(value_type: GGUFMetadataValueType, values: Vec<GGUFMetadataValue>) -> GGUFMetadataValue {
+    GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+        value_type,
+        len: values.len() as u64,
+        value: values,
+        offset: None,
+    })
+}
+
+// Sets the llama.* keys, returns the rope frequency factors of Llama 3.1 models.
+fn set_hyperparameters(gguf: &mut GGUF, config: &Value) -> Result<Option<Vec<f32>>, String> {
+    let required = |key: &str| {
+        config_u64(config, key).ok_or(format!("Missing config.json key: {}", key))
+    };
+    let embedding_length = required("hidden_size")?;
+    let head_count = required("num_attention_heads")?;
+    let head_dimension = config_u64(config, "head_dim").unwrap_or(embedding_length / head_count);
+    let mut set_u32 = |key: &str, value: u64| {
+        gguf.set_metadata(key, GGUFMetadataValue::Uint32(value as u32));
+    };
+    set_u32("llama.context_length", required("max_position_embeddings")?);
+    set_u32("llama.embedding_length", embedding_length);
+    set_u32("llama.block_count", required("num_hidden_layers")?);
+    set_u32("llama.feed_forward_length", required("intermediate_size")?);
+    set_u32("llama.attention.head_count", head_count);
+    set_u32(
+        "llama.attention.head_count_kv",
+        config_u64(config, "num_key_value_heads").unwrap_or(head_count)
+    );
+    set_u32("llama.rope.dimension_count", head_dimension);
+    if let Some(vocab_size) = config_u64(config, "vocab_size") {
+        set_u32("llama.vocab_size", vocab_size);
+    }
+    let rope_theta = config["rope_theta"].as_f64().unwrap_or(10000.0);
+    gguf.set_metadata("llama.rope.freq_base", GGUFMetadataValue::Float32(rope_theta as f32));
+    gguf.set_metadata(
+        "llama.attention.layer_norm_rms_epsilon",
+        GGUFMetadataValue::Float32(config["rms_norm_eps"].as_f64().unwrap_or(1e-5) as f32)
+    );
+
+    let scaling = &config["rope_scaling"];
+    let scaling_type = scaling["rope_type"].as_str().or(scaling["type"].as_str());
+    let factor = scaling["factor"].as_f64().unwrap_or(1.0);
+    match scaling_type {
+        Some("linear") | Some("yarn") => {
+            let scaling_type = scaling_type.unwrap_or_default().to_string();
+            gguf.set_metadata("llama.rope.scaling.type", GGUFMetadataValue::String(scaling_type));
+            gguf.set_metadata("llama.rope.scaling.factor", GGUFMetadataValue::Float32(factor as f32));
+            if let Some(length) = scaling["original_max_position_embeddings"].as_u64() {
+                gguf.set_metadata(
+                    "llama.rope.scaling.original_context_length",
+                    GGUFMetadataValue::Uint32(length as u32)
+                );
+            }
+        }
+        Some("llama3") => {
+            return Ok(Some(llama3_rope_factors(scaling, rope_theta, head_dimension)));
+        }
+        _ => {}
+    }
+    Ok(None)
+}
+
+// Frequency factors of the Llama 3.1 rope scaling, stored in the rope_freqs tensor.
+fn llama3_rope_factors(scaling: &Value, rope_theta: f64, head_dimension: u64) -> Vec<f32> {
+    let factor = scaling["factor"].as_f64().unwrap_or(8.0);
+    let low_freq_factor = scaling["low_freq_factor"].as_f64().unwrap_or(1.0);
+    let high_freq_factor = scaling["high_freq_factor"].as_f64().unwrap_or(4.0);
+    let context_length = scaling["original_max_position_embeddings"].as_f64().unwrap_or(8192.0);
+    let low_freq_wavelength = context_length / low_freq_factor;
+    let high_freq_wavelength = context_length / high_freq_factor;
+    (0..head_dimension)
+        .step_by(2)
+        .map(|i| {
+            let frequency = 1.0 / rope_theta.powf((i as f64) / (head_dimension as f64));
+            let wavelength = (2.0 * PI) / frequency;
+            let rope_factor = if wavelength < high_freq_wavelength {
+                1.0
+            } else if wavelength > low_freq_wavelength {
+                factor
+            } else {
+                let smooth =
+                    (context_length / wavelength - low_freq_factor) /
+                    (high_freq_factor - low_freq_factor);
+                1.0 / ((1.0 - smooth) / factor + smooth)
+            };
+            rope_factor as f32
+        })
+        .collect()
+}
+
+// Content of a special token in tokenizer_config.json, a string or an AddedToken.
+fn token_content(value: &Value) -> Option<&str> {
+    value.as_str().or(value["content"].as_str())
+}
+
+// Sets the tokenizer.* keys from tokenizer.json. Tokenizers with byte fallback were converted
+// from SentencePiece and are written as llama, the others as byte level BPE.
+fn set_tokenizer(gguf: &mut GGUF, directory: &Path, config: &Value) -> Result<(), String> {
+    let tokenizer = read_json(&directory.join("tokenizer.json"))?;
+    let tokenizer_config = read_json(&directory.join("tokenizer_config.json")).unwrap_or_default();
+    let model = &tokenizer["model"];
+    if model["type"].as_str() != Some("BPE") {
+        return Err(format!("Unsupported tokenizer model: {}", model["type"]));
+    }
+    let is_spm = model["byte_fallback"].as_bool().unwrap_or(false);
+
+    let mut ids = HashMap::new();
+    for (token, id) in model["vocab"].as_object().ok_or("Invalid tokenizer.json vocab")? {
+        ids.insert(token.clone(), (id.as_u64().ok_or("Invalid tokenizer.json vocab")?, false));
+    }
+    for added in tokenizer["added_tokens"].as_array().into_iter().flatten() {
+        if let (Some(content), Some(id)) = (added["content"].as_str(), added["id"].as_u64()) {
+            ids.insert(content.to_string(), (id, added["special"].as_bool().unwrap_or(false)));
+        }
+    }
+    let vocab_size = ids
+        .values()
+        .map(|(id, _)| id + 1)
+        .max()
+        .unwrap_or_default()
+        .max(config_u64(config, "vocab_size").unwrap_or_default()) as usize;
+    let unknown_token = model["unk_token"].as_str();
+
+    let mut tokens: Vec<String> = (0..vocab_size).map(|id| format!("[PAD{}]", id)).collect();
+    let mut token_types = vec![TOKEN_TYPE_UNUSED; vocab_size];
+    for (token, (id, special)) in ids.iter() {
+        let id = *id as usize;
+        tokens[id] = token.clone();
+        token_types[id] = if Some(token.as_str()) == unknown_token {
+            TOKEN_TYPE_UNKNOWN
+        } else if *special {
+            TOKEN_TYPE_CONTROL
+        } else if model["vocab"].get(token).is_none() {
+            TOKEN_TYPE_USER_DEFINED
+        } else if is_spm && token.len() == 6 && token.starts_with("<0x") && token.ends_with('>') {
+            TOKEN_TYPE_BYTE
+        } else {
+            TOKEN_TYPE_NORMAL
+        };
+    }
+
+    let merges: Vec<String> = model["merges"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|merge| {
+            match merge {
+                Value::String(merge) => Some(merge.clone()),
+                // Newer tokenizers store each merge as a pair.
+                Value::Array(pair) if pair.len() == 2 =>
+                    Some(format!("{} {}", pair[0].as_str()?, pair[1].as_str()?)),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let strings = |values: &[String]| {
+        array(
+            GGUFMetadataValueType::String,
+            values
+                .iter()
+                .map(|value| GGUFMetadataValue::String(value.clone()))
+                .collect()
+        )
+    };
+    if is_spm {
+        gguf.set_metadata("tokenizer.ggml.model", GGUFMetadataValue::String("llama".to_string()));
+        gguf.set_metadata("tokenizer.ggml.tokens", strings(&tokens));
+        // SentencePiece merges the pair with the highest score, scores follow the merge ranks.
+        let mut scores: Vec<f32> = tokens
+            .iter()
+            .map(|token| if token.chars().count() == 1 { 0.0 } else { -(merges.len() as f32) - 1.0 })
+            .collect();
+        for (rank, merge) in merges.iter().enumerate().rev() {
+            if let Some((id, _)) = ids.get(&merge.replacen(' ', "", 1)) {
+                scores[*id as usize] = -(rank as f32);
+            }
+        }
+        gguf.set_metadata(
+            "tokenizer.ggml.scores",
+            array(
+                GGUFMetadataValueType::Float32,
+                scores.into_iter().map(GGUFMetadataValue::Float32).collect()
+            )
+        );
+    } else {
+        let pre_tokenizer = tokenizer["pre_tokenizer"].to_string();
+        let pre = if pre_tokenizer.contains("p{N}{1,3}") { "llama-bpe" } else { "default" };
+        gguf.set_metadata("tokenizer.ggml.model", GGUFMetadataValue::String("gpt2".to_string()));
+        gguf.set_metadata("tokenizer.ggml.pre", GGUFMetadataValue::String(pre.to_string()));
+        gguf.set_metadata("tokenizer.ggml.tokens", strings(&tokens));
+    }
+    gguf.set_metadata(
+        "tokenizer.ggml.token_type",
+        array(
+            GGUFMetadataValueType::Int32,
+            token_types.into_iter().map(GGUFMetadataValue::Int32).collect()
+        )
+    );
+    if !is_spm {
+        gguf.set_metadata("tokenizer.ggml.merges", strings(&merges));
+    }
+
+    let special_ids = [
+        ("bos_token", "tokenizer.ggml.bos_token_id", config_u64(config, "bos_token_id")),
+        ("eos_token", "tokenizer.ggml.eos_token_id", config_u64(config, "eos_token_id")),
+        ("unk_token", "tokenizer.ggml.unknown_token_id", None),
+        ("pad_token", "tokenizer.ggml.padding_token_id", config_u64(config, "pad_token_id")),
+    ];
+    for (name, key, default) in special_ids {
+        let id = token_content(&tokenizer_config[name])
+            .and_then(|token| ids.get(token))
+            .map(|(id, _)| *id)
+            .or(default);
+        if let Some(id) = id {
+            gguf.set_metadata(key, GGUFMetadataValue::Uint32(id as u32));
+        }
+    }
+    let add_bos_token = tokenizer_config["add_bos_token"].as_bool().unwrap_or(true);
+    let add_eos_token = tokenizer_config["add_eos_token"].as_bool().unwrap_or(false);
+    gguf.set_metadata("tokenizer.ggml.add_bos_token", GGUFMetadataValue::Bool(add_bos_token));
+    gguf.set_metadata("tokenizer.ggml.add_eos_token", GGUFMetadataValue::Bool(add_eos_token));
+    if is_spm {
+        let normalizer = format!("{}{}", tokenizer["normalizer"], tokenizer["pre_tokenizer"]);
+        let add_space_prefix =
+            normalizer.contains("\"Prepend\"") ||
+            normalizer.contains("\"prepend_scheme\":\"first\"") ||
+            normalizer.contains("\"prepend_scheme\":\"always\"");
+        gguf.set_metadata(
+            "tokenizer.ggml.add_space_prefix",
+            GGUFMetadataValue::Bool(add_space_prefix)
+        );
+    }
+
+    // A list of named templates is also allowed, the default one is used.
+    let chat_template = match &tokenizer_config["chat_template"] {
+        Value::String(template) => template.as_str(),
+        Value::Array(templates) =>
+            templates
+                .iter()
+                .find(|template| template["name"] == "default")
+                .or(templates.first())
+                .and_then(|template| template["template"].as_str())
+                .unwrap_or_default(),
+        _ => "",
+    };
+    let chat_template = match chat_template {
+        "" if is_spm => LLAMA2_CHAT_TEMPLATE,
+        "" => LLAMA3_CHAT_TEMPLATE,
+        template => template,
+    };
+    gguf.set_metadata(
+        "tokenizer.chat_template",
+        GGUFMetadataValue::String(chat_template.to_string())
+    );
+    Ok(())
+}
+
+// Name of a tensor in llama.cpp, None for tensors that are not needed.
+fn gguf_tensor_name(name: &str) -> Result<Option<String>, String> {
+    let unsupported = || format!("Unsupported tensor: {}", name);
+    let tensor_name = match name {
+        "model.embed_tokens.weight" => "token_embd.weight",
+        "model.norm.weight" => "output_norm.weight",
+        "lm_head.weight" => "output.weight",
+        _ => {
+            let (layer, tensor) = name
+                .strip_prefix("model.layers.")
+                .and_then(|name| name.split_once('.'))
+                .ok_or_else(unsupported)?;
+            let layer: u64 = layer.parse().map_err(|_| unsupported())?;
+            let tensor = match tensor {
+                "input_layernorm.weight" => "attn_norm.weight",
+                "self_attn.q_proj.weight" => "attn_q.weight",
+                "self_attn.k_proj.weight" => "attn_k.weight",
+                "self_attn.v_proj.weight" => "attn_v.weight",
+                "self_attn.o_proj.weight" => "attn_output.weight",
+                "post_attention_layernorm.weight" => "ffn_norm.weight",
+                "mlp.gate_proj.weight" => "ffn_gate.weight",
+                "mlp.up_proj.weight" => "ffn_up.weight",
+                "mlp.down_proj.weight" => "ffn_down.weight",
+                "self_attn.rotary_emb.inv_freq" => {
+                    return Ok(None);
+                }
+                _ => {
+                    return Err(unsupported());
+                }
+            };
+            return Ok(Some(format!("blk.{}.{}", layer, tensor)));
+        }
+    };
+    Ok(Some(tensor_name.to_string()))
+}
+
+// Hugging Face stores the rotary halves of each query and key head apart, llama.cpp interleaves
+// them. Rows h * head + j * head / 2 + i move to h * head + i * 2 + j.
+fn permute_rows(
+    values: &[f32],
+    tensor: &GGUFTensorInfo,
+    head_count: usize
+) -> Result<Vec<f32>, anyhow::Error> {
+    let columns = tensor.dimensions.first().copied().unwrap_or_default() as usize;
+    let rows = tensor.dimensions.get(1).copied().unwrap_or_default() as usize;
+    if head_count == 0 || !rows.is_multiple_of(head_count * 2) {
+        return Err(
+            anyhow::Error::msg(format!("Can't split {} in {} heads", tensor.name, head_count))
+        );
+    }
+    let half = rows / head_count / 2;
+    let mut result = vec![0.0; values.len()];
+    for head in 0..head_count {
+        for j in 0..2 {
+            for i in 0..half {
+                let source = (head * 2 + j) * half + i;
+                let target = head * 2 * half + i * 2 + j;
+                result[target * columns..(target + 1) * columns].copy_from_slice(
+                    &values[source * columns..(source + 1) * columns]
+                );
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ collections::BTreeMap, fs, path::Path };
+    use half::bf16;
+    use serde_json::json;
+
+    use crate::{
+        chat_template::{ ChatMessage, ChatTemplate },
+        descriptor::ModelDescriptor,
+        gguf::{ GGMLType, GGUF },
+    };
+    use super::{ convert_hf_to_gguf, ConvertOptions };
+
+    // Values are the index of each element, rows of q_proj can be followed.
+    fn write_safetensors(path: &Path, tensors: &[(&str, &str, Vec<u64>)]) {
+        let mut header = BTreeMap::new();
+        let mut data = Vec::new();
+        for (name, dtype, shape) in tensors {
+            let start = data.len();
+            for i in 0..shape.iter().product::<u64>() {
+                match *dtype {
+                    "BF16" => data.extend(bf16::from_f32(i as f32).to_le_bytes()),
+                    _ => data.extend((i as f32).to_le_bytes()),
+                }
+            }
+            header.insert(
+                name.to_string(),
+                json!({ "dtype": dtype, "shape": shape, "data_offsets": [start, data.len()] })
+            );
+        }
+        let header = serde_json::to_vec(&header).unwrap();
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header);
+        file.extend(data);
+        fs::write(path, file).unwrap();
+    }
+
+    fn create_checkpoint(directory: &Path) {
+        fs::create_dir_all(directory).unwrap();
+        let config =
+            json!({
+            "architectures": ["LlamaForCausalLM"],
+            "_name_or_path": "meta-llama/Tiny-Llama",
+            "hidden_size": 8,
+            "intermediate_size": 16,
+            "max_position_embeddings": 128,
+            "num_attention_heads": 2,
+            "num_hidden_layers": 1,
+            "num_key_value_heads": 1,
+            "rms_norm_eps": 1e-5,
+            "rope_theta": 500000.0,
+            "rope_scaling": { "rope_type": "llama3", "factor": 8.0, "original_max_position_embeddings": 64 },
+            "vocab_size": 8,
+        });
+        let tokenizer =
+            json!({
+            "added_tokens": [
+                { "id": 5, "content": "<|begin_of_text|>", "special": true },
+                { "id": 6, "content": "<|eot_id|>", "special": true },
+            ],
+            "pre_tokenizer": { "type": "Sequence", "pretokenizers": [
+                { "type": "Split", "pattern": { "Regex": "\\p{N}{1,3}" } },
+                { "type": "ByteLevel" },
+            ] },
+            "model": {
+                "type": "BPE",
+                "vocab": { "a": 0, "b": 1, "Ġ": 2, "ab": 3, "Ġab": 4 },
+                "merges": [["a", "b"], ["Ġ", "ab"]],
+            },
+        });
+        let tokenizer_config =
+            json!({
+            "bos_token": "<|begin_of_text|>",
+            "eos_token": { "content": "<|eot_id|>" },
+        });
+        for (name, value) in [
+            ("config.json", config),
+            ("tokenizer.json", tokenizer),
+            ("tokenizer_config.json", tokenizer_config),
+        ] {
+            fs::write(directory.join(name), value.to_string()).unwrap();
+        }
+        write_safetensors(
+            &directory.join("model-00001-of-00002.safetensors"),
+            &[
+                ("model.embed_tokens.weight", "BF16", vec![8, 8]),
+                ("model.layers.0.input_layernorm.weight", "F32", vec![8]),
+                ("model.layers.0.self_attn.q_proj.weight", "F32", vec![8, 8]),
+                ("model.layers.0.self_attn.k_proj.weight", "F32", vec![4, 8]),
+                ("model.layers.0.self_attn.v_proj.weight", "F32", vec![4, 8]),
+                ("model.layers.0.self_attn.o_proj.weight", "F32", vec![8, 8]),
+            ]
+        );
+        write_safetensors(
+            &directory.join("model-00002-of-00002.safetensors"),
+            &[
+                ("model.layers.0.post_attention_layernorm.weight", "F32", vec![8]),
+                ("model.layers.0.mlp.gate_proj.weight", "BF16", vec![16, 8]),
+                ("model.layers.0.mlp.up_proj.weight", "BF16", vec![16, 8]),
+                ("model.layers.0.mlp.down_proj.weight", "BF16", vec![8, 16]),
+                ("model.layers.0.self_attn.rotary_emb.inv_freq", "F32", vec![2]),
+                ("model.norm.weight", "F32", vec![8]),
+                ("lm_head.weight", "F32", vec![8, 8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_llama() {
+        let directory = std::env::temp_dir().join(format!("opla-{}-convert", std::process::id()));
+        let output = directory.join("model.gguf");
+        let output = output.to_str().unwrap();
+        create_checkpoint(&directory);
+        convert_hf_to_gguf(directory.to_str().unwrap(), output, &ConvertOptions::default()).unwrap();
+
+        let mut gguf = GGUF::new(output);
+        gguf.read(output).unwrap();
+        assert_eq!(gguf.get_metadata_string("general.name"), Some("Tiny-Llama"));
+        assert_eq!(gguf.tensors.len(), 13);
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        assert_eq!(descriptor.head_count_kv, Some(1));
+        assert_eq!(descriptor.vocab_size, Some(8));
+        assert_eq!(descriptor.quantization.as_deref(), Some("F16"));
+
+        let tensor = gguf.get_tensor_info("blk.0.attn_norm.weight").unwrap();
+        assert_eq!(tensor.tensor_type, GGMLType::F32);
+        let tensor = gguf.get_tensor_info("token_embd.weight").unwrap();
+        assert_eq!(tensor.tensor_type, GGMLType::F16);
+        assert_eq!(gguf.get_tensor_f32(tensor).unwrap()[9], 9.0);
+        // Rows 0 1 2 3 of each head become 0 2 1 3.
+        let tensor = gguf.get_tensor_info("blk.0.attn_q.weight").unwrap();
+        let values = gguf.get_tensor_f32(tensor).unwrap();
+        assert_eq!(values[8], 16.0);
+        assert_eq!(values[5 * 8], 6.0 * 8.0);
+        let tensor = gguf.get_tensor_info("rope_freqs.weight").unwrap();
+        assert_eq!(gguf.get_tensor_f32(tensor).unwrap(), vec![1.0, 8.0]);
+
+        let tokens = gguf.get_metadata_array("tokenizer.ggml.tokens").unwrap();
+        assert_eq!(tokens[2].as_str(), Some("Ġ"));
+        assert_eq!(tokens[7].as_str(), Some("[PAD7]"));
+        assert_eq!(gguf.get_metadata_string("tokenizer.ggml.pre"), Some("llama-bpe"));
+        assert_eq!(gguf.get_metadata_u64("tokenizer.ggml.eos_token_id"), Some(6));
+        let template = ChatTemplate::from_gguf(&gguf).unwrap();
+        let prompt = template.render(&[ChatMessage::new("user", "Hi")], true).unwrap();
+        assert!(prompt.starts_with("<|begin_of_text|><|start_header_id|>user"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
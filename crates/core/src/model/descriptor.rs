@@ -0,0 +1,414 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    architecture::{ get_architecture_definition, hf_architecture, GENERIC_ARCHITECTURE },
+    gguf::{ GGMLType, GGUFMetadataValue, GGUFTensorInfo, GGUF },
+    safetensors::SafeTensors,
+};
+
+// Name of a llama_ftype, as used in file names and model cards.
+pub fn file_type_name(file_type: u64) -> Option<&'static str> {
+    let name = match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        4 => "Q4_1_SOME_F16",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        36 => "TQ1_0",
+        37 => "TQ2_0",
+        38 => "MXFP4_MOE",
+        _ => {
+            return None;
+        }
+    };
+    Some(name)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RopeSettings {
+    pub dimension_count: Option<u64>,
+    pub freq_base: Option<f64>,
+    pub scaling_type: Option<String>,
+    pub scaling_factor: Option<f64>,
+    pub original_context_length: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelDescriptor {
+    pub architecture: String,
+    pub name: Option<String>,
+    pub context_length: u64,
+    pub embedding_length: u64,
+    pub block_count: u64,
+    pub feed_forward_length: Option<u64>,
+    pub head_count: Option<u64>,
+    pub head_count_kv: Option<u64>,
+    // Dimensions of a key and a value vector for one head, when they are set.
+    pub key_length: Option<u64>,
+    pub value_length: Option<u64>,
+    pub rope: Option<RopeSettings>,
+    pub file_type: Option<u64>,
+    pub quantization: Option<String>,
+    pub parameter_count: u64,
+    pub bits_per_weight: Option<f32>,
+    pub vocab_size: Option<u64>,
+    pub chat_template: Option<String>,
+    pub bos_token_id: Option<u64>,
+    pub eos_token_id: Option<u64>,
+}
+
+impl ModelDescriptor {
+    pub fn from_gguf(gguf: &GGUF) -> Result<ModelDescriptor, String> {
+        let architecture = match gguf.get_architecture() {
+            Some(architecture) => architecture.to_string(),
+            None => {
+                return Err("Missing metadata key: general.architecture".to_string());
+            }
+        };
+        let key = |name: &str| format!("{}.{}", architecture, name);
+        let definition = get_architecture_definition(&architecture).unwrap_or(
+            &GENERIC_ARCHITECTURE
+        );
+
+        for name in definition.required_keys {
+            if gguf.get_metadata_value(&key(name)).is_none() {
+                return Err(format!("Missing {} metadata key: {}", architecture, key(name)));
+            }
+        }
+        let get_u64 = |name: &str| gguf.get_metadata_u64(&key(name));
+        // Some architectures store a value per layer, the largest one is kept.
+        let get_max_u64 = |name: &str| {
+            match gguf.get_metadata_value(&key(name)) {
+                Some(GGUFMetadataValue::Array(array)) =>
+                    array.value
+                        .iter()
+                        .filter_map(|v| v.as_u64())
+                        .max(),
+                Some(value) => value.as_u64(),
+                None => None,
+            }
+        };
+
+        let head_count = get_max_u64("attention.head_count");
+        let head_count_kv = get_max_u64("attention.head_count_kv").or(head_count);
+        let rope = if !definition.rope {
+            None
+        } else {
+            Some(RopeSettings {
+                dimension_count: get_u64("rope.dimension_count"),
+                freq_base: gguf.get_metadata_f64(&key("rope.freq_base")),
+                scaling_type: gguf
+                    .get_metadata_string(&key("rope.scaling.type"))
+                    .map(|v| v.to_string()),
+                scaling_factor: gguf.get_metadata_f64(&key("rope.scaling.factor")),
+                original_context_length: get_u64("rope.scaling.original_context_length"),
+            })
+        };
+
+        let file_type = gguf.get_metadata_u64("general.file_type");
+        let quantization = match file_type.and_then(file_type_name) {
+            Some(name) => Some(name.to_string()),
+            None => dominant_tensor_type(&gguf.tensors).map(|t| t.to_string()),
+        };
+        let parameter_count = gguf.tensors
+            .iter()
+            .map(|t| t.element_count())
+            .sum();
+        let bits_per_weight = if parameter_count > 0 {
+            Some(((gguf.tensors_size() * 8) as f32) / (parameter_count as f32))
+        } else {
+            None
+        };
+        let vocab_size = match gguf.get_metadata_value("tokenizer.ggml.tokens") {
+            Some(GGUFMetadataValue::Array(array)) => Some(array.len),
+            _ => get_u64("vocab_size"),
+        };
+
+        Ok(ModelDescriptor {
+            name: gguf.get_metadata_string("general.name").map(|v| v.to_string()),
+            context_length: get_u64("context_length").unwrap_or_default(),
+            embedding_length: get_u64("embedding_length").unwrap_or_default(),
+            block_count: get_u64("block_count").unwrap_or_default(),
+            feed_forward_length: get_max_u64("feed_forward_length"),
+            head_count,
+            head_count_kv,
+            key_length: get_u64("attention.key_length"),
+            value_length: get_u64("attention.value_length"),
+            rope,
+            file_type,
+            quantization,
+            parameter_count,
+            bits_per_weight,
+            vocab_size,
+            chat_template: gguf
+                .get_metadata_string("tokenizer.chat_template")
+                .map(|v| v.to_string()),
+            bos_token_id: gguf.get_metadata_u64("tokenizer.ggml.bos_token_id"),
+            eos_token_id: gguf.get_metadata_u64("tokenizer.ggml.eos_token_id"),
+            architecture,
+        })
+    }
+
+    // Hyperparameters are read from the config.json of a Hugging Face model.
+    pub fn from_safetensors(safetensors: &SafeTensors) -> Result<ModelDescriptor, String> {
+        let model_type = safetensors
+            .get_config_string("model_type")
+            .ok_or("Missing config.json key: model_type")?;
+        let architecture = hf_architecture(model_type).to_string();
+        for key in ["max_position_embeddings", "hidden_size", "num_hidden_layers"] {
+            if safetensors.get_config_value(key).is_none() {
+                return Err(format!("Missing config.json key: {}", key));
+            }
+        }
+        let get_u64 = |key: &str| safetensors.get_config_u64(key);
+
+        let definition = get_architecture_definition(&architecture).unwrap_or(
+            &GENERIC_ARCHITECTURE
+        );
+
+        let head_count = get_u64("num_attention_heads");
+        let rope = if !definition.rope {
+            None
+        } else {
+            let scaling = safetensors.get_config_value("rope_scaling");
+            let get_scaling = |key: &str| scaling.and_then(|scaling| scaling.get(key));
+            Some(RopeSettings {
+                dimension_count: get_u64("head_dim"),
+                freq_base: safetensors.get_config_f64("rope_theta"),
+                scaling_type: get_scaling("rope_type")
+                    .or(get_scaling("type"))
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()),
+                scaling_factor: get_scaling("factor").and_then(|v| v.as_f64()),
+                original_context_length: get_scaling("original_max_position_embeddings").and_then(
+                    |v| v.as_u64()
+                ),
+            })
+        };
+        let parameter_count = safetensors.tensors
+            .iter()
+            .map(|t| t.element_count())
+            .sum();
+        let bits_per_weight = if parameter_count > 0 {
+            Some(((safetensors.tensors_size() * 8) as f32) / (parameter_count as f32))
+        } else {
+            None
+        };
+
+        Ok(ModelDescriptor {
+            name: safetensors.get_config_string("_name_or_path").map(|v| v.to_string()),
+            context_length: get_u64("max_position_embeddings").unwrap_or_default(),
+            embedding_length: get_u64("hidden_size").unwrap_or_default(),
+            block_count: get_u64("num_hidden_layers").unwrap_or_default(),
+            feed_forward_length: get_u64("intermediate_size"),
+            head_count,
+            head_count_kv: get_u64("num_key_value_heads").or(head_count),
+            key_length: get_u64("head_dim"),
+            value_length: get_u64("head_dim"),
+            rope,
+            file_type: None,
+            quantization: dominant_tensor_type(&safetensors.tensors).map(|t| t.to_string()),
+            parameter_count,
+            bits_per_weight,
+            vocab_size: get_u64("vocab_size"),
+            chat_template: None,
+            bos_token_id: get_u64("bos_token_id"),
+            eos_token_id: get_u64("eos_token_id"),
+            architecture,
+        })
+    }
+
+    // Nominal bits of the quantization, ie 4 for Q4_K_M or 16 for F16.
+    pub fn bits(&self) -> Option<i32> {
+        let quantization = self.quantization.as_ref()?;
+        let digits: String = quantization
+            .trim_start_matches(char::is_alphabetic)
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    }
+
+    // Dimension of a key vector for one head.
+    pub fn head_dimension(&self) -> Option<u64> {
+        self.key_length.or_else(|| self.embedding_head_dimension())
+    }
+
+    // Dimension of a value vector for one head.
+    pub fn value_head_dimension(&self) -> Option<u64> {
+        self.value_length.or_else(|| self.embedding_head_dimension())
+    }
+
+    // As llama.cpp does when the key and value lengths are not set.
+    fn embedding_head_dimension(&self) -> Option<u64> {
+        match self.head_count {
+            Some(head_count) if head_count > 0 => Some(self.embedding_length / head_count),
+            _ => None,
+        }
+    }
+}
+
+// The tensor type using the most bytes, used when general.file_type is missing.
+fn dominant_tensor_type(tensors: &[GGUFTensorInfo]) -> Option<GGMLType> {
+    let mut sizes: HashMap<GGMLType, u64> = HashMap::new();
+    for tensor in tensors.iter() {
+        *sizes.entry(tensor.tensor_type).or_default() += tensor.size();
+    }
+    sizes
+        .into_iter()
+        .max_by_key(|(_, size)| *size)
+        .map(|(tensor_type, _)| tensor_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gguf::{ GGMLType, GGUFMetadataValue, GGUF },
+        safetensors::SafeTensors,
+        testing::{ create_model, tensor },
+    };
+    use super::ModelDescriptor;
+
+    fn create_base(architecture: &str) -> GGUF {
+        let key = |name: &str| format!("{}.{}", architecture, name);
+        create_model(
+            architecture,
+            &[
+                (&key("context_length"), GGUFMetadataValue::Uint32(8192)),
+                (&key("embedding_length"), GGUFMetadataValue::Uint32(4096)),
+                (&key("block_count"), GGUFMetadataValue::Uint32(32)),
+            ],
+            &[]
+        )
+    }
+
+    #[test]
+    fn describe_llama() {
+        let mut gguf = create_base("llama");
+        gguf.set_metadata("llama.attention.head_count", GGUFMetadataValue::Uint32(32));
+        gguf.set_metadata("llama.attention.head_count_kv", GGUFMetadataValue::Uint32(8));
+        gguf.set_metadata("llama.rope.freq_base", GGUFMetadataValue::Float32(500000.0));
+        gguf.set_metadata("general.file_type", GGUFMetadataValue::Uint32(15));
+        gguf.set_metadata("tokenizer.ggml.eos_token_id", GGUFMetadataValue::Uint32(128009));
+        gguf.tensors.push(tensor("token_embd.weight", &[4096, 256], GGMLType::Q4_K));
+
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        assert_eq!(descriptor.context_length, 8192);
+        assert_eq!(descriptor.head_count_kv, Some(8));
+        assert_eq!(descriptor.head_dimension(), Some(128));
+        assert_eq!(descriptor.rope.as_ref().unwrap().freq_base, Some(500000.0));
+        assert_eq!(descriptor.quantization.as_deref(), Some("Q4_K_M"));
+        assert_eq!(descriptor.bits(), Some(4));
+        assert_eq!(descriptor.parameter_count, 4096 * 256);
+        assert_eq!(descriptor.bits_per_weight, Some(4.5));
+        assert_eq!(descriptor.eos_token_id, Some(128009));
+    }
+
+    #[test]
+    fn describe_gemma() {
+        // gemma-7b: the heads are larger than the embedding split between them.
+        let mut gguf = create_base("gemma");
+        gguf.set_metadata("gemma.embedding_length", GGUFMetadataValue::Uint32(3072));
+        gguf.set_metadata("gemma.attention.head_count", GGUFMetadataValue::Uint32(16));
+        gguf.set_metadata("gemma.attention.head_count_kv", GGUFMetadataValue::Uint32(16));
+        gguf.set_metadata("gemma.attention.key_length", GGUFMetadataValue::Uint32(256));
+        gguf.set_metadata("gemma.attention.value_length", GGUFMetadataValue::Uint32(256));
+
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        assert_eq!(descriptor.key_length, Some(256));
+        assert_eq!(descriptor.head_dimension(), Some(256));
+        assert_eq!(descriptor.value_head_dimension(), Some(256));
+
+        gguf.remove_metadata("gemma.attention.key_length");
+        gguf.remove_metadata("gemma.attention.value_length");
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        assert_eq!(descriptor.head_dimension(), Some(192));
+    }
+
+    #[test]
+    fn missing_keys_depend_on_architecture() {
+        let gguf = create_base("llama");
+        let error = ModelDescriptor::from_gguf(&gguf).unwrap_err();
+        assert!(error.contains("llama.attention.head_count"));
+
+        let mut gguf = create_base("mamba");
+        gguf.set_metadata("mamba.ssm.conv_kernel", GGUFMetadataValue::Uint32(4));
+        gguf.set_metadata("mamba.ssm.inner_size", GGUFMetadataValue::Uint32(8192));
+        gguf.set_metadata("mamba.ssm.state_size", GGUFMetadataValue::Uint32(16));
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        assert!(descriptor.head_count.is_none());
+        assert!(descriptor.rope.is_none());
+    }
+
+    #[test]
+    fn describe_safetensors() {
+        let mut safetensors = SafeTensors::new("model.safetensors");
+        let embeddings = tensor("model.embed_tokens.weight", &[4096, 32000], GGMLType::BF16);
+        safetensors.tensors.push(embeddings);
+        assert!(ModelDescriptor::from_safetensors(&safetensors).is_err());
+
+        safetensors.config = Some(
+            serde_json::json!({
+                "model_type": "mistral",
+                "max_position_embeddings": 32768,
+                "hidden_size": 4096,
+                "num_hidden_layers": 32,
+                "num_attention_heads": 32,
+                "num_key_value_heads": 8,
+                "rope_theta": 1000000.0,
+                "vocab_size": 32000,
+                "eos_token_id": [2, 32000],
+            })
+        );
+        let descriptor = ModelDescriptor::from_safetensors(&safetensors).unwrap();
+        assert_eq!(descriptor.architecture, "llama");
+        assert_eq!(descriptor.context_length, 32768);
+        assert_eq!(descriptor.head_dimension(), Some(128));
+        assert_eq!(descriptor.head_count_kv, Some(8));
+        assert_eq!(descriptor.rope.as_ref().unwrap().freq_base, Some(1000000.0));
+        assert_eq!(descriptor.quantization.as_deref(), Some("BF16"));
+        assert_eq!(descriptor.bits_per_weight, Some(16.0));
+        assert_eq!(descriptor.eos_token_id, Some(2));
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Serialize };
+
+use crate::{ descriptor::ModelDescriptor, gguf::{ GGMLType, GGUFTensorInfo, GGUF } };
+
+// Parameters of the inference that change the memory needed, as given to llama.cpp.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemoryOptions {
+    // None or 0 use the context length of the model, as llama.cpp does.
+    pub context_size: Option<u64>,
+    pub batch_size: u64,
+    // Type of the KV cache, F16 unless quantized with --cache-type-k/v.
+    pub kv_type: GGMLType,
+    // Number of layers offloaded to the GPU.
+    pub gpu_layers: u64,
+}
+
+impl Default for MemoryOptions {
+    fn default() -> Self {
+        MemoryOptions {
+            context_size: None,
+            batch_size: 512,
+            kv_type: GGMLType::F16,
+            gpu_layers: 0,
+        }
+    }
+}
+
+// Estimated memory in bytes, split between RAM and VRAM.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryRequirements {
+    pub context_size: u64,
+    pub weights: u64,
+    pub kv_cache: u64,
+    pub compute: u64,
+    pub ram: u64,
+    pub vram: u64,
+}
+
+impl MemoryRequirements {
+    pub fn estimate(
+        gguf: &GGUF,
+        descriptor: &ModelDescriptor,
+        options: &MemoryOptions
+    ) -> MemoryRequirements {
+        let context_size = match options.context_size {
+            Some(context_size) if context_size > 0 => context_size,
+            _ => descriptor.context_length,
+        };
+        let block_count = descriptor.block_count;
+        let gpu_layers = options.gpu_layers.min(block_count + 1);
+        // llama.cpp offloads the last layers first, and the output layer only when more layers
+        // than the blocks of the model are offloaded.
+        let first_gpu_layer = block_count.saturating_sub(gpu_layers);
+        let is_offloaded = |layer: u64| gpu_layers > 0 && layer >= first_gpu_layer;
+        let is_output_offloaded = gpu_layers > block_count;
+
+        let mut weights = 0;
+        let mut vram = 0;
+        for tensor in gguf.tensors.iter() {
+            let size = tensor.size();
+            weights += size;
+            match tensor_layer(tensor) {
+                Some(layer) if is_offloaded(layer) => {
+                    vram += size;
+                }
+                // The token embeddings always stay in RAM.
+                None if tensor.name.starts_with("output") && is_output_offloaded => {
+                    vram += size;
+                }
+                _ => {}
+            }
+        }
+
+        // Keys and values for each layer, recurrent architectures only keep
+        // a small fixed state which is not counted.
+        let head_dimensions = descriptor.head_dimension().zip(descriptor.value_head_dimension());
+        let kv_per_layer = match (descriptor.head_count_kv, head_dimensions) {
+            (Some(head_count_kv), Some((key_length, value_length))) => {
+                let elements = context_size * head_count_kv * (key_length + value_length);
+                (elements * options.kv_type.type_size()) / options.kv_type.block_size()
+            }
+            _ => 0,
+        };
+        let kv_cache = kv_per_layer * block_count;
+        vram += kv_per_layer * (0..block_count).filter(|layer| is_offloaded(*layer)).count() as u64;
+
+        let compute = compute_buffer_size(descriptor, context_size, options.batch_size);
+        if gpu_layers > 0 {
+            vram += compute;
+        }
+
+        let total = weights + kv_cache + compute;
+        MemoryRequirements {
+            context_size,
+            weights,
+            kv_cache,
+            compute,
+            ram: total.saturating_sub(vram),
+            vram,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.weights + self.kv_cache + self.compute
+    }
+}
+
+// Layer of a tensor named blk.N.*
+fn tensor_layer(tensor: &GGUFTensorInfo) -> Option<u64> {
+    tensor.name.strip_prefix("blk.")?.split('.').next()?.parse().ok()
+}
+
+// Rough size of the f32 buffers used to evaluate a batch: activations, attention scores
+// without flash attention, and the logits.
+fn compute_buffer_size(descriptor: &ModelDescriptor, context_size: u64, batch_size: u64) -> u64 {
+    let batch_size = batch_size.min(context_size.max(1));
+    let feed_forward_length = descriptor.feed_forward_length.unwrap_or(
+        4 * descriptor.embedding_length
+    );
+    let activations = batch_size * (4 * descriptor.embedding_length + 2 * feed_forward_length);
+    let attention = batch_size * context_size * descriptor.head_count.unwrap_or_default();
+    let logits = batch_size * descriptor.vocab_size.unwrap_or_default();
+    (activations + attention + logits) * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        descriptor::ModelDescriptor,
+        gguf::{ GGMLType, GGUFMetadataValue, GGUF },
+        testing::{ create_model, tensor },
+    };
+    use super::{ MemoryOptions, MemoryRequirements };
+
+    fn create_llama() -> GGUF {
+        let tensors: Vec<_> = [
+            "token_embd.weight",
+            "blk.0.attn_q.weight",
+            "blk.1.attn_q.weight",
+            "output.weight",
+        ]
+            .iter()
+            .map(|name| tensor(name, &[4096, 256], GGMLType::Q8_0))
+            .collect();
+        create_model(
+            "llama",
+            &[
+                ("llama.context_length", GGUFMetadataValue::Uint32(4096)),
+                ("llama.embedding_length", GGUFMetadataValue::Uint32(4096)),
+                ("llama.block_count", GGUFMetadataValue::Uint32(2)),
+                ("llama.attention.head_count", GGUFMetadataValue::Uint32(32)),
+                ("llama.attention.head_count_kv", GGUFMetadataValue::Uint32(8)),
+            ],
+            &tensors
+        )
+    }
+
+    #[test]
+    fn estimate_kv_cache_and_offload() {
+        let gguf = create_llama();
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+        let tensor_size = gguf.tensors[0].size();
+
+        let requirements = MemoryRequirements::estimate(&gguf, &descriptor, &MemoryOptions::default());
+        assert_eq!(requirements.context_size, 4096);
+        assert_eq!(requirements.weights, 4 * tensor_size);
+        // 2 layers * K and V * 4096 tokens * 8 heads * 128 dimensions * 2 bytes
+        assert_eq!(requirements.kv_cache, 2 * 2 * 4096 * 8 * 128 * 2);
+        assert_eq!(requirements.vram, 0);
+        assert_eq!(requirements.ram, requirements.total());
+
+        let options = MemoryOptions {
+            context_size: Some(1024),
+            kv_type: GGMLType::Q8_0,
+            gpu_layers: 1,
+            ..MemoryOptions::default()
+        };
+        let requirements = MemoryRequirements::estimate(&gguf, &descriptor, &options);
+        assert_eq!(requirements.kv_cache, (2 * 2 * 1024 * 8 * 128 * 34) / 32);
+        // Only the last block and its keys and values are offloaded.
+        assert_eq!(
+            requirements.vram,
+            tensor_size + requirements.kv_cache / 2 + requirements.compute
+        );
+
+        let options = MemoryOptions { gpu_layers: 2, ..MemoryOptions::default() };
+        let requirements = MemoryRequirements::estimate(&gguf, &descriptor, &options);
+        // The output layer stays in RAM with the token embeddings.
+        assert_eq!(requirements.ram, 2 * tensor_size);
+
+        let options = MemoryOptions { gpu_layers: 99, ..MemoryOptions::default() };
+        let requirements = MemoryRequirements::estimate(&gguf, &descriptor, &options);
+        assert_eq!(requirements.ram, tensor_size);
+    }
+
+    #[test]
+    fn estimate_kv_cache_with_key_and_value_lengths() {
+        let mut gguf = create_llama();
+        gguf.set_metadata("llama.attention.key_length", GGUFMetadataValue::Uint32(256));
+        gguf.set_metadata("llama.attention.value_length", GGUFMetadataValue::Uint32(192));
+        let descriptor = ModelDescriptor::from_gguf(&gguf).unwrap();
+
+        // A context size of 0 is the context length of the model.
+        let options = MemoryOptions { context_size: Some(0), ..MemoryOptions::default() };
+        let requirements = MemoryRequirements::estimate(&gguf, &descriptor, &options);
+        assert_eq!(requirements.context_size, 4096);
+        // 2 layers * 4096 tokens * 8 heads * (256 + 192) dimensions * 2 bytes
+        assert_eq!(requirements.kv_cache, 2 * 4096 * 8 * (256 + 192) * 2);
+    }
+}
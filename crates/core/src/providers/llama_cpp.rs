@@ -0,0 +1,242 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Serialize };
+
+use crate::chat_template::{ ChatMessage, ChatTemplate };
+
+use super::llm::{ LlmCompletionResponse, LlmQueryCompletion, LlmUsage };
+
+// Parameters of the /completion endpoint.
+pub const LLAMACPP_COMPLETION_PARAMETERS: &[&str] = &[
+    "temperature",
+    "frequency_penalty",
+    "presence_penalty",
+    "seed",
+    "stop",
+    "top_p",
+    "top_k",
+    "min_p",
+    "n_predict",
+    "n_keep",
+    "tfs_z",
+    "typical_p",
+    "repeat_penalty",
+    "repeat_last_n",
+    "penalize_nl",
+    "penalty_prompt",
+    "mirostat",
+    "mirostat_tau",
+    "mirostat_eta",
+    "grammar",
+    "ignore_eos",
+];
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlamaCppCompletionQuery {
+    pub prompt: String,
+    pub stream: Option<bool>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub seed: Option<f32>,
+    pub stop: Option<Vec<String>>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<f32>,
+    pub min_p: Option<f32>,
+    pub n_predict: Option<f32>,
+    pub n_keep: Option<f32>,
+    pub tfs_z: Option<f32>,
+    pub typical_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    pub repeat_last_n: Option<f32>,
+    pub penalize_nl: Option<bool>,
+    pub penalty_prompt: Option<Vec<String>>,
+    pub mirostat: Option<f32>,
+    pub mirostat_tau: Option<f32>,
+    pub mirostat_eta: Option<f32>,
+    pub grammar: Option<String>,
+    pub ignore_eos: Option<bool>,
+}
+
+impl LlmQueryCompletion {
+    pub fn to_llama_cpp_parameters(&self, prompt: String) -> LlamaCppCompletionQuery {
+        LlamaCppCompletionQuery {
+            prompt,
+            stream: self.get_parameter_as_boolean("stream"),
+            temperature: self.get_parameter_as_f32("temperature"),
+            stop: self.get_parameter_array("stop"),
+            frequency_penalty: self.get_parameter_as_f32("frequency_penalty"),
+            presence_penalty: self.get_parameter_as_f32("presence_penalty"),
+            seed: self.get_parameter_as_f32("seed"),
+            top_p: self.get_parameter_as_f32("top_p"),
+            top_k: self.get_parameter_as_f32("top_k"),
+            min_p: self.get_parameter_as_f32("min_p"),
+            n_predict: self.get_parameter_as_f32("n_predict"),
+            n_keep: self.get_parameter_as_f32("n_keep"),
+            tfs_z: self.get_parameter_as_f32("tfs_z"),
+            typical_p: self.get_parameter_as_f32("typical_p"),
+            repeat_penalty: self.get_parameter_as_f32("repeat_penalty"),
+            repeat_last_n: self.get_parameter_as_f32("repeat_last_n"),
+            penalize_nl: self.get_parameter_as_boolean("penalize_nl"),
+            penalty_prompt: self.get_parameter_array("penalty_prompt"),
+            mirostat: self.get_parameter_as_f32("mirostat"),
+            mirostat_tau: self.get_parameter_as_f32("mirostat_tau"),
+            mirostat_eta: self.get_parameter_as_f32("mirostat_eta"),
+            grammar: self.get_parameter_value("grammar"),
+            ignore_eos: self.get_parameter_as_boolean("ignore_eos"),
+        }
+    }
+
+    pub fn to_chat_prompt(
+        &self,
+        template: &ChatTemplate,
+        system: Option<&str>
+    ) -> Result<String, String> {
+        let messages: Vec<ChatMessage> = self
+            .to_messages(system)
+            .iter()
+            .map(|message| ChatMessage::new(&message.role, &message.content))
+            .collect();
+        let prompt = template.render(&messages, true)?;
+        // The server already adds the bos token.
+        Ok(prompt.strip_prefix(&template.bos_token).unwrap_or(&prompt).to_string())
+    }
+
+    // Used when the model has no chat template.
+    pub fn to_raw_prompt(&self, system: Option<&str>) -> String {
+        let mut prompt = String::new();
+        if let Some(system) = system {
+            prompt.push_str(&format!("{}\n", system));
+        }
+        for message in &self.messages {
+            match message.role.as_str() {
+                "user" => prompt.push_str("Question:"),
+                "assistant" => prompt.push_str("Answer:"),
+                _ => {}
+            }
+            prompt.push_str(message.content.trim());
+            prompt.push('\n');
+        }
+        prompt.push_str("Answer:");
+        prompt
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlamaCppChatTimings {
+    pub predicted_ms: f32,
+    pub predicted_n: i32,
+    pub predicted_per_second: f32,
+    pub predicted_per_token_ms: f32,
+    pub prompt_ms: f32,
+    pub prompt_n: i32,
+    pub prompt_per_second: f32,
+    pub prompt_per_token_ms: f32,
+}
+
+impl LlamaCppChatTimings {
+    pub fn to_llm_usage(&self) -> LlmUsage {
+        LlmUsage {
+            completion_tokens: Some(self.predicted_n),
+            prompt_tokens: Some(self.prompt_n),
+            total_tokens: Some(self.predicted_n + self.prompt_n),
+            completion_ms: Some(self.predicted_ms as i64),
+            prompt_ms: Some(self.prompt_ms as i64),
+            total_ms: Some((self.predicted_ms + self.prompt_ms) as i64),
+            prompt_per_second: Some(self.prompt_per_second),
+            completion_per_second: Some(self.predicted_per_second),
+            total_per_second: Some(self.predicted_per_second + self.prompt_per_second),
+        }
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlamaCppCompletionResponse {
+    pub content: String,
+    pub timings: LlamaCppChatTimings,
+}
+
+impl LlamaCppCompletionResponse {
+    pub fn to_llm_response(&self) -> LlmCompletionResponse {
+        LlmCompletionResponse {
+            created: 0,
+            status: "finished".to_owned(),
+            content: self.content.clone(),
+            usage: Some(self.timings.to_llm_usage()),
+        }
+    }
+}
+
+// The last chunk of a stream has the stop flag and the timings.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlamaCppChatCompletionChunk {
+    pub content: String,
+    pub stop: Option<bool>,
+    pub timings: Option<LlamaCppChatTimings>,
+}
+
+impl LlamaCppChatCompletionChunk {
+    // Parses the data of a server-sent event.
+    pub fn from_event(data: &str) -> Result<Self, String> {
+        serde_json
+            ::from_str::<LlamaCppChatCompletionChunk>(data)
+            .map_err(|error| format!("Failed to parse response: {}", error))
+    }
+
+    pub fn is_stop(&self) -> bool {
+        self.stop.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::providers::llm::{ LlmMessage, LlmParameter, LlmQueryCompletion };
+
+    use super::LlamaCppChatCompletionChunk;
+
+    #[test]
+    fn completion_query_and_chunks() {
+        let query = LlmQueryCompletion {
+            conversation_id: None,
+            message_id: None,
+            messages: vec![LlmMessage::new("user", "Hello"), LlmMessage::new("assistant", "Hi")],
+            prompt: None,
+            parameters: Some(
+                vec![LlmParameter { key: "n_predict".to_string(), value: "128".to_string() }]
+            ),
+        };
+        let prompt = query.to_raw_prompt(Some("Be brief"));
+        assert_eq!(prompt, "Be brief\nQuestion:Hello\nAnswer:Hi\nAnswer:");
+        let body = serde_json::to_value(query.to_llama_cpp_parameters(prompt)).unwrap();
+        assert_eq!(body["n_predict"], 128.0);
+        assert!(body.get("stream").is_none());
+
+        let chunk = LlamaCppChatCompletionChunk::from_event("{\"content\":\"Hel\"}").unwrap();
+        assert_eq!(chunk.content, "Hel");
+        assert!(!chunk.is_stop());
+        let data =
+            "{\"content\":\"\",\"stop\":true,\"timings\":{\"predicted_ms\":10.0,\"predicted_n\":2,\
+            \"predicted_per_second\":200.0,\"predicted_per_token_ms\":5.0,\"prompt_ms\":4.0,\
+            \"prompt_n\":4,\"prompt_per_second\":1000.0,\"prompt_per_token_ms\":1.0}}";
+        let chunk = LlamaCppChatCompletionChunk::from_event(data).unwrap();
+        assert!(chunk.is_stop());
+        assert_eq!(chunk.timings.unwrap().to_llm_usage().total_tokens, Some(6));
+        assert!(LlamaCppChatCompletionChunk::from_event("{}").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Serialize };
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmMessage {
+    pub content: String,
+    pub role: String,
+    pub name: Option<String>,
+}
+
+impl LlmMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        LlmMessage { content: content.to_string(), role: role.to_string(), name: None }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmParameter {
+    pub key: String,
+    pub value: String,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmQueryCompletion {
+    pub conversation_id: Option<String>,
+    pub message_id: Option<String>,
+    pub messages: Vec<LlmMessage>,
+    pub prompt: Option<String>,
+    pub parameters: Option<Vec<LlmParameter>>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmCompletionOptions {
+    pub context_window_policy: Option<String>,
+    pub keep_system: Option<bool>,
+    pub system: Option<String>,
+}
+
+// Parameters are strings, as set in the presets, and converted when the body of the request is
+// built: numbers, booleans, and comma separated lists for arrays.
+impl LlmQueryCompletion {
+    pub fn get_parameter_value(&self, key: &str) -> Option<String> {
+        self.parameters
+            .as_ref()?
+            .iter()
+            .find(|p| p.key == key)
+            .map(|p| p.value.clone())
+    }
+
+    pub fn get_parameter_as_boolean(&self, key: &str) -> Option<bool> {
+        match self.get_parameter_value(key)?.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn get_parameter_as_f32(&self, key: &str) -> Option<f32> {
+        self.get_parameter_value(key)?.parse::<f32>().ok()
+    }
+
+    pub fn get_parameter_array(&self, key: &str) -> Option<Vec<String>> {
+        let value = self.get_parameter_value(key)?;
+        Some(
+            value
+                .split(',')
+                .map(|item| item.to_owned())
+                .collect()
+        )
+    }
+
+    // The system prompt of the options comes first.
+    pub fn to_messages(&self, system: Option<&str>) -> Vec<LlmMessage> {
+        let mut messages = Vec::new();
+        if let Some(system) = system {
+            messages.push(LlmMessage::new("system", system));
+        }
+        // TODO: handle context_window_policy and keep_system
+        messages.extend(self.messages.iter().cloned());
+        messages
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmQuery<T> {
+    pub command: String,
+    pub options: T,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LlmUsage {
+    // Tokens
+    pub completion_tokens: Option<i32>,
+    pub prompt_tokens: Option<i32>,
+    pub total_tokens: Option<i32>,
+
+    // Timing
+    pub completion_ms: Option<i64>,
+    pub prompt_ms: Option<i64>,
+    pub total_ms: Option<i64>,
+    pub prompt_per_second: Option<f32>,
+    pub completion_per_second: Option<f32>,
+    pub total_per_second: Option<f32>,
+}
+
+impl LlmUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmCompletionResponse {
+    pub created: i64,
+    pub status: String,
+    pub content: String,
+    pub usage: Option<LlmUsage>,
+}
+
+impl LlmCompletionResponse {
+    pub fn new(created: i64, status: &str, content: &str) -> Self {
+        Self {
+            created,
+            status: status.to_owned(),
+            content: content.to_owned(),
+            usage: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ LlmMessage, LlmParameter, LlmQueryCompletion };
+
+    #[test]
+    fn query_parameters() {
+        let parameters = [
+            ("temperature", "0.7"),
+            ("stream", "true"),
+            ("stop", "</s>,User:"),
+        ];
+        let query = LlmQueryCompletion {
+            conversation_id: None,
+            message_id: None,
+            messages: vec![LlmMessage::new("user", "Hello")],
+            prompt: None,
+            parameters: Some(
+                parameters
+                    .iter()
+                    .map(|(key, value)| LlmParameter {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect()
+            ),
+        };
+        assert_eq!(query.get_parameter_as_f32("temperature"), Some(0.7));
+        assert_eq!(query.get_parameter_as_boolean("stream"), Some(true));
+        assert_eq!(
+            query.get_parameter_array("stop"),
+            Some(vec!["</s>".to_string(), "User:".to_string()])
+        );
+        assert_eq!(query.get_parameter_value("seed"), None);
+
+        let messages = query.to_messages(Some("Be brief"));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(messages[1].content, "Hello");
+    }
+}
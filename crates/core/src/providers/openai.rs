@@ -0,0 +1,253 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ Deserialize, Serialize };
+
+use super::llm::{
+    LlmCompletionOptions,
+    LlmCompletionResponse,
+    LlmMessage,
+    LlmQueryCompletion,
+    LlmUsage,
+};
+
+// Parameters of the chat completions endpoint.
+pub const OPENAI_COMPLETION_PARAMETERS: &[&str] = &[
+    "temperature",
+    "frequency_penalty",
+    "presence_penalty",
+    "seed",
+    "stop",
+    "top_p",
+    "max_tokens",
+];
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: String,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIErrorResponse {
+    pub error: OpenAIError,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIBodyCompletion {
+    pub model: String,
+    pub messages: Vec<LlmMessage>,
+    pub stream: Option<bool>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub seed: Option<f32>,
+    pub stop: Option<Vec<String>>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<f32>,
+}
+
+impl OpenAIBodyCompletion {
+    pub fn new(
+        model: String,
+        from: &LlmQueryCompletion,
+        options: Option<LlmCompletionOptions>
+    ) -> Self {
+        let system = options.and_then(|options| options.system);
+        Self {
+            model,
+            messages: from.to_messages(system.as_deref()),
+            stream: from.get_parameter_as_boolean("stream"),
+            temperature: from.get_parameter_as_f32("temperature"),
+            stop: from.get_parameter_array("stop"),
+            frequency_penalty: from.get_parameter_as_f32("frequency_penalty"),
+            presence_penalty: from.get_parameter_as_f32("presence_penalty"),
+            seed: from.get_parameter_as_f32("seed"),
+            top_p: from.get_parameter_as_f32("top_p"),
+            max_tokens: from.get_parameter_as_f32("max_tokens"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIChatChoice {
+    pub message: LlmMessage,
+    pub index: i32,
+    pub finish_reason: String,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LlmChunkMessage {
+    pub content: Option<String>,
+    pub role: Option<String>,
+    pub name: Option<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIChatChunkChoice {
+    pub delta: LlmChunkMessage,
+    pub index: i32,
+    pub finish_reason: Option<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIChatCompletionChunk {
+    pub id: String,
+    pub choices: Vec<OpenAIChatChunkChoice>,
+    pub model: String,
+    pub created: i64,
+    pub system_fingerprint: Option<String>,
+    pub object: String,
+}
+
+impl OpenAIChatCompletionChunk {
+    // Parses the data of a server-sent event, None at the end of the stream.
+    pub fn from_event(data: &str) -> Result<Option<Self>, String> {
+        if data == "[DONE]" {
+            return Ok(None);
+        }
+        serde_json
+            ::from_str::<OpenAIChatCompletionChunk>(data)
+            .map(Some)
+            .map_err(|error| format!("Failed to deserialize event data: {}", error))
+    }
+
+    // TODO: handle multiple choices index
+    pub fn content(&self) -> &str {
+        self.choices
+            .first()
+            .and_then(|choice| choice.delta.content.as_deref())
+            .unwrap_or("")
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIChatUsage {
+    pub completion_tokens: i32,
+    pub prompt_tokens: i32,
+    pub total_tokens: i32,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenAIChatCompletion {
+    pub id: String,
+    pub choices: Vec<OpenAIChatChoice>,
+    pub object: String,
+    pub created: i64,
+    pub system_fingerprint: Option<String>,
+    pub usage: Option<OpenAIChatUsage>,
+}
+
+impl OpenAIChatCompletion {
+    pub fn from_chunks(chunks: &[OpenAIChatCompletionChunk]) -> Self {
+        let first = chunks.first();
+        let last_choice = chunks.last().and_then(|chunk| chunk.choices.first());
+        let content: String = chunks
+            .iter()
+            .map(|chunk| chunk.content())
+            .collect();
+        let choice = OpenAIChatChoice {
+            message: LlmMessage {
+                content,
+                role: last_choice
+                    .and_then(|choice| choice.delta.role.clone())
+                    .unwrap_or("assistant".to_owned()),
+                name: last_choice.and_then(|choice| choice.delta.name.clone()),
+            },
+            index: 0,
+            finish_reason: last_choice
+                .and_then(|choice| choice.finish_reason.clone())
+                .unwrap_or_default(),
+        };
+        Self {
+            id: first.map(|chunk| chunk.id.clone()).unwrap_or_default(),
+            choices: vec![choice],
+            object: first.map(|chunk| chunk.object.clone()).unwrap_or_default(),
+            created: first.map(|chunk| chunk.created).unwrap_or_default(),
+            system_fingerprint: first.and_then(|chunk| chunk.system_fingerprint.clone()),
+            // TODO implement usage
+            // see :https://community.openai.com/t/usage-stats-now-available-when-using-streaming-with-the-chat-completions-api-or-completions-api/738156/3
+            usage: None,
+        }
+    }
+
+    pub fn to_llm_response(&self) -> LlmCompletionResponse {
+        let content = self.choices
+            .first()
+            .map(|choice| choice.message.content.as_str())
+            .unwrap_or("");
+        let mut response = LlmCompletionResponse::new(self.created, "finished", content);
+        response.usage = self.usage.as_ref().map(|usage| LlmUsage {
+            completion_tokens: Some(usage.completion_tokens),
+            prompt_tokens: Some(usage.prompt_tokens),
+            total_tokens: Some(usage.total_tokens),
+            ..LlmUsage::default()
+        });
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::providers::llm::{ LlmCompletionOptions, LlmMessage, LlmQueryCompletion };
+
+    use super::{ OpenAIBodyCompletion, OpenAIChatCompletion, OpenAIChatCompletionChunk };
+
+    #[test]
+    fn body_and_chunks() {
+        let query = LlmQueryCompletion {
+            conversation_id: None,
+            message_id: None,
+            messages: vec![LlmMessage::new("user", "Hello")],
+            prompt: None,
+            parameters: None,
+        };
+        let options = LlmCompletionOptions {
+            context_window_policy: None,
+            keep_system: None,
+            system: Some("Be brief".to_string()),
+        };
+        let body = OpenAIBodyCompletion::new("gpt-4o".to_string(), &query, Some(options));
+        let body = serde_json::to_value(body).unwrap();
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "Hello");
+        assert!(body.get("temperature").is_none());
+
+        let chunks: Vec<OpenAIChatCompletionChunk> = ["Hel", "lo"]
+            .iter()
+            .map(|content| {
+                let data = format!(
+                    "{{\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":2,\
+                    \"model\":\"gpt-4o\",\"choices\":[{{\"index\":0,\"delta\":\
+                    {{\"content\":\"{}\"}}}}]}}",
+                    content
+                );
+                OpenAIChatCompletionChunk::from_event(&data).unwrap().unwrap()
+            })
+            .collect();
+        assert!(OpenAIChatCompletionChunk::from_event("[DONE]").unwrap().is_none());
+        let response = OpenAIChatCompletion::from_chunks(&chunks).to_llm_response();
+        assert_eq!(response.content, "Hello");
+        assert_eq!(response.created, 2);
+        assert!(response.usage.is_none());
+    }
+}
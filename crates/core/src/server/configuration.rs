@@ -0,0 +1,278 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Configuration of the inference server: its parameters as stored by the app, and the
+// definitions used to turn them into command line arguments.
+
+use std::{ collections::HashMap, fmt };
+use serde::{ Deserialize, Serialize };
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    String(String),
+    Number(f32),
+    Integer(i32),
+    Boolean(bool),
+    Option(Option<String>),
+    Metadata(HashMap<String, MetadataValue>),
+}
+
+impl fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", s),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl MetadataValue {
+    pub fn to_int(&self, default_value: i32) -> i32 {
+        match self {
+            Self::Integer(i) => *i,
+            Self::Number(f) => *f as i32,
+            _ => default_value,
+        }
+    }
+
+    pub fn to_float(&self, default_value: f32) -> f32 {
+        match self {
+            Self::Integer(i) => *i as f32,
+            Self::Number(f) => *f,
+            _ => default_value,
+        }
+    }
+
+    pub fn to_bool(&self, default_value: bool) -> bool {
+        match self {
+            Self::Boolean(v) => *v,
+            _ => default_value,
+        }
+    }
+}
+
+pub type Metadata = HashMap<String, MetadataValue>;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum ServerParameterType {
+    #[serde(rename = "string")]
+    String,
+    #[serde(rename = "number")]
+    Number,
+    #[serde(rename = "integer")]
+    Integer,
+    #[serde(rename = "boolean")]
+    Boolean,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ServerParameterValue<'a> {
+    String(&'a str),
+    Number(f32),
+    Integer(i32),
+    Boolean(bool),
+    None(()),
+}
+
+impl fmt::Display for ServerParameterValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", s),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl ServerParameterValue<'_> {
+    pub fn to_int(&self, default_value: i32) -> i32 {
+        match self {
+            Self::Integer(i) => *i,
+            Self::Number(f) => *f as i32,
+            _ => default_value,
+        }
+    }
+
+    pub fn to_float(&self, default_value: f32) -> f32 {
+        match self {
+            Self::Integer(i) => *i as f32,
+            Self::Number(f) => *f,
+            _ => default_value,
+        }
+    }
+
+    pub fn to_bool(&self, default_value: bool) -> bool {
+        match self {
+            Self::Boolean(v) => *v,
+            _ => default_value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ServerParameterDefinition<'a> {
+    pub key: &'a str,
+    pub optional: bool,
+    pub r#type: ServerParameterType,
+    pub default_value: ServerParameterValue<'a>,
+    pub option: &'a str,
+    pub long_option: &'a str,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerConfiguration {
+    pub name: String,
+    pub parameters: Metadata,
+}
+
+impl ServerConfiguration {
+    pub fn remove_parameter(&mut self, key: &str) {
+        self.parameters.remove(key);
+    }
+
+    pub fn set_parameter_string(&mut self, key: &str, value: String) {
+        self.parameters.insert(key.to_string(), MetadataValue::String(value));
+    }
+
+    pub fn get_parameter_string(&self, key: &str, default_value: String) -> String {
+        self.parameters
+            .get(key)
+            .map(|s| s.to_string())
+            .unwrap_or(default_value)
+    }
+
+    pub fn get_optional_parameter_string(&self, key: &str) -> Option<String> {
+        self.parameters.get(key).map(|s| s.to_string())
+    }
+
+    pub fn set_parameter_int(&mut self, key: &str, value: i32) {
+        self.parameters.insert(key.to_string(), MetadataValue::Integer(value));
+    }
+
+    pub fn get_parameter_int(&self, key: &str, default_value: i32) -> i32 {
+        self.parameters
+            .get(key)
+            .map(|s| s.to_int(default_value))
+            .unwrap_or(default_value)
+    }
+
+    pub fn contains_parameter(&self, key: &str) -> bool {
+        self.parameters.contains_key(key)
+    }
+
+    pub fn remove_model(&mut self) {
+        self.remove_parameter("model_id");
+        self.remove_parameter("model_path");
+    }
+
+    pub fn has_same_model(&self, other: &ServerConfiguration) -> bool {
+        let model_id = self.get_optional_parameter_string("model_id");
+        model_id.is_some() && other.get_optional_parameter_string("model_id") == model_id
+    }
+
+    // Parameters with their default value are left to the server. The model file is the
+    // model_path argument when a model parameter is set, otherwise the model_path parameter.
+    // No arguments are returned if there is no model.
+    pub fn to_args(
+        &self,
+        model_path: &str,
+        definitions: &phf::Map<&str, ServerParameterDefinition>
+    ) -> Vec<String> {
+        let mut parameters: Vec<String> = Vec::new();
+        let mut is_model = false;
+        for (key, value) in self.parameters.iter() {
+            // model_id and model_path are not arguments of the server.
+            let definition = match definitions.get(key.as_str()) {
+                Some(d) => d,
+                None => {
+                    continue;
+                }
+            };
+            let mut option = definition.option;
+            if option.is_empty() {
+                option = definition.long_option;
+            }
+            parameters.push(option.to_string());
+            let mut validate = true;
+            let mut string_value = value.to_string();
+            if definition.key == "model" {
+                string_value = model_path.to_string();
+                is_model = true;
+            } else if definition.r#type == ServerParameterType::String {
+                let default_value = definition.default_value.to_string();
+                validate = string_value != default_value;
+            } else if definition.r#type == ServerParameterType::Number {
+                let default_value = definition.default_value.to_float(0.0);
+                let value = value.to_float(default_value);
+                validate = value != default_value;
+                string_value = value.to_string();
+            } else if definition.r#type == ServerParameterType::Integer {
+                let default_value = definition.default_value.to_int(0);
+                let value = value.to_int(default_value);
+                validate = value != default_value;
+                string_value = value.to_string();
+            } else if definition.r#type == ServerParameterType::Boolean {
+                let default_value = definition.default_value.to_bool(false);
+                validate = value.to_bool(default_value);
+            }
+            if !validate {
+                parameters.pop();
+            } else if definition.r#type != ServerParameterType::Boolean {
+                parameters.push(string_value);
+            }
+        }
+        if !is_model {
+            let (Some(model), Some(definition)) = (
+                self.parameters.get("model_path"),
+                definitions.get("model"),
+            ) else {
+                return Vec::new();
+            };
+            parameters.push(definition.option.to_string());
+            parameters.push(model.to_string());
+        }
+        parameters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{ MetadataValue, ServerConfiguration };
+    use crate::llama_cpp::LLAMACPP_PARAMETERS_DEFINITIONS;
+
+    #[test]
+    fn llama_cpp_args() {
+        let parameters = HashMap::from([
+            ("model_id".to_string(), MetadataValue::String("1".to_string())),
+            ("model_path".to_string(), MetadataValue::String("/models/phi.gguf".to_string())),
+            ("host".to_string(), MetadataValue::String("127.0.0.1".to_string())),
+            ("port".to_string(), MetadataValue::Integer(8081)),
+            ("context_size".to_string(), MetadataValue::Integer(512)),
+            ("mlock".to_string(), MetadataValue::Boolean(true)),
+        ]);
+        let mut configuration = ServerConfiguration { name: "llama.cpp".to_string(), parameters };
+        let args = configuration.to_args("", &LLAMACPP_PARAMETERS_DEFINITIONS);
+        let args = args.join(" ");
+        assert!(args.contains("--port 8081"));
+        assert!(args.contains("--mlock"));
+        assert!(!args.contains("--host") && !args.contains("--ctx-size"));
+        assert!(args.ends_with("-m /models/phi.gguf"));
+
+        configuration.remove_model();
+        assert!(configuration.to_args("", &LLAMACPP_PARAMETERS_DEFINITIONS).is_empty());
+    }
+}
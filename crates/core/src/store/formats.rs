@@ -0,0 +1,122 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Serde helpers for the values written by the app: dates are ISO 8601 strings or
+// milliseconds timestamps, entities and resources are strings or structs.
+
+use std::{ fmt, marker::PhantomData, str::FromStr };
+use serde::{ de::{ self, MapAccess, Visitor }, Deserialize, Deserializer };
+use void::Void;
+
+pub mod option_date_format {
+    use chrono::{ DateTime, Utc };
+    use serde::{ de, Deserialize, Deserializer };
+    use serde_json::Value;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let value = Option::<Value>::deserialize(deserializer)?;
+        let datetime = match value {
+            Some(v) =>
+                match v.as_str() {
+                    Some(s) =>
+                        DateTime::parse_from_rfc3339(s)
+                            .map_err(de::Error::custom)?
+                            .with_timezone(&Utc),
+                    None =>
+                        match DateTime::from_timestamp_millis(v.as_i64().unwrap_or(-1)) {
+                            Some(d) => d,
+                            None => {
+                                return Err(de::Error::custom("Invalid timestamp millis"));
+                            }
+                        }
+                }
+            None => {
+                return Ok(None);
+            }
+        };
+        Ok(Some(datetime))
+    }
+
+    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        match date {
+            Some(date) => serializer.serialize_str(&date.with_timezone(&Utc).to_rfc3339()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+// See https://serde.rs/string-or-struct.html
+pub fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where T: Deserialize<'de> + FromStr<Err = Void>, D: Deserializer<'de>
+{
+    // Strings are read with the FromStr implementation of T, maps with its Deserialize one.
+    struct StringOrStruct<T>(PhantomData<fn() -> T>);
+
+    impl<'de, T> Visitor<'de>
+        for StringOrStruct<T>
+        where T: Deserialize<'de> + FromStr<Err = Void>
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("string or map")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<T, E> where E: de::Error {
+            match FromStr::from_str(value) {
+                Ok(v) => Ok(v),
+                Err(_) => Err(de::Error::custom("invalid string")),
+            }
+        }
+
+        fn visit_map<M>(self, map: M) -> Result<T, M::Error> where M: MapAccess<'de> {
+            Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(StringOrStruct(PhantomData))
+}
+
+pub fn option_string_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where T: Deserialize<'de> + FromStr<Err = Void>, D: Deserializer<'de>
+{
+    struct OptStringOrStruct<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de>
+        for OptStringOrStruct<T>
+        where T: Deserialize<'de> + FromStr<Err = Void>
+    {
+        type Value = Option<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a null, a string or map")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E> where E: de::Error {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where D: Deserializer<'de>
+        {
+            string_or_struct(deserializer).map(Some)
+        }
+    }
+
+    deserializer.deserialize_option(OptStringOrStruct(PhantomData))
+}
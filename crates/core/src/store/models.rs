@@ -0,0 +1,617 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The models of the app configuration: the installed models, their files and the folder
+// where they are stored.
+
+use std::{ fs::create_dir_all, path::{ Path, PathBuf }, str::FromStr };
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use serde_with::{ serde_as, OneOrMany, formats::PreferOne };
+use uuid::Uuid;
+use void::Void;
+
+use crate::{
+    adapter::{ LoraAdapter, LoraCompatibility },
+    chat_template::ChatTemplate,
+    descriptor::ModelDescriptor,
+    directories::{ get_data_directory, get_home_directory },
+    gguf::GGUF,
+    memory::{ MemoryOptions, MemoryRequirements },
+};
+use super::formats::{ option_date_format, option_string_or_struct };
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entity {
+    pub name: String,
+    pub email: Option<String>,
+    pub url: Option<String>,
+}
+
+impl FromStr for Entity {
+    type Err = Void;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Entity {
+            name: s.to_string(),
+            email: None,
+            url: None,
+        })
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Resource {
+    pub url: String,
+    pub name: Option<String>,
+}
+
+impl FromStr for Resource {
+    type Err = Void;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Resource {
+            url: s.to_string(),
+            name: None,
+        })
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Logo {
+    pub url: String,
+    pub name: Option<String>,
+    pub color: Option<String>,
+}
+
+impl FromStr for Logo {
+    type Err = Void;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http") {
+            Ok(Logo {
+                url: s.to_string(),
+                name: None,
+                color: None,
+            })
+        } else {
+            Ok(Logo {
+                url: "".to_string(),
+                name: Some(s.to_string()),
+                color: None,
+            })
+        }
+    }
+}
+
+#[serde_as]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Model {
+    pub id: Option<String>,
+    pub name: String,
+    pub base_model: Option<String>,
+    #[serde(
+        with = "option_date_format",
+        alias = "updatedAt",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(
+        with = "option_date_format",
+        alias = "createdAt",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub summary: Option<String>,
+    pub version: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub icon: Option<Logo>,
+    pub creator: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub author: Option<Entity>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub publisher: Option<Entity>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub license: Option<Entity>,
+    #[serde_as(deserialize_as = "Option<OneOrMany<_, PreferOne>>")]
+    pub languages: Option<Vec<String>>,
+
+    #[serde_as(deserialize_as = "Option<OneOrMany<_, PreferOne>>")]
+    pub tags: Option<Vec<String>>,
+    pub recommendations: Option<String>,
+    pub recommended: Option<bool>,
+    pub deprecated: Option<bool>,
+    pub private: Option<bool>,
+    pub featured: Option<bool>,
+
+    pub model_type: Option<String>, // TODO enum
+    pub library: Option<String>, // TODO enum
+    pub tensor_type: Option<String>, // TODO enum
+    pub quantization: Option<String>, // TODO enum
+    pub bits: Option<i32>,
+    pub size: Option<f32>,
+    pub max_ram: Option<f32>,
+    pub sha: Option<String>,
+    pub file_size: Option<u64>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub repository: Option<Resource>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub download: Option<Resource>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub documentation: Option<Resource>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "option_string_or_struct"
+    )]
+    pub paper: Option<Resource>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub include: Option<Vec<Model>>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub system: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub context_window: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub editable: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub chat_template: Option<String>,
+}
+
+impl Model {
+    pub fn new(name: String) -> Self {
+        Model {
+            id: None,
+            name,
+            base_model: None,
+            created_at: None,
+            updated_at: None,
+            title: None,
+            description: None,
+            summary: None,
+            version: None,
+            icon: None,
+            creator: None,
+            author: None,
+            publisher: None,
+            license: None,
+            languages: None,
+            tags: None,
+            recommendations: None,
+            recommended: None,
+            deprecated: None,
+            private: None,
+            featured: None,
+            model_type: None,
+            library: None,
+            tensor_type: None,
+            quantization: None,
+            bits: None,
+            size: None,
+            max_ram: None,
+            repository: None,
+            download: None,
+            documentation: None,
+            paper: None,
+            include: None,
+            system: None,
+            context_window: None,
+            editable: None,
+            chat_template: None,
+            sha: None,
+            file_size: None,
+        }
+    }
+
+    pub fn is_same_id(&self, id2: &str) -> bool {
+        match self.id {
+            Some(ref id) => id == id2,
+            None => false,
+        }
+    }
+
+    pub fn is_same_id_or_name(&self, id_or_name: &str) -> bool {
+        match self.id {
+            Some(ref id) => id == id_or_name || self.name == id_or_name,
+            None => self.name == id_or_name,
+        }
+    }
+
+    pub fn is_same_model(&self, another_model: &Model) -> bool {
+        self.id.is_some() && another_model.id.is_some() && self.id == another_model.id
+    }
+
+    pub fn is_some_id_or_name(&self, id_or_name: &Option<String>) -> bool {
+        match id_or_name {
+            Some(id_or_name) => self.is_same_id_or_name(id_or_name),
+            None => false,
+        }
+    }
+
+    pub fn get_file_size(&self) -> u64 {
+        self.file_size.unwrap_or(0)
+    }
+
+    pub fn get_sha(&self) -> Option<String> {
+        self.sha.clone()
+    }
+
+    // Fills the fields that are missing using the model file metadata.
+    pub fn update_from_descriptor(&mut self, descriptor: &ModelDescriptor) {
+        if self.quantization.is_none() {
+            self.quantization = descriptor.quantization.clone();
+        }
+        if self.context_window.is_none() {
+            self.context_window = i32::try_from(descriptor.context_length).ok();
+        }
+        if self.bits.is_none() {
+            self.bits = descriptor.bits();
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelEntity {
+    #[serde(flatten)]
+    pub reference: Model,
+    pub state: Option<String>,
+    pub path: Option<String>,
+    pub file_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModelStorage {
+    pub path: Option<String>,
+    pub items: Vec<ModelEntity>,
+}
+
+impl ModelStorage {
+    pub fn new() -> Self {
+        ModelStorage {
+            path: None,
+            items: vec![],
+        }
+    }
+
+    pub fn set_models_path(&mut self, models_path: Option<String>) -> Result<String, String> {
+        // TODO validate models_path, current models and truncate from home/data directory
+        self.path = models_path;
+        match self.get_models_path()?.to_str() {
+            Some(value) => Ok(value.to_string()),
+            None => Err("Can't get models path".to_string()),
+        }
+    }
+
+    // The models folder of the settings, relative to the home directory, or the app data one.
+    pub fn get_models_path(&self) -> Result<PathBuf, String> {
+        let models_path = match self.path {
+            Some(ref path) => {
+                let p = PathBuf::from(path);
+                if p.is_absolute() {
+                    p
+                } else {
+                    get_home_directory()?.join(path)
+                }
+            }
+            None => get_data_directory()?.join("models"),
+        };
+        Ok(models_path)
+    }
+
+    pub fn get_full_path(
+        &self,
+        filepath: String,
+        filename: Option<String>
+    ) -> Result<PathBuf, String> {
+        let path_filename = Path::new(&filepath).join(filename.unwrap_or_default());
+        if path_filename.is_absolute() {
+            return Ok(path_filename);
+        }
+        let models_path = self.get_models_path()?;
+        Ok(models_path.join(path_filename))
+    }
+
+    pub fn create_model_path_filename(
+        &self,
+        path: String,
+        file_name: String
+    ) -> Result<String, String> {
+        let models_path = self.get_full_path(path, None)?;
+        let result = create_dir_all(models_path.clone());
+        if result.is_err() {
+            return Err(format!("Failed to create model directory: {:?}", result));
+        }
+        self.get_model_path_filename_in(&models_path, &file_name)
+    }
+
+    pub fn get_model_path_filename(
+        &self,
+        path: String,
+        file_name: String
+    ) -> Result<String, String> {
+        let models_path = self.get_full_path(path, None)?;
+        self.get_model_path_filename_in(&models_path, &file_name)
+    }
+
+    fn get_model_path_filename_in(
+        &self,
+        models_path: &Path,
+        file_name: &str
+    ) -> Result<String, String> {
+        match models_path.join(file_name).to_str() {
+            Some(path) => Ok(path.to_string()),
+            None => Err(format!("Failed to create model path: {:?}/{:?}", models_path, file_name)),
+        }
+    }
+
+    pub fn get_path(&self, id_or_name: String) -> Result<String, String> {
+        let (file_name, path) = match self.get_model_entity(&id_or_name) {
+            Some(model) => (model.file_name, model.path),
+            None => {
+                return Err(format!("get_path Model not found: {:?}", id_or_name));
+            }
+        };
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return Err(format!("Model path not found: {:?}", id_or_name));
+            }
+        };
+        let file_name = match file_name {
+            Some(file_name) => file_name,
+            None => {
+                return Err(format!("Model file name not found: {:?}", id_or_name));
+            }
+        };
+        self.get_model_path_filename(path, file_name)
+    }
+
+    pub fn get_model_path(&self, id_or_name: String) -> Result<String, String> {
+        let model_path = self.get_path(id_or_name)?;
+
+        let gguf = GGUF::read_split(&model_path).map_err(|err| err.to_string())?;
+
+        // llama.cpp loads the other shards from the first one.
+        match gguf.splits().first() {
+            Some(shard) => Ok(shard.file_name.clone()),
+            None => Ok(model_path),
+        }
+    }
+
+    pub fn get_model_file(&self, id_or_name: String) -> Result<GGUF, String> {
+        let model_path = self.get_path(id_or_name)?;
+
+        // Shards of a split model are read as a single model.
+        GGUF::read_split(&model_path).map_err(|err| err.to_string())
+    }
+
+    // Fills the model fields that are missing from the metadata of its file.
+    // On error the model is kept as it is, callers only report it.
+    pub fn update_model_from_file(&mut self, id_or_name: &str) -> Result<(), String> {
+        let gguf = self.get_model_file(id_or_name.to_string())?;
+        let descriptor = ModelDescriptor::from_gguf(&gguf).map_err(|err|
+            format!("Model {} can't be described from its file: {}", id_or_name, err)
+        )?;
+        let mut model = match self.get_model(id_or_name) {
+            Some(model) => model,
+            None => {
+                return Err(format!("Model not found: {:?}", id_or_name));
+            }
+        };
+        model.update_from_descriptor(&descriptor);
+        if model.max_ram.is_none() {
+            // Using the full context length of the model.
+            let requirements = MemoryRequirements::estimate(
+                &gguf,
+                &descriptor,
+                &MemoryOptions::default()
+            );
+            model.max_ram = Some((requirements.total() as f32) / 1_000_000_000.0);
+        }
+        self.update_model(model);
+        Ok(())
+    }
+
+    pub fn estimate_model_memory(
+        &self,
+        id_or_name: &str,
+        options: &MemoryOptions
+    ) -> Result<MemoryRequirements, String> {
+        let gguf = self.get_model_file(id_or_name.to_string())?;
+        let descriptor = ModelDescriptor::from_gguf(&gguf)?;
+        Ok(MemoryRequirements::estimate(&gguf, &descriptor, options))
+    }
+
+    // The template set on the model overrides the one embedded in its file.
+    pub fn get_chat_template(&self, id_or_name: &str) -> Result<ChatTemplate, String> {
+        let gguf = self.get_model_file(id_or_name.to_string())?;
+        match self.get_model(id_or_name).and_then(|model| model.chat_template) {
+            Some(source) => Ok(ChatTemplate::from_gguf_with_source(&gguf, &source)),
+            None => ChatTemplate::from_gguf(&gguf),
+        }
+    }
+
+    // Checks that a LoRA adapter file can be applied to the model.
+    pub fn check_lora_adapter(
+        &self,
+        id_or_name: &str,
+        adapter_path: &str
+    ) -> Result<LoraCompatibility, String> {
+        let gguf = self.get_model_file(id_or_name.to_string())?;
+        let mut adapter = GGUF::new(adapter_path);
+        adapter.read(adapter_path).map_err(|err| err.to_string())?;
+        Ok(LoraAdapter::from_gguf(&adapter)?.check_compatibility(&gguf))
+    }
+
+    pub fn validate_model(&self, model: &Model) -> Result<(), String> {
+        if model.id.is_none() {
+            return Err("Model ID is required".to_string());
+        }
+        if model.name.is_empty() {
+            return Err("Model name is required".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn get_model_entity(&self, id_or_name: &str) -> Option<ModelEntity> {
+        self.items
+            .iter()
+            .find(|m| m.reference.is_same_id_or_name(id_or_name))
+            .cloned()
+    }
+
+    pub fn get_model(&self, id_or_name: &str) -> Option<Model> {
+        self.get_model_entity(id_or_name).map(|m| m.reference)
+    }
+
+    // The id of the model becomes its base model and a new one is created.
+    pub fn create_model(
+        &mut self,
+        model: Model,
+        state: Option<String>,
+        path: Option<String>,
+        file_name: Option<String>
+    ) -> (ModelEntity, String) {
+        let mut model = model;
+        model.base_model = model.id;
+        let uuid = Uuid::new_v4().to_string();
+        model.id = Some(uuid.clone());
+
+        (
+            ModelEntity {
+                reference: model,
+                state,
+                path,
+                file_name,
+            },
+            uuid,
+        )
+    }
+
+    pub fn add_model(&mut self, model: ModelEntity) {
+        self.items.push(model);
+    }
+
+    // A model in use is only marked as removed.
+    pub fn remove_model(&mut self, id: &str, in_use: bool) -> Option<ModelEntity> {
+        if in_use {
+            let mut model = self.get_model_entity(id)?;
+            model.state = Some("removed".to_string());
+            self.update_model_entity(&model);
+            return Some(model);
+        }
+        self.items
+            .iter()
+            .position(|m| m.reference.is_same_id_or_name(id))
+            .map(|index| self.items.remove(index))
+    }
+
+    pub fn update_model(&mut self, model: Model) {
+        if let Some(model_entity) = self.items
+            .iter_mut()
+            .find(|m| m.reference.is_same_model(&model)) {
+            model_entity.reference = model;
+        }
+    }
+
+    pub fn update_model_entity(&mut self, model_entity: &ModelEntity) {
+        if let Some(entity) = self.items
+            .iter_mut()
+            .find(|m| m.reference.is_same_model(&model_entity.reference)) {
+            *entity = model_entity.clone();
+        }
+    }
+
+    pub fn set_model_state(&mut self, model_id: &str, state: &str) {
+        let mut model_entity = match self.get_model_entity(model_id) {
+            Some(model_entity) => model_entity,
+            None => {
+                return;
+            }
+        };
+        model_entity.state = Some(state.to_string());
+        self.update_model_entity(&model_entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Model, ModelStorage };
+
+    #[test]
+    fn app_models() {
+        let json =
+            r#"{"path":"/models","items":[{"id":"1","name":"phi","base_model":"TheBloke/phi","created_at":"2024-06-10T06:13:20Z","author":"TheBloke","download":{"url":"https://huggingface.co/phi.gguf"},"tags":"chat","state":"ok","path":"TheBloke","file_name":"phi.gguf"}]}"#;
+        let mut models: ModelStorage = serde_json::from_str(json).unwrap();
+        let model = models.get_model("phi").unwrap();
+        assert_eq!(model.author.unwrap().name, "TheBloke");
+        assert_eq!(model.tags, Some(vec!["chat".to_string()]));
+        assert_eq!(models.get_path("1".to_string()).unwrap(), "/models/TheBloke/phi.gguf");
+
+        let (entity, id) = models.create_model(
+            Model::new("phi".to_string()),
+            Some("ok".to_string()),
+            Some("/other".to_string()),
+            Some("phi.gguf".to_string())
+        );
+        models.add_model(entity);
+        assert_eq!(models.get_model_entity(&id).unwrap().path.as_deref(), Some("/other"));
+        models.remove_model("1", true);
+        assert_eq!(models.get_model_entity("1").unwrap().state.as_deref(), Some("removed"));
+        models.remove_model("1", false);
+        assert_eq!(models.items.len(), 1);
+
+        let value = serde_json::to_value(&models).unwrap();
+        assert_eq!(value["items"][0]["id"], id);
+        assert!(value["items"][0].get("base_model").is_none());
+    }
+}
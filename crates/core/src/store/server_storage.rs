@@ -0,0 +1,52 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use serde::{ Deserialize, Serialize };
+
+use crate::configuration::{ Metadata, MetadataValue, ServerConfiguration };
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerStorage {
+    #[serde(default)]
+    pub launch_at_startup: bool,
+    pub binary: String,
+    #[serde(flatten)]
+    pub configuration: ServerConfiguration,
+}
+
+impl Default for ServerStorage {
+    fn default() -> Self {
+        let mut server_parameters: Metadata = HashMap::new();
+        server_parameters.insert("port".to_string(), MetadataValue::Integer(8081));
+        server_parameters.insert(
+            "host".to_string(),
+            MetadataValue::String("127.0.0.1".to_string())
+        );
+        server_parameters.insert("model_id".to_string(), MetadataValue::Option(None));
+        server_parameters.insert("model_path".to_string(), MetadataValue::Option(None));
+        server_parameters.insert("context_size".to_string(), MetadataValue::Integer(512));
+        server_parameters.insert("threads".to_string(), MetadataValue::Integer(6));
+        server_parameters.insert("n_gpu_layers".to_string(), MetadataValue::Integer(0));
+        ServerStorage {
+            launch_at_startup: true,
+            binary: String::from("binaries/llama.cpp/llama.cpp.server"),
+            configuration: ServerConfiguration {
+                name: String::from("llama.cpp"),
+                parameters: server_parameters,
+            },
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Helpers shared by the tests: temporary files and small GGUF models.
+
+use std::{ borrow::Cow, path::{ Path, PathBuf } };
+
+use crate::gguf::{ GGMLType, GGUFMetadataValue, GGUFTensorInfo, GGUF };
+
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("opla-{}-{}.gguf", std::process::id(), name))
+}
+
+// A v2+ little endian string, for the files built byte by byte.
+pub fn string_bytes(value: &str) -> Vec<u8> {
+    [&(value.len() as u64).to_le_bytes()[..], value.as_bytes()].concat()
+}
+
+pub fn tensor(name: &str, dimensions: &[u64], tensor_type: GGMLType) -> GGUFTensorInfo {
+    GGUFTensorInfo {
+        name: name.to_string(),
+        n_dimensions: dimensions.len() as u32,
+        dimensions: dimensions.to_vec(),
+        tensor_type,
+        offset: 0,
+    }
+}
+
+// A v3 model of the given architecture, with its metadata and tensors.
+pub fn create_model(
+    architecture: &str,
+    metadata: &[(&str, GGUFMetadataValue)],
+    tensors: &[GGUFTensorInfo]
+) -> GGUF {
+    let mut gguf = GGUF::new("test.gguf");
+    gguf.header.version = 3;
+    gguf.set_metadata(
+        "general.architecture",
+        GGUFMetadataValue::String(architecture.to_string())
+    );
+    for (key, value) in metadata {
+        gguf.set_metadata(key, value.clone());
+    }
+    gguf.tensors = tensors.to_vec();
+    gguf.header.tensor_count = gguf.tensors.len() as u64;
+    gguf
+}
+
+// The data is different for each tensor name, so misplaced tensors are caught.
+pub fn tensor_data(tensor: &GGUFTensorInfo) -> Vec<u8> {
+    let seed = tensor.name.len() as u64;
+    (0..tensor.size()).map(|i| ((seed + i) % 251) as u8).collect()
+}
+
+pub fn write_model(gguf: &GGUF, path: &Path) {
+    gguf.write_with_tensor_data(path.to_str().unwrap(), |tensor| {
+        Ok(Cow::Owned(tensor_data(tensor)))
+    }).unwrap();
+}
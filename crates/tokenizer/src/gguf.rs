@@ -0,0 +1,715 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tokenizers built from the tokenizer.ggml.* metadata of a GGUF file,
+// following llm_tokenizer_spm and llm_tokenizer_bpe of llama.cpp.
+
+use std::{ cmp::Ordering, collections::{ BinaryHeap, HashMap } };
+use fancy_regex::Regex;
+use opla_core::gguf::{ GGUFMetadataValue, GGUF };
+
+use crate::vendors::tiktoken::Rank;
+
+// Replaces spaces in SentencePiece vocabularies.
+const SPM_SPACE: char = '\u{2581}';
+
+const GPT2_PATTERN: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+const LLAMA3_PATTERN: &str =
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const QWEN2_PATTERN: &str =
+    r"(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenizerModel {
+    // SentencePiece, tokenizer.ggml.model = llama
+    Spm,
+    // Byte level BPE, tokenizer.ggml.model = gpt2
+    Bpe,
+}
+
+// llama_token_type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenType {
+    Undefined = 0,
+    Normal = 1,
+    Unknown = 2,
+    Control = 3,
+    UserDefined = 4,
+    Unused = 5,
+    Byte = 6,
+}
+
+impl From<i64> for TokenType {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => TokenType::Normal,
+            2 => TokenType::Unknown,
+            3 => TokenType::Control,
+            4 => TokenType::UserDefined,
+            5 => TokenType::Unused,
+            6 => TokenType::Byte,
+            _ => TokenType::Undefined,
+        }
+    }
+}
+
+pub struct GGUFTokenizer {
+    pub model: TokenizerModel,
+    tokens: Vec<String>,
+    scores: Vec<f32>,
+    token_types: Vec<TokenType>,
+    token_ids: HashMap<String, Rank>,
+    merges: HashMap<(String, String), usize>,
+    // Control and user defined tokens, longest first.
+    special_tokens: Vec<Rank>,
+    pattern: Option<Regex>,
+    byte_encoder: [char; 256],
+    byte_decoder: HashMap<char, u8>,
+    pub bos_token_id: Option<Rank>,
+    pub eos_token_id: Option<Rank>,
+    pub unknown_token_id: Option<Rank>,
+    pub add_bos_token: bool,
+    pub add_eos_token: bool,
+    add_space_prefix: bool,
+}
+
+impl GGUFTokenizer {
+    pub fn from_gguf(gguf: &GGUF) -> Result<GGUFTokenizer, String> {
+        let model = match gguf.get_metadata_string("tokenizer.ggml.model") {
+            Some("llama") => TokenizerModel::Spm,
+            Some("gpt2") => TokenizerModel::Bpe,
+            Some(model) => {
+                return Err(format!("Tokenizer model not supported: {}", model));
+            }
+            None => {
+                return Err("Missing metadata key: tokenizer.ggml.model".to_string());
+            }
+        };
+
+        let tokens: Vec<String> = get_array(gguf, "tokenizer.ggml.tokens")?
+            .into_iter()
+            .map(|value| value.as_str().map(|v| v.to_string()))
+            .collect::<Option<_>>()
+            .ok_or("Invalid tokenizer.ggml.tokens")?;
+        let scores = match gguf.get_metadata_value("tokenizer.ggml.scores") {
+            Some(_) =>
+                get_array(gguf, "tokenizer.ggml.scores")?
+                    .iter()
+                    .map(|value| value.as_f64().unwrap_or_default() as f32)
+                    .collect(),
+            None => vec![0.0; tokens.len()],
+        };
+        let token_types: Vec<TokenType> = match
+            gguf.get_metadata_value("tokenizer.ggml.token_type")
+        {
+            Some(_) =>
+                get_array(gguf, "tokenizer.ggml.token_type")?
+                    .iter()
+                    .map(|value| match value {
+                        GGUFMetadataValue::Int32(v) => TokenType::from(*v as i64),
+                        value => TokenType::from(value.as_u64().unwrap_or_default() as i64),
+                    })
+                    .collect(),
+            None => vec![TokenType::Normal; tokens.len()],
+        };
+        if scores.len() != tokens.len() || token_types.len() != tokens.len() {
+            return Err("Tokenizer scores and token types don't match tokens".to_string());
+        }
+
+        let mut merges = HashMap::new();
+        if gguf.get_metadata_value("tokenizer.ggml.merges").is_some() {
+            for (rank, merge) in get_array(gguf, "tokenizer.ggml.merges")?.iter().enumerate() {
+                let merge = merge.as_str().ok_or("Invalid tokenizer.ggml.merges")?;
+                if let Some((left, right)) = merge.split_once(' ') {
+                    merges.insert((left.to_string(), right.to_string()), rank);
+                }
+            }
+        }
+        if model == TokenizerModel::Bpe && merges.is_empty() {
+            return Err("Missing metadata key: tokenizer.ggml.merges".to_string());
+        }
+
+        let pattern = match model {
+            TokenizerModel::Spm => None,
+            TokenizerModel::Bpe => {
+                let pattern = match gguf.get_metadata_string("tokenizer.ggml.pre") {
+                    Some("llama-bpe" | "llama3" | "smaug-bpe") => LLAMA3_PATTERN,
+                    Some("qwen2" | "deepseek-r1-qwen") => QWEN2_PATTERN,
+                    _ => GPT2_PATTERN,
+                };
+                Some(Regex::new(pattern).map_err(|err| err.to_string())?)
+            }
+        };
+
+        let token_ids = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as Rank))
+            .collect();
+        let mut special_tokens: Vec<Rank> = (0..tokens.len() as Rank)
+            .filter(|id| {
+                matches!(token_types[*id as usize], TokenType::Control | TokenType::UserDefined)
+            })
+            .collect();
+        special_tokens.sort_by_key(|id| std::cmp::Reverse(tokens[*id as usize].len()));
+
+        let byte_encoder = bytes_to_unicode();
+        let byte_decoder = byte_encoder
+            .iter()
+            .enumerate()
+            .map(|(byte, c)| (*c, byte as u8))
+            .collect();
+        // SentencePiece vocabularies default to <unk>, <s> and </s> as the first tokens.
+        let get_token_id = |key: &str, spm_default: u64| {
+            let default_value = Some(spm_default).filter(|_| model == TokenizerModel::Spm);
+            gguf.get_metadata_u64(key)
+                .or(default_value)
+                .filter(|id| (*id as usize) < tokens.len())
+                .map(|id| id as Rank)
+        };
+        let get_bool = |key: &str, default_value: bool| {
+            gguf.get_metadata_value(key)
+                .and_then(|value| value.as_bool())
+                .unwrap_or(default_value)
+        };
+
+        Ok(GGUFTokenizer {
+            model,
+            bos_token_id: get_token_id("tokenizer.ggml.bos_token_id", 1),
+            eos_token_id: get_token_id("tokenizer.ggml.eos_token_id", 2),
+            unknown_token_id: get_token_id("tokenizer.ggml.unknown_token_id", 0),
+            add_bos_token: get_bool("tokenizer.ggml.add_bos_token", model == TokenizerModel::Spm),
+            add_eos_token: get_bool("tokenizer.ggml.add_eos_token", false),
+            add_space_prefix: get_bool(
+                "tokenizer.ggml.add_space_prefix",
+                model == TokenizerModel::Spm
+            ),
+            tokens,
+            scores,
+            token_types,
+            token_ids,
+            merges,
+            special_tokens,
+            pattern,
+            byte_encoder,
+            byte_decoder,
+        })
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn token_to_piece(&self, token: Rank) -> Option<&str> {
+        self.tokens.get(token as usize).map(|t| t.as_str())
+    }
+
+    // add_special adds the BOS and EOS tokens when the model expects them,
+    // parse_special matches control tokens written in the text, ie <|im_start|>.
+    pub fn encode(&self, text: &str, add_special: bool, parse_special: bool) -> Vec<Rank> {
+        let mut output = Vec::new();
+        if add_special && self.add_bos_token {
+            output.extend(self.bos_token_id);
+        }
+        let fragments = if parse_special {
+            self.split_special(text)
+        } else {
+            vec![Fragment::Text(text)]
+        };
+        for (index, fragment) in fragments.into_iter().enumerate() {
+            match fragment {
+                Fragment::Token(token) => output.push(token),
+                Fragment::Text(text) =>
+                    match self.model {
+                        TokenizerModel::Spm => {
+                            let mut text = text.replace(' ', &SPM_SPACE.to_string());
+                            if self.add_space_prefix && index == 0 {
+                                text.insert(0, SPM_SPACE);
+                            }
+                            self.encode_spm(&text, &mut output);
+                        }
+                        TokenizerModel::Bpe => self.encode_bpe(text, &mut output),
+                    }
+            }
+        }
+        if add_special && self.add_eos_token {
+            output.extend(self.eos_token_id);
+        }
+        output
+    }
+
+    pub fn decode(&self, tokens: &[Rank], render_special: bool) -> String {
+        let text = String::from_utf8_lossy(&self.decode_bytes(tokens, render_special)).to_string();
+        match self.model {
+            TokenizerModel::Spm if self.add_space_prefix =>
+                text.strip_prefix(' ').map(|t| t.to_string()).unwrap_or(text),
+            _ => text,
+        }
+    }
+
+    // Bytes of the tokens, a token can be only a part of an UTF-8 character.
+    pub fn decode_bytes(&self, tokens: &[Rank], render_special: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for token in tokens {
+            let piece = match self.tokens.get(*token as usize) {
+                Some(piece) => piece,
+                None => {
+                    continue;
+                }
+            };
+            match self.token_types[*token as usize] {
+                TokenType::Control if !render_special => {}
+                TokenType::Control | TokenType::UserDefined => bytes.extend(piece.as_bytes()),
+                TokenType::Byte =>
+                    match parse_byte_token(piece) {
+                        Some(byte) => bytes.push(byte),
+                        None => bytes.extend(piece.as_bytes()),
+                    }
+                _ =>
+                    match self.model {
+                        TokenizerModel::Spm => {
+                            bytes.extend(piece.replace(SPM_SPACE, " ").as_bytes());
+                        }
+                        TokenizerModel::Bpe => {
+                            for c in piece.chars() {
+                                match self.byte_decoder.get(&c) {
+                                    Some(byte) => bytes.push(*byte),
+                                    None => {
+                                        let mut buffer = [0; 4];
+                                        bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+                                    }
+                                }
+                            }
+                        }
+                    }
+            }
+        }
+        bytes
+    }
+
+    fn split_special<'a>(&self, text: &'a str) -> Vec<Fragment<'a>> {
+        let mut fragments = Vec::new();
+        let mut start = 0;
+        let mut position = 0;
+        while position < text.len() {
+            let rest = &text[position..];
+            let special = self.special_tokens
+                .iter()
+                .find(|id| {
+                    let piece = &self.tokens[**id as usize];
+                    !piece.is_empty() && rest.starts_with(piece.as_str())
+                });
+            match special {
+                Some(id) => {
+                    if start < position {
+                        fragments.push(Fragment::Text(&text[start..position]));
+                    }
+                    fragments.push(Fragment::Token(*id));
+                    position += self.tokens[*id as usize].len();
+                    start = position;
+                }
+                None => {
+                    position += rest.chars().next().map_or(1, |c| c.len_utf8());
+                }
+            }
+        }
+        if start < text.len() {
+            fragments.push(Fragment::Text(&text[start..]));
+        }
+        fragments
+    }
+
+    // Merges the pair of symbols giving the token with the best score, until none is found.
+    fn encode_spm(&self, text: &str, output: &mut Vec<Rank>) {
+        let mut symbols = Symbols::new(text.char_indices().map(|(i, c)| (i, c.len_utf8())));
+        let mut queue = BinaryHeap::new();
+        let try_add_bigram = |queue: &mut BinaryHeap<Bigram>, symbols: &Symbols, left: usize| {
+            let right = match symbols.next(left) {
+                Some(right) => right,
+                None => {
+                    return;
+                }
+            };
+            let piece = symbols.text(text, left, right);
+            if let Some(id) = self.token_ids.get(piece) {
+                queue.push(Bigram {
+                    // Higher scores first
+                    score: self.scores[*id as usize],
+                    left,
+                    right,
+                    size: piece.len(),
+                });
+            }
+        };
+        for index in 0..symbols.len() {
+            try_add_bigram(&mut queue, &symbols, index);
+        }
+        while let Some(bigram) = queue.pop() {
+            if !symbols.merge(bigram.left, bigram.right, bigram.size) {
+                continue;
+            }
+            if let Some(previous) = symbols.previous(bigram.left) {
+                try_add_bigram(&mut queue, &symbols, previous);
+            }
+            try_add_bigram(&mut queue, &symbols, bigram.left);
+        }
+
+        for (start, size) in symbols.iter() {
+            let piece = &text[start..start + size];
+            match self.token_ids.get(piece) {
+                Some(id) => output.push(*id),
+                None => {
+                    // Byte fallback for characters missing from the vocabulary.
+                    for byte in piece.bytes() {
+                        let token = self.token_ids.get(&format!("<0x{:02X}>", byte));
+                        output.extend(token.or(self.unknown_token_id.as_ref()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Pre-tokenizes with the regex, then merges the pairs with the lowest merge rank.
+    fn encode_bpe(&self, text: &str, output: &mut Vec<Rank>) {
+        let pattern = match &self.pattern {
+            Some(pattern) => pattern,
+            None => {
+                return;
+            }
+        };
+        for word in pattern.find_iter(text).filter_map(|m| m.ok()) {
+            let word: String = word
+                .as_str()
+                .bytes()
+                .map(|b| self.byte_encoder[b as usize])
+                .collect();
+            if let Some(id) = self.token_ids.get(&word) {
+                output.push(*id);
+                continue;
+            }
+
+            let mut symbols = Symbols::new(word.char_indices().map(|(i, c)| (i, c.len_utf8())));
+            let mut queue = BinaryHeap::new();
+            let try_add_bigram = |queue: &mut BinaryHeap<Bigram>, symbols: &Symbols, left: usize| {
+                let right = match symbols.next(left) {
+                    Some(right) => right,
+                    None => {
+                        return;
+                    }
+                };
+                let key = (
+                    symbols.text(&word, left, left).to_string(),
+                    symbols.text(&word, right, right).to_string(),
+                );
+                if let Some(rank) = self.merges.get(&key) {
+                    queue.push(Bigram {
+                        // Lower ranks first
+                        score: -(*rank as f32),
+                        left,
+                        right,
+                        size: key.0.len() + key.1.len(),
+                    });
+                }
+            };
+            for index in 0..symbols.len() {
+                try_add_bigram(&mut queue, &symbols, index);
+            }
+            while let Some(bigram) = queue.pop() {
+                if !symbols.merge(bigram.left, bigram.right, bigram.size) {
+                    continue;
+                }
+                if let Some(previous) = symbols.previous(bigram.left) {
+                    try_add_bigram(&mut queue, &symbols, previous);
+                }
+                try_add_bigram(&mut queue, &symbols, bigram.left);
+            }
+
+            for (start, size) in symbols.iter() {
+                let piece = &word[start..start + size];
+                match self.token_ids.get(piece) {
+                    Some(id) => output.push(*id),
+                    None => {
+                        for c in piece.chars() {
+                            let token = self.token_ids.get(&c.to_string());
+                            output.extend(token.or(self.unknown_token_id.as_ref()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Fragment<'a> {
+    Text(&'a str),
+    Token(Rank),
+}
+
+struct Bigram {
+    score: f32,
+    left: usize,
+    right: usize,
+    size: usize,
+}
+
+impl Ord for Bigram {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Best score first, then leftmost.
+        self.score.total_cmp(&other.score).then_with(|| other.left.cmp(&self.left))
+    }
+}
+
+impl PartialOrd for Bigram {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Bigram {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Bigram {}
+
+// Doubly linked list of text ranges, merged in place.
+struct Symbols {
+    start: Vec<usize>,
+    size: Vec<usize>,
+    previous: Vec<Option<usize>>,
+    next: Vec<Option<usize>>,
+}
+
+impl Symbols {
+    fn new(ranges: impl Iterator<Item = (usize, usize)>) -> Symbols {
+        let (start, size): (Vec<usize>, Vec<usize>) = ranges.unzip();
+        let len = start.len();
+        Symbols {
+            start,
+            size,
+            previous: (0..len).map(|i| i.checked_sub(1)).collect(),
+            next: (0..len).map(|i| Some(i + 1).filter(|n| *n < len)).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.start.len()
+    }
+
+    fn next(&self, index: usize) -> Option<usize> {
+        if self.size[index] == 0 {
+            return None;
+        }
+        self.next[index]
+    }
+
+    fn previous(&self, index: usize) -> Option<usize> {
+        self.previous[index]
+    }
+
+    fn text<'a>(&self, text: &'a str, left: usize, right: usize) -> &'a str {
+        &text[self.start[left]..self.start[right] + self.size[right]]
+    }
+
+    // Returns false if one of the symbols changed since the bigram was queued.
+    fn merge(&mut self, left: usize, right: usize, size: usize) -> bool {
+        if self.size[left] == 0 || self.size[right] == 0 || self.size[left] + self.size[right] != size {
+            return false;
+        }
+        if self.next[left] != Some(right) {
+            return false;
+        }
+        self.size[left] += self.size[right];
+        self.size[right] = 0;
+        self.next[left] = self.next[right];
+        if let Some(next) = self.next[right] {
+            self.previous[next] = Some(left);
+        }
+        true
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.len())
+            .filter(|i| self.size[*i] > 0)
+            .map(|i| (self.start[i], self.size[i]))
+    }
+}
+
+fn get_array(gguf: &GGUF, key: &str) -> Result<Vec<GGUFMetadataValue>, String> {
+    gguf.get_metadata_array(key).map_err(|err| err.to_string())
+}
+
+fn parse_byte_token(piece: &str) -> Option<u8> {
+    let hex = piece.strip_prefix("<0x")?.strip_suffix('>')?;
+    u8::from_str_radix(hex, 16).ok()
+}
+
+// GPT-2 mapping of bytes to printable characters, used by byte level BPE vocabularies.
+fn bytes_to_unicode() -> [char; 256] {
+    let mut mapping = ['\0'; 256];
+    let mut n = 0;
+    for byte in 0..256u32 {
+        let printable = matches!(byte, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff);
+        let c = if printable {
+            byte
+        } else {
+            n += 1;
+            255 + n
+        };
+        mapping[byte as usize] = char::from_u32(c).unwrap_or('\0');
+    }
+    mapping
+}
+
+#[cfg(test)]
+mod tests {
+    use opla_core::gguf::{
+        GGUFMetadataArrayValue,
+        GGUFMetadataValue,
+        GGUFMetadataValueType,
+        GGUF,
+    };
+    use super::{ GGUFTokenizer, TokenizerModel };
+
+    fn array(value_type: GGUFMetadataValueType, value: Vec<GGUFMetadataValue>) -> GGUFMetadataValue {
+        GGUFMetadataValue::Array(GGUFMetadataArrayValue {
+            value_type,
+            len: value.len() as u64,
+            value,
+            offset: None,
+        })
+    }
+
+    fn create_model(model: &str, tokens: &[(&str, f32, i32)], merges: &[&str]) -> GGUF {
+        let mut gguf = GGUF::new("test.gguf");
+        gguf.set_metadata("tokenizer.ggml.model", GGUFMetadataValue::String(model.to_string()));
+        let strings = |values: Vec<&str>| {
+            array(
+                GGUFMetadataValueType::String,
+                values
+                    .into_iter()
+                    .map(|v| GGUFMetadataValue::String(v.to_string()))
+                    .collect()
+            )
+        };
+        gguf.set_metadata(
+            "tokenizer.ggml.tokens",
+            strings(
+                tokens
+                    .iter()
+                    .map(|t| t.0)
+                    .collect()
+            )
+        );
+        gguf.set_metadata(
+            "tokenizer.ggml.scores",
+            array(
+                GGUFMetadataValueType::Float32,
+                tokens
+                    .iter()
+                    .map(|t| GGUFMetadataValue::Float32(t.1))
+                    .collect()
+            )
+        );
+        gguf.set_metadata(
+            "tokenizer.ggml.token_type",
+            array(
+                GGUFMetadataValueType::Int32,
+                tokens
+                    .iter()
+                    .map(|t| GGUFMetadataValue::Int32(t.2))
+                    .collect()
+            )
+        );
+        if !merges.is_empty() {
+            gguf.set_metadata("tokenizer.ggml.merges", strings(merges.to_vec()));
+        }
+        gguf
+    }
+
+    #[test]
+    fn sentencepiece() {
+        let mut gguf = create_model(
+            "llama",
+            &[
+                ("<unk>", 0.0, 2),
+                ("<s>", 0.0, 3),
+                ("</s>", 0.0, 3),
+                ("<0x21>", 0.0, 6),
+                ("\u{2581}", -1.0, 1),
+                ("h", -2.0, 1),
+                ("e", -2.0, 1),
+                ("l", -2.0, 1),
+                ("o", -2.0, 1),
+                ("\u{2581}h", -3.0, 1),
+                ("ll", -4.0, 1),
+                ("\u{2581}he", -5.0, 1),
+                ("llo", -6.0, 1),
+                ("\u{2581}hello", -7.0, 1),
+                ("\u{2581}l", -8.0, 1),
+            ],
+            &[]
+        );
+        gguf.set_metadata("tokenizer.ggml.eos_token_id", GGUFMetadataValue::Uint32(2));
+        let tokenizer = GGUFTokenizer::from_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.model, TokenizerModel::Spm);
+
+        let tokens = tokenizer.encode("hello hello!", true, false);
+        assert_eq!(tokens, vec![1, 13, 13, 3]);
+        assert_eq!(tokenizer.decode(&tokens, false), "hello hello!");
+
+        let tokens = tokenizer.encode("hello</s>", false, true);
+        assert_eq!(tokens, vec![13, 2]);
+        assert_eq!(tokenizer.decode(&tokens, true), "hello</s>");
+        assert_eq!(tokenizer.encode("hello</s>", false, false), vec![13, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn byte_level_bpe() {
+        let mut gguf = create_model(
+            "gpt2",
+            &[
+                ("h", 0.0, 1),
+                ("e", 0.0, 1),
+                ("l", 0.0, 1),
+                ("o", 0.0, 1),
+                ("\u{120}", 0.0, 1),
+                ("w", 0.0, 1),
+                ("r", 0.0, 1),
+                ("d", 0.0, 1),
+                ("he", 0.0, 1),
+                ("ll", 0.0, 1),
+                ("llo", 0.0, 1),
+                ("hello", 0.0, 1),
+                ("\u{120}w", 0.0, 1),
+                ("or", 0.0, 1),
+                ("\u{120}wor", 0.0, 1),
+                ("ld", 0.0, 1),
+                ("\u{120}world", 0.0, 1),
+                ("<|endoftext|>", 0.0, 3),
+            ],
+            &["h e", "l l", "ll o", "he llo", "\u{120} w", "o r", "\u{120}w or", "l d", "\u{120}wor ld"]
+        );
+        gguf.set_metadata("tokenizer.ggml.pre", GGUFMetadataValue::String("llama-bpe".to_string()));
+        let tokenizer = GGUFTokenizer::from_gguf(&gguf).unwrap();
+        assert_eq!(tokenizer.model, TokenizerModel::Bpe);
+
+        let tokens = tokenizer.encode("hello world<|endoftext|>", true, true);
+        assert_eq!(tokens, vec![11, 16, 17]);
+        assert_eq!(tokenizer.decode(&tokens, false), "hello world");
+        assert_eq!(tokenizer.encode("world hello", false, false), vec![5, 13, 15, 4, 11]);
+    }
+}
@@ -13,12 +13,19 @@
 // limitations under the License.
 
 mod encodings;
+mod gguf;
 mod vendors;
 
 use std::collections::HashSet;
-use vendors::tiktoken::Rank;
+use opla_core::gguf::GGUF;
+use vendors::tiktoken::{ CoreBPE, Rank };
 
 use crate::encodings::cl100k_base_singleton;
+pub use gguf::{ GGUFTokenizer, TokenType, TokenizerModel };
+pub use vendors::tiktoken::Rank as TokenId;
+
+// Encodings that can be selected by name.
+pub const ENCODINGS: &[&str] = &["cl100k_base"];
 
 pub fn encode_gpt(text: String) -> Result<Vec<Rank>, String> {
     let allowed_special = HashSet::new();
@@ -36,9 +43,84 @@ pub fn encode(text: String, model: String, encoding: Option<String>) -> Result<V
     Err(format!("Model not supported {}", model))
 }
 
+enum TokenizerKind {
+    Tiktoken(&'static CoreBPE),
+    Gguf(Box<GGUFTokenizer>),
+}
+
+// A tokenizer selected by the name of its encoding, or read from a GGUF model.
+pub struct Tokenizer {
+    pub name: String,
+    kind: TokenizerKind,
+}
+
+impl Tokenizer {
+    pub fn from_encoding(name: &str) -> Result<Tokenizer, String> {
+        let kind = match name {
+            "cl100k_base" => TokenizerKind::Tiktoken(cl100k_base_singleton()),
+            _ => {
+                return Err(
+                    format!("Unknown encoding {}, expected one of: {}", name, ENCODINGS.join(", "))
+                );
+            }
+        };
+        Ok(Tokenizer { name: name.to_string(), kind })
+    }
+
+    pub fn from_gguf(gguf: &GGUF) -> Result<Tokenizer, String> {
+        let tokenizer = GGUFTokenizer::from_gguf(gguf)?;
+        let name = gguf.get_metadata_string("general.name").unwrap_or(&gguf.file_name);
+        Ok(Tokenizer {
+            name: name.to_string(),
+            kind: TokenizerKind::Gguf(Box::new(tokenizer)),
+        })
+    }
+
+    // add_special adds the BOS and EOS tokens of models that expect them.
+    pub fn encode(&self, text: &str, add_special: bool) -> Vec<TokenId> {
+        match self.kind {
+            TokenizerKind::Tiktoken(bpe) => bpe.encode(text, HashSet::new()),
+            TokenizerKind::Gguf(ref tokenizer) => tokenizer.encode(text, add_special, false),
+        }
+    }
+
+    pub fn decode(&self, tokens: &[TokenId]) -> Result<String, String> {
+        match self.kind {
+            TokenizerKind::Tiktoken(_) =>
+                Ok(String::from_utf8_lossy(&self.decode_bytes(tokens)?).to_string()),
+            TokenizerKind::Gguf(ref tokenizer) => {
+                let unknown = tokens.iter().find(|t| (**t as usize) >= tokenizer.vocab_size());
+                if let Some(token) = unknown {
+                    return Err(format!("Unknown token {}", token));
+                }
+                Ok(tokenizer.decode(tokens, false))
+            }
+        }
+    }
+
+    // Bytes of the tokens, special tokens included, to show the boundaries of each token.
+    pub fn decode_bytes(&self, tokens: &[TokenId]) -> Result<Vec<u8>, String> {
+        match self.kind {
+            TokenizerKind::Tiktoken(bpe) => bpe.decode_bytes(tokens).map_err(|err| err.to_string()),
+            TokenizerKind::Gguf(ref tokenizer) => Ok(tokenizer.decode_bytes(tokens, true)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::encode;
+    use super::{ encode, Tokenizer };
+
+    #[test]
+    fn encoding_by_name() {
+        let tokenizer = Tokenizer::from_encoding("cl100k_base").unwrap();
+        let tokens = tokenizer.encode("hello world", false);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokenizer.decode(&tokens).unwrap(), "hello world");
+        assert_eq!(tokenizer.decode_bytes(&tokens[1..]).unwrap(), b" world");
+        assert!(tokenizer.decode(&[u32::MAX]).is_err());
+        assert!(Tokenizer::from_encoding("p50k_base").is_err());
+    }
 
     #[test]
     fn it_works() {
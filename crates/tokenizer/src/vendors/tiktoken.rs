@@ -503,6 +503,17 @@ impl CoreBPE {
         self._encode_native(text, &allowed_special).0
     }
 
+    // Bytes of the tokens, unknown tokens are an error instead of a panic.
+    pub fn decode_bytes(&self, tokens: &[Rank]) -> Result<Vec<u8>, anyhow::Error> {
+        let unknown = tokens
+            .iter()
+            .find(|t| !self.decoder.contains_key(t) && !self.special_tokens_decoder.contains_key(t));
+        if let Some(token) = unknown {
+            return Err(anyhow!("Unknown token {}", token));
+        }
+        Ok(self._decode_native(tokens))
+    }
+
     /*
     pub fn encode_with_special_tokens(&self, text: &str) -> Vec<Rank> {
         let allowed_special = self.special_tokens_encoder
@@ -62,6 +62,7 @@ import { useAssistantStore, useModelsStore, useProviderStore, useThreadStore } f
 import { OrangePill } from '@/components/ui/Pills';
 import { getLocalProvider } from '@/utils/data/providers';
 import OpenAI from '@/utils/providers/openai';
+import { getRemoteModelDescriptor } from '@/utils/providers/hf';
 import ModelIcon from '@/components/common/ModelIcon';
 import Parameter, { ParametersRecord } from '../../components/common/Parameter';
 import { Button } from '../../components/ui/button';
@@ -241,9 +242,20 @@ function ModelView({ selectedId: selectedModelId }: ModelViewProps) {
       ({ id } = sameModel);
       restored = true;
     } else {
+      const url = getResourceUrl(selectedModel.download);
+      if (url) {
+        // Only the header is downloaded, the model is described before its download starts.
+        try {
+          const descriptor = await getRemoteModelDescriptor(url);
+          const { architecture, contextLength } = descriptor;
+          toast.info(`${selectedModel.name}: ${architecture}, ${t('context')} ${contextLength}`);
+        } catch (error) {
+          logger.error(`Can't describe ${url}`, error);
+        }
+      }
       id = await installModel(
         selectedModel,
-        getResourceUrl(selectedModel.download),
+        url,
         path,
         selectedModel.name,
       );
@@ -29,14 +29,9 @@ import { useEffect, useState } from 'react';
 import Parameter, { ParameterValue } from '@/components/common/Parameter';
 import { ScrollArea } from '@/components/ui/scroll-area';
 import useTranslation from '@/hooks/useTranslation';
-import {
-  GGUF,
-  GGUFFileType,
-  GGUFMetadata,
-  GGUFMetadataArrayValue,
-  GGUFMetadataValueType,
-} from '@/types/gguf';
+import { GGUF, GGUFFileType, GGUFMetadata } from '@/types/gguf';
 import { getModelFileHeader } from '@/utils/backend/commands';
+import { formatMetadataValue } from '@/utils/models/gguf';
 import logger from '@/utils/logger';
 
 export type ModelFileInspectorProps = {
@@ -44,14 +39,10 @@ export type ModelFileInspectorProps = {
 };
 
 const convertMetadataToParameter = (metadata: GGUFMetadata): ParameterValue => {
-  if (metadata.valueType === GGUFMetadataValueType.Array) {
-    const value = metadata.value as GGUFMetadataArrayValue;
-    return `[:${value.len}]`;
-  }
   if (metadata.key.toLowerCase() === 'general.file_type') {
-    return GGUFFileType[metadata.value as GGUFFileType];
+    return GGUFFileType[metadata.value.value as GGUFFileType];
   }
-  return metadata.value as ParameterValue;
+  return formatMetadataValue(metadata.value);
 };
 
 function ModelFileInspector({ modelId }: ModelFileInspectorProps) {
@@ -26,14 +26,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+import { Fragment, useEffect, useState } from 'react';
 import Parameter, { ParameterValue } from '@/components/common/Parameter';
 import useTranslation from '@/hooks/useTranslation';
 import useBackend from '@/hooks/useBackendContext';
 import { Provider, ServerStatus } from '@/types';
+import { LoraCompatibility } from '@/types/gguf';
 import { deepGet } from '@/utils/data';
 import SelectModel from '@/components/common/SelectModel';
 import { getLocalModels, getLocalModelsAsItems } from '@/utils/data/models';
-import { getServerConfig, setActiveModel } from '@/utils/backend/commands';
+import { checkLoraAdapter, getServerConfig, setActiveModel } from '@/utils/backend/commands';
 import { LllamaCppParameterDefinitions } from '@/utils/providers/llama.cpp/constants';
 import { useModelsStore, useServerStore } from '@/stores';
 import { getCommandLineOptions } from '@/utils/providers/llama.cpp';
@@ -54,6 +56,21 @@ export default function Opla({
   const selectedModel = models.find((m) => m.id === modelId || m.fileName === modelId);
   const modelPath = config.parameters.modelPath as string;
   const items = getLocalModelsAsItems(modelStorage, selectedModel?.id);
+  const lora = deepGet(provider, 'metadata.server.parameters.lora', '') as string;
+  const selectedModelId = selectedModel?.id;
+  const [loraCompatibility, setLoraCompatibility] = useState<LoraCompatibility>();
+  useEffect(() => {
+    setLoraCompatibility(undefined);
+    if (!lora || !selectedModelId) {
+      return undefined;
+    }
+    // The adapter is checked against the active model once its path is typed,
+    // before the server is restarted with --lora.
+    const timeout = setTimeout(async () => {
+      setLoraCompatibility(await checkLoraAdapter(selectedModelId, lora));
+    }, 1000);
+    return () => clearTimeout(timeout);
+  }, [lora, selectedModelId]);
   const changeActiveModel = async (modelIdOrName: string) => {
     await setActiveModel(modelIdOrName);
     if (server.status === ServerStatus.STARTED || server.status === ServerStatus.STARTING) {
@@ -102,20 +119,35 @@ export default function Opla({
         <fieldset className="grid gap-6 rounded-lg border p-4">
           <legend className="-ml-1 px-1 text-sm font-medium">{t('Parameters')}</legend>
           {LllamaCppParameterDefinitions.map((def) => (
-            <Parameter
-              key={`llama_${def.name}`}
-              label={t(def.label || def.name)}
-              name={`metadata.server.parameters.${def.name}`}
-              value={
-                def.name === 'model'
-                  ? modelPath
-                  : deepGet(provider, `metadata.server.parameters.${def.name}`, def.defaultValue)
-              }
-              type={def.type}
-              disabled={def.disabled}
-              onChange={onParameterChange}
-              description={def.description}
-            />
+            <Fragment key={`llama_${def.name}`}>
+              <Parameter
+                label={t(def.label || def.name)}
+                name={`metadata.server.parameters.${def.name}`}
+                value={
+                  def.name === 'model'
+                    ? modelPath
+                    : deepGet(provider, `metadata.server.parameters.${def.name}`, def.defaultValue)
+                }
+                type={def.type}
+                disabled={def.disabled}
+                onChange={onParameterChange}
+                description={def.description}
+              />
+              {def.name === 'lora' && loraCompatibility && (
+                <div className="px-4 text-sm break-all">
+                  {loraCompatibility.errors.map((error) => (
+                    <div key={error} className="text-error dark:text-destructive">
+                      {error}
+                    </div>
+                  ))}
+                  {loraCompatibility.warnings.map((warning) => (
+                    <div key={warning} className="text-muted-foreground">
+                      {warning}
+                    </div>
+                  ))}
+                </div>
+              )}
+            </Fragment>
           ))}
         </fieldset>
       </form>
@@ -15,8 +15,13 @@
 use crate::ServerStatus;
 use crate::{ api::hf::search_hf_models, start_server, OplaContext };
 use crate::data::model::{ Model, ModelEntity };
+use crate::store::model::ModelStorageEvents;
+use crate::store::server::ServerStorageEvents;
 use crate::models::{ fetch_models_collection, ModelsCollection };
-use opla_core::gguf::GGUF;
+use opla_core::adapter::LoraCompatibility;
+use opla_core::descriptor::ModelDescriptor;
+use opla_core::gguf::{ GgufError, GGUF };
+use opla_core::safetensors::SafeTensors;
 use serde::Serialize;
 use tauri::{ Manager, Runtime, State };
 
@@ -48,6 +53,60 @@ pub async fn search_hfhub_models<R: Runtime>(
     })
 }
 
+// Describes a remote model file without downloading it, only its header is read.
+// Safetensors models are described with the config.json of their repository.
+#[tauri::command]
+pub async fn get_remote_model_descriptor<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    _window: tauri::Window<R>,
+    url: String
+) -> Result<ModelDescriptor, String> {
+    if !url.ends_with(".safetensors") {
+        let remote_url = url.clone();
+        let gguf = tauri::async_runtime
+            ::spawn_blocking(move || {
+                let mut gguf = GGUF::new(&remote_url);
+                gguf.read_url(&remote_url).map(|_| gguf)
+            }).await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())?;
+        return ModelDescriptor::from_gguf(&gguf);
+    }
+
+    let config_url = match url.rsplit_once('/') {
+        Some((base, _)) => format!("{}/config.json", base),
+        None => {
+            return Err(format!("Invalid model url: {}", url));
+        }
+    };
+    let config = reqwest
+        ::get(config_url).await
+        .map_err(|err| err.to_string())?
+        .json::<serde_json::Value>().await
+        .map_err(|err| format!("Invalid config.json: {}", err))?;
+    let remote_url = url.clone();
+    let mut safetensors = tauri::async_runtime
+        ::spawn_blocking(move || {
+            let mut safetensors = SafeTensors::new(&remote_url);
+            safetensors.read_url(&remote_url).map(|_| safetensors)
+        }).await
+        .map_err(|err| err.to_string())??;
+    safetensors.config = Some(config);
+    ModelDescriptor::from_safetensors(&safetensors)
+}
+
+#[tauri::command]
+pub async fn check_lora_adapter<R: Runtime>(
+    _app: tauri::AppHandle<R>,
+    _window: tauri::Window<R>,
+    context: State<'_, OplaContext>,
+    model_id: String,
+    adapter_path: String
+) -> Result<LoraCompatibility, String> {
+    let store = context.store.lock().await;
+    store.models.check_lora_adapter(&model_id, &adapter_path)
+}
+
 #[tauri::command]
 pub async fn get_model_full_path<R: Runtime>(
     _app: tauri::AppHandle<R>,
@@ -87,6 +146,46 @@ pub async fn install_model<R: Runtime>(
     path: String,
     file_name: String
 ) -> Result<String, String> {
+    let mut model = model;
+    if let Some(ref url) = url {
+        // Only the header is downloaded, to check the model before downloading it.
+        let remote_url = url.clone();
+        let result = tauri::async_runtime::spawn_blocking(move || {
+            let mut gguf = GGUF::new(&remote_url);
+            gguf.read_url(&remote_url).map(|_| gguf)
+        }).await;
+        match result {
+            // The descriptor only fills missing fields, the model is installed without it.
+            Ok(Ok(gguf)) =>
+                match ModelDescriptor::from_gguf(&gguf) {
+                    Ok(descriptor) => {
+                        println!(
+                            "Install model {} context_length={}",
+                            descriptor.architecture,
+                            descriptor.context_length
+                        );
+                        model.update_from_descriptor(&descriptor);
+                    }
+                    Err(err) => {
+                        println!("Install model can't describe model: {}", err);
+                    }
+                }
+            Ok(Err(err @ GgufError::BadMagic { .. })) => {
+                return Err(format!("Install model error: {}", err));
+            }
+            Ok(Err(GgufError::Io { source, .. })) => {
+                println!("Install model can't read remote header: {:?}", source);
+            }
+            // The parser is stricter than llama.cpp, the file may still load.
+            Ok(Err(err)) => {
+                println!("Warning: install model can't parse remote header: {}", err);
+            }
+            Err(err) => {
+                println!("Install model can't read remote header: {:?}", err);
+            }
+        }
+    }
+
     let mut store = context.store.lock().await;
     let was_empty = store.models.items.is_empty();
     let model_name = model.name.clone();
@@ -131,6 +230,9 @@ pub async fn install_model<R: Runtime>(
         None => {
             model_entity.state = Some("ok".to_string());
             store.models.add_model(model_entity);
+            if let Err(err) = store.models.update_model_from_file(&model_id) {
+                println!("Install model can't read model file: {:?}", err);
+            }
             store.save().map_err(|err| err.to_string())?;
             store.models.emit_update_all(app.app_handle());
             drop(store);
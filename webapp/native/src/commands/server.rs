@@ -14,6 +14,7 @@
 
 use tauri::{ Manager, Runtime, State };
 use crate::{data::{Metadata, Payload}, OplaContext};
+use crate::store::server::ServerStorageEvents;
 
 #[tauri::command]
 pub async fn get_opla_server_status<R: Runtime>(
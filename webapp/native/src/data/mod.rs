@@ -19,7 +19,7 @@ use service::Service;
 use std::{ collections::HashMap, fmt };
 use std::marker::PhantomData;
 use std::str::FromStr;
-use serde::de::{ self, Visitor, MapAccess };
+use serde::de::{ self, Visitor };
 use void::Void;
 
 pub mod asset;
@@ -58,50 +58,9 @@ pub fn is_false(b: &bool) -> bool {
     *b == false
 }
 
-#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum MetadataValue {
-    String(String),
-    Number(f32),
-    Integer(i32),
-    Boolean(bool),
-    Option(Option<String>),
-    Metadata(HashMap<String, MetadataValue>),
-}
-
-impl MetadataValue {
-    pub fn to_string(&self) -> String {
-        match self {
-            Self::String(i) => i.to_string(),
-            _ => format!("{:?}", &self),
-        }
-    }
-
-    pub fn to_int(&self, default_value: i32) -> i32 {
-        match self {
-            Self::Integer(i) => *i,
-            Self::Number(f) => *f as i32,
-            _ => default_value,
-        }
-    }
-
-    pub fn to_float(&self, default_value: f32) -> f32 {
-        match self {
-            Self::Integer(i) => *i as f32,
-            Self::Number(f) => *f,
-            _ => default_value,
-        }
-    }
-
-    pub fn to_bool(&self, default_value: bool) -> bool {
-        match self {
-            Self::Boolean(v) => *v,
-            _ => default_value,
-        }
-    }
-}
-
-pub type Metadata = HashMap<String, MetadataValue>;
+pub use opla_core::configuration::{ Metadata, MetadataValue };
+pub use opla_core::formats::{ option_date_format, option_string_or_struct, string_or_struct };
+pub use opla_core::models::{ Entity, Resource };
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
@@ -207,44 +166,6 @@ impl FromStr for Avatar {
     }
 }
 
-// See https://serde.rs/string-or-struct.html
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Entity {
-    pub name: String,
-    pub email: Option<String>,
-    pub url: Option<String>,
-}
-impl FromStr for Entity {
-    type Err = Void;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Entity {
-            name: s.to_string(),
-            email: None,
-            url: None,
-        })
-    }
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Resource {
-    pub url: String,
-    pub name: Option<String>,
-    // TODO handle filename
-}
-
-impl FromStr for Resource {
-    type Err = Void;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // TODO check if s is a valid URL or a file path
-        Ok(Resource {
-            url: s.to_string(),
-            name: None,
-        })
-    }
-}
-
 pub mod date_format {
     use chrono::{ DateTime, Utc };
     use serde::{ de, Deserialize, Deserializer };
@@ -289,132 +210,6 @@ pub mod date_format {
     }
 }
 
-pub mod option_date_format {
-    use chrono::{ DateTime, Utc };
-    use serde::{ de, Deserializer, Deserialize };
-    use serde_json::Value;
-
-    // const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.%f%z";
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
-        where D: Deserializer<'de>
-    {
-        /* let s = Option::<String>::deserialize(deserializer)?;
-        // println!("Parsing date: {:?}", s);
-        let datetime = match s {
-            Some(s) =>
-                DateTime::parse_from_rfc3339(&s)
-                    .map_err(serde::de::Error::custom)?
-                    .with_timezone(&Utc),
-            None => {
-                return Ok(None);
-            }
-        }; */
-        let value = Option::<Value>::deserialize(deserializer)?;
-        // println!("Parsing date: {:?}", s);
-        let datetime = match value {
-            Some(v) =>
-                match v.as_str() {
-                    Some(s) =>
-                        DateTime::parse_from_rfc3339(s)
-                            .map_err(de::Error::custom)?
-                            .with_timezone(&Utc),
-                    None =>
-                        match DateTime::from_timestamp_millis(v.as_i64().unwrap_or(-1)) {
-                            Some(d) => d,
-                            None => {
-                                return Err(de::Error::custom("Invalid timestamp millis"));
-                            }
-                        }
-                }
-            None => {
-                return Ok(None);
-            }
-        };
-        // println!("Parsed date: {:?}", datetime);
-        Ok(Some(datetime))
-    }
-
-    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
-        where S: serde::Serializer
-    {
-        match date {
-            Some(date) => {
-                // println!("Serializing date: {:?}", date);
-                let s = date.with_timezone(&Utc).to_rfc3339(); // date.format(FORMAT);
-                // println!("Serialized date: {}", s);
-                serializer.serialize_str(&s)
-            }
-            None => serializer.serialize_none(),
-        }
-    }
-}
-
-pub fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
-    where T: Deserialize<'de> + FromStr<Err = Void>, D: Deserializer<'de>
-{
-    // This is a Visitor that forwards string types to T's `FromStr` impl and
-    // forwards map types to T's `Deserialize` impl. The `PhantomData` is to
-    // keep the compiler from complaining about T being an unused generic type
-    // parameter. We need T in order to know the Value type for the Visitor
-    // impl.
-    struct StringOrStruct<T>(PhantomData<fn() -> T>);
-
-    impl<'de, T> Visitor<'de> for StringOrStruct<T> where T: Deserialize<'de> + FromStr<Err = Void> {
-        type Value = T;
-
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("string or map")
-        }
-
-        fn visit_str<E>(self, value: &str) -> Result<T, E> where E: de::Error {
-            match FromStr::from_str(value) {
-                Ok(v) => Ok(v),
-                Err(_) => Err(de::Error::custom("invalid string")),
-            }
-        }
-
-        fn visit_map<M>(self, map: M) -> Result<T, M::Error> where M: MapAccess<'de> {
-            // `MapAccessDeserializer` is a wrapper that turns a `MapAccess`
-            // into a `Deserializer`, allowing it to be used as the input to T's
-            // `Deserialize` implementation. T then deserializes itself using
-            // the entries from the map visitor.
-            Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))
-        }
-    }
-
-    deserializer.deserialize_any(StringOrStruct(PhantomData))
-}
-
-pub fn option_string_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
-    where T: Deserialize<'de> + FromStr<Err = Void>, D: Deserializer<'de>
-{
-    struct OptStringOrStruct<T>(PhantomData<T>);
-
-    impl<'de, T> Visitor<'de>
-        for OptStringOrStruct<T>
-        where T: Deserialize<'de> + FromStr<Err = Void>
-    {
-        type Value = Option<T>;
-
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a null, a string or map")
-        }
-
-        fn visit_none<E>(self) -> Result<Self::Value, E> where E: de::Error {
-            Ok(None)
-        }
-
-        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
-            where D: Deserializer<'de>
-        {
-            string_or_struct(deserializer).map(Some)
-        }
-    }
-
-    deserializer.deserialize_option(OptStringOrStruct(PhantomData))
-}
-
 pub fn f32_or_u32<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     where T: Deserialize<'de>, D: Deserializer<'de>
 {
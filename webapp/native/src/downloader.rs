@@ -109,6 +109,11 @@ impl Downloader {
         downloader.finish_download(id);
         let mut store = context.store.lock().await;
         store.models.set_model_state(id, &state);
+        if state == "ok" {
+            if let Err(err) = store.models.update_model_from_file(id) {
+                println!("Downloaded model can't read model file: {:?}", err);
+            }
+        }
         let _ = store.save();
     }
 
@@ -15,6 +15,9 @@
 use tokio::sync::Mutex;
 use std::collections::HashMap;
 use std::sync::Arc;
+use opla_core::adapter::LoraAdapter;
+use opla_core::architecture::validate_gguf;
+use opla_core::gguf::GGUF;
 use crate::data::{Payload, ServerPayload};
 use crate::engines::llama_cpp::{ LLamaCppEngine, LLAMACPP_PARAMETERS_DEFINITIONS };
 use crate::store::server::{ ServerConfiguration, ServerStorage };
@@ -161,6 +164,28 @@ impl LocalServer {
                 return Err("Opla server can't read parameters: model_id".to_string());
             }
         };
+        // llama.cpp fails with an opaque error on incomplete models.
+        let gguf = GGUF::read_split(&model_path).map_err(|err| err.to_string())?;
+        let validation = validate_gguf(&gguf);
+        if let Some(error) = validation.to_error() {
+            println!("Opla server invalid model {}: {}", model_path, error);
+            return Err(error);
+        }
+        if let Some(warning) = validation.to_warning() {
+            println!("Opla server model {}: {}", model_path, warning);
+        }
+        let lora = configuration
+            .get_optional_parameter_string("lora")
+            .filter(|lora| !lora.is_empty());
+        if let Some(lora) = lora {
+            let mut adapter = GGUF::new(&lora);
+            adapter.read(&lora).map_err(|err| err.to_string())?;
+            let compatibility = LoraAdapter::from_gguf(&adapter)?.check_compatibility(&gguf);
+            if let Some(error) = compatibility.to_error() {
+                println!("Opla server invalid LoRA adapter {}: {}", lora, error);
+                return Err(error);
+            }
+        }
         let arguments = configuration.to_args(&model_path, &LLAMACPP_PARAMETERS_DEFINITIONS);
         println!("Opla server arguments: {}", arguments.join(" "));
         if status == ServerStatus::Starting.as_str().to_string() {
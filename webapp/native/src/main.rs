@@ -47,6 +47,7 @@ use providers::ProvidersManager;
 use store::Store;
 use local_server::*;
 use sys::Sys;
+use opla_core::memory::MemoryOptions;
 use tauri::{ EventLoopMessage, Manager, Runtime, State };
 
 #[cfg(target_os = "macos")]
@@ -80,6 +81,7 @@ async fn start_server<R: Runtime>(
             return Err(format!("Opla server not started model path not found: {:?}", err));
         }
     };
+    check_server_memory(&app, &store, &active_model, &context).await;
     let mut server = context.server.lock().await;
     store.server.configuration.set_parameter_string("model_id", active_model);
     store.server.configuration.set_parameter_string("model_path", model_path);
@@ -93,6 +95,90 @@ async fn start_server<R: Runtime>(
     Ok(())
 }
 
+// Warns when the model is not expected to fit in memory with the server parameters,
+// the server is still started as the estimate is approximate.
+async fn check_server_memory<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    store: &Store,
+    model_id: &str,
+    context: &State<'_, OplaContext>
+) {
+    let configuration = &store.server.configuration;
+    let options = MemoryOptions {
+        context_size: Some(configuration.get_parameter_int("context_size", 512).max(0) as u64),
+        batch_size: configuration.get_parameter_int("batch_size", 512).max(1) as u64,
+        gpu_layers: configuration.get_parameter_int("n_gpu_layers", 0).max(0) as u64,
+        ..MemoryOptions::default()
+    };
+    let requirements = match store.models.estimate_model_memory(model_id, &options) {
+        Ok(requirements) => requirements,
+        Err(err) => {
+            println!("Opla can't estimate model memory: {}", err);
+            return;
+        }
+    };
+    let total_memory = context.sys.lock().await.refresh().total_memory;
+    println!("Opla model memory estimate: {:?} total memory: {}", requirements, total_memory);
+    let mut warnings = Vec::new();
+    if requirements.ram > total_memory {
+        warnings.push(
+            format!(
+                "Model needs about {:.1} GB of RAM with a context size of {}, only {:.1} GB available",
+                (requirements.ram as f64) / 1e9,
+                requirements.context_size,
+                (total_memory as f64) / 1e9
+            )
+        );
+    }
+    if requirements.vram > 0 {
+        let vram = (requirements.vram as f64) / 1e9;
+        match gpu_memory(total_memory.saturating_sub(requirements.ram)) {
+            Some(gpu_memory) if requirements.vram > gpu_memory => {
+                warnings.push(
+                    format!(
+                        "Model needs about {:.1} GB of VRAM with {} GPU layers, only {:.1} GB available",
+                        vram,
+                        options.gpu_layers,
+                        (gpu_memory as f64) / 1e9
+                    )
+                );
+            }
+            Some(_) => {}
+            None => {
+                warnings.push(
+                    format!(
+                        "Model needs about {:.1} GB of VRAM with {} GPU layers, GPU memory is unknown",
+                        vram,
+                        options.gpu_layers
+                    )
+                );
+            }
+        }
+    }
+    for message in warnings {
+        println!("Opla warning: {}", message);
+        let _ = app.emit_all(
+            "opla-server",
+            Payload::Server(ServerPayload {
+                status: ServerStatus::Stderr.as_str().to_string(),
+                message,
+            })
+        );
+    }
+}
+
+// GPU memory available for the offloaded layers. Apple silicon shares the RAM left by the
+// layers kept on the CPU.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+fn gpu_memory(free_memory: u64) -> Option<u64> {
+    Some(free_memory)
+}
+
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+fn gpu_memory(_free_memory: u64) -> Option<u64> {
+    sys::nvidia_gpu_memory()
+}
+
 async fn model_download_event<R: Runtime>(
     app: tauri::AppHandle<R>,
     model_id: String,
@@ -373,6 +459,8 @@ fn main() {
                 crate::commands::model::get_models_collection,
                 crate::commands::model::get_model_file,
                 crate::commands::model::search_hfhub_models,
+                crate::commands::model::get_remote_model_descriptor,
+                crate::commands::model::check_lora_adapter,
                 crate::commands::model::get_model_full_path,
                 crate::commands::model::install_model,
                 crate::commands::model::cancel_download_model,
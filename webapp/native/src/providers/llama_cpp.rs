@@ -19,8 +19,17 @@ use crate::{
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use opla_core::{
+    chat_template::ChatTemplate,
+    providers::llama_cpp::{
+        LlamaCppChatCompletionChunk,
+        LlamaCppChatTimings,
+        LlamaCppCompletionQuery,
+        LlamaCppCompletionResponse,
+    },
+};
 use serde::{ Deserialize, Serialize };
-use crate::providers::llm::{ LlmQuery, LlmCompletionResponse, LlmUsage };
+use crate::providers::llm::{ LlmQuery, LlmCompletionResponse };
 
 use super::{
     llm::{
@@ -34,138 +43,27 @@ use super::{
     ProviderAdapter, ServerParameters,
 };
 
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlamaCppCompletionQuery {
-    pub prompt: String,
-    pub stream: Option<bool>,
-    pub temperature: Option<f32>,
-    pub frequency_penalty: Option<f32>,
-    pub presence_penalty: Option<f32>,
-    pub seed: Option<f32>,
-    pub stop: Option<Vec<String>>,
-    pub top_p: Option<f32>,
-    pub top_k: Option<f32>,
-    pub min_p: Option<f32>,
-    pub n_predict: Option<f32>,
-    pub n_keep: Option<f32>,
-    pub tfs_z: Option<f32>,
-    pub typical_p: Option<f32>,
-    pub repeat_penalty: Option<f32>,
-    pub repeat_last_n: Option<f32>,
-    pub penalize_nl: Option<bool>,
-    pub penalty_prompt: Option<Vec<String>>,
-    pub mirostat: Option<f32>,
-    pub mirostat_tau: Option<f32>,
-    pub mirostat_eta: Option<f32>,
-    pub grammar: Option<String>,
-    pub ignore_eos: Option<bool>,
-}
-
-impl LlmQueryCompletion {
-    fn to_llama_cpp_parameters(
-        &self,
-        options: Option<LlmCompletionOptions>
-    ) -> LlamaCppCompletionQuery {
-        let mut prompt = String::new();
-        match options {
-            Some(options) => {
-                match options.system {
-                    Some(system) => {
-                        prompt.push_str(&format!("{}\n", system));
-                    }
-                    None => {}
+// Formats the prompt with the chat template of the model, the request body is built by
+// opla_core::providers::llama_cpp.
+fn to_llama_cpp_parameters(
+    query: &LlmQueryCompletion,
+    options: Option<LlmCompletionOptions>,
+    template: &Option<ChatTemplate>
+) -> LlamaCppCompletionQuery {
+    let system = options.and_then(|options| options.system);
+    let prompt = match template {
+        Some(template) =>
+            match query.to_chat_prompt(template, system.as_deref()) {
+                Ok(prompt) => prompt,
+                Err(error) => {
+                    println!("Chat template error, fallback to raw prompt: {}", error);
+                    query.to_raw_prompt(system.as_deref())
                 }
             }
-            None => {}
-        }
-        // TODO: handle context_window_policy and keep_system
-        for message in &self.messages {
-            match message.role.as_str() {
-                "user" => {
-                    prompt.push_str("Question:");
-                }
-                "assistant" => {
-                    prompt.push_str("Answer:");
-                }
-                _ => {}
-            }
-            prompt.push_str(&message.content.trim());
-            prompt.push('\n');
-        }
-        prompt.push_str("Answer:");
-        // println!("prompt: {}", prompt);
-        LlamaCppCompletionQuery {
-            prompt,
-            stream: self.get_parameter_as_boolean("stream"),
-            temperature: self.get_parameter_as_f32("temperature"),
-            stop: self.get_parameter_array("stop"),
-            frequency_penalty: self.get_parameter_as_f32("frequency_penalty"),
-            presence_penalty: self.get_parameter_as_f32("presence_penalty"),
-            seed: self.get_parameter_as_f32("seed"),
-            top_p: self.get_parameter_as_f32("top_p"),
-            top_k: self.get_parameter_as_f32("top_k"),
-            min_p: self.get_parameter_as_f32("min_p"),
-            n_predict: self.get_parameter_as_f32("n_predict"),
-            n_keep: self.get_parameter_as_f32("n_keep"),
-            tfs_z: self.get_parameter_as_f32("tfs_z"),
-            typical_p: self.get_parameter_as_f32("typical_p"),
-            repeat_penalty: self.get_parameter_as_f32("repeat_penalty"),
-            repeat_last_n: self.get_parameter_as_f32("repeat_last_n"),
-            penalize_nl: self.get_parameter_as_boolean("penalize_nl"),
-            penalty_prompt: self.get_parameter_array("penalty_prompt"),
-            mirostat: self.get_parameter_as_f32("mirostat"),
-            mirostat_tau: self.get_parameter_as_f32("mirostat_tau"),
-            mirostat_eta: self.get_parameter_as_f32("mirostat_eta"),
-            grammar: self.get_parameter_value("grammar"),
-            ignore_eos: self.get_parameter_as_boolean("ignore_eos"),
-        }
-    }
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlamaCppChatTimings {
-    pub predicted_ms: f32,
-    pub predicted_n: i32,
-    pub predicted_per_second: f32,
-    pub predicted_per_token_ms: f32,
-    pub prompt_ms: f32,
-    pub prompt_n: i32,
-    pub prompt_per_second: f32,
-    pub prompt_per_token_ms: f32,
-}
-impl LlamaCppChatTimings {
-    pub fn to_llm_usage(&self) -> LlmUsage {
-        LlmUsage {
-            completion_tokens: Some(self.predicted_n),
-            prompt_tokens: Some(self.prompt_n),
-            total_tokens: Some(self.predicted_n + self.prompt_n),
-            completion_ms: Some(self.predicted_ms as i64),
-            prompt_ms: Some(self.prompt_ms as i64),
-            total_ms: Some((self.predicted_ms + self.prompt_ms) as i64),
-            prompt_per_second: Some(self.prompt_per_second),
-            completion_per_second: Some(self.predicted_per_second),
-            total_per_second: Some(self.predicted_per_second + self.prompt_per_second),
-        }
-    }
-}
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlamaCppCompletionResponse {
-    pub content: String,
-    pub timings: LlamaCppChatTimings,
-}
-
-impl LlamaCppCompletionResponse {
-    pub fn to_llm_response(&self) -> LlmCompletionResponse {
-        LlmCompletionResponse {
-            created: 0,
-            status: "finished".to_owned(),
-            content: self.content.clone(),
-            usage: Some(self.timings.to_llm_usage()),
-        }
-    }
+        None => query.to_raw_prompt(system.as_deref()),
+    };
+    // println!("prompt: {}", prompt);
+    query.to_llama_cpp_parameters(prompt)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -213,12 +111,6 @@ impl HttpResponse<LlmCompletionResponse> for LlamaCppCompletionResponse {
         }
     }
 }
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlamaCppChatCompletionChunk {
-    pub content: String,
-    pub stop: Option<bool>,
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LlamaCppQueryTokenize {
     pub content: String,
@@ -238,12 +130,14 @@ impl LlamaCppTokenize {
 #[derive(Clone, Debug)]
 pub struct LlamaCppInferenceClient {
     pub server_parameters: Option<ServerParameters>,
+    pub chat_template: Option<ChatTemplate>,
 }
 
 impl LlamaCppInferenceClient {
     pub fn new(parameters: Option<ServerParameters>) -> Self {
         LlamaCppInferenceClient {
             server_parameters: parameters.clone(),
+            chat_template: None,
         }
     }
 
@@ -272,6 +166,10 @@ impl LlmInferenceInterface for LlamaCppInferenceClient {
         self.server_parameters = Some(parameters);
     }
 
+    fn set_chat_template(&mut self, template: Option<ChatTemplate>) {
+        self.chat_template = template;
+    }
+
     fn deserialize_response(&mut self, full: &Bytes) -> Result<LlmCompletionResponse, LlmError> {
         serde_json
             ::from_slice::<LlamaCppCompletionResponse>(&full)
@@ -291,16 +189,15 @@ impl LlmInferenceInterface for LlamaCppInferenceClient {
         data: String,
         _created: i64
     ) -> Result<Option<String>, LlmError> {
-        let chunk = match serde_json::from_str::<LlamaCppChatCompletionChunk>(&data) {
+        let chunk = match LlamaCppChatCompletionChunk::from_event(&data) {
             Ok(r) => r,
-            Err(error) => {
-                let message = format!("Failed to parse response: {}", error);
+            Err(message) => {
                 println!("{}", message);
                 return Err(LlmError::new(&message, "FailedParsingResponse")); // Err(Box::new(error));
             }
         };
         println!("chunk: {:?}", chunk);
-        if chunk.stop.unwrap_or(false) {
+        if chunk.is_stop() {
             println!("chunk: stop");
             return Ok(None);
         } else {
@@ -315,7 +212,11 @@ impl LlmInferenceInterface for LlamaCppInferenceClient {
         adapter: &mut ProviderAdapter
         /* sender: Sender<Result<LlmCompletionResponse, LlmError>> */
     ) -> Result<HttpService<LlmCompletionResponse, LlmError>, LlmError> {
-        let parameters = query.options.to_llama_cpp_parameters(completion_options);
+        let parameters = to_llama_cpp_parameters(
+            &query.options,
+            completion_options,
+            &self.chat_template
+        );
 
         // let is_stream = parameters.stream.unwrap_or(false);
 
@@ -13,6 +13,7 @@ use bytes::Bytes;
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use dyn_clone::DynClone;
+use opla_core::chat_template::ChatTemplate;
 use std::fmt;
 use async_trait::async_trait;
 use serde::{ Deserialize, Serialize };
@@ -24,6 +25,16 @@ use crate::{
 
 use super::{ services::HttpService, ProviderAdapter, ServerParameters };
 
+pub use opla_core::providers::llm::{
+    LlmCompletionOptions,
+    LlmCompletionResponse,
+    LlmMessage,
+    LlmParameter,
+    LlmQuery,
+    LlmQueryCompletion,
+    LlmUsage,
+};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LlmError {
     pub message: String,
@@ -71,132 +82,6 @@ impl HttpError for LlmError {
     }
 }
 
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmMessage {
-    pub content: String,
-    pub role: String,
-    pub name: Option<String>,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmParameter {
-    pub key: String,
-    pub value: String,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmQueryCompletion {
-    pub conversation_id: Option<String>,
-    pub message_id: Option<String>,
-    pub messages: Vec<LlmMessage>,
-    pub prompt: Option<String>,
-    pub parameters: Option<Vec<LlmParameter>>,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmCompletionOptions {
-    pub context_window_policy: Option<String>,
-    pub keep_system: Option<bool>,
-    pub system: Option<String>,
-}
-
-impl LlmQueryCompletion {
-    pub fn get_parameter_value(&self, key: &str) -> Option<String> {
-        let parameters = match &self.parameters {
-            Some(p) => p,
-            None => {
-                return None;
-            }
-        };
-        parameters
-            .iter()
-            .find(|p| p.key == key)
-            .map(|p| Some(p.value.clone()))
-            .unwrap_or(None)
-    }
-
-    pub fn get_parameter_as_boolean(&self, key: &str) -> Option<bool> {
-        let value = match self.get_parameter_value(key) {
-            Some(v) => v,
-            None => {
-                return None;
-            }
-        };
-        match value.as_str() {
-            "true" => Some(true),
-            "false" => Some(false),
-            _ => None,
-        }
-    }
-
-    pub fn get_parameter_as_f32(&self, key: &str) -> Option<f32> {
-        let value = match self.get_parameter_value(key) {
-            Some(v) => v,
-            None => {
-                return None;
-            }
-        };
-        match value.parse::<f32>() {
-            Ok(v) => Some(v),
-            Err(_) => None,
-        }
-    }
-
-    pub fn get_parameter_array(&self, key: &str) -> Option<Vec<String>> {
-        let value = match self.get_parameter_value(key) {
-            Some(v) => v,
-            None => {
-                return None;
-            }
-        };
-        let mut array: Vec<String> = vec![];
-        for item in value.split(",") {
-            array.push(item.to_owned());
-        }
-        Some(array)
-    }
-}
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmQuery<T> {
-    pub command: String,
-    pub options: T,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmUsage {
-    // Tokens
-    pub completion_tokens: Option<i32>,
-    pub prompt_tokens: Option<i32>,
-    pub total_tokens: Option<i32>,
-
-    // Timing
-    pub completion_ms: Option<i64>,
-    pub prompt_ms: Option<i64>,
-    pub total_ms: Option<i64>,
-    pub prompt_per_second: Option<f32>,
-    pub completion_per_second: Option<f32>,
-    pub total_per_second: Option<f32>,
-}
-impl LlmUsage {
-    pub fn new() -> Self {
-        Self {
-            completion_tokens: None,
-            prompt_tokens: None,
-            total_tokens: None,
-            completion_ms: None,
-            prompt_ms: None,
-            total_ms: None,
-            prompt_per_second: None,
-            completion_per_second: None,
-            total_per_second: None,
-        }
-    }
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LlmResponse {}
 
@@ -205,15 +90,6 @@ pub trait LlmResponseImpl {
     fn completion_to(response: LlmCompletionResponse) -> Self;
 }
 
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmCompletionResponse {
-    pub created: i64,
-    pub status: String,
-    pub content: String,
-    pub usage: Option<LlmUsage>,
-}
-
 #[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LlmCompletionPayload {
@@ -234,17 +110,6 @@ impl HttpChunk for LlmCompletionResponse {
     }
 }
 
-impl LlmCompletionResponse {
-    pub fn new(created: i64, status: &str, content: &str) -> Self {
-        Self {
-            created: created,
-            status: status.to_owned(),
-            content: content.to_owned(),
-            usage: None,
-        }
-    }
-}
-
 impl LlmResponseImpl for LlmCompletionResponse {
     fn new(created: i64, status: &str, content: &str) -> Self {
         Self {
@@ -274,6 +139,9 @@ pub struct LlmTokenizeResponse {
 pub trait LlmInferenceInterface: DynClone {
     fn set_parameters(&mut self, parameters: ServerParameters);
 
+    // Used by local inference to format the prompt as the model expects.
+    fn set_chat_template(&mut self, _template: Option<ChatTemplate>) {}
+
     fn deserialize_response(&mut self, full: &Bytes) -> Result<LlmCompletionResponse, LlmError>;
 
     fn deserialize_response_error(&mut self, full: &Bytes) -> Result<LlmResponseError, LlmError>;
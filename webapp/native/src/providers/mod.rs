@@ -14,16 +14,17 @@
 
 use std::{ collections::HashMap, sync::Arc };
 use llm::LlmCompletionPayload;
+use opla_core::gguf::GGUF;
 use serde::Serialize;
 use tauri::{ AppHandle, Manager, Runtime };
-use tokenizer::encode;
+use tokenizer::{ encode, GGUFTokenizer };
 use tokio::{ spawn, sync::Mutex };
 use bytes::Bytes;
 use uuid::Uuid;
 
 use crate::{
     data::{ provider::{ Provider, ProviderType }, LLMErrorPayload, Payload },
-    store::server::{ ServerConfiguration, ServerStorage },
+    store::server::{ ServerConfiguration, ServerStorage, ServerStorageEvents },
     utils::http_client::{ HttpChunk, NewHttpError },
     OplaContext,
     ServerStatus,
@@ -156,6 +157,8 @@ impl ProviderAdapter {
 pub struct ProvidersManager {
     interfaces: HashMap<String, Box<dyn LlmInferenceInterface + 'static + Send + Sync>>,
     completion_handles: Arc<Mutex<HashMap<String, Arc<tokio::task::AbortHandle>>>>,
+    // Tokenizers of the local models, by model path.
+    tokenizers: HashMap<String, Arc<GGUFTokenizer>>,
 }
 
 impl ProvidersManager {
@@ -168,6 +171,7 @@ impl ProvidersManager {
         ProvidersManager {
             interfaces,
             completion_handles: Arc::new(Mutex::new(HashMap::new())),
+            tokenizers: HashMap::new(),
         }
     }
 
@@ -434,11 +438,16 @@ impl ProvidersManager {
             }
         };
         if llm_provider_type == "opla" {
-            let interface = self.create_interface(
+            let mut interface = self.create_interface(
                 app.app_handle(),
                 model.to_string(),
                 llm_provider_type
             ).await?;
+            let template = context.store.lock().await.models.get_chat_template(model);
+            match template {
+                Ok(template) => interface.set_chat_template(Some(template)),
+                Err(error) => println!("No chat template for {}: {}", model, error),
+            }
             let handle = app.app_handle();
             let response = self.request_completion::<R>(
                 handle,
@@ -512,6 +521,17 @@ impl ProvidersManager {
         return Err(format!("LLM provider not found: {:?}", llm_provider_type));
     }
 
+    // Decoding the vocabulary is slow, tokenizers are built once per model file.
+    fn get_local_tokenizer(&mut self, path: &str) -> Result<Arc<GGUFTokenizer>, String> {
+        if let Some(tokenizer) = self.tokenizers.get(path) {
+            return Ok(tokenizer.clone());
+        }
+        let gguf = GGUF::read_split(path).map_err(|err| err.to_string())?;
+        let tokenizer = Arc::new(GGUFTokenizer::from_gguf(&gguf)?);
+        self.tokenizers.insert(path.to_string(), tokenizer.clone());
+        Ok(tokenizer)
+    }
+
     pub async fn llm_call_tokenize<R: Runtime>(
         &mut self,
         app: tauri::AppHandle<R>,
@@ -521,6 +541,23 @@ impl ProvidersManager {
     ) -> Result<LlmTokenizeResponse, String> {
         let llm_provider_type = provider.r#type;
         if llm_provider_type == "opla" {
+            // Local models are tokenized from their file, the server is used as a fallback.
+            let context = app.state::<OplaContext>();
+            let path = context.store.lock().await.models.get_path(model.to_string());
+            match path.and_then(|path| self.get_local_tokenizer(&path)) {
+                Ok(tokenizer) => {
+                    // Same as the server /tokenize, special tokens are parsed but not added.
+                    let tokens = tokenizer
+                        .encode(&text, false, true)
+                        .iter()
+                        .map(|&x| x as u64)
+                        .collect();
+                    return Ok(LlmTokenizeResponse { tokens });
+                }
+                Err(err) => {
+                    println!("LLM local tokenizer not available: {}", err);
+                }
+            }
             let client = self.create_interface(
                 app.app_handle(),
                 model.to_string(),
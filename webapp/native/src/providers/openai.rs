@@ -18,6 +18,12 @@ use tauri::Runtime;
 use eventsource_stream::Eventsource;
 use futures_util::stream::StreamExt;
 
+use opla_core::providers::openai::{
+    OpenAIBodyCompletion,
+    OpenAIChatCompletion,
+    OpenAIChatCompletionChunk,
+    OpenAIErrorResponse,
+};
 use tokenizer::encode;
 
 use crate::{
@@ -36,197 +42,6 @@ use crate::{
 
 use super::llm::{ LlmImageGenerationResponse, LlmModelsResponse };
 
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIError {
-    pub message: String,
-    #[serde(rename = "type")]
-    pub error_type: String,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIErrorResponse {
-    pub error: OpenAIError,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIBodyCompletion {
-    pub model: String,
-    pub messages: Vec<LlmMessage>,
-    pub stream: Option<bool>,
-    pub temperature: Option<f32>,
-    pub frequency_penalty: Option<f32>,
-    pub presence_penalty: Option<f32>,
-    pub seed: Option<f32>,
-    pub stop: Option<Vec<String>>,
-    pub top_p: Option<f32>,
-    pub max_tokens: Option<f32>,
-}
-
-impl OpenAIBodyCompletion {
-    pub fn new(
-        model: String,
-        from: &LlmQueryCompletion,
-        options: Option<LlmCompletionOptions>
-    ) -> Self {
-        let mut messages: Vec<LlmMessage> = vec![];
-
-        match options {
-            Some(options) => {
-                match options.system {
-                    Some(system) => {
-                        messages.push(LlmMessage {
-                            content: system.clone(),
-                            role: "system".to_owned(),
-                            name: None,
-                        });
-                    }
-                    None => {}
-                }
-            }
-            None => {}
-        }
-        messages.extend(from.messages.clone());
-        // TODO: handle context_window_policy and keep_system
-        Self {
-            model,
-            messages,
-            stream: from.get_parameter_as_boolean("stream"),
-            temperature: from.get_parameter_as_f32("temperature"),
-            stop: from.get_parameter_array("stop"),
-            frequency_penalty: from.get_parameter_as_f32("frequency_penalty"),
-            presence_penalty: from.get_parameter_as_f32("presence_penalty"),
-            seed: from.get_parameter_as_f32("seed"),
-            top_p: from.get_parameter_as_f32("top_p"),
-            max_tokens: from.get_parameter_as_f32("max_tokens"),
-        }
-    }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIChatChoice {
-    pub message: LlmMessage,
-    pub index: i32,
-    pub finish_reason: String,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct LlmChunkMessage {
-    pub content: Option<String>,
-    pub role: Option<String>,
-    pub name: Option<String>,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIChatChunkChoice {
-    pub delta: LlmChunkMessage,
-    pub index: i32,
-    pub finish_reason: Option<String>,
-}
-
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct OpenAIChatCompletionChunk {
-    pub id: String,
-    pub choices: Vec<OpenAIChatChunkChoice>,
-    pub model: String,
-    pub created: i64,
-    pub system_fingerprint: Option<String>,
-    pub object: String,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIChatUsage {
-    pub completion_tokens: i32,
-    pub prompt_tokens: i32,
-    pub total_tokens: i32,
-}
-#[serde_with::skip_serializing_none]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct OpenAIChatCompletion {
-    pub id: String,
-    pub choices: Vec<OpenAIChatChoice>,
-    pub object: String,
-    pub created: i64,
-    pub system_fingerprint: Option<String>,
-    pub usage: OpenAIChatUsage,
-}
-
-impl OpenAIChatCompletion {
-    fn from_chunks(chunks: Vec<OpenAIChatCompletionChunk>) -> Self {
-        let mut choices: Vec<OpenAIChatChoice> = vec![];
-        let id = chunks[0].id.clone();
-        let object = chunks[0].object.clone();
-        let created = chunks[0].created;
-        let system_fingerprint = chunks[0].system_fingerprint.clone();
-        let finish_reason = match &chunks[chunks.len() - 1].choices[0].finish_reason {
-            Some(f) => f,
-            None => "",
-        };
-        let role = match &chunks[chunks.len() - 1].choices[0].delta.role {
-            Some(f) => f,
-            None => "assistant",
-        };
-        let name = &chunks[chunks.len() - 1].choices[0].delta.name.clone();
-        let mut content = String::new();
-        for chunk in &chunks {
-            // TODO: handle multiple choices index
-            match &chunk.choices[0].delta.content {
-                Some(c) => content.push_str(c),
-                None => (),
-            }
-        }
-        choices.push(OpenAIChatChoice {
-            message: LlmMessage {
-                content,
-                role: role.to_owned(),
-                name: name.clone(),
-            },
-            index: 0,
-            finish_reason: finish_reason.to_owned(),
-        });
-        Self {
-            id,
-            choices,
-            object,
-            created,
-            system_fingerprint,
-            usage: OpenAIChatUsage {
-                completion_tokens: 0,
-                prompt_tokens: 0,
-                total_tokens: 0,
-            },
-            // TODO implement usage
-            // see :https://community.openai.com/t/usage-stats-now-available-when-using-streaming-with-the-chat-completions-api-or-completions-api/738156/3
-        }
-    }
-
-    fn to_llm_response(&self) -> LlmCompletionResponse {
-        let mut response = LlmCompletionResponse::new(
-            self.created,
-            "finished",
-            &self.choices[0].message.content
-        );
-        let usage = LlmUsage {
-            completion_tokens: Some(self.usage.completion_tokens),
-            prompt_tokens: Some(self.usage.prompt_tokens),
-            total_tokens: Some(self.usage.total_tokens),
-            completion_ms: None,
-            prompt_ms: None,
-            total_ms: None,
-            prompt_per_second: None,
-            completion_per_second: None,
-            total_per_second: None,
-        };
-        response.usage = Some(usage);
-        response
-    }
-}
-
 #[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OpenAIBodyImageGeneration {
@@ -417,31 +232,30 @@ async fn stream_request<R: Runtime>(
     while let Some(event) = stream.next().await {
         match event {
             Ok(event) => {
-                // break the loop at the end of SSE stream
-                if event.data == "[DONE]" {
-                    match callback {
-                        Some(mut cb) => {
-                            cb(
-                                Ok(
-                                    LlmCompletionResponse::new(
-                                        chrono::Utc::now().timestamp_millis(),
-                                        "finished",
-                                        "done"
+                // parse the event data into a Completion object
+                let chunk = match OpenAIChatCompletionChunk::from_event(&event.data) {
+                    Ok(Some(t)) => t,
+                    // break the loop at the end of SSE stream
+                    Ok(None) => {
+                        match callback {
+                            Some(mut cb) => {
+                                cb(
+                                    Ok(
+                                        LlmCompletionResponse::new(
+                                            chrono::Utc::now().timestamp_millis(),
+                                            "finished",
+                                            "done"
+                                        )
                                     )
-                                )
-                            );
+                                );
+                            }
+                            None => (),
                         }
-                        None => (),
+                        break;
                     }
-                    break;
-                }
-
-                // parse the event data into a Completion object
-                let chunk = match serde_json::from_str::<OpenAIChatCompletionChunk>(&event.data) {
-                    Ok(t) => t,
-                    Err(error) => {
-                        println!("Failed to dezerialize event data: {}", error);
-                        return Err(Box::new(error));
+                    Err(message) => {
+                        println!("{}", message);
+                        return Err(Box::new(LlmError::new(&message, "FailedDeserialize")));
                     }
                 };
                 match callback {
@@ -451,9 +265,7 @@ async fn stream_request<R: Runtime>(
                                 LlmCompletionResponse::new(
                                     chunk.created,
                                     "success",
-                                    chunk.choices[0].delta.content
-                                        .as_ref()
-                                        .unwrap_or(&String::from(""))
+                                    chunk.content()
                                 )
                             )
                         );
@@ -475,7 +287,7 @@ async fn stream_request<R: Runtime>(
             }
         }
     }
-    let completion = OpenAIChatCompletion::from_chunks(chunks);
+    let completion = OpenAIChatCompletion::from_chunks(&chunks);
 
     Ok(completion.to_llm_response())
 }
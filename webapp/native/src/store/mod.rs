@@ -26,10 +26,10 @@ use crate::{
     utils::get_config_directory,
 };
 
-use self::model::ModelStorage;
+use self::model::{ ModelStorage, ModelStorageEvents };
 use self::service::ServiceStorage;
 use self::workspace::WorkspaceStorage;
-use self::server::ServerStorage;
+use self::server::{ ServerStorage, ServerStorageEvents };
 
 pub mod settings;
 pub mod thread;
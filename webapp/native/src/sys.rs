@@ -11,6 +11,8 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::process::Command;
+
 use sysinfo::System;
 use serde::{ Deserialize, Serialize };
 
@@ -97,3 +99,21 @@ impl Sys {
         return self.infos.clone();
     }
 }
+
+// Memory of the NVIDIA GPUs in bytes, as given by nvidia-smi, layers are split between them.
+// None when it can't be queried.
+pub fn nvidia_gpu_memory() -> Option<u64> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=memory.total", "--format=csv,noheader,nounits"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut total = 0;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mebibytes: u64 = line.trim().parse().ok()?;
+        total += mebibytes * 1024 * 1024;
+    }
+    if total > 0 { Some(total) } else { None }
+}
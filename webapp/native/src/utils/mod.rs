@@ -15,27 +15,4 @@
 pub mod http_client;
 pub mod image;
 
-use std::{ path::{ Path, PathBuf }, fs };
-
-pub fn get_home_directory() -> Result<PathBuf, String> {
-    let home_dir = dirs::home_dir().ok_or("Failed to get home directory")?;
-    Ok(home_dir)
-}
-
-pub fn get_data_directory() -> Result<PathBuf, String> {
-    let data_dir = dirs::data_dir().ok_or("Failed to get data directory")?;
-    let home_dir = data_dir.join("Opla");
-    if !Path::exists(&home_dir) {
-        fs::create_dir(&home_dir).map_err(|_| "Failed to create data directory")?;
-    }
-    Ok(home_dir)
-}
-
-pub fn get_config_directory() -> Result<PathBuf, String> {
-    let config_dir = dirs::config_dir().ok_or("Failed to get conf directory")?;
-    let config_dir = config_dir.join("Opla");
-    if !Path::exists(&config_dir) {
-        fs::create_dir(&config_dir).map_err(|_| "Failed to create conf directory")?;
-    }
-    Ok(config_dir)
-}
+pub use opla_core::directories::{ get_config_directory, get_data_directory, get_home_directory };
@@ -101,12 +101,22 @@ export enum GGUFMetadataValueType {
   Float64 = 'Float64', // 12,
 }
 
-export type GGUFMetadataValue = number | boolean | string | GGUFMetadataArrayValue;
+// Values are tagged with their type, array elements are typed by valueType.
+// Infinite and NaN floats are the strings 'inf', '-inf' and 'nan'.
+export type GGUFMetadataValue =
+  | { type: GGUFMetadataValueType.Array; value: GGUFMetadataArrayValue }
+  | {
+      type: Exclude<GGUFMetadataValueType, GGUFMetadataValueType.Array>;
+      value: number | boolean | string;
+    };
+
+export type GGUFMetadataArrayElement = number | boolean | string | GGUFMetadataArrayValue;
 
 export type GGUFMetadataArrayValue = {
   valueType: GGUFMetadataValueType;
   len: number;
-  value: GGUFMetadataValue[];
+  value: GGUFMetadataArrayElement[];
+  offset?: number; // set when the values are not loaded
 };
 
 export type GGUFMetadata = {
@@ -120,9 +130,55 @@ export type GGUFHeader = {
   tensorCount: number;
   metadataKvCount: number;
   metadataKv: GGUFMetadata[];
+  byteOrder?: 'LittleEndian' | 'BigEndian';
+};
+
+export type GGUFTensorInfo = {
+  name: string;
+  nDimensions: number;
+  dimensions: number[];
+  tensorType: string;
+  offset: number;
 };
 
 export type GGUF = {
   fileName: string;
   header: GGUFHeader;
+  tensors: GGUFTensorInfo[];
+  alignment: number;
+  tensorDataOffset: number;
+};
+
+export type ModelDescriptor = {
+  architecture: string;
+  name?: string;
+  contextLength: number;
+  embeddingLength: number;
+  blockCount: number;
+  feedForwardLength?: number;
+  headCount?: number;
+  headCountKv?: number;
+  keyLength?: number;
+  valueLength?: number;
+  fileType?: number;
+  quantization?: string;
+  parameterCount: number;
+  bitsPerWeight?: number;
+  vocabSize?: number;
+  chatTemplate?: string;
+  bosTokenId?: number;
+  eosTokenId?: number;
+};
+
+export type LoraCompatibility = {
+  compatible: boolean;
+  architecture?: string;
+  baseArchitecture?: string;
+  embeddingLength?: number;
+  baseEmbeddingLength?: number;
+  rank?: number;
+  alpha?: number;
+  tensorCount: number;
+  errors: string[];
+  warnings: string[];
 };
@@ -27,7 +27,7 @@ import {
   Sys,
 } from '@/types';
 import { toast } from '@/components/ui/Toast';
-import { GGUF } from '@/types/gguf';
+import { GGUF, LoraCompatibility } from '@/types/gguf';
 import { mapKeys } from '../data';
 import { toCamelCase, toSnakeCase } from '../string';
 import logger from '../logger';
@@ -224,6 +224,25 @@ export const getModelFileHeader = async (modelId: string): Promise<Partial<GGUF>
   return {};
 };
 
+export const checkLoraAdapter = async (
+  modelId: string,
+  adapterPath: string,
+): Promise<LoraCompatibility | undefined> => {
+  try {
+    let compatibility = await invokeTauri<LoraCompatibility>('check_lora_adapter', {
+      modelId,
+      adapterPath,
+    });
+    compatibility = await mapKeys(compatibility, toCamelCase);
+    logger.info('checkLoraAdapter:', compatibility);
+    return compatibility;
+  } catch (error) {
+    logger.error(error);
+    toast.error(`Error checkLoraAdapter: ${error}`);
+  }
+  return undefined;
+};
+
 export const loadConversationMessages = async (
   conversationId: string,
   cache = true,
@@ -0,0 +1,44 @@
+// Copyright 2024 Mik Bry
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+import { GGUFMetadataArrayValue, GGUFMetadataValue, GGUFMetadataValueType } from '@/types/gguf';
+
+// Same rendering as the Display implementation of opla_core.
+export const GGUF_ARRAY_PREVIEW_LEN = 8;
+
+const formatArray = (array: GGUFMetadataArrayValue, full: boolean): string => {
+  if (array.offset !== undefined && array.offset !== null) {
+    return `[${array.valueType}; ${array.len}]`;
+  }
+  const count = full ? array.value.length : GGUF_ARRAY_PREVIEW_LEN;
+  const items = array.value.slice(0, count).map((item) => {
+    if (typeof item === 'string') {
+      return JSON.stringify(item);
+    }
+    if (typeof item === 'object') {
+      return formatArray(item, full);
+    }
+    return String(item);
+  });
+  const more = array.value.length > count ? `, ... ${array.len} items` : '';
+  return `[${items.join(', ')}${more}]`;
+};
+
+// Long arrays are truncated, unless full is set.
+export const formatMetadataValue = (metadata: GGUFMetadataValue, full = false): string => {
+  if (metadata.type === GGUFMetadataValueType.Array) {
+    return formatArray(metadata.value, full);
+  }
+  return String(metadata.value);
+};
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 import { Model, ModelsCollection } from '@/types';
+import { ModelDescriptor } from '@/types/gguf';
 import { invokeTauri } from '@/utils/backend/tauri';
 import { mapKeys } from '@/utils/data';
 import logger from '@/utils/logger';
@@ -24,6 +25,12 @@ export const searchModels = async (query: string): Promise<Model[]> => {
   return collection.models;
 };
 
+// Works for GGUF and safetensors files, only the header is downloaded.
+export const getRemoteModelDescriptor = async (url: string): Promise<ModelDescriptor> => {
+  const descriptor = await invokeTauri<ModelDescriptor>('get_remote_model_descriptor', { url });
+  return mapKeys(descriptor, toCamelCase);
+};
+
 export const getModel = async (id: string): Promise<Model> => {
   logger.info('HF getModel TODO', id);
   return {